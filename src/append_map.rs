@@ -0,0 +1,213 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::sync::OnceLock;
+use std::sync::atomic::{ AtomicUsize, Ordering::* };
+
+use fxhash::hash64;
+use parking_lot::Mutex;
+
+/// More than enough levels for anything that fits in memory.
+const NUM_LEVELS : usize = 48;
+const MIN_FIRST_CAP : usize = 64;
+
+/// A concurrent hash map that can only be added to. Reading never takes
+/// a lock : once a key is in the map it stays there with the same value,
+/// so `get` can hand out plain references to it, and writers only ever
+/// fill in empty slots, which readers see all at once or not at all.
+///
+/// The slots are split into levels, each twice the size of the one
+/// before it. Writers (which take turns) only add to the newest level,
+/// and start a new one once it's half full, instead of moving everything
+/// over to a bigger table like a `HashMap` would, since readers may be
+/// holding on to things in the old one. A key is looked for in each
+/// level in turn, oldest first; the first few levels are small, so this
+/// doesn't cost much more than a single table.
+pub struct AppendMap<K, V> {
+    levels : Vec<OnceLock<Slots<K, V>>>,
+    len : AtomicUsize,
+    writer : Mutex<Fill>,
+}
+
+/// One level's worth of slots.
+type Slots<K, V> = Box<[OnceLock<(K, V)>]>;
+
+/// The newest level, and how many of its slots are taken.
+struct Fill {
+    top : usize,
+    used : usize,
+}
+
+impl<K : Hash + Eq, V> AppendMap<K, V> {
+    /// `capacity` is how many entries fit before the map needs a
+    /// second level.
+    pub fn with_capacity(capacity : usize) -> Self {
+        let first_cap = (capacity * 2).next_power_of_two().max(MIN_FIRST_CAP);
+        let levels = (0..NUM_LEVELS).map(|_| OnceLock::new()).collect::<Vec<_>>();
+        let _ = levels[0].set(empty_level(first_cap));
+        AppendMap {
+            levels,
+            len : AtomicUsize::new(0),
+            writer : Mutex::new(Fill { top : 0, used : 0 }),
+        }
+    }
+
+    pub fn get<Q>(&self, key : &Q) -> Option<&V>
+    where K : Borrow<Q>,
+          Q : Hash + Eq + ?Sized {
+        let hash = hash64(key);
+        for level in self.levels.iter().map_while(|level| level.get()) {
+            let mask = level.len() - 1;
+            let mut idx = start_idx(hash, level.len());
+            while let Some((k, v)) = level[idx].get() {
+                if k.borrow() == key {
+                    return Some(v)
+                }
+                idx = (idx + 1) & mask;
+            }
+        }
+        None
+    }
+
+    pub fn contains_key<Q>(&self, key : &Q) -> bool
+    where K : Borrow<Q>,
+          Q : Hash + Eq + ?Sized {
+        self.get(key).is_some()
+    }
+
+    /// Add `value` under `key`, unless something's already there, in
+    /// which case the map is left alone, and we get back what's there
+    /// along with `value`.
+    pub fn insert(&self, key : K, value : V) -> Result<(), (&V, V)> {
+        let mut fill = self.writer.lock();
+        if let Some(existing) = self.get(&key) {
+            return Err((existing, value))
+        }
+
+        let mut level = self.level(fill.top);
+        if (fill.used + 1) * 2 > level.len() {
+            let cap = level.len() * 2;
+            fill.top += 1;
+            fill.used = 0;
+            let _ = self.levels[fill.top].set(empty_level(cap));
+            level = self.level(fill.top);
+        }
+
+        let mask = level.len() - 1;
+        let mut idx = start_idx(hash64(&key), level.len());
+        while level[idx].get().is_some() {
+            idx = (idx + 1) & mask;
+        }
+        // Writers take turns, so nobody else can have taken the slot.
+        let _ = level[idx].set((key, value));
+        fill.used += 1;
+        self.len.fetch_add(1, Release);
+        Ok(())
+    }
+
+    fn level(&self, idx : usize) -> &[OnceLock<(K, V)>] {
+        self.levels[idx].get().expect("levels up to the newest one are always initialized")
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every entry, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.levels
+            .iter()
+            .map_while(|level| level.get())
+            .flat_map(|level| level.iter().filter_map(|slot| slot.get()))
+            .map(|(k, v)| (k, v))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+fn empty_level<K, V>(cap : usize) -> Slots<K, V> {
+    (0..cap).map(|_| OnceLock::new()).collect()
+}
+
+/// Where to start probing a level with `cap` slots. The high bits of an
+/// fxhash are the well mixed ones.
+fn start_idx(hash : u64, cap : usize) -> usize {
+    (hash >> (64 - cap.trailing_zeros())) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num_levels<K, V>(map : &AppendMap<K, V>) -> usize {
+        map.levels.iter().filter(|level| level.get().is_some()).count()
+    }
+
+    #[test]
+    fn grows_past_the_first_level() {
+        let map = AppendMap::with_capacity(0);
+        assert_eq!(map.level(0).len(), MIN_FIRST_CAP);
+        for n in 0..10_000u64 {
+            assert!(map.insert(n, n * 2).is_ok());
+        }
+        // 64, 128, ... 16384 slots, each at most half full, hold 16352.
+        assert_eq!(num_levels(&map), 9);
+        assert_eq!(map.len(), 10_000);
+        assert_eq!(map.iter().count(), 10_000);
+        for n in 0..10_000u64 {
+            assert_eq!(map.get(&n), Some(&(n * 2)));
+        }
+        assert_eq!(map.get(&10_000), None);
+    }
+
+    #[test]
+    fn duplicate_insert_is_an_error() {
+        let map = AppendMap::with_capacity(4);
+        assert!(map.insert("a", 1).is_ok());
+        assert_eq!(map.insert("a", 2), Err((&1, 2)));
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.len(), 1);
+    }
+
+    /// Readers running alongside the writers only ever see complete
+    /// entries, and never lose one they've already seen.
+    #[test]
+    fn reads_during_concurrent_inserts() {
+        const N : u64 = 20_000;
+        let map = AppendMap::with_capacity(0);
+        std::thread::scope(|scope| {
+            for w in 0..2 {
+                let map = &map;
+                scope.spawn(move || {
+                    for n in (w..N).step_by(2) {
+                        assert!(map.insert(n, n * 2).is_ok());
+                    }
+                });
+            }
+            for _ in 0..4 {
+                let map = &map;
+                scope.spawn(move || {
+                    let mut seen = Vec::new();
+                    while map.len() < N as usize {
+                        for n in (0..N).step_by(97) {
+                            if let Some(v) = map.get(&n) {
+                                assert_eq!(*v, n * 2);
+                                seen.push(n);
+                            }
+                        }
+                        for n in seen.iter() {
+                            assert!(map.contains_key(n));
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), N as usize);
+        assert!((0..N).all(|n| map.get(&n) == Some(&(n * 2))));
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use hashbrown::{ HashMap, HashSet };
+
+use crate::name::Name;
+use crate::expr::unique_const_names;
+use crate::env::Env;
+use crate::errors::{ KernelResult, KernelError::* };
+
+/// The axioms that ship with Lean's core library (under their Lean 3
+/// and Lean 4 names). Anything else that turns up in an axiom report
+/// was declared with `#AX` by the user.
+pub const STANDARD_AXIOMS : [&str; 5] = ["classical.choice", "quot.sound", "propext", "Classical.choice", "Quot.sound"];
+
+/// An axiom is a declaration that isn't a definition of any kind and
+/// didn't come out of an inductive or quotient declaration. Since the
+/// base type of an inductive is added as an axiom (see `Inductive::new`),
+/// we have to look in `Env::inductives` to tell the two apart.
+pub fn is_axiom(env : &Env, n : &Name) -> bool {
+    match env.declarations.get(n) {
+        None => false,
+        Some(d) => !d.builtin
+                   && d.kind.is_none()
+                   && !env.inductives.contains_key(n)
+    }
+}
+
+pub fn is_standard_axiom(n : &Name) -> bool {
+    STANDARD_AXIOMS.iter().any(|s| n.to_string() == *s)
+}
+
+/// Every constant mentioned in the type of `n`, plus the ones in its
+/// value if it's a definition.
+fn direct_deps(env : &Env, n : &Name) -> Vec<Name> {
+    let mut deps = env.get_value_deps(n).unwrap_or_default().into_iter().collect::<HashSet<Name>>();
+    if let Some(d) = env.declarations.get(n) {
+        deps.extend(unique_const_names(&d.ty).into_iter().cloned());
+    }
+    deps.remove(n);
+    deps.into_iter().collect()
+}
+
+/// The axioms each of a list of declarations depends on, directly or
+/// through the types and values of other declarations. This is the
+/// same thing Lean's `#print axioms` tells you, just for many
+/// declarations at once.
+#[derive(Debug, Clone)]
+pub struct AxiomReport {
+    pub entries : Vec<(Name, Vec<Name>)>,
+}
+
+impl AxiomReport {
+    /// Build a report for `targets`, or for every declaration in `env`
+    /// (in the order they were added, leaving out the ones generated
+    /// for inductives and quot) if `targets` is `None`.
+    pub fn new(env : &Env, targets : Option<&[Name]>) -> KernelResult<Self> {
+        let targets = match targets {
+            Some(ns) => {
+                if let Some(missing) = ns.iter().find(|n| !env.declarations.contains_key(*n)) {
+                    return Err(UnknownConst(missing.clone()))
+                }
+                ns.to_vec()
+            },
+            None => {
+                let mut ds = env.declarations.values()
+                                .filter(|d| !d.builtin)
+                                .collect::<Vec<_>>();
+                ds.sort_by_key(|d| d.pos);
+                ds.into_iter().map(|d| d.name.clone()).collect()
+            }
+        };
+
+        let mut cache = HashMap::with_capacity(env.num_declars());
+        let entries = targets.into_iter().map(|n| {
+            let mut axioms = axiom_closure(env, &n, &mut cache).iter().cloned().collect::<Vec<Name>>();
+            axioms.sort_by_key(|ax| ax.to_string());
+            (n, axioms)
+        }).collect();
+
+        Ok(AxiomReport { entries })
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut acc = String::from("[");
+        for (idx, (n, axioms)) in self.entries.iter().enumerate() {
+            let (standard, user) : (Vec<&Name>, Vec<&Name>) = axioms.iter().partition(|ax| is_standard_axiom(ax));
+            if idx > 0 {
+                acc.push(',');
+            }
+            acc.push_str(&format!("\n  {{\"name\": {}, \"standard_axioms\": {}, \"user_axioms\": {}}}",
+                                  json_string(&n.to_string()),
+                                  json_list(standard.as_slice()),
+                                  json_list(user.as_slice())));
+        }
+        acc.push_str("\n]");
+        acc
+    }
+}
+
+impl std::fmt::Display for AxiomReport {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (n, axioms) in self.entries.iter() {
+            if axioms.is_empty() {
+                writeln!(f, "{} does not depend on any axioms", n)?;
+                continue
+            }
+            let shown = axioms.iter().map(|ax| {
+                if is_standard_axiom(ax) {
+                    ax.to_string()
+                } else {
+                    format!("{} (user declared)", ax)
+                }
+            }).collect::<Vec<String>>();
+            writeln!(f, "{} depends on axioms : [{}]", n, shown.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The set of axioms `n` depends on. We walk the dependency graph
+/// with an explicit stack (exports can have very long chains of
+/// definitions), filling in `cache` in post-order so that shared
+/// dependencies are only visited once. The environment should be
+/// acyclic once everything in it has been checked; if it isn't
+/// (IE in keep going mode), a back edge just contributes nothing.
+fn axiom_closure(env : &Env, n : &Name, cache : &mut HashMap<Name, Arc<HashSet<Name>>>) -> Arc<HashSet<Name>> {
+    let mut in_progress = HashSet::new();
+    let mut stack = vec![(n.clone(), false)];
+
+    while let Some((cursor, deps_done)) = stack.pop() {
+        if cache.contains_key(&cursor) {
+            continue
+        }
+
+        let deps = direct_deps(env, &cursor);
+        if deps_done {
+            let mut axioms = HashSet::new();
+            if is_axiom(env, &cursor) {
+                axioms.insert(cursor.clone());
+            }
+            for dep in deps.iter() {
+                if let Some(dep_axioms) = cache.get(dep) {
+                    axioms.extend(dep_axioms.iter().cloned());
+                }
+            }
+            in_progress.remove(&cursor);
+            cache.insert(cursor, Arc::new(axioms));
+        } else if in_progress.insert(cursor.clone()) {
+            stack.push((cursor, true));
+            for dep in deps {
+                if !cache.contains_key(&dep) && !in_progress.contains(&dep) {
+                    stack.push((dep, false));
+                }
+            }
+        }
+    }
+
+    cache.get(n).cloned().unwrap_or_default()
+}
+
+fn json_list(ns : &[&Name]) -> String {
+    let items = ns.iter().map(|n| json_string(&n.to_string())).collect::<Vec<String>>();
+    format!("[{}]", items.join(", "))
+}
+
+fn json_string(s : &str) -> String {
+    let mut acc = String::with_capacity(s.len() + 2);
+    acc.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => acc.push_str("\\\""),
+            '\\' => acc.push_str("\\\\"),
+            c if (c as u32) < 0x20 => acc.push_str(&format!("\\u{:04x}", c as u32)),
+            owise => acc.push(owise)
+        }
+    }
+    acc.push('"');
+    acc
+}
@@ -1,15 +1,18 @@
 use std::sync::Arc;
 use std::fs::read_to_string;
 
-use std::path::PathBuf;
+use std::path::{ Path, PathBuf };
 
-use parking_lot::RwLock;
 use structopt::StructOpt;
 
 
-use crate::name::{ Name, mk_anon };
-use crate::pretty::pretty_printer::{ PrettyPrinter, PPOptions };
-use crate::env::Env;
+use nanoda::name::Name;
+use nanoda::pretty::pretty_printer::{ PrettyPrinter, PPOptions };
+use nanoda::env::Env;
+use nanoda::parser::ExportFormat;
+use nanoda::axioms::AxiomReport;
+use nanoda::errors::KernelError;
+use nanoda::incremental::cache_path_for;
 
 /// A basic example
 #[derive(StructOpt, Debug)]
@@ -50,6 +53,55 @@ pub struct Opt {
     #[structopt(short = "p", long = "print")]
     pub print : bool,
 
+    /** don't stop at the first ill-typed declaration. Failures are
+        recorded and skipped (along with everything that depends on them),
+        and a summary of failed, skipped and passed items is printed at
+        the end. Exits with a nonzero status if anything failed. */
+    #[structopt(short = "k", long = "keep-going")]
+    pub keep_going : bool,
+
+    /** after checking, print the axioms each declaration depends on
+        (directly, or through the declarations it uses), like Lean's
+        `#print axioms`. Declarations generated for inductives and
+        quot are left out unless asked for with `--axioms-of`. */
+    #[structopt(short = "a", long = "axioms")]
+    pub axioms : bool,
+
+    /** like `--axioms`, but only for the named declaration. Can be
+        passed more than once. */
+    #[structopt(long = "axioms-of", number_of_values = 1)]
+    pub axioms_of : Vec<String>,
+
+    /** print the axiom report as JSON instead of text. */
+    #[structopt(long = "json")]
+    pub json : bool,
+
+    /** the export format to read, `lean3` or `lean4`. By default
+        it's detected from the file. */
+    #[structopt(long = "format")]
+    pub format : Option<ExportFormat>,
+
+    /** Lean 3 exports don't say which definitions are theorems. With
+        this set, any definition whose type is a proposition is treated
+        as a theorem, and never unfolded. */
+    #[structopt(long = "guess-theorems")]
+    pub guess_theorems : bool,
+
+    /** try to keep memory use under this many MiB, for exports too
+        big to check otherwise. Expressions the parser is done with are
+        dropped, and the type checkers' caches are capped, at some cost
+        in speed. Peak memory is reported at the end either way. */
+    #[structopt(long = "memory-budget")]
+    pub memory_budget : Option<usize>,
+
+    /** keep a cache of the declarations that passed next to each
+        export (`<export>.nanoda-cache`). On later runs, a declaration
+        is skipped when neither it nor anything it depends on, directly
+        or indirectly, has changed. Anything that depends on a changed
+        declaration gets checked again. */
+    #[structopt(short = "i", long = "incremental")]
+    pub incremental : bool,
+
     /** File(s) to type check. Passing only a filename will look in the
         current directory. A full path will look for the file in the
         specified location*/
@@ -59,17 +111,44 @@ pub struct Opt {
 
 impl Opt {
     pub fn try_read_files(&self) -> Result<Vec<String>, std::io::Error>{
-        self.files.iter().map(|x| try_read_cwd(x)).collect()
+        self.files.iter().map(|file| try_read_cwd(file)).collect()
     }
 
+    /// Where each file's result cache goes, or all `None` if we're not
+    /// keeping any.
+    pub fn result_caches(&self) -> Vec<Option<PathBuf>> {
+        self.files.iter().map(|file| {
+            if self.incremental {
+                Some(cache_path_for(&resolve_cwd(file)))
+            } else {
+                None
+            }
+        }).collect()
+    }
+
+    /// `None` if we're not printing an axiom report. Otherwise, the
+    /// declarations to report on, where an empty list means all of them.
+    pub fn axiom_targets(&self) -> Option<Result<Vec<Name>, String>> {
+        if !self.axioms && self.axioms_of.is_empty() {
+            return None
+        }
+        Some(self.axioms_of.iter().map(|s| {
+            s.parse::<Name>().map_err(|_| format!("couldn't read {:?} as a declaration name", s))
+        }).collect())
+    }
+
+}
+
+fn try_read_cwd(suggestion : &Path) -> Result<String, std::io::Error> {
+    read_to_string(resolve_cwd(suggestion))
 }
 
-fn try_read_cwd(suggestion : &PathBuf) -> Result<String, std::io::Error> {
+fn resolve_cwd(suggestion : &Path) -> PathBuf {
     match std::env::current_dir() {
-        Err(_) => read_to_string(suggestion),
+        Err(_) => suggestion.to_path_buf(),
         Ok(mut path) => {
-            path.push(suggestion.clone());
-            read_to_string(path)
+            path.push(suggestion);
+            path
         }
     }
 }
@@ -77,11 +156,7 @@ fn try_read_cwd(suggestion : &PathBuf) -> Result<String, std::io::Error> {
 // I'll fix these at some point; at the moment we're (very)
 // fast and loose with the parsing, and parsing fails silently.
 fn find_true_else_false(s : &str) -> bool {
-    if s.contains("true") {
-        return true
-    } else {
-        false
-    }
+    s.contains("true")
 }
 
 fn find_first_usize(s : &str) -> Option<usize> {
@@ -147,9 +222,20 @@ pub fn try_read_pp_file() -> Option<(Vec<Name>, Vec<String>)> {
     Some((names, errs))
 }
 
+pub fn print_axiom_report(env : &Arc<Env>, targets : &[Name], json : bool) -> Result<(), KernelError> {
+    let targets = if targets.is_empty() { None } else { Some(targets) };
+    let report = AxiomReport::new(env, targets)?;
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        println!("\n{}", report);
+    }
+    Ok(())
+}
+
 // Just prints to stdout until I figure out what I actually
 // want to do with this.
-pub fn pp_bundle(env : &Arc<RwLock<Env>>) {
+pub fn pp_bundle(env : &Arc<Env>) {
     match try_read_pp_file() {
         None => (),
         Some((ns, _)) => {
@@ -160,7 +246,7 @@ pub fn pp_bundle(env : &Arc<RwLock<Env>>) {
                 //let mut outputs = Vec::<String>::with_capacity(ns.len());
                 println!("\nBEGIN PRETTY PRINTER OUTPUT : \n");
                 for n in ns.iter() {
-                    let rendered = PrettyPrinter::print_declar(pp_options.clone(), n, &env);
+                    let rendered = PrettyPrinter::print_declar(pp_options.clone(), n, env);
                     println!("{}\n", rendered);
                 }
                 println!("END PRETTY PRINTER OUTPUT : \n");
@@ -169,33 +255,3 @@ pub fn pp_bundle(env : &Arc<RwLock<Env>>) {
     }
 }
 
-
-impl std::str::FromStr for Name {
-    type Err = String;
-    fn from_str(s : &str) -> Result<Name, String> {
-        let mut base = mk_anon();
-
-        if s.is_empty() {
-            return Err(format!("Cannot pretty print the empty/anonymous Lean name!"))
-        }
-
-        let fragments = s.split_terminator('.');
-
-        for f in fragments {
-            match f.parse::<u64>() {
-                Ok(n) => { base = base.extend_num(n); },
-                _ => {
-                    if f.is_empty() {
-                        return Err(format!("Name cannot be empty!"))
-                    } else if f.starts_with('#') {
-                        return Err(format!("Commented out"))
-                    } else {
-                        base = base.extend_str(f);
-                    }
-                }
-            }
-        }
-
-        Ok(base)
-    }
-}
\ No newline at end of file
@@ -1,16 +1,23 @@
 
-use std::sync::Arc;
+use std::sync::{ Arc, OnceLock };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering::SeqCst };
+use std::cmp::Ordering;
 use hashbrown::{ HashMap, HashSet };
-use parking_lot::RwLock;
 
+use crate::seq;
 use crate::name::Name;
 use crate::level::Level;
 use crate::expr::{ Expr, unique_const_names, univ_params_subset, mk_const };
 use crate::reduction::{ ReductionRule, ReductionMap };
+use crate::append_map::AppendMap;
 use crate::quot::Quot;
-use crate::inductive::Inductive;
+use crate::inductive::{ Inductive, IndInfo };
+use crate::nested::ElimNested;
+use crate::nat::NatExt;
+use crate::string::StrExt;
 use crate::tc::TypeChecker;
 use crate::pretty::components::Notation;
+use crate::errors::{ KernelResult, KernelError::*, DeclarError };
 
 use Modification::*;
 use CompiledModification::*;
@@ -19,31 +26,109 @@ use CompiledModification::*;
 /// Generic wrapper used to describe items to be added to
 /// the environment in some capacity, including axioms, 
 /// parts of inductive declarations, and parts of 
-/// quotient. See the method `tc::def_height()` for a description
-/// of what height is.
+/// quotient. See `ReducibilityHint` for a description of what
+/// height is. `pos` is the declaration's position in the
+/// order things were added to the environment, and is filled in by
+/// `Env::insert_declaration`; when we check a declaration, only
+/// declarations with a smaller `pos` are visible.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Declaration {
     pub name: Name,
     pub univ_params: Arc<Vec<Level>>,
     pub ty: Expr,
     pub height: u16,
+    pub hint: ReducibilityHint,
+    /// `None` for anything that didn't come from a `Definition`.
+    pub kind: Option<DefKind>,
+    /// Set for a definition whose kind the export didn't give. Its `kind`
+    /// is `Definition` until we know better; see `TypeChecker::def_kind`.
+    pub guessed_kind: bool,
     pub builtin: bool,
+    pub pos: usize,
+}
+
+/// How eagerly a definition gets unfolded when we're checking whether
+/// two terms are definitionally equal. The "height" of a definition is
+/// one more than the greatest height of the definitions its value
+/// refers to. If we need `E1 == E2`, we want to unfold the HIGHER one
+/// first, since it will eventually unfold into (something resembling)
+/// the lower one, whereas unfolding the lower one just gets us more and
+/// more primitive terms that get further away from the goal.
+/// Lean 4 exports a hint for every definition; for Lean 3 exports, every
+/// definition is `Regular` with the height we work out ourselves.
+/// See `TypeChecker::lazy_delta_step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReducibilityHint {
+    /// Unfolded only when the other side can't be.
+    Opaque,
+    /// Unfolded before anything else.
+    Abbrev,
+    Regular(u16),
+}
+
+impl ReducibilityHint {
+    /// `Greater` if a definition with hint `self` should be unfolded before
+    /// one with hint `other`, and `Equal` if both should be unfolded.
+    pub fn unfold_order(&self, other : &ReducibilityHint) -> Ordering {
+        use ReducibilityHint::*;
+        match (self, other) {
+            (Regular(h1), Regular(h2)) => h1.cmp(h2),
+            _ if self == other => Ordering::Equal,
+            (Opaque, _) | (_, Abbrev) => Ordering::Less,
+            (_, Opaque) | (Abbrev, _) => Ordering::Greater,
+        }
+    }
+}
+
+/// What sort of definition a `Definition` is. Only `Definition`s get a
+/// reduction rule (or have it used, if their kind was guessed; see
+/// `TypeChecker::def_kind`); theorems and opaque definitions have their
+/// values checked, but they're never unfolded. We never need to unfold a
+/// theorem to decide definitional equality, since any two proofs of the
+/// same proposition are equal by proof irrelevance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefKind {
+    Definition,
+    Theorem,
+    Opaque,
 }
 
 /// Environment containing the declarations, reduction rules, 
 /// and notations that make up the context for a set of Lean 
 /// items. Essentially, "the place where everything goes", and
-/// "the place you go to get stuff". We interact with this
-/// through an atomically reference counted RwLock so we can 
-/// interact with it from different threads, but because Arc<RwLock<T>>
-/// dereferences to <T>, and ParkingLot's RwLock implementation
-/// doesn't need to return a result, that part of it is usually 
-/// transparent.
-#[derive(Clone)]
+/// "the place you go to get stuff". Every thread shares one
+/// through an `Arc`. Nothing in an environment is ever taken out
+/// or changed once it's been added, so it's built out of `AppendMap`s
+/// and cells that are only set once, and checkers can read from it
+/// while the adder adds to it without anyone waiting on a lock. A
+/// checker only sees the declarations that came before the item it's
+/// checking (see `Declaration::bounded_tc`).
 pub struct Env {
-    pub declarations: HashMap<Name, Declaration>,
+    pub declarations: AppendMap<Name, Declaration>,
     pub reduction_map: ReductionMap,
-    pub notations : HashMap<Name, Notation>,
+    pub notations : AppendMap<Name, Notation>,
+    pub inductives : AppendMap<Name, IndInfo>,
+    /// Maps the introduction rule of each structure to the structure.
+    pub struct_ctors : AppendMap<Name, Name>,
+    /// The constants mentioned in the value of each theorem and opaque
+    /// definition, which is all the axiom report needs from them. The
+    /// type checker never unfolds them, so the values themselves aren't
+    /// kept once they've been checked.
+    pub opaque_deps : AppendMap<Name, Vec<Name>>,
+    pub quot_initialized : AtomicBool,
+    pub nat_ext : OnceLock<Arc<NatExt>>,
+    /// Whether the nat extension can stand in for each of the operations
+    /// it's been asked about so far; see `TypeChecker::nat_op_ok`.
+    pub checked_nat_ops : AppendMap<Name, bool>,
+    /// The kinds worked out so far for definitions with a guessed kind;
+    /// see `TypeChecker::def_kind`.
+    pub guessed_kinds : AppendMap<Name, DefKind>,
+    pub str_ext : OnceLock<Arc<StrExt>>,
+    /// With a memory budget, the most entries each of a `TypeChecker`'s
+    /// caches can hold; a cache that fills up has half of its entries
+    /// dropped.
+    pub cache_limit : Option<usize>,
+    next_pos : AtomicUsize,
 }
 
 /// What you see is what you get. Has a name, a vector of universe
@@ -75,7 +160,13 @@ pub struct Definition {
     pub name : Name,
     pub univ_params : Arc<Vec<Level>>,
     pub ty : Expr,
-    pub val : Expr
+    pub val : Expr,
+    /// `None` unless the export file gave one; see `ReducibilityHint`.
+    pub hint : Option<ReducibilityHint>,
+    /// `None` means we have to work it out ourselves : it's a theorem
+    /// if its type is a proposition, and a definition otherwise (see
+    /// `TypeChecker::def_kind`).
+    pub kind : Option<DefKind>,
 }
 
 impl Definition {
@@ -87,10 +178,22 @@ impl Definition {
             name,
             univ_params,
             ty,
-            val
+            val,
+            hint : None,
+            kind : Some(DefKind::Definition),
         }
     }
 
+    pub fn with_kind(mut self, kind : Option<DefKind>) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_hint(mut self, hint : ReducibilityHint) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
 }
 
 
@@ -106,27 +209,74 @@ impl Declaration {
             univ_params,
             ty,
             height : height.unwrap_or(0u16),
-            builtin : builtin.unwrap_or(false)
+            hint : ReducibilityHint::Regular(height.unwrap_or(0u16)),
+            kind : None,
+            guessed_kind : false,
+            builtin : builtin.unwrap_or(false),
+            pos : 0usize,
+        }
+    }
+
+    pub fn with_hint(mut self, hint : ReducibilityHint) -> Self {
+        self.hint = hint;
+        self
+    }
+
+    pub fn with_kind(mut self, kind : DefKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn with_guessed_kind(mut self, guessed_kind : bool) -> Self {
+        self.guessed_kind = guessed_kind;
+        self
+    }
+
+    pub fn to_axiom(&self) -> KernelResult<Modification> {
+        if let Some(bad) = self.univ_params.iter().find(|x| !x.is_param()) {
+            return Err(NonParamUnivParam(bad.clone()))
         }
+        Ok(Modification::AxiomMod(Axiom::new(self.name.clone(), self.univ_params.clone(), self.ty.clone())))
     }
 
-    pub fn to_axiom(&self) -> Modification {
-        assert!(self.univ_params.iter().all(|x| x.is_param()));
-        Modification::AxiomMod(Axiom::new(self.name.clone(), self.univ_params.clone(), self.ty.clone()))
+    /// A `TypeChecker` that can only see the declarations that were
+    /// added to `env` before this one.
+    pub fn bounded_tc(&self, env : Arc<Env>) -> TypeChecker {
+        let pos = env.get_declaration_pos(&self.name);
+        TypeChecker::new(None, env).with_visible_below(pos)
     }
 
-    pub fn indep_declaration_check(&self, env : Arc<RwLock<Env>>) {
-        let mut tc = TypeChecker::new(None, env);
-        self.declaration_check(&mut tc);
+    pub fn indep_declaration_check(&self, env : Arc<Env>) -> KernelResult<()> {
+        let mut tc = self.bounded_tc(env);
+        self.declaration_check(&mut tc)
     }
 
-    pub fn declaration_check(&self, tc : &mut TypeChecker) {
-        assert!(univ_params_subset(&self.ty, &self.univ_params
-                                                  .iter()
-                                                  .collect::<HashSet<&Level>>()));
-        assert!(!self.ty.has_vars());
-        assert!(!self.ty.has_locals());
-        tc.infer_universe_of_type(&self.ty);
+    /// The universe parameters have to be distinct, and they have to
+    /// account for every universe parameter that shows up in the type.
+    pub fn check_univ_params(&self) -> KernelResult<()> {
+        let mut declared = HashSet::with_capacity(self.univ_params.len());
+        for lvl in self.univ_params.iter() {
+            if !declared.insert(lvl) {
+                return Err(DuplicateUnivParam(self.name.clone(), lvl.clone()))
+            }
+        }
+
+        if !univ_params_subset(&self.ty, &declared) {
+            return Err(UndeclaredUnivParams(self.name.clone(), self.ty.clone()))
+        }
+        Ok(())
+    }
+
+    pub fn declaration_check(&self, tc : &mut TypeChecker) -> KernelResult<()> {
+        self.check_univ_params()?;
+        if self.ty.has_vars() {
+            return Err(LooseBVars(self.ty.clone()))
+        }
+        if self.ty.has_locals() {
+            return Err(HasLocals(self.ty.clone()))
+        }
+        tc.infer_universe_of_type(&self.ty)?;
+        Ok(())
     }
 
 
@@ -137,24 +287,26 @@ impl Declaration {
 /** This is the thing we actually add to the environment and type check.
  They have the following strucutres :
  Axiom : Has one `Declaration` to add to the environment.
- CompiledDefinition : Has one `Declaration`, one `ReductionRule`, as well
-                      as a type (a pi expr) and a value (a lambda expr).
-                      The latter two are only type checked, not added to
-                      the environment.
+ CompiledDefinition : Has one `Declaration`, a `ReductionRule` unless it's
+                      a theorem or opaque, as well as a type (a pi expr)
+                      and a value (a lambda expr). The latter two are
+                      only type checked, not added to the environment.
  Quot : Has four Declarations rules (quot, quot.mk, quot.lift, quot.ind)
         and one reduction rule.
- Inductive : Has its base type as a `Declaration`, a sequence of `Declaration`
-             items representing its introduction rules, a `Declaration`            
-             representing its elimination rule, and a sequence of 
-             `ReductionRule`s. */
+ Inductive : Has the base types of its block as `Declaration`s, a sequence
+             of `Declaration` items representing the introduction rules
+             of every type in the block, one `Declaration` per type
+             representing its elimination rule, a sequence of
+             `ReductionRule`s, and the `IndInfo`s that get
+             registered in the environment. */
 #[derive(Debug, Clone)]
 pub enum CompiledModification {
     CompiledAxiomMod     (Declaration),
-    CompiledDefinition   (Declaration, ReductionRule, Expr, Expr),
+    CompiledDefinition   (Declaration, Option<ReductionRule>, Expr, Expr),
     //                                              Type, and Value
     CompiledQuotMod      (Vec<Declaration>, ReductionRule),
-    CompiledInductive    (Declaration, Vec<Declaration>, Declaration, Vec<ReductionRule>),
-    // (base_type_axiom, intro_declarations, elim_declaration(rec), reduction_rules)
+    CompiledInductive    (Vec<Declaration>, Vec<Declaration>, Vec<Declaration>, Vec<ReductionRule>, Vec<IndInfo>),
+    // (base_type_axioms, intro_declarations, elim_declarations(recs), reduction_rules, infos)
 }
 
 
@@ -171,42 +323,92 @@ pub enum Modification {
     AxiomMod (Axiom),
     DefMod   (Definition),
     QuotMod  (Quot),
-    IndMod   (crate::inductive::ProtoInd),
+    /// A block of (mutually) inductive types; a lone inductive is a block
+    /// of one. The types in a block have the same universe parameters
+    /// and number of parameters.
+    IndMod   (Vec<crate::inductive::ProtoInd>),
 }
 
 
 impl Env {
     pub fn new(num_mods : usize) -> Self {
         Env {
-            declarations : HashMap::with_capacity(num_mods),
+            declarations : AppendMap::with_capacity(num_mods),
             reduction_map : ReductionMap::new(num_mods),
-            notations : HashMap::with_capacity(500)
+            notations : AppendMap::with_capacity(500),
+            inductives : AppendMap::with_capacity(num_mods / 10),
+            struct_ctors : AppendMap::with_capacity(num_mods / 10),
+            opaque_deps : AppendMap::with_capacity(num_mods / 2),
+            quot_initialized : AtomicBool::new(false),
+            nat_ext : OnceLock::new(),
+            checked_nat_ops : AppendMap::with_capacity(16),
+            guessed_kinds : AppendMap::with_capacity(num_mods / 2),
+            str_ext : OnceLock::new(),
+            cache_limit : None,
+            next_pos : AtomicUsize::new(0usize),
         }
     }
 
+    pub fn with_cache_limit(mut self, cache_limit : Option<usize>) -> Self {
+        self.cache_limit = cache_limit;
+        self
+    }
+
     pub fn get_declaration_height(&self, name : &Name) -> Option<u16> {
         self.declarations.get(name).map(|dec| dec.height)
     }
 
-    pub fn insert_declaration(&mut self, d : Declaration) {
-        self.declarations.insert(d.name.clone(), d);
+    pub fn get_declaration_pos(&self, name : &Name) -> Option<usize> {
+        self.declarations.get(name).map(|dec| dec.pos)
     }
 
-    pub fn insert_reduction_rule(&mut self, r : ReductionRule) {
-        self.reduction_map.add_rule(r);
+    /// Names can only be declared once; trying to add a declaration
+    /// whose name is already taken (by anything, including generated
+    /// names like `nat.rec`) is an error.
+    pub fn insert_declaration(&self, mut d : Declaration) -> KernelResult<()> {
+        d.pos = self.next_pos.fetch_add(1, SeqCst);
+        self.declarations
+            .insert(d.name.clone(), d)
+            .map_err(|(original, d)| DuplicateDeclaration(Box::new(original.clone()), Box::new(d)))
     }
 
+    /// Check that none of `ds` would collide with each other or with
+    /// something already in the environment.
+    pub fn check_fresh<'d>(&self, ds : impl Iterator<Item = &'d Declaration>) -> KernelResult<()> {
+        let mut seen = HashMap::<&Name, &Declaration>::new();
+        for d in ds {
+            if let Some(original) = self.declarations.get(&d.name).or_else(|| seen.get(&d.name).copied()) {
+                return Err(DuplicateDeclaration(Box::new(original.clone()), Box::new(d.clone())))
+            }
+            seen.insert(&d.name, d);
+        }
+        Ok(())
+    }
+
+    /// See `ReductionMap::add_rules`.
+    pub fn insert_reduction_rules(&self, rs : impl IntoIterator<Item = ReductionRule>) -> KernelResult<()> {
+        self.reduction_map.add_rules(rs)
+    }
+
+    /// The value of `n` if it's a definition that can be unfolded.
     pub fn get_value(&self, n : &Name) -> Option<&Expr> {
         self.reduction_map.get_value(n)
     }
 
-    pub fn add_notation(&mut self, n : &Name, notation: Notation) {
-        match self.notations.get(n) {
-            Some(_) => (),
-            None => { self.notations.insert(n.clone(), notation); }
+    /// The constants mentioned in the value of `n`, for any kind of
+    /// definition (see `opaque_deps`).
+    pub fn get_value_deps(&self, n : &Name) -> Option<Vec<Name>> {
+        match self.get_value(n) {
+            Some(val) => Some(unique_const_names(val).into_iter().cloned().collect()),
+            None => self.opaque_deps.get(n).cloned()
         }
     }
 
+    /// The first notation given for `n` is the one that sticks.
+    pub fn add_notation(&self, n : &Name, notation: Notation) {
+        let _ = self.notations.insert(n.clone(), notation);
+    }
+
     pub fn num_declars(&self) -> usize {
         self.declarations.len()
     }
@@ -216,7 +418,51 @@ impl Env {
 }
 
 impl Modification {
-    pub fn compile(self, env : &Arc<RwLock<Env>>) -> CompiledModification {
+    /// The name we report errors under. For inductives and quot, this
+    /// is the name of the (first) base type.
+    pub fn get_name(&self) -> Name {
+        match self {
+            AxiomMod(axiom) => axiom.name.clone(),
+            DefMod(def) => def.name.clone(),
+            QuotMod(quot) => quot.declarations[0].name.clone(),
+            IndMod(block) => block[0].name.clone(),
+        }
+    }
+
+    /// Every name this item will put in the environment once compiled.
+    pub fn declared_names(&self) -> Vec<Name> {
+        match self {
+            AxiomMod(axiom) => vec![axiom.name.clone()],
+            DefMod(def) => vec![def.name.clone()],
+            QuotMod(quot) => quot.declarations.iter().map(|d| d.name.clone()).collect(),
+            IndMod(block) => block.iter().flat_map(|ind| {
+                let intro_names = ind.intros.iter().map(|(n, _)| n.clone());
+                seq![Some(ind.name.clone()), intro_names, Some(ind.name.extend_str("rec"))]
+            }).collect()
+        }
+    }
+
+    /// The names of every constant mentioned by this item.
+    pub fn used_names(&self) -> HashSet<Name> {
+        let exprs : Vec<&Expr> = match self {
+            AxiomMod(axiom) => vec![&axiom.ty],
+            DefMod(def) => vec![&def.ty, &def.val],
+            QuotMod(quot) => quot.declarations.iter().map(|d| &d.ty).collect(),
+            IndMod(block) => block.iter().flat_map(|ind| {
+                Some(&ind.ty).into_iter().chain(ind.intros.iter().map(|(_, ty)| ty))
+            }).collect(),
+        };
+        exprs.into_iter()
+             .flat_map(|e| unique_const_names(e).into_iter().cloned())
+             .collect()
+    }
+
+    pub fn compile(self, env : &Arc<Env>) -> Result<CompiledModification, DeclarError> {
+        let name = self.get_name();
+        self.compile_core(env).map_err(|e| DeclarError::new(&name, e))
+    }
+
+    fn compile_core(self, env : &Arc<Env>) -> KernelResult<CompiledModification> {
         match self {
             AxiomMod(axiom) => {
                 let derived_declaration = Declaration::mk(axiom.name,
@@ -224,43 +470,55 @@ impl Modification {
                                                           axiom.ty,
                                                           None,
                                                           None);
-                CompiledAxiomMod(derived_declaration)
+                Ok(CompiledAxiomMod(derived_declaration))
             },
             DefMod(def) => {
+                // reduction rules need a closed right hand side.
+                if def.val.has_vars() {
+                    return Err(LooseBVars(def.val))
+                }
+
                 let height = 
                     match unique_const_names(&def.val)
                           .iter()
-                          .filter_map(|name| env.read().get_declaration_height(&name))
+                          .filter_map(|name| env.get_declaration_height(&name))
                           .max() {
                               Some(h) => h + 1,
                               None => 1
                           };
+                // A guessed theorem still gets a reduction rule, since we
+                // don't know it's a theorem until it's checked; see
+                // `TypeChecker::def_kind`.
+                let kind = def.kind.unwrap_or(DefKind::Definition);
                 let derived_declaration = 
                     Declaration::mk(def.name.clone(),
                                     def.univ_params.clone(),
                                     def.ty.clone(),
                                     Some(height),
-                                    None);
-                let derived_reduction_rule = 
-                    ReductionRule::new_rr(mk_const(def.name, def.univ_params),  
-                                          def.val.clone(),
-                                          Vec::new());
-                CompiledDefinition(derived_declaration, 
-                                   derived_reduction_rule, 
-                                   def.ty,
-                                   def.val)
+                                    None)
+                    .with_hint(def.hint.unwrap_or(ReducibilityHint::Regular(height)))
+                    .with_kind(kind)
+                    .with_guessed_kind(def.kind.is_none());
+                let derived_reduction_rule = match kind {
+                    DefKind::Definition => Some(ReductionRule::new_rr(mk_const(def.name, def.univ_params),  
+                                                                      def.val.clone(),
+                                                                      Vec::new())?),
+                    DefKind::Theorem | DefKind::Opaque => None
+                };
+                Ok(CompiledDefinition(derived_declaration, 
+                                      derived_reduction_rule, 
+                                      def.ty,
+                                      def.val))
+            },
+            QuotMod(quot) => {
+                quot.check_preconditions(env)?;
+                Ok(quot.compile_self())
             },
-            QuotMod(quot) => quot.compile_self(),
-            IndMod(ind) => {
-                let ind = Inductive::new(
-                    ind.name,
-                    ind.params,
-                    ind.ty,
-                    ind.num_params,
-                    ind.intros,
-                    env.clone()
-                );
-                ind.compile(&env.clone())
+            IndMod(block) => {
+                let (nested, block) = ElimNested::new(block, env)?;
+                let ind = Inductive::new(block, env.clone())?;
+                let compiled = ind.compile()?;
+                nested.restore(compiled)
             }
 
         }
@@ -271,60 +529,129 @@ impl Modification {
 
 
 impl CompiledModification {
+   /// The declarations `add_only` will insert, in the order it inserts
+   /// them. An inductive's base types come before its introduction rules.
+   pub fn new_declarations(&self) -> Vec<&Declaration> {
+       match self {
+           CompiledAxiomMod(declaration) => vec![declaration],
+           CompiledDefinition(declaration, ..) => vec![declaration],
+           CompiledQuotMod(declarations, _) => declarations.iter().collect(),
+           CompiledInductive(base_types, intros, elim_declarations, ..) => {
+               base_types.iter().chain(intros).chain(elim_declarations).collect()
+           }
+       }
+   }
+
+   /// The names of everything this item puts in the environment.
+   pub fn declared_names(&self) -> Vec<Name> {
+       self.new_declarations()
+           .into_iter()
+           .map(|declaration| declaration.name.clone())
+           .collect()
+   }
+
    // All this does is add the (as of yet unchecked) item to 
    // the environment. We then have to come back and check it later. 
-   pub fn add_only(&self, env : &Arc<RwLock<Env>>) {
-       let mut write_guard = env.write();
+   // We make sure all of the names are fresh before inserting anything,
+   // so a rejected item doesn't leave part of itself behind.
+   pub fn add_only(&self, env : &Arc<Env>) -> Result<(), DeclarError> {
+       self.add_core(env).map_err(|e| DeclarError::new(&self.get_name(), e))
+   }
+
+   fn add_core(&self, env : &Arc<Env>) -> KernelResult<()> {
+       env.check_fresh(self.new_declarations().into_iter())?;
         match self {
             CompiledAxiomMod(declaration) => {
-                write_guard.insert_declaration(declaration.clone());
+                env.insert_declaration(declaration.clone())?;
             },
-            CompiledDefinition(declaration, rule, ..) => {
-                write_guard.insert_declaration(declaration.clone());
-                write_guard.insert_reduction_rule(rule.clone());
+            CompiledDefinition(declaration, rule, _, val) => {
+                env.insert_declaration(declaration.clone())?;
+                match rule {
+                    Some(rule) => env.insert_reduction_rules(Some(rule.clone()))?,
+                    None => {
+                        let deps = unique_const_names(val).into_iter().cloned().collect();
+                        let _ = env.opaque_deps.insert(declaration.name.clone(), deps);
+                    }
+                }
             },
             CompiledQuotMod(declarations, rule) => {
+                // Checking and setting the flag in one step means there's
+                // no window for a second `#QUOT` to get past the check.
+                if env.quot_initialized.compare_exchange(false, true, SeqCst, SeqCst).is_err() {
+                    return Err(QuotAlreadyInitialized)
+                }
                 for d in declarations {
-                    write_guard.insert_declaration(d.clone());
+                    env.insert_declaration(d.clone())?;
                 }
-                write_guard.insert_reduction_rule(rule.clone());
+                env.insert_reduction_rules(Some(rule.clone()))?;
             },
-            CompiledInductive(_base_type, intros, elim_declaration, reductions) => {
-                for d in intros {
-                    write_guard.insert_declaration(d.clone());
+            CompiledInductive(base_types, intros, elim_declarations, reductions, infos) => {
+                for d in base_types.iter().chain(intros).chain(elim_declarations) {
+                    env.insert_declaration(d.clone())?;
                 }
 
-                write_guard.insert_declaration(elim_declaration.clone());
-
-                for r in reductions {
-                    write_guard.insert_reduction_rule(r.clone())
+                env.insert_reduction_rules(reductions.iter().cloned())?;
+
+                for info in infos {
+                    let _ = env.inductives.insert(info.name.clone(), info.clone());
+                    if let Some(structure) = info.structure.as_ref() {
+                        let _ = env.struct_ctors.insert(structure.ctor.clone(), info.name.clone());
+                    }
+
+                    // Declaring `nat` (or `string`'s structure) turns on
+                    // the corresponding literal extension.
+                    if let Some(ext) = NatExt::new(info) {
+                        let _ = env.nat_ext.set(Arc::new(ext));
+                    }
+                    if let Some(ext) = StrExt::new(info) {
+                        let _ = env.str_ext.set(Arc::new(ext));
+                    }
                 }
-
             }
         }
+        Ok(())
+    }
+
+    /// The name we report errors under; see `Modification::get_name`.
+    pub fn get_name(&self) -> Name {
+        match self {
+            CompiledAxiomMod(declaration) => declaration.name.clone(),
+            CompiledDefinition(declaration, ..) => declaration.name.clone(),
+            CompiledQuotMod(declarations, _) => declarations[0].name.clone(),
+            CompiledInductive(base_types, ..) => base_types[0].name.clone(),
+        }
     }
 
     // Checks a given item.
-    pub fn check_only(&self, env : &Arc<RwLock<Env>>) {
+    pub fn check_only(&self, env : &Arc<Env>) -> Result<(), DeclarError> {
+        self.check_core(env).map_err(|e| DeclarError::new(&self.get_name(), e))
+    }
+
+    fn check_core(&self, env : &Arc<Env>) -> KernelResult<()> {
         match self {
             CompiledAxiomMod(declaration) => {
-                let mut tc = TypeChecker::new(None, env.clone());
-                declaration.declaration_check(&mut tc);
+                let mut tc = declaration.bounded_tc(env.clone());
+                declaration.declaration_check(&mut tc)
             },
             CompiledDefinition(declaration, _, ty, val) => {
-                let mut tc = TypeChecker::new(None, env.clone());
-                declaration.declaration_check(&mut tc);
-                tc.check_type(val, ty);
+                let mut tc = declaration.bounded_tc(env.clone());
+                declaration.declaration_check(&mut tc)?;
+                if tc.def_kind(declaration) == Some(DefKind::Theorem) && !tc.is_proposition(ty)? {
+                    return Err(TheoremNotProp(ty.clone()))
+                }
+                tc.check_type(val, ty)
             },
             CompiledQuotMod(declarations, _) => {
                 for d in declarations {
-                    d.indep_declaration_check(env.clone());
+                    d.indep_declaration_check(env.clone())?;
                 }
+                Ok(())
             },
-            CompiledInductive(base_type, intros, ..) => {
-                for d in Some(base_type).into_iter().chain(intros.into_iter()) {
-                    d.indep_declaration_check(env.clone());
+            CompiledInductive(base_types, intros, ..) => {
+                for d in base_types.iter().chain(intros) {
+                    d.indep_declaration_check(env.clone())?;
                 }
+                Ok(())
             }
         }
     }
@@ -1,115 +1,235 @@
+//! Errors come in two flavors. The first is `KernelError`, which
+//! describes a reason the kernel rejected some item in an export file;
+//! ill-typed terms, references to missing constants, malformed inductive
+//! declarations and so on. These are returned as the `Err` side of a
+//! `KernelResult` all the way up to the checking pipeline, so one bad
+//! declaration doesn't take the whole process down with it.
+//! When a `KernelError` reaches the point where we know which
+//! declaration we were working on, it gets wrapped in a `DeclarError`.
+//!
+//! The second flavor is the group of `err_*` functions further down, which
+//! get thrown in the event that a partial function gets an argument it's
+//! not defined on, IE `Expr::get_serial` on something other than a `Local`.
+//! Ideally we would get rid of those, but Rust's type system doesn't offer
+//! discrimination of enum variants at the type level, and my experience trying
+//! to break each enum variant out into its own struct suggested that the amount
+//! of extra code you would need just to do explicit casting between types would
+//! be a huge hit to readability and directness. Reaching one of these means
+//! there's a bug in nanoda rather than in the export file, so they panic.
+
 use std::fmt::Debug;
 
-/// Most of these are errors that get thrown in the event
-/// that a pattern match expects something that it doesn't end up getting.
-/// For instance, partial conversions or failed definitional equality/
-/// inference checks. Ideally we would get rid of the ones related to partial functions,
-/// but Rust's type system doesn't offer discrimination of enum variants
-/// at the type level, and my experience trying to break each enum variant
-/// out into its own struct suggested that the amount of extra code you would need
-/// just to do explicit casting between types would be a huge hit to readability
-/// and directness. 
+use crate::name::Name;
+use crate::level::Level;
+use crate::expr::Expr;
+use crate::env::Declaration;
+
+use KernelError::*;
+
+pub type KernelResult<T> = std::result::Result<T, KernelError>;
+
+/// Everything the kernel can reject an item for. Variants carry the
+/// offending expressions so that callers can report (or pretty print)
+/// them.
+#[derive(Debug, Clone)]
+pub enum KernelError {
+    /// `infer` was handed a term with a loose bound variable.
+    InferVar(Expr),
+    /// A constant was used that isn't in the environment.
+    UnknownConst(Name),
+    /// A declaration refers to itself.
+    SelfReference(Name),
+    /// A declaration refers to a constant that comes after
+    /// it in the export file.
+    ForwardReference(Name),
+    /// Tried to add a declaration (second) whose name was already
+    /// taken by another declaration (first).
+    DuplicateDeclaration(Box<Declaration>, Box<Declaration>),
+    /// A constant was applied to the wrong number of universe levels.
+    /// (name, expected, got)
+    NumLevelsMismatch(Name, usize, usize),
+    /// Expected the type of the first expression to be a `Sort`, but
+    /// it reduced to the second expression.
+    NotASort(Expr, Expr),
+    /// Expected the first expression to have a Pi type, but its type
+    /// reduced to the second expression.
+    NotAPi(Expr, Expr),
+    /// `check_type` found that the inferred type of `term` was not
+    /// definitionally equal to the type it was supposed to have.
+    TypeMismatch { term : Expr, expected : Expr, inferred : Expr },
+    /// Two expressions that were required to be definitionally
+    /// equal were not.
+    DefEqFailure(Expr, Expr),
+    /// A declaration's type mentions universe parameters that aren't
+    /// in its list of universe parameters. (declaration name, type)
+    UndeclaredUnivParams(Name, Expr),
+    /// A declaration's list of universe parameters has the same
+    /// parameter in it more than once.
+    DuplicateUnivParam(Name, Level),
+    /// A declaration's universe parameter list has something other
+    /// than a `Level::Param` in it.
+    NonParamUnivParam(Level),
+    /// A top level type or value has loose bound variables.
+    LooseBVars(Expr),
+    /// A top level type or value has free `Local` terms.
+    HasLocals(Expr),
+    /// A theorem's type isn't a proposition.
+    TheoremNotProp(Expr),
+    /// The type of an inductive (first) doesn't end in a `Sort` once
+    /// its Pi binders are stripped; it ends in the second expression.
+    IndCodomainNotSort(Expr, Expr),
+    /// An inductive claims more parameters than its type has Pi binders.
+    /// (inductive name, num_params, number of binders)
+    IndNumParams(Name, usize, usize),
+    /// An introduction rule (or one of its recursive arguments) is
+    /// applied to fewer arguments than the inductive's number of parameters.
+    /// (intro name, num_params, num args)
+    IntroNumParams(Name, usize, usize),
+    /// An argument of an introduction rule lives in a universe that is
+    /// larger than the inductive type's.
+    IntroUniverseTooBig { intro : Name, arg : Expr, arg_level : Level, ind_level : Level },
+    /// A type in a mutual block (`other`) doesn't agree with the first
+    /// type in the block (`first`) about `what` (its universe parameters,
+    /// parameters, or the universe it lives in).
+    MutualMismatch { first : Name, other : Name, what : &'static str },
+    /// A nested occurrence of an inductive being declared, where the
+    /// parameters of the outer inductive depend on the introduction
+    /// rule's own arguments rather than just the block's parameters.
+    InvalidNestedOccurrence(Expr),
+    /// A second `#QUOT` showed up.
+    QuotAlreadyInitialized,
+    /// `eq` wasn't declared the way the quotient needs it to be.
+    QuotBadEq(String),
+    /// A Lean 4 `#IND` line disagrees with what we worked out about
+    /// the inductive `ind`'s `what` (its number of indices, or whether
+    /// its block is recursive).
+    ExportMismatch { ind : Name, what : &'static str },
+    /// The inductive being declared appears in the domain of a Pi
+    /// inside the type of an introduction rule's argument (arguments
+    /// are counted from 1, after the parameters).
+    NonPositiveOccurrence { intro : Name, arg_idx : usize, arg_ty : Expr },
+    /// The inductive being declared appears in an argument's type, but
+    /// not as the inductive applied to its parameters and some indices.
+    InvalidIndOccurrence { intro : Name, arg_idx : usize, arg_ty : Expr },
+    /// The result type of an introduction rule has one of the inductives
+    /// being declared in its indices. (intro name, result type)
+    IndOccurrenceInIndex(Name, Expr),
+    /// A natural number literal was used before `nat` was declared.
+    NatLitWithoutNat(Expr),
+    /// A string literal was used before `string` was declared.
+    StrLitWithoutString(Expr),
+    /// A projection's argument doesn't have the type of the structure
+    /// it projects from, or the structure doesn't have that many fields.
+    InvalidProj { proj : Expr, ty : Expr },
+    /// A projection takes something other than a proof out of a proof.
+    PropProj(Expr),
+    /// Tried to bind something other than a `Local` with a Pi or lambda.
+    NotALocal(Expr),
+    /// The reduction rules for a constant were added in more than one batch.
+    RulesAlreadyAdded(Name),
+}
+
+impl std::fmt::Display for KernelError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InferVar(e) => write!(f, "infer function got a variable term, but that should never happen. Got {:?}", e),
+            UnknownConst(n) => write!(f, "expected a declaration named {} to be in the environment, but it was missing", n),
+            SelfReference(n) => write!(f, "declaration refers to itself (via constant {})", n),
+            ForwardReference(n) => write!(f, "declaration refers to {}, which isn't declared until later in the export file", n),
+            DuplicateDeclaration(original, new) => write!(f, "tried to declare {} with type {:?}, but the name {} was already declared (at position {}) with type {:?}", new.name, new.ty, original.name, original.pos, original.ty),
+            NumLevelsMismatch(n, expected, got) => write!(f, "constant {} expects {} universe levels, but was given {}", n, expected, got),
+            NotASort(e, ty) => write!(f, "expected a type, but the type of {:?} is {:?}, which is not a Sort", e, ty),
+            NotAPi(e, ty) => write!(f, "expected a function, but the type of {:?} is {:?}, which is not a Pi", e, ty),
+            TypeMismatch { term, expected, inferred } => write!(f, "type mismatch; the term {:?}\n\nwas expected to have type {:?}\n\nbut was inferred to have type {:?}", term, expected, inferred),
+            DefEqFailure(e1, e2) => write!(f, "the following expressions were required to be definitionally equal, but were not. E1 : {:?}\n\nE2 : {:?}", e1, e2),
+            UndeclaredUnivParams(n, e) => write!(f, "the type of {}, {:?}, uses universe parameters that were not declared", n, e),
+            DuplicateUnivParam(n, l) => write!(f, "universe parameter {:?} appears more than once in the universe parameters of {}", l, n),
+            NonParamUnivParam(l) => write!(f, "universe parameter lists may only contain parameters, but got {:?}", l),
+            LooseBVars(e) => write!(f, "expected a closed term, but {:?} has loose bound variables", e),
+            HasLocals(e) => write!(f, "expected a closed term, but {:?} has free local constants", e),
+            TheoremNotProp(ty) => write!(f, "the type of a theorem must be a proposition, but {:?} is not", ty),
+            IndCodomainNotSort(ty, cod) => write!(f, "the type of an inductive must end in a Sort, but {:?} ends in {:?}", ty, cod),
+            IndNumParams(n, num_params, got) => write!(f, "inductive {} is declared with {} parameters, but its type only has {} binders", n, num_params, got),
+            IntroNumParams(n, num_params, got) => write!(f, "introduction rule {} should apply the inductive to its {} parameters, but only {} arguments were given", n, num_params, got),
+            IntroUniverseTooBig { intro, arg, arg_level, ind_level } => write!(f, "argument {:?} of introduction rule {} lives in universe {:?}, which is too big for the inductive's universe {:?}", arg, intro, arg_level, ind_level),
+            MutualMismatch { first, other, what } => write!(f, "inductives {} and {} are declared in the same mutual block, but don't have the same {}", first, other, what),
+            InvalidNestedOccurrence(e) => write!(f, "invalid nested occurrence {:?}; the parameters of a nested inductive can't depend on the introduction rule's arguments", e),
+            ExportMismatch { ind, what } => write!(f, "the export file's {} for inductive {} doesn't match the declaration", what, ind),
+            QuotAlreadyInitialized => write!(f, "the quotient type can only be initialized once"),
+            QuotBadEq(msg) => write!(f, "cannot initialize the quotient type; {}", msg),
+            NonPositiveOccurrence { intro, arg_idx, arg_ty } => write!(f, "arg #{} of introduction rule {} has a non positive occurrence of the datatype being declared; its type is {:?}", arg_idx, intro, arg_ty),
+            InvalidIndOccurrence { intro, arg_idx, arg_ty } => write!(f, "arg #{} of introduction rule {} has a non valid occurrence of the datatype being declared; its type is {:?}", arg_idx, intro, arg_ty),
+            IndOccurrenceInIndex(n, ty) => write!(f, "the result type {:?} of introduction rule {} has the datatype being declared in its indices", ty, n),
+            NatLitWithoutNat(e) => write!(f, "found the natural number literal {:?}, but the natural numbers haven't been declared", e),
+            StrLitWithoutString(e) => write!(f, "found the string literal {:?}, but strings haven't been declared", e),
+            InvalidProj { proj, ty } => write!(f, "the projection {:?} is applied to something of type {:?}, which is not the structure it projects from, or doesn't have that many fields", proj, ty),
+            PropProj(e) => write!(f, "the projection {:?} takes data out of a proof, but only proofs can be projected out of proofs", e),
+            NotALocal(e) => write!(f, "only local constants can be bound by a Pi or lambda, but got {:?}", e),
+            RulesAlreadyAdded(n) => write!(f, "the reduction rules for {} were added in more than one batch", n),
+        }
+    }
+}
+
+impl std::error::Error for KernelError {}
+
+/// A `KernelError` together with the name of the declaration that
+/// was being added or checked when it came up.
+#[derive(Debug, Clone)]
+pub struct DeclarError {
+    pub name : Name,
+    pub err : KernelError,
+}
+
+impl DeclarError {
+    pub fn new(name : &Name, err : KernelError) -> Self {
+        DeclarError {
+            name : name.clone(),
+            err
+        }
+    }
+}
+
+impl std::fmt::Display for DeclarError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "declaration {} was rejected; {}", self.name, self.err)
+    }
+}
+
+impl std::error::Error for DeclarError {}
 
 
 pub fn err_get_serial<T : Debug>(loc : u32, owise : &T) -> ! {
-    eprintln!("expr line {}; Expr::get_serial is a partial function defined only on expresisons made with the `Local` constructor, but it was called with {:?}\n", loc, owise);
-    std::process::exit(-1);
+    panic!("expr line {}; Expr::get_serial is a partial function defined only on expresisons made with the `Local` constructor, but it was called with {:?}\n", loc, owise);
 }
 
 pub fn err_lc_binding<T : Debug>(loc : u32, owise : &T) -> ! {
-    eprintln!("expr line {}; Expr::get_serial is a partial function defined only on expresisons made with the `Local` constructor, but it was called with {:?}\n", loc, owise);
-    std::process::exit(-1);
+    panic!("expr line {}; Expr::get_serial is a partial function defined only on expresisons made with the `Local` constructor, but it was called with {:?}\n", loc, owise);
 }
 
 pub fn err_binding_lc<T : Debug>(loc : u32, owise : &T) -> ! {
-    eprintln!("`expr line {}; From` conversion for Level -> Binding is a partial function defined only on arguments of the form Expr::Local, but it was called with the following expression {:?}\n\n", loc, owise);
-    std::process::exit(-1);
+    panic!("`expr line {}; From` conversion for Level -> Binding is a partial function defined only on arguments of the form Expr::Local, but it was called with the following expression {:?}\n\n", loc, owise);
 }
-                
+
 pub fn err_swap_local_binding_name<T : Debug>(loc : u32, owise : &T) -> !{
-    eprintln!("expr line {}; Expr::swap_local_binding_name is a partial function defined only on expresisons made with the `Local` constructor, but it was called with {:?}\n", loc, owise);
-    std::process::exit(-1);
+    panic!("expr line {}; Expr::swap_local_binding_name is a partial function defined only on expresisons made with the `Local` constructor, but it was called with {:?}\n", loc, owise);
 }
 
 pub fn err_offset_cache(loc : u32, idx : usize, len : usize) -> ! {
-    eprintln!("expr line {}; OffsetCache failed to retrieve HashMap at index {}; vec length was {}\n", loc, idx, len);
-    std::process::exit(-1);
-}
-
-pub fn err_normalize_pis<T : Debug>(loc : u32, got : &T) -> ! {
-    eprintln!("expr line {}; Expected a `Sort` term in inductive mod, got {:?}\n", loc, got);
-    std::process::exit(-1);
-}
-
-pub fn err_infer_var<T : Debug>(loc : u32, got : &T) -> ! {
-    eprintln!("tc line {}; infer function got a variable term, but that should never happen. received this term : {:?}\n", loc, got);
-    std::process::exit(-1);
-}
-
-pub fn err_infer_const<T : Debug>(loc : u32, name : &T) -> ! {
-    eprintln!("tc line {}; infer_const function expected a declaration to be in the environment, but it was missing. Looked for {:?}\n", loc, name);
-    std::process::exit(-1);
-}
-
-pub fn err_infer_universe<T : Debug>(loc : u32, got : &T) -> ! {
-    eprintln!("tc line {}; infer_universe function expected to be passed a term of type Sort, but got something else. Got term {:?}\n", loc, got);
-    std::process::exit(-1);
-}
-
-pub fn err_infer_apps<T : Debug>(loc : u32, got : &T) -> ! {
-    eprintln!("tc line {}; infer_apps function expected to be match a Pi term, but got something else. Got term {:?}\n", loc, got);
-    std::process::exit(-1);
-}
-
-pub fn err_req_def_eq<T : Debug>(loc : u32, got1 : &T, got2 : &T) -> ! {
-    eprintln!("tc line {}; function require_def_eq received the following two functions expecting them to be found definitionally equal, but they were found not to be. Got E1 : {:?}\n\nE2 : {:?}\n\n", loc, got1, got2);
-    std::process::exit(-1);
-}
-
-pub fn err_check_type<T : Debug>(loc : u32, got1 : &T, got2 : &T) -> ! {
-    eprintln!("tc line {}; the function check_type expected the following two expression to be definitionally equal, but they were not. Got E1 : {:?}\n\nE2 : {:?}\n\n", loc, got1, got2);
-    std::process::exit(-1);
+    panic!("expr line {}; OffsetCache failed to retrieve HashMap at index {}; vec length was {}\n", loc, idx, len);
 }
 
 pub fn err_rr_const<T : Debug>(loc : u32, got : &T) -> ! {
-    eprintln!("rr line {}; creation of new reduction rule expected to get a Const expression, but got {:?}\n", loc, got);
-    std::process::exit(-1);
-}
-
-pub fn err_add_rule<T : Debug>(loc : u32, name : &T) -> ! {
-    eprintln!("env line {}; in reduction module, expected to find a major premise corresponding to name {:?}, but got nothing.", loc, name);
-    std::process::exit(-1)
+    panic!("rr line {}; creation of new reduction rule expected to get a Const expression, but got {:?}\n", loc, got);
 }
 
 pub fn err_param_name<T : Debug>(loc : u32, got : &T) -> ! {
-    eprintln!("level line {}; Level::param_name() is a partial function defined only for Param variants. Got {:?}\n", loc, got);
-    std::process::exit(-1)
-}
-
-
-pub fn join_panic(loc : u32) -> ! {
-    eprintln!("main line {}; a worker thread in the `check_parallel` function panicked! More information should be available in the console.", loc);
-    std::process::exit(-1)
-}
-
-
-pub fn scope_err(loc : u32) -> ! {
-    eprintln!("main line {}; a worker thread in the `check_parallel` function panicked! More information should be available in the console.", loc);
-    std::process::exit(-1)
-}
-
-
-pub fn export_file_parse_err<T : std::fmt::Display>(loc : u32, err : T) -> ! {
-    eprintln!("cli line {}; failed to parse at least one of the specified export files. Please check that the file exists at the specified path. Error details : {}\n", loc, err);
-    std::process::exit(-1)
+    panic!("level line {}; Level::param_name() is a partial function defined only for Param variants. Got {:?}\n", loc, got);
 }
 
 pub fn partial_is_pi<T : Debug>(loc : u32, item : T) -> ! {
-    eprintln!("expr line {}; bad call to partial function `binder_is_pi`; expected Pi or Labmda, got {:?}\n", loc, item);
-    std::process::exit(-1);
+    panic!("expr line {}; bad call to partial function `binder_is_pi`; expected Pi or Labmda, got {:?}\n", loc, item);
 }
 
 pub fn err_parse_kind<T : Debug>(t : &T) -> String {
    format!("unrecognized match on item kind while parsing. Expected 'N' 'U', or 'E', got {:?}\n", t)
 }
-
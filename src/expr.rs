@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::sync::{ Arc, OnceLock };
 use std::cmp::max;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering::Relaxed;
@@ -6,11 +6,14 @@ use std::hash::{ Hash, Hasher };
 
 use fxhash::hash64;
 use hashbrown::{ HashMap, HashSet };
+use num_bigint::BigUint;
 
 use crate::name::{ Name, mk_anon };
 use crate::level::{ Level, unique_univ_params, mk_zero };
 use crate::utils::{ safe_minus_one, max3 };
+use crate::hashcons::{ HashCons, ConsTable, intern };
 use crate::errors;
+use crate::errors::{ KernelResult, KernelError::NotALocal };
 
 use InnerExpr::*;
 
@@ -24,6 +27,9 @@ use InnerExpr::*;
 pub const LAMBDA_HASH   : u64 = 402653189;
 pub const PI_HASH       : u64 = 1610612741;
 pub const PROP_HASH     : u64 = 786433;
+pub const NAT_LIT_HASH  : u64 = 3145739;
+pub const STR_LIT_HASH  : u64 = 12582917;
+pub const PROJ_HASH     : u64 = 50331653;
 pub const PROP_CACHE    : ExprCache = ExprCache { digest : PROP_HASH, 
                                                   var_bound : 0, 
                                                   has_locals : false };
@@ -96,9 +102,25 @@ impl Binding {
 }
 
 /// Arc wrapper around `InnerExpr`. See  InnerExpr's docs.
-#[derive(Clone, PartialEq, Eq, Hash)]
+/// Expressions are hash-consed (see `hashcons::HashCons`), so two
+/// expressions are equal exactly when they point to the same `InnerExpr`.
+#[derive(Clone)]
 pub struct Expr(Arc<InnerExpr>);
 
+impl PartialEq for Expr {
+    fn eq(&self, other : &Expr) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Expr {}
+
+impl Hash for Expr {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        self.as_ref().hash(state)
+    }
+}
+
 impl std::fmt::Debug for Expr {
     fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:?}", self.as_ref())
@@ -172,6 +194,32 @@ pub fn mk_let(domain : Binding, val : Expr, body : Expr) -> Expr {
     Let(ExprCache::mk(digest, var_bound, has_locals), domain, val, body).into() // InnerLevel -> Level
 }
 
+/// A natural number literal. These never show up in Lean 3 exports, but
+/// the type checker makes them out of `nat.zero`/`nat.succ` chains and
+/// arithmetic on them; see the `nat` module.
+pub fn mk_nat_lit(n : BigUint) -> Expr {
+    let digest = hash64(&(NAT_LIT_HASH, &n));
+    NatLit(ExprCache::mk(digest, 0, false), n).into()
+}
+
+/// Field `idx` (counting from 0, after the parameters) of `structure`,
+/// whose type should be the structure `struct_name`. This is what
+/// `struct_name.rec` applied to a minor premise that picks out one field
+/// would reduce to, without having to build the minor premise.
+pub fn mk_proj(struct_name : Name, idx : usize, structure : Expr) -> Expr {
+    let digest = hash64(&(PROJ_HASH, &struct_name, idx, structure.get_digest()));
+    let var_bound = structure.var_bound();
+    let has_locals = structure.has_locals();
+    Proj(ExprCache::mk(digest, var_bound, has_locals), struct_name, idx, structure).into()
+}
+
+/// A string literal; see the `string` module.
+pub fn mk_str_lit(s : impl Into<String>) -> Expr {
+    let s = s.into();
+    let digest = hash64(&(STR_LIT_HASH, &s));
+    StrLit(ExprCache::mk(digest, 0, false), s).into()
+}
+
 /// A `Local` represents a free variable. All `Local` terms have a unique
 /// identifier (here we just use a monotonically increasing counter, with each
 /// local's identifier being called a `serial`), and carries its type around.
@@ -192,10 +240,7 @@ pub fn mk_local(name : impl Into<Name>, ty : Expr, style : BinderStyle) -> Expr
 impl Expr {
 
     pub fn is_local(&self) -> bool {
-        match self.as_ref() {
-            Local(..) => true,
-            _ => false
-        }
+        matches!(self.as_ref(), Local(..))
     }
 
     pub fn get_digest(&self) -> u64 {
@@ -321,6 +366,10 @@ impl Expr {
                         let new_body = body.abstract_core(offset + 1, locals, cache);
                         mk_let(dom.swap_ty(new_domty), new_val, new_body)
                     },
+                    Proj(_, struct_name, idx, structure) => {
+                        let new_structure = structure.abstract_core(offset, locals, cache);
+                        mk_proj(struct_name.clone(), *idx, new_structure)
+                    },
                     owise => unreachable!("Illegal match item in Expr::abstract_core {:?}\n", owise)
                 };
 
@@ -364,7 +413,7 @@ impl Expr {
               .cloned()
               .unwrap_or_else(|| self.clone())
         } else {
-            cache.get(&self, offset).cloned().unwrap_or_else(|| {
+            cache.get(self, offset).cloned().unwrap_or_else(|| {
                 let calcd = match self.as_ref() {
                     App(_, lhs, rhs) => {
                         let new_lhs = lhs.instantiate_core(offset, es.clone(), cache);
@@ -387,6 +436,10 @@ impl Expr {
                         let new_body = body.instantiate_core(offset + 1, es, cache);
                         mk_let(dom.swap_ty(new_dom_ty), new_val, new_body)
                     },
+                    Proj(_, struct_name, idx, structure) => {
+                        let new_structure = structure.instantiate_core(offset, es, cache);
+                        mk_proj(struct_name.clone(), *idx, new_structure)
+                    },
                     owise => unreachable!("Illegal match result in Expr::instantiate_core {:?}\n", owise)
                 };
                 cache.insert(self.clone(), calcd.clone(), offset);
@@ -430,7 +483,11 @@ impl Expr {
                     let new_of_ty = of.ty.instantiate_ps(substs);
                     of.swap_ty(new_of_ty).as_local()
                 },
-                Var(..) => self.clone(),
+                Proj(_, struct_name, idx, structure) => {
+                    let new_structure = structure.instantiate_ps(substs);
+                    mk_proj(struct_name.clone(), *idx, new_structure)
+                },
+                Var(..) | NatLit(..) | StrLit(..) => self.clone(),
                 Sort(_, lvl) => {
                     let instd_level = lvl.instantiate_lvl(substs);
                     mk_sort(instd_level)
@@ -518,10 +575,13 @@ impl Expr {
     /// let E = E.abstract(L)
     /// return (Π (L) (E'))
     ///```
-    pub fn apply_pi(&self, domain : &Expr) -> Expr {
-        assert!(domain.is_local());
+    /// Fails with `NotALocal` if `L` isn't a Local.
+    pub fn apply_pi(&self, domain : &Expr) -> KernelResult<Expr> {
+        if !domain.is_local() {
+            return Err(NotALocal(domain.clone()))
+        }
         let abstracted = self.clone().abstract_(Some(domain).into_iter());
-        mk_pi(Binding::from(domain), abstracted)
+        Ok(mk_pi(Binding::from(domain), abstracted))
     }
 
 
@@ -544,14 +604,14 @@ impl Expr {
     ///                L_n   E
     ///```
     /// same as fold_pis, but generic over iterators.
-    pub fn fold_pis<'q, Q>(&self, doms : Q) -> Expr 
+    pub fn fold_pis<'q, Q>(&self, doms : Q) -> KernelResult<Expr> 
     where Q : Iterator<Item = &'q Expr> + DoubleEndedIterator {
         let mut acc = self.clone();
         for next in doms.rev() {
-            acc = acc.apply_pi(next)
+            acc = acc.apply_pi(next)?
         }
     
-        acc
+        Ok(acc)
     }
 
     /// This unfolds consecutive applications of `Pi` into the "core" term,
@@ -570,7 +630,7 @@ impl Expr {
             let local = dom.clone().as_local();
             let instd = body.instantiate(Some(&local).into_iter());
             binder_acc.push(local);
-            std::mem::replace(self, instd);
+            *self = instd;
         }
     }
 
@@ -579,10 +639,13 @@ impl Expr {
     ///  let E' = E.abstract(L)
     ///  return (λ L, E')
     ///```
-    pub fn apply_lambda(&self, domain : &Expr) -> Expr {
-        assert!(domain.is_local());
+    /// Fails with `NotALocal` if `L` isn't a Local.
+    pub fn apply_lambda(&self, domain : &Expr) -> KernelResult<Expr> {
+        if !domain.is_local() {
+            return Err(NotALocal(domain.clone()))
+        }
         let abstracted = self.clone().abstract_(Some(domain).into_iter());
-        mk_lambda(Binding::from(domain), abstracted)
+        Ok(mk_lambda(Binding::from(domain), abstracted))
     }
 
 
@@ -601,13 +664,13 @@ impl Expr {
     ///                  L_n   E
     ///```
     /// same as fold_lambdas, but generic over iterators.
-    pub fn fold_lambdas<'q, Q>(&self, doms : Q) -> Expr 
+    pub fn fold_lambdas<'q, Q>(&self, doms : Q) -> KernelResult<Expr> 
     where Q : Iterator<Item = &'q Expr> + DoubleEndedIterator {
         let mut acc = self.clone();
         for next in doms.rev() {
-            acc = acc.apply_lambda(next)
+            acc = acc.apply_lambda(next)?
         }
-        acc
+        Ok(acc)
     }
 
 }
@@ -628,7 +691,10 @@ pub enum InnerExpr {
     App    (ExprCache, Expr,  Expr),
     Lambda (ExprCache, Binding, Expr),
     Pi     (ExprCache, Binding, Expr),
-    Let    (ExprCache, Binding, Expr, Expr)
+    Let    (ExprCache, Binding, Expr, Expr),
+    NatLit (ExprCache, BigUint),
+    StrLit (ExprCache, String),
+    Proj   (ExprCache, Name, usize, Expr),
 }
 
 impl InnerExpr {
@@ -645,7 +711,10 @@ impl InnerExpr {
             | App    (info, ..) 
             | Lambda (info, ..) 
             | Pi     (info, ..) 
-            | Let    (info, ..)  => *info
+            | Let    (info, ..)
+            | NatLit (info, ..)
+            | StrLit (info, ..)
+            | Proj   (info, ..)  => *info
         }
     }
 }
@@ -699,7 +768,18 @@ impl std::convert::AsRef<InnerExpr> for Expr {
 
 impl From<InnerExpr> for Expr {
     fn from(x : InnerExpr) -> Expr {
-        Expr(Arc::new(x))
+        Expr(intern(x))
+    }
+}
+
+impl HashCons for InnerExpr {
+    fn cons_hash(&self) -> u64 {
+        self.get_digest()
+    }
+
+    fn table() -> &'static ConsTable<InnerExpr> {
+        static TABLE : OnceLock<ConsTable<InnerExpr>> = OnceLock::new();
+        TABLE.get_or_init(ConsTable::new)
     }
 }
 
@@ -720,9 +800,14 @@ impl From<&Expr> for Binding {
 /// but this way of doing itturned out to be (much to my surprise, 
 /// shout-outs to @GEbner) faster than (Expr x Int) -> Expr, probably 
 /// due in large part because of how tuples work in Rust. 
-
 pub struct OffsetCache(Vec<HashMap<Expr, Expr>>);
 
+impl Default for OffsetCache {
+    fn default() -> Self {
+        OffsetCache::new()
+    }
+}
+
 impl OffsetCache {
     pub fn new() -> Self {
         OffsetCache(Vec::with_capacity(200))
@@ -736,9 +821,7 @@ impl OffsetCache {
     }
 
     pub fn insert(&mut self, e1 : Expr, e2 : Expr, offset : usize) {
-        let map_vec = match self {
-            OffsetCache(x) => x
-        };
+        let OffsetCache(map_vec) = self;
 
         while map_vec.len() <= offset {
             map_vec.push(HashMap::with_capacity(50));
@@ -753,13 +836,13 @@ impl OffsetCache {
 }
 
 /// For some expression `E`, traverse `E`, putting the `Name` field 
-/// of any constant into a set `S`. This is only used once, when compiling 
-/// a `Definition`; we get all of the names out of an expression's constant terms,
-/// and use them to look up the height of those definitions in the environment. 
-/// There's more information about definition height under tc::def_height().
+/// of any constant into a set `S`. When compiling a `Definition`, we get all
+/// of the names out of an expression's constant terms, and use them to look
+/// up the height of those definitions in the environment. 
+/// There's more information about definition height under env::ReducibilityHint.
 /// This isn't defined as an associated method because it wanted more 
 /// detailed lifetime information than could be provided by `self`.   
-pub fn unique_const_names<'l, 's>(n : &'l Expr) -> HashSet<&'l Name> {
+pub fn unique_const_names<'l>(n : &'l Expr) -> HashSet<&'l Name> {
     let mut acc = HashSet::with_capacity(80);
     let mut cache = HashSet::with_capacity(200);
     unique_const_names_core(n, &mut acc, &mut cache);
@@ -769,9 +852,7 @@ pub fn unique_const_names<'l, 's>(n : &'l Expr) -> HashSet<&'l Name> {
 pub fn unique_const_names_core<'l, 's>(n : &'l Expr, 
                                        s : &'s mut HashSet<&'l Name>, 
                                        cache : &'s mut HashSet<&'l Expr>) {
-    if cache.contains(n) {
-        return
-    } else {
+    if !cache.contains(n) {
         match n.as_ref() {
             App(_, lhs, rhs) => {
                 unique_const_names_core(lhs, s, cache);
@@ -780,7 +861,7 @@ pub fn unique_const_names_core<'l, 's>(n : &'l Expr,
             | Lambda(_, dom, body)
             | Pi(_, dom, body) => {
                 unique_const_names_core(&dom.ty, s, cache);
-                unique_const_names_core(&body, s, cache);
+                unique_const_names_core(body, s, cache);
 
             },
             Let(_, dom, val, body) => {
@@ -788,6 +869,9 @@ pub fn unique_const_names_core<'l, 's>(n : &'l Expr,
                 unique_const_names_core(&val, s, cache);
                 unique_const_names_core(&body, s, cache);
             },
+            Proj(.., structure) => {
+                unique_const_names_core(structure, s, cache);
+            },
             Const(_, name, _) => {
                 s.insert(name);
             },
@@ -827,6 +911,7 @@ fn univ_params_subset_core<'l, 's>(e : &'l Expr, s : &'s mut HashSet<&'l Level>)
             univ_params_subset_core(val, s);
             univ_params_subset_core(body, s);
         },
+        Proj(.., structure) => univ_params_subset_core(structure, s),
         Sort(_, lvl) => { s.extend(unique_univ_params(lvl)); },
         Const(.., lvls) => for lvl in lvls.as_ref() {
             s.extend(unique_univ_params(lvl));
@@ -864,6 +949,15 @@ impl std::fmt::Debug for InnerExpr {
             Local(_, serial, of) => {
                 let truncated = serial.to_string().chars().take(6).collect::<String>();
                 write!(f, "Local(serial : {:?}, of : {:?}", truncated, of)
+            },
+            NatLit(_, n) => {
+                write!(f, "NatLit({})", n)
+            },
+            StrLit(_, s) => {
+                write!(f, "StrLit({:?})", s)
+            },
+            Proj(_, struct_name, idx, structure) => {
+                write!(f, "Proj({}, {}, {:?})", struct_name, idx, structure)
             }
         }
     }
@@ -0,0 +1,232 @@
+use std::hash::{ BuildHasherDefault, Hash, Hasher };
+use std::sync::{ Arc, Weak };
+
+use hashbrown::HashMap;
+use hashbrown::hash_map::RawEntryMut;
+use parking_lot::Mutex;
+
+const NUM_SHARDS : usize = 64;
+/// A shard doesn't bother sweeping until it has at least this many slots.
+const MIN_SWEEP_AT : usize = 1 << 12;
+
+/// Implemented by `InnerExpr`, `InnerLevel` and `InnerName`, which are
+/// hash-consed : every `Expr`, `Level` and `Name` is made by handing the
+/// inner value to `intern`, which returns the `Arc` of an equal value if
+/// one is still alive, so terms that are equal share one allocation, and
+/// the outer types can be compared by address.
+///
+/// Since a value's children have already been hash-consed by the time
+/// it is, `Eq` and `cons_hash` only need to look at the children's
+/// addresses, not walk them.
+pub trait HashCons : Eq + Sized + Send + Sync + 'static {
+    fn cons_hash(&self) -> u64;
+    fn table() -> &'static ConsTable<Self>;
+}
+
+pub fn intern<T : HashCons>(value : T) -> Arc<T> {
+    T::table().intern(value)
+}
+
+/// The address of an `Arc`'s contents, used by `cons_hash` to hash
+/// children that have already been hash-consed.
+pub fn addr<T>(arc : &Arc<T>) -> usize {
+    Arc::as_ptr(arc) as usize
+}
+
+/// Concurrent table of every live value of some type, split into shards
+/// that are each behind their own lock.
+///
+/// The table only holds `Weak` references, so it never keeps anything
+/// alive; a value is freed as soon as the last `Expr` (or `Level`, or
+/// `Name`) pointing to it is dropped, taking any children nothing else
+/// holds with it. What's left behind is a dead slot, which keeps the
+/// value's (now empty) allocation around until it's swept. Each shard
+/// sweeps itself when it has grown to twice the number of slots that
+/// were live after its last sweep, so dead slots never make up much more
+/// than half the table, and sweeping costs O(1) per insertion on
+/// average. `sweep` reclaims them straight away, IE after dropping an
+/// environment you don't need anymore.
+pub struct ConsTable<T> {
+    shards : Vec<Mutex<Shard<T>>>,
+}
+
+struct Shard<T> {
+    slots : HashMap<Slot<T>, (), BuildHasherDefault<SlotHasher>>,
+    sweep_at : usize,
+}
+
+struct Slot<T> {
+    hash : u64,
+    value : Weak<T>,
+}
+
+impl<T> Hash for Slot<T> {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        state.write_u64(self.hash)
+    }
+}
+
+impl<T> PartialEq for Slot<T> {
+    fn eq(&self, other : &Slot<T>) -> bool {
+        self.hash == other.hash && self.value.ptr_eq(&other.value)
+    }
+}
+
+impl<T> Eq for Slot<T> {}
+
+/// Slots already carry their hash, so this just passes it through.
+#[derive(Default)]
+struct SlotHasher(u64);
+
+impl Hasher for SlotHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes : &[u8]) {
+        for b in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(*b);
+        }
+    }
+
+    fn write_u64(&mut self, n : u64) {
+        self.0 = n;
+    }
+}
+
+impl<T : HashCons> Default for ConsTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T : HashCons> ConsTable<T> {
+    pub fn new() -> Self {
+        ConsTable {
+            shards : (0..NUM_SHARDS).map(|_| Mutex::new(Shard {
+                slots : HashMap::default(),
+                sweep_at : MIN_SWEEP_AT,
+            })).collect()
+        }
+    }
+
+    fn shard(&self, hash : u64) -> &Mutex<Shard<T>> {
+        // hashbrown uses the top and bottom bits of the hash itself.
+        &self.shards[((hash >> 32) as usize) % NUM_SHARDS]
+    }
+
+    pub fn intern(&self, value : T) -> Arc<T> {
+        let hash = value.cons_hash();
+        let mut shard = self.shard(hash).lock();
+        let is_match = |slot : &Slot<T>| {
+            slot.hash == hash && slot.value.upgrade().map(|live| *live == value).unwrap_or(false)
+        };
+        if let RawEntryMut::Occupied(mut entry) = shard.slots.raw_entry_mut().from_hash(hash, is_match) {
+            // It may have been freed since we matched it.
+            match entry.key().value.upgrade() {
+                Some(live) => return live,
+                None => {
+                    let fresh = Arc::new(value);
+                    entry.key_mut().value = Arc::downgrade(&fresh);
+                    return fresh
+                }
+            }
+        }
+
+        if shard.slots.len() >= shard.sweep_at {
+            shard.sweep();
+        }
+        let fresh = Arc::new(value);
+        let slot = Slot { hash, value : Arc::downgrade(&fresh) };
+        shard.slots.raw_entry_mut().from_hash(hash, |_| false).or_insert(slot, ());
+        fresh
+    }
+
+    /// Drop the slots of every value that's been freed.
+    pub fn sweep(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().sweep();
+        }
+    }
+}
+
+impl<T> Shard<T> {
+    fn sweep(&mut self) {
+        self.slots.retain(|slot, _| slot.value.strong_count() > 0);
+        self.sweep_at = (self.slots.len() * 2).max(MIN_SWEEP_AT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+    use crate::name::Name;
+    use crate::expr::{ Expr, Binding, BinderStyle, mk_app, mk_const, mk_lambda, mk_prop, mk_var };
+
+    /// A type with its own table, so sweeping it doesn't race with
+    /// the other tests.
+    #[derive(Debug, PartialEq, Eq)]
+    struct Val(u64);
+
+    impl HashCons for Val {
+        fn cons_hash(&self) -> u64 {
+            fxhash::hash64(&self.0)
+        }
+
+        fn table() -> &'static ConsTable<Val> {
+            static TABLE : OnceLock<ConsTable<Val>> = OnceLock::new();
+            TABLE.get_or_init(ConsTable::new)
+        }
+    }
+
+    fn num_slots<T>(table : &ConsTable<T>) -> usize {
+        table.shards.iter().map(|shard| shard.lock().slots.len()).sum()
+    }
+
+    fn lam(name : &str, style : BinderStyle) -> Expr {
+        mk_lambda(Binding::mk(Name::from(name), mk_prop(), style), mk_var(0))
+    }
+
+    #[test]
+    fn equal_structure_is_shared() {
+        let f_a = || mk_app(mk_const("f", Vec::new()), mk_const("a", Vec::new()));
+        // `Expr`'s `==` compares addresses.
+        assert!(f_a() == f_a());
+        assert!(f_a() != mk_app(mk_const("f", Vec::new()), mk_const("b", Vec::new())));
+        assert!(lam("x", BinderStyle::Default) == lam("x", BinderStyle::Default));
+    }
+
+    /// Binder names and styles don't matter to the kernel, but the pretty
+    /// printer shows them, so terms that differ only in them are kept apart.
+    #[test]
+    fn binder_names_and_styles_arent_merged() {
+        let x = lam("x", BinderStyle::Default);
+        assert!(x != lam("y", BinderStyle::Default));
+        assert!(x != lam("x", BinderStyle::Implicit));
+    }
+
+    #[test]
+    fn sweeping_frees_only_dead_slots() {
+        let table = ConsTable::<Val>::new();
+        let live = (0..1000).map(|n| table.intern(Val(n))).collect::<Vec<Arc<Val>>>();
+        for n in 1000..3000 {
+            drop(table.intern(Val(n)));
+        }
+        assert_eq!(num_slots(&table), 3000);
+
+        // A value that comes back after it was freed gets a new slot.
+        let again = table.intern(Val(1500));
+        assert_eq!(num_slots(&table), 3001);
+        drop(again);
+
+        table.sweep();
+        assert_eq!(num_slots(&table), 1000);
+        for (n, value) in live.iter().enumerate() {
+            assert!(Arc::ptr_eq(value, &table.intern(Val(n as u64))));
+        }
+        assert_eq!(num_slots(&table), 1000);
+        assert_eq!(*table.intern(Val(2999)), Val(2999));
+        assert_eq!(num_slots(&table), 1001);
+    }
+}
@@ -0,0 +1,242 @@
+use std::fs;
+use std::io::{ self, Write, BufWriter };
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicUsize, Ordering::* };
+
+use fxhash::hash64;
+use hashbrown::{ HashMap, HashSet };
+use parking_lot::Mutex;
+
+use crate::name::Name;
+use crate::expr::{ Expr, InnerExpr::* };
+use crate::env::{ Env, Declaration, CompiledModification, CompiledModification::* };
+
+/// Bump this whenever a change to the kernel could change whether an
+/// item passes, so caches written by older versions get thrown out.
+pub const CACHE_VERSION : u32 = 3;
+/// What gets tacked on to an export's file name to get its cache's.
+pub const CACHE_EXTENSION : &str = "nanoda-cache";
+const HEADER : &str = "nanoda result cache";
+/// Stands in for the key of a dependency that hasn't been added.
+const MISSING_DEP : u8 = 0;
+
+/// The results of an earlier run over the same export, so that items
+/// which haven't changed since then don't need to be checked again.
+///
+/// Every item gets a key, which is a hash of its name, the structural
+/// digests (`ExprCache::digest`) of its declarations' types and value,
+/// and the keys of everything it depends on. Since a dependency's key
+/// covers its own dependencies, changing anything an item depends on,
+/// however indirectly, changes the item's key too. Items are only ever
+/// added after their dependencies, so their keys are always available
+/// by the time we need them. An item whose key is in the cache passed
+/// last time, with the same declarations in the same context, so it's
+/// added to the environment as usual but not checked.
+///
+/// The cache is a text file with a header line giving the cache's
+/// version, followed by one `<key> <name>` line per item that passed.
+/// The name is just there to make the file readable, and to carry over
+/// items that this run didn't get to. The digests are fxhashes, which
+/// are fast but not collision resistant, so the cache only guards
+/// against accidents : anyone who can write to it can make nanoda skip
+/// whatever they like.
+pub struct ResultCache {
+    path : PathBuf,
+    previous : HashMap<u64, String>,
+    keys : Mutex<HashMap<Name, u64>>,
+    passed : Mutex<Vec<(u64, Name)>>,
+    num_unchanged : AtomicUsize,
+}
+
+/// Where the cache for the export at `export` goes; IE the cache for
+/// `core.out` is `core.out.nanoda-cache`, in the same directory.
+pub fn cache_path_for(export : &Path) -> PathBuf {
+    let mut path = export.as_os_str().to_owned();
+    path.push(".");
+    path.push(CACHE_EXTENSION);
+    PathBuf::from(path)
+}
+
+fn header() -> String {
+    format!("{} {} {}", HEADER, CACHE_VERSION, env!("CARGO_PKG_VERSION"))
+}
+
+impl ResultCache {
+    /// Read the cache at `path`. A cache that's missing, unreadable, or
+    /// was written by a different version counts as empty, which just
+    /// means everything gets checked.
+    pub fn load(path : PathBuf) -> Self {
+        let previous = fs::read_to_string(&path)
+                       .ok()
+                       .and_then(|contents| parse_cache(&contents))
+                       .unwrap_or_default();
+        ResultCache {
+            path,
+            previous,
+            keys : Mutex::new(HashMap::new()),
+            passed : Mutex::new(Vec::new()),
+            num_unchanged : AtomicUsize::new(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Work out the key of `compiled`, which has just been added to
+    /// `env`, and remember it under every name it declares. Returns
+    /// `true` if the item passed last time, in which case it counts as
+    /// having passed this time too, and doesn't need to be checked.
+    pub fn register(&self, compiled : &CompiledModification, env : &Env) -> bool {
+        let declared = compiled.declared_names();
+        let mut keys = self.keys.lock();
+        let key = item_key(compiled, &declared, env, &keys);
+        for name in declared.iter() {
+            keys.entry(name.clone()).or_insert(key);
+        }
+        drop(keys);
+
+        let unchanged = self.previous.contains_key(&key);
+        if unchanged {
+            self.num_unchanged.fetch_add(declared.len(), Relaxed);
+            self.passed.lock().push((key, compiled.get_name()));
+        }
+        unchanged
+    }
+
+    /// Record that `compiled` (which has been `register`ed) passed.
+    pub fn record_pass(&self, compiled : &CompiledModification) {
+        let name = compiled.get_name();
+        let key = self.keys.lock().get(&name).copied();
+        if let Some(key) = key {
+            self.passed.lock().push((key, name));
+        }
+    }
+
+    /// How many declarations were skipped because their item hadn't
+    /// changed since the last run.
+    pub fn num_unchanged(&self) -> usize {
+        self.num_unchanged.load(Relaxed)
+    }
+
+    /// Replace the cache file with everything that passed in this run,
+    /// plus anything from the old cache that this run never got to
+    /// (IE because it stopped at an earlier failure). The new contents
+    /// are written to a temporary file first, so a run that's cut off
+    /// halfway through never leaves a cache that's only partly written.
+    pub fn save(&self) -> io::Result<()> {
+        let registered = self.keys.lock().keys().map(|name| name.to_string()).collect::<HashSet<String>>();
+        let passed = self.passed.lock();
+        let carried = self.previous.iter().filter(|(_, name)| !registered.contains(*name));
+
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut out = BufWriter::new(fs::File::create(&tmp_path)?);
+        writeln!(out, "{}", header())?;
+        for (key, name) in passed.iter() {
+            writeln!(out, "{:016x} {}", key, name)?;
+        }
+        for (key, name) in carried {
+            writeln!(out, "{:016x} {}", key, name)?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+fn parse_cache(contents : &str) -> Option<HashMap<u64, String>> {
+    let mut lines = contents.lines();
+    if lines.next()? != header() {
+        return None
+    }
+
+    let mut previous = HashMap::new();
+    for line in lines {
+        let mut fields = line.splitn(2, ' ');
+        let key = u64::from_str_radix(fields.next()?, 16).ok()?;
+        previous.insert(key, fields.next()?.to_string());
+    }
+    Some(previous)
+}
+
+fn declaration_digest(d : &Declaration) -> u64 {
+    hash64(&(&d.name, &d.univ_params, d.ty.get_digest(), d.kind, d.guessed_kind, d.hint))
+}
+
+/// The key of `compiled` (see `ResultCache`). `declared` is the list of
+/// names it declares, and `keys` has the keys of everything that was
+/// added before it.
+fn item_key(compiled : &CompiledModification,
+            declared : &[Name],
+            env : &Env,
+            keys : &HashMap<Name, u64>) -> u64 {
+    let declarations = compiled.new_declarations();
+
+    let mut roots = declarations.iter().map(|d| &d.ty).collect::<Vec<&Expr>>();
+    let mut own = declarations.iter().map(|d| declaration_digest(d)).collect::<Vec<u64>>();
+    if let CompiledDefinition(_, _, _, val) = compiled {
+        roots.push(val);
+        own.push(val.get_digest());
+    }
+
+    let mut dep_keys = dependencies(roots, env)
+                       .into_iter()
+                       .filter(|dep| !declared.contains(dep))
+                       .map(|dep| match keys.get(&dep) {
+                           Some(key) => *key,
+                           None => hash64(&(MISSING_DEP, &dep))
+                       })
+                       .collect::<Vec<u64>>();
+    dep_keys.sort_unstable();
+
+    hash64(&(own, dep_keys))
+}
+
+/// Every name whose declaration could make a difference to checking
+/// `roots`. That's each constant they mention (as in
+/// `unique_const_names`), along with the structure of each projection,
+/// and, if there are any literals, the names the literal extensions
+/// are built on, since those never show up as constants.
+fn dependencies(roots : Vec<&Expr>, env : &Env) -> HashSet<Name> {
+    let mut deps = HashSet::new();
+    let mut seen = HashSet::<&Expr>::with_capacity(200);
+    let (mut has_nat_lit, mut has_str_lit) = (false, false);
+    let mut todo = roots;
+
+    while let Some(e) = todo.pop() {
+        if !seen.insert(e) {
+            continue
+        }
+        match e.as_ref() {
+            App(_, lhs, rhs) => todo.extend(&[lhs, rhs]),
+            | Lambda(_, dom, body)
+            | Pi(_, dom, body) => todo.extend(&[&dom.ty, body]),
+            Let(_, dom, val, body) => todo.extend(&[&dom.ty, val, body]),
+            Proj(_, struct_name, _, structure) => {
+                deps.insert(struct_name.clone());
+                todo.push(structure);
+            },
+            Const(_, name, _) => { deps.insert(name.clone()); },
+            NatLit(..) => has_nat_lit = true,
+            StrLit(..) => has_str_lit = true,
+            _ => ()
+        }
+    }
+
+    // A string literal unfolds to characters made out of nat literals.
+    if has_nat_lit || has_str_lit {
+        if let Some(ext) = env.nat_ext.get() {
+            deps.extend(ext.names());
+        }
+    }
+    if has_str_lit {
+        if let Some(ext) = env.str_ext.get() {
+            let names = [&ext.string, &ext.char];
+            deps.extend(names.iter().chain(ext.unfold_names().iter()).map(|n| (*n).clone()));
+        }
+    }
+
+    deps
+}
@@ -9,8 +9,8 @@ use crate::level::{ Level, mk_param, mk_zero };
 use crate::reduction::ReductionRule;
 use crate::env::{ Env, Declaration, CompiledModification };
 use crate::tc::TypeChecker;
-use crate::utils::{ Either, Either::* };
-use crate::errors;
+use crate::utils::{ Either, Either::*, ShortCircuit::EqShort };
+use crate::errors::{ KernelResult, KernelError, KernelError::* };
 use crate::expr::{ Expr, 
                    Binding, 
                    BinderStyle, 
@@ -18,29 +18,36 @@ use crate::expr::{ Expr,
                    mk_const, 
                    mk_sort, 
                    mk_local, 
-                   mk_app };
-
-
-/// This module implements inductive types. The general flow is:
-/// 1. The parser collects the elements needed to call `Inductive::new(..)`
-/// 2. Eventually we call `compile()` on the thing from 1
-/// 3. `compile()` creates the `Inductive`'s related declarations,
-///    introduction, and elimination rules. Introduction rules have
-///    two instances; one is `CompiledIntro`, which has intermediate info
-///    used for checking the introduction, and the other is the persistent
-///    `Declaration` spun off from an introduction rule that actually persists
-///    in the environment after typechecking is done, and is a member
-///    of the eventual `CompiledInductive`.
-///    Formation of the reduction rules is done by the constructors for
-///    `ReductionRule`, though there is special handling for k-like reduction
-///    which is discussed more below.
-/// 
-/// Many of the functions defined on `Inductive` are just defined to 
-/// pull them out of the body of `compile()` to keep it from just being
-/// a giant list of instructions. Most of them are only called once per
-/// inductive and could just as easily be placed inline.
-
-
+                   mk_app,
+                   unique_const_names };
+
+
+// This module implements inductive types. The general flow is:
+// 1. The parser collects the elements needed to call `Inductive::new(..)`
+// 2. Eventually we call `compile()` on the thing from 1
+// 3. `compile()` creates the `Inductive`'s related declarations,
+//    introduction, and elimination rules. Introduction rules have
+//    two instances; one is `CompiledIntro`, which has intermediate info
+//    used for checking the introduction, and the other is the persistent
+//    `Declaration` spun off from an introduction rule that actually persists
+//    in the environment after typechecking is done, and is a member
+//    of the eventual `CompiledInductive`.
+//    Formation of the reduction rules is done by the constructors for
+//    `ReductionRule`, though there is special handling for k-like reduction
+//    which is discussed more below.
+// 
+// Many of the functions defined on `Inductive` are just defined to 
+// pull them out of the body of `compile()` to keep it from just being
+// a giant list of instructions. Most of them are only called once per
+// inductive and could just as easily be placed inline.
+
+
+/// `always_dep_elim` is set for Lean 4 inductives, whose recursors
+/// always take a motive that depends on the major premise. In Lean 3,
+/// only types that aren't always a `Prop` get one.
+/// A Lean 4 `#IND` line also says how many indices the type has and
+/// whether its block is recursive; we work both out for ourselves, and
+/// reject the block if they don't match.
 #[derive(Debug, Clone)]
 pub struct ProtoInd {
     pub name: Name,
@@ -48,53 +55,166 @@ pub struct ProtoInd {
     pub ty: Expr,
     pub num_params: usize,
     pub intros: Vec<(Name, Expr)>,
+    pub always_dep_elim: bool,
+    pub num_indices: Option<usize>,
+    pub is_rec: Option<bool>,
+}
+
+/// What the environment remembers about an inductive once it's been
+/// added, so later items can ask things like "what are the introduction
+/// rules of `eq`" without having the original `#IND` line around.
+#[derive(Debug, Clone)]
+pub struct IndInfo {
+    pub name : Name,
+    pub num_params : usize,
+    pub num_indices : usize,
+    pub intros : Vec<Name>,
+    pub elim : Name,
+    /// Set if the inductive is a structure (exactly one introduction
+    /// rule, no indices, and not recursive), which is what projections
+    /// and structure eta work on.
+    pub structure : Option<StructInfo>,
+    /// A unit-like inductive has one introduction rule with no arguments
+    /// and no indices (the same shape `detect_k` looks for), and isn't
+    /// always a `Prop`. All of its inhabitants are definitionally equal.
+    pub is_unit_like : bool,
 }
 
+/// The single introduction rule of a structure. `field_names` are the
+/// binder names of the rule's arguments after the parameters.
+#[derive(Debug, Clone)]
+pub struct StructInfo {
+    pub ctor : Name,
+    pub num_fields : usize,
+    pub field_names : Vec<Name>,
+}
+
+/// A block of one or more (mutually) inductive types. The types share
+/// their parameters (the locals in `params`), their universe parameters,
+/// and the universe they live in.
 #[derive(Debug, Clone)]
 pub struct Inductive {
     pub num_params: usize,
-    pub intros: Vec<(Name, Expr)>,
     pub tc : Arc<RwLock<TypeChecker>>,
     pub codomain_sort : Level,
-    pub params_and_indices : Vec<Expr>,
+    pub params : Vec<Expr>,
     pub use_dep_elim : bool,
+    pub types : Vec<IndType>,
+}
+
+/// One of the types declared by an `Inductive` block. `indices` are
+/// locals for the binders of its type after the parameters.
+#[derive(Debug, Clone)]
+pub struct IndType {
+    pub intros: Vec<(Name, Expr)>,
+    pub indices : Vec<Expr>,
     pub minimal_const : Expr,
     pub base_declaration: Declaration,
+    pub exported_is_rec : Option<bool>,
 }
 
-impl Inductive {
-    pub fn new(name: Name,
-               univ_params: Arc<Vec<Level>>,
-               type_: Expr,
-               num_params: usize,
-               intros: Vec<(Name, Expr)>,
-               env : Arc<RwLock<Env>>) -> Self {
+impl IndType {
+    pub fn get_name(&self) -> &Name {
+        &self.base_declaration.name
+    }
 
-        let minimal_const = mk_const(name.clone(), univ_params.clone());
-        let base_declaration = Declaration::mk(name, univ_params, type_, None, Some(true));
+    /// The type applied to `params` and its own indices.
+    pub fn applied(&self, params : &[Expr]) -> Expr {
+        self.minimal_const.fold_apps(params.iter().chain(self.indices.iter()))
+    }
+}
+
+impl Inductive {
+    /// `block` can't be empty; the parsers always produce at least one type.
+    /// The base types are only visible to the block's own type checker;
+    /// they go into the environment along with the rest of the block in
+    /// `CompiledModification::add_only`.
+    pub fn new(block : Vec<ProtoInd>, env : Arc<Env>) -> KernelResult<Self> {
+        let first_name = block[0].name.clone();
+        let univ_params = block[0].params.clone();
+        let num_params = block[0].num_params;
+        let always_dep_elim = block[0].always_dep_elim;
 
         let mut tc = TypeChecker::new(None, env);
+        let mut params = Vec::new();
+        let mut codomain_sort : Option<Level> = None;
+        let mut types = Vec::with_capacity(block.len());
+
+        for ind in block {
+            let mismatch = |what| MutualMismatch { first : first_name.clone(), other : ind.name.clone(), what };
+            if ind.params != univ_params {
+                return Err(mismatch("universe parameters"))
+            }
+            if ind.num_params != num_params {
+                return Err(mismatch("number of parameters"))
+            }
 
-        base_declaration.to_axiom().compile(&tc.env).add_only(&tc.env);
+            let minimal_const = mk_const(ind.name.clone(), univ_params.clone());
+            let base_declaration = Declaration::mk(ind.name.clone(), univ_params.clone(), ind.ty.clone(), None, Some(true));
+
+            // Check that the universe parameters are in order for the inductive
+            // and its introduction rules before anything goes into the environment.
+            base_declaration.check_univ_params()?;
+            for (intro_name, intro_type) in ind.intros.iter() {
+                Declaration::mk(intro_name.clone(), 
+                                base_declaration.univ_params.clone(), 
+                                intro_type.clone(), 
+                                None, 
+                                Some(true)).check_univ_params()?;
+            }
 
-        let (codomain_expr, params_and_indices) = tc.normalize_pis(&base_declaration.ty);
-        let codomain_sort = match codomain_expr.as_ref() {
-            Sort(_, lvl) => lvl.clone(),
-            owise => errors::err_normalize_pis(line!(), owise)
-        };
+            // The first type in the block makes the parameters, and the
+            // others have to agree with it.
+            let (codomain_expr, indices) = if types.is_empty() {
+                let (codomain_expr, params_and_indices) = tc.normalize_pis(&base_declaration.ty)?;
+                if num_params > params_and_indices.len() {
+                    return Err(IndNumParams(base_declaration.name.clone(), num_params, params_and_indices.len()))
+                }
+                params = params_and_indices[..num_params].to_vec();
+                (codomain_expr, params_and_indices[num_params..].to_vec())
+            } else {
+                let instd = instantiate_params(&mut tc, &base_declaration.ty, &params, &ind.name, mismatch)?;
+                tc.normalize_pis(&instd)?
+            };
+
+            let codomain = match codomain_expr.as_ref() {
+                Sort(_, lvl) => lvl.clone(),
+                _ => return Err(IndCodomainNotSort(base_declaration.ty.clone(), codomain_expr))
+            };
+
+            match codomain_sort.as_ref() {
+                None => codomain_sort = Some(codomain),
+                Some(sort) if !sort.eq_by_antisymm(&codomain) => return Err(mismatch("universe")),
+                Some(_) => ()
+            }
+
+            base_declaration.to_axiom()?;
+            tc.add_local_declaration(base_declaration.clone())?;
+
+            if ind.num_indices.map(|n| n != indices.len()).unwrap_or(false) {
+                return Err(ExportMismatch { ind : ind.name.clone(), what : "number of indices" })
+            }
 
-        let use_dep_elim = codomain_sort.maybe_nonzero();
+            types.push(IndType {
+                intros : ind.intros,
+                indices,
+                minimal_const,
+                base_declaration,
+                exported_is_rec : ind.is_rec,
+            });
+        }
+
+        let codomain_sort = codomain_sort.unwrap_or_else(mk_zero);
+        let use_dep_elim = always_dep_elim || codomain_sort.maybe_nonzero();
 
-        Inductive {
+        Ok(Inductive {
             num_params,
-            intros,
             tc : Arc::new(RwLock::new(tc)),
             codomain_sort,
-            params_and_indices,
+            params,
             use_dep_elim,
-            minimal_const,
-            base_declaration,
-        }
+            types,
+        })
     }
 
     pub fn map_tc<T>(&self, f : impl FnOnce(&mut TypeChecker) -> T) -> T {
@@ -102,48 +222,49 @@ impl Inductive {
     }
 
     pub fn get_params(&self) -> &[Expr] {
-        &self.params_and_indices[0 .. self.num_params]
-    }
-
-    pub fn get_indices(&self) -> &[Expr] {
-        &self.params_and_indices[self.num_params .. ]
+        self.params.as_slice()
     }
 
-
+    /// The name of the first type in the block, which is what errors
+    /// are reported under.
     pub fn get_name(&self) -> &Name {
-        &self.base_declaration.name
+        self.types[0].get_name()
     }
 
     pub fn get_univ_params(&self) -> &Vec<Level> {
-        &self.base_declaration.univ_params.as_ref()
+        &self.types[0].base_declaration.univ_params.as_ref()
     }
 
-    pub fn get_type(&self) -> &Expr {
-        &self.base_declaration.ty
+    /// The position of the type called `name` in the block, if it's one of ours.
+    pub fn type_idx(&self, name : &Name) -> Option<usize> {
+        self.types.iter().position(|ind_type| ind_type.get_name() == name)
     }
 
-    pub fn elim_into_prop(&self, compiled_intros : &Vec<CompiledIntro>) -> bool {
+    pub fn elim_into_prop(&self, compiled_intros : &Vec<CompiledIntro>) -> KernelResult<bool> {
         let bool1 = self.codomain_sort.maybe_zero();
-        let bool2 = self.intros.len() > 1;
-        let bool3 = compiled_intros.iter().any(|intro| {
-            intro.intro_arguments.iter().any(|arg| {
-                !self.map_tc(|tc| tc.is_proof(arg).0) && !intro.intro_type_args.contains(arg)
-            })
-        });
+        let bool2 = self.types.len() > 1 || compiled_intros.len() > 1;
+        let mut bool3 = false;
+        for intro in compiled_intros.iter() {
+            for arg in intro.intro_arguments.iter() {
+                if !self.map_tc(|tc| tc.is_proof(arg))?.0 && !intro.intro_type_args.contains(arg) {
+                    bool3 = true;
+                }
+            }
+        }
 
-        bool1 && (bool2 || bool3)
+        Ok(bool1 && (bool2 || bool3))
     }
 
-    pub fn elim_level(&self, compiled_intros : &Vec<CompiledIntro>) -> Level {
-        if self.elim_into_prop(&compiled_intros) {
-            mk_zero()
+    pub fn elim_level(&self, compiled_intros : &Vec<CompiledIntro>) -> KernelResult<Level> {
+        if self.elim_into_prop(&compiled_intros)? {
+            Ok(mk_zero())
         } else {
             let forbidden_names = self.get_univ_params()
                                       .iter()
                                       .map(|x| x.get_param_name())
                                       .collect::<HashSet<&Name>>();
             let fresh_name = Name::fresh_name("l", forbidden_names);
-            mk_param(fresh_name)
+            Ok(mk_param(fresh_name))
         }
     }
 
@@ -156,100 +277,183 @@ impl Inductive {
         }
     }
 
+    /// Motives are called `C`, or `C_1`, `C_2`, ... in a mutual block.
+    fn motive_name(&self, ty_idx : usize) -> Name {
+        if self.types.len() == 1 {
+            Name::from("C")
+        } else {
+            Name::from(format!("C_{}", ty_idx + 1).as_str())
+        }
+    }
+
+    /// Do any of the types being declared show up anywhere in `e`?
+    pub fn has_ind_occ(&self, e : &Expr) -> bool {
+        let names = unique_const_names(e);
+        self.types.iter().any(|ind_type| names.contains(ind_type.get_name()))
+    }
+
+    /// A valid recursive occurrence is one of the types in the block (at
+    /// its own universe parameters) applied to exactly its parameters followed
+    /// by indices that don't themselves mention the block. Returns the
+    /// position of the type that occurs.
+    pub fn is_valid_ind_app(&self, e : &Expr) -> Option<usize> {
+        let (fn_, args) = e.unfold_apps_special();
+        match fn_.as_ref() {
+            Const(_, name, lvls) => {
+                let ty_idx = self.type_idx(name)?;
+                let valid = lvls.as_ref() == self.get_univ_params()
+                            && args.len() == self.num_params + self.types[ty_idx].indices.len()
+                            && args[..self.num_params] == *self.get_params()
+                            && !args[self.num_params..].iter().any(|idx| self.has_ind_occ(idx));
+                if valid { Some(ty_idx) } else { None }
+            },
+            _ => None
+        }
+    }
+
+    /// Strict positivity check for the type of an introduction rule's
+    /// argument. In pseudo-code :
+    ///```pseudo
+    /// match whnf(ty) {
+    ///     t if ind doesn't appear in t => ok (non-recursive argument),
+    ///     Pi (x : A), B if ind doesn't appear in A => check B,
+    ///     t if is_valid_ind_app(t) => ok (recursive argument),
+    ///     _ => error
+    /// }
+    ///```
+    pub fn check_positivity(&self, ty : &Expr, intro_name : &Name, arg_idx : usize) -> KernelResult<()> {
+        let whnfd = self.map_tc(|tc| tc.whnf(ty))?;
+        if !self.has_ind_occ(&whnfd) {
+            Ok(())
+        } else if let Pi(_, dom, body) = whnfd.as_ref() {
+            if self.has_ind_occ(&dom.ty) {
+                return Err(NonPositiveOccurrence { intro : intro_name.clone(), arg_idx, arg_ty : ty.clone() })
+            }
+            let local = dom.clone().as_local();
+            self.check_positivity(&body.instantiate(Some(&local).into_iter()), intro_name, arg_idx)
+        } else if self.is_valid_ind_app(&whnfd).is_some() {
+            Ok(())
+        } else {
+            Err(InvalidIndOccurrence { intro : intro_name.clone(), arg_idx, arg_ty : ty.clone() })
+        }
+    }
+
     pub fn mk_motive_app(&self, e : &Expr, indices : &[Expr], motive : &Expr) -> Expr {
         if self.use_dep_elim {
-            mk_app(motive.fold_apps(indices.into_iter()), e.clone())
+            mk_app(motive.fold_apps(indices.iter()), e.clone())
         } else {
             motive.fold_apps(indices.iter())
         }
     }
 
-    pub fn compile(self, env : &Arc<RwLock<Env>>) -> CompiledModification {
-
-
-        let base_type_folded_w_params = (&self.minimal_const).fold_apps(self.get_params().into_iter());
-        let base_type_folded_w_params_and_indices = &self.minimal_const.fold_apps(self.params_and_indices.iter());
+    /// Every type in the block gets a recursor, which takes a motive for
+    /// each type and a minor premise for every introduction rule in the
+    /// block. The reduction rule for an introduction rule is attached to
+    /// the recursor of its own type, and its recursive calls go to the
+    /// recursor of whichever type the recursive argument belongs to.
+    pub fn compile(self) -> KernelResult<CompiledModification> {
 
         let compiled_intros = 
-            self.intros.iter().map(|(intro_name, raw_intro_type)| {
+            self.types.iter().enumerate().flat_map(|(ty_idx, ind_type)| {
+                ind_type.intros.iter().map(move |(intro_name, raw_intro_type)| (ty_idx, intro_name, raw_intro_type))
+            }).map(|(ty_idx, intro_name, raw_intro_type)| {
                 CompiledIntro::new(&self,
+                                   ty_idx,
                                    raw_intro_type,
-                                   intro_name,
-                                   &base_type_folded_w_params)
-            }).collect::<Vec<CompiledIntro>>();
-
+                                   intro_name)
+            }).collect::<KernelResult<Vec<CompiledIntro>>>()?;
+
+        // Lean 4 calls a block recursive if any introduction rule has an
+        // argument whose type mentions one of the block's types, which
+        // (once nested occurrences are gone) is what a recursive argument is.
+        let is_rec = compiled_intros.iter().any(|intro| intro.intro_arg_data.iter().any(|arg| matches!(arg, Right(_))));
+        for ind_type in self.types.iter() {
+            if ind_type.exported_is_rec.map(|claimed| claimed != is_rec).unwrap_or(false) {
+                return Err(ExportMismatch { ind : ind_type.get_name().clone(), what : "recursiveness" })
+            }
+        }
 
-        let elim_level = self.elim_level(&compiled_intros);
+        let elim_level = self.elim_level(&compiled_intros)?;
         let elim_level_params = self.elim_level_params(&elim_level);
         let sort_of_elim_lvl = mk_sort(elim_level);
 
-        let motive_type = if self.use_dep_elim {
-            let lc = mk_local(Name::from("c"), 
-                                    base_type_folded_w_params_and_indices.clone(), 
-                                    BinderStyle::Default);
-            sort_of_elim_lvl.fold_pis(self.get_indices().into_iter().chain(Some(&lc)))
-        } else {
-            sort_of_elim_lvl.fold_pis(self.get_indices().into_iter())
-        };
-
-        let motive = mk_local(Name::from("C"), motive_type, BinderStyle::Implicit);
+        let motives = self.types.iter().enumerate().map(|(ty_idx, ind_type)| {
+            let motive_type = if self.use_dep_elim {
+                let lc = mk_local(Name::from("c"), 
+                                  ind_type.applied(self.get_params()), 
+                                  BinderStyle::Default);
+                sort_of_elim_lvl.fold_pis(ind_type.indices.iter().chain(Some(&lc)))?
+            } else {
+                sort_of_elim_lvl.fold_pis(ind_type.indices.iter())?
+            };
+            Ok(mk_local(self.motive_name(ty_idx), motive_type, BinderStyle::Implicit))
+        }).collect::<KernelResult<Vec<Expr>>>()?;
 
         // Motive is the reason why you can't set it from the start.
         let intro_minors = compiled_intros.iter().map(|intro| {
-            intro.mk_intro_minor_premise(&motive)
-        }).collect::<Vec<Expr>>();
-
-        let major_premise = 
-            mk_local(Name::from("x"),
-                           base_type_folded_w_params_and_indices.clone(),
-                           BinderStyle::Default);
-
-        let elim_type_args = seq![&self.get_params(),
-                            Some(&motive),
-                            &intro_minors, 
-                            &self.get_indices(), 
-                            Some(&major_premise)];
-
-        let elim_type = self.mk_motive_app(&major_premise,
-                                           self.get_indices(),
-                                           &motive).fold_pis(elim_type_args.iter());
-
-        let elim_declaration = Declaration::mk(
-                                        self.get_name().extend_str("rec"),
-                                        elim_level_params.clone(),
-                                        elim_type,
-                                        None,
-                                        Some(true)
-                                    );
+            intro.mk_intro_minor_premise(&motives)
+        }).collect::<KernelResult<Vec<Expr>>>()?;
+
+        // The arguments of each type's recursor; only the indices and
+        // the major premise differ between types.
+        let elim_type_args = self.types.iter().map(|ind_type| {
+            let major_premise = 
+                mk_local(Name::from("x"),
+                         ind_type.applied(self.get_params()),
+                         BinderStyle::Default);
+            seq![self.get_params(),
+                 &motives,
+                 &intro_minors, 
+                 &ind_type.indices, 
+                 Some(&major_premise)]
+        }).collect::<Vec<Vec<Expr>>>();
+
+        let elim_declarations = self.types.iter().enumerate().map(|(ty_idx, ind_type)| {
+            let args = &elim_type_args[ty_idx];
+            let major_premise = &args[args.len() - 1];
+            let elim_type = self.mk_motive_app(major_premise,
+                                               &ind_type.indices,
+                                               &motives[ty_idx]).fold_pis(args.iter())?;
+            Ok(Declaration::mk(
+                ind_type.get_name().extend_str("rec"),
+                elim_level_params.clone(),
+                elim_type,
+                None,
+                Some(true)
+            ))
+        }).collect::<KernelResult<Vec<Declaration>>>()?;
+
+        let elim_names = elim_declarations.iter().map(|d| d.name.clone()).collect::<Vec<Name>>();
 
         // The 'flag' for whether you're going to end up using a k value is :
-        // `compiled_intros` has only one element `e`,
-        // AND the intro_arguments of `e` are empty
-        let detect_k = compiled_intros.len() == 1 
-                       && compiled_intros.get(0)
+        // the block has only one type, `compiled_intros` has only one 
+        // element `e`, AND the intro_arguments of `e` are empty
+        let detect_k = self.types.len() == 1
+                       && compiled_intros.len() == 1 
+                       && compiled_intros.first()
                                          .map(|intro| intro.intro_arguments.is_empty())
                                          .unwrap_or(false);
 
         let k_intro_rule = if detect_k {
             let k_intro_arg2 = 
-                mk_const(elim_declaration.name.clone(), 
+                mk_const(elim_names[0].clone(), 
                                elim_level_params.clone())
-                                   .fold_apps(elim_type_args.iter());
+                                   .fold_apps(elim_type_args[0].iter());
         
             let k_intro_arg3 = intro_minors[0].clone();
         
             let k_intro_arg4 = compiled_intros[0]
                                     .intro_type_args
                                     .iter()
-                                    .zip(self.params_and_indices.iter())
+                                    .zip(self.get_params().iter().chain(self.types[0].indices.iter()))
                                     .filter(|(a, b)| a != b);
 
             let rr = ReductionRule::new_nondef_rr(
-                elim_type_args.as_slice(),
+                elim_type_args[0].as_slice(),
                 k_intro_arg2,
                 k_intro_arg3,
                 k_intro_arg4,
-            );
+            )?;
             Some(rr)
          } else {
               None
@@ -277,14 +481,14 @@ impl Inductive {
                            .map(|(intro_minors_idx, intro)| intro.mk_reduction_rule(
                 intro_minors_idx,
                 &intro_minors,
-                &motive,
-                &elim_declaration.name,
-                &elim_declaration.univ_params,
-            )).collect::<Vec<ReductionRule>>()
+                &motives,
+                &elim_names,
+                &elim_level_params,
+            )).collect::<KernelResult<Vec<ReductionRule>>>()?
         };
 
         for i in compiled_intros.iter() {
-            i.check_intro(env)
+            i.check_intro()?;
         }
 
         // We want to be able to drop non-essential
@@ -293,17 +497,81 @@ impl Inductive {
         // the `CompiledInductive` item. This is also what lets
         // us take `parent` by reference in CompiledIntro.
 
-        CompiledModification::CompiledInductive(self.base_declaration,
-                                                intro_declarations,
-                                                elim_declaration,
-                                                reduction_rules)
+        // As in Lean, every type in a block is recursive if any of the
+        // block's introduction rules has a recursive argument.
+        let is_recursive = compiled_intros.iter().any(|intro| {
+            intro.intro_arg_data.iter().any(|data| matches!(data, Right(_)))
+        });
+
+        let infos = self.types.iter().enumerate().map(|(ty_idx, ind_type)| {
+            let intros = compiled_intros.iter()
+                                        .filter(|intro| intro.ty_idx == ty_idx)
+                                        .collect::<Vec<&CompiledIntro>>();
+
+            let structure = match intros.as_slice() {
+                [intro] if ind_type.indices.is_empty() && !is_recursive => Some(StructInfo {
+                    ctor : intro.intro_name.clone(),
+                    num_fields : intro.intro_arguments.len(),
+                    field_names : intro.intro_arguments.iter().map(|arg| arg.lc_binding().pp_name.clone()).collect(),
+                }),
+                _ => None
+            };
+
+            let is_unit_like = matches!(intros.as_slice(), [intro] if intro.intro_arguments.is_empty())
+                               && ind_type.indices.is_empty()
+                               && !self.codomain_sort.is_zero();
+
+            IndInfo {
+                name : ind_type.get_name().clone(),
+                num_params : self.num_params,
+                num_indices : ind_type.indices.len(),
+                intros : intros.iter().map(|intro| intro.intro_name.clone()).collect(),
+                elim : elim_names[ty_idx].clone(),
+                structure,
+                is_unit_like,
+            }
+        }).collect::<Vec<IndInfo>>();
+
+        let base_declarations = self.types.iter().map(|ind_type| ind_type.base_declaration.clone()).collect();
+
+        Ok(CompiledModification::CompiledInductive(base_declarations,
+                                                   intro_declarations,
+                                                   elim_declarations,
+                                                   reduction_rules,
+                                                   infos))
+    }
+}
+
+/// Replace the first `params.len()` binders of `ty` (the type of one of
+/// the later types in a mutual block) with the block's parameters, checking
+/// that each binder has the same type as the parameter that replaces it.
+fn instantiate_params(tc : &mut TypeChecker, 
+                      ty : &Expr, 
+                      params : &[Expr], 
+                      name : &Name,
+                      mismatch : impl Fn(&'static str) -> KernelError) -> KernelResult<Expr> {
+    let mut acc = ty.clone();
+    for (idx, param) in params.iter().enumerate() {
+        acc = tc.whnf(&acc)?;
+        acc = match acc.as_ref() {
+            Pi(_, dom, body) => {
+                if tc.check_def_eq(&dom.ty, &param.lc_binding().ty)? != EqShort {
+                    return Err(mismatch("parameters"))
+                }
+                body.instantiate(Some(param).into_iter())
+            },
+            _ => return Err(IndNumParams(name.clone(), params.len(), idx))
+        };
     }
+    Ok(acc)
 }
 
 
 #[derive(Debug)]
 pub struct CompiledIntro<'p> {
     pub parent : &'p Inductive,
+    /// The position of the type this introduces in `parent.types`
+    pub ty_idx : usize,
     pub intro_name : Name,
     pub intro_arguments : Vec<Expr>,
     pub intro_type : Expr,
@@ -312,107 +580,123 @@ pub struct CompiledIntro<'p> {
     pub intro_type_args : Vec<Expr>,
 }
 
-type ArgData = Either<Expr, (Vec<Expr>, Vec<Expr>)>;
+/// A recursive argument is `Right((binders, indices, ty_idx))`, where
+/// `ty_idx` is the position of its type in the block.
+type ArgData = Either<Expr, (Vec<Expr>, Vec<Expr>, usize)>;
 
 impl<'p> CompiledIntro<'p> {
     pub fn new(parent : &'p Inductive,
+               ty_idx : usize,
                raw_intro_type : &Expr,
-               intro_name : &Name,
-               ind_ty_w_params : &Expr) -> Self {
+               intro_name : &Name) -> KernelResult<Self> {
 
-        let instd_pi = parent.map_tc(|tc| tc.instantiate_pis(raw_intro_type, parent.get_params()));
-        let (fn_f, arguments) = parent.map_tc(|tc| tc.normalize_pis(&instd_pi));
+        let instd_pi = parent.map_tc(|tc| tc.instantiate_pis(raw_intro_type, parent.get_params()))?;
+        let (fn_f, arguments) = parent.map_tc(|tc| tc.normalize_pis(&instd_pi))?;
         let (new_intro_type, intro_type_args) = fn_f.unfold_apps_special();
+        // Building the minor premise and reduction rule takes the
+        // parameters off the front of these, so this can't wait until
+        // `check_intro`.
+        if intro_type_args.len() < parent.num_params {
+            return Err(IntroNumParams(intro_name.clone(), parent.num_params, intro_type_args.len()))
+        }
 
-        let all_arg_infos = arguments.iter().map(|arg| {
+        let all_arg_infos = arguments.iter().enumerate().map(|(idx, arg)| {
             if let Local(.., binding) = arg.as_ref() {
-                let (fn_, binders) = parent.map_tc(|tc| tc.normalize_pis(&binding.ty));
+                parent.check_positivity(&binding.ty, intro_name, idx + 1)?;
+                let (fn_, binders) = parent.map_tc(|tc| tc.normalize_pis(&binding.ty))?;
                 let (rec_arg_ind_ty, rec_args) = fn_.unfold_apps_special();
 
                 match rec_arg_ind_ty.as_ref() {
-                    Const(_, name, _) if name == parent.get_name() => {
-                        assert!(rec_args.len() >= parent.num_params);
+                    Const(_, name, _) if parent.type_idx(name).is_some() => {
+                        let rec_ty_idx = parent.type_idx(name).unwrap_or(ty_idx);
+                        if rec_args.len() < parent.num_params {
+                            return Err(IntroNumParams(intro_name.clone(), parent.num_params, rec_args.len()))
+                        }
                         let (rec_args_lhs, rec_args_rhs) = rec_args.split_at(parent.num_params);
+                        let ind_ty_w_params = parent.types[rec_ty_idx].minimal_const.fold_apps(parent.get_params());
                         parent.map_tc(|tc| {
                             tc.require_def_eq(&rec_arg_ind_ty.fold_apps(rec_args_lhs), 
-                                              ind_ty_w_params);
-                        });
-                        Right((binders, rec_args_rhs.to_vec()))
+                                              &ind_ty_w_params)
+                        })?;
+                        Ok(Right((binders, rec_args_rhs.to_vec(), rec_ty_idx)))
                     },
-                    _ => Left(arg.clone())
+                    _ => Ok(Left(arg.clone()))
                 }
             } else {
-                Left(arg.clone())
+                Ok(Left(arg.clone()))
             }
-        }).collect::<Vec<ArgData>>();
+        }).collect::<KernelResult<Vec<ArgData>>>()?;
 
-        let compiled_intro = CompiledIntro {
+        Ok(CompiledIntro {
             parent,
+            ty_idx,
             intro_name : intro_name.clone(),
             intro_arguments : arguments,
             intro_type : new_intro_type,
             raw_type : raw_intro_type.clone(),
             intro_arg_data : all_arg_infos,
-            intro_type_args : intro_type_args,
-        };
+            intro_type_args,
+        })
 
-        compiled_intro
+    }
 
+    pub fn ind_type(&self) -> &'p IndType {
+        &self.parent.types[self.ty_idx]
     }
 
     // Create a declaration's inductive hypotheses
-    pub fn ihs(&self, motive : &Expr) -> Vec<Expr> {
+    pub fn ihs(&self, motives : &[Expr]) -> KernelResult<Vec<Expr>> {
         self.intro_arguments.iter().zip(&self.intro_arg_data).filter_map(|(a, b)| {
             match b {
-                Right((v1, v2)) => {
+                Right((v1, v2, rec_ty_idx)) => {
                     let apps = a.fold_apps(v1);
-                    let motive_app = self.parent.mk_motive_app(&apps, &v2, &motive);
+                    let motive_app = self.parent.mk_motive_app(&apps, v2, &motives[*rec_ty_idx]);
                     let pis = motive_app.fold_pis(v1.iter());
-                    Some(mk_local(Name::from("ih"), pis, BinderStyle::Default))
+                    Some(pis.map(|pis| mk_local(Name::from("ih"), pis, BinderStyle::Default)))
                 },
                 _ => None
             }
         }).collect()
     }
 
-    pub fn mk_intro_minor_premise(&self, motive : &Expr) -> Expr {
+    pub fn mk_intro_minor_premise(&self, motives : &[Expr]) -> KernelResult<Expr> {
         let params_and_args = seq![self.parent.get_params(), &self.intro_arguments];
         let lhs_const = mk_const(self.intro_name.clone(), self.parent.get_univ_params().clone());
         let lhs_app = lhs_const.fold_apps(params_and_args.iter());
         let motive_app = self.parent.mk_motive_app(&lhs_app,
                                           &self.intro_type_args[self.parent.num_params..],
-                                          &motive);
-        let args_and_ihs = seq![&self.intro_arguments, self.ihs(motive)];
-        let pis = motive_app.fold_pis(args_and_ihs.iter());
+                                          &motives[self.ty_idx]);
+        let args_and_ihs = seq![&self.intro_arguments, self.ihs(motives)?];
+        let pis = motive_app.fold_pis(args_and_ihs.iter())?;
         let hypothesis_binding = Binding::mk(Name::from("h"), pis, BinderStyle::Default);
-        hypothesis_binding.as_local()
+        Ok(hypothesis_binding.as_local())
     }
 
 
     pub fn recursive_calls(&self, 
-                           motive : &Expr, 
+                           motives : &[Expr], 
                            minor_premises : &Vec<Expr>,
-                           elim_declar_name : &Name,
-                           elim_level_params : &Vec<Level>) -> Vec<Expr> {
+                           elim_names : &[Name],
+                           elim_level_params : &[Level]) -> KernelResult<Vec<Expr>> {
         let mut results_vec = Vec::with_capacity(self.intro_arguments.len().max(self.intro_arg_data.len()));
 
         for (rec_arg, x) in self.intro_arguments.clone().into_iter().zip(self.intro_arg_data.clone()) {
             match x {
-                Right((eps, rec_arg_indices)) => {
+                Right((eps, rec_arg_indices, rec_ty_idx)) => {
                     let apps_rhs = seq![self.parent.get_params(),
-                                        Some(motive),
+                                        motives,
                                         &minor_premises,
                                         &rec_arg_indices,
                                         Some(rec_arg.fold_apps(eps.iter()))];
-                    let apps_lhs = mk_const(elim_declar_name.clone(), elim_level_params.clone());
+                    let apps_lhs = mk_const(elim_names[rec_ty_idx].clone(), elim_level_params.to_vec());
                     let fold_result = apps_lhs.fold_apps(apps_rhs.iter());
-                    results_vec.push(fold_result.fold_lambdas(eps.iter()));
+                    results_vec.push(fold_result.fold_lambdas(eps.iter())?);
                 },
                 _ => continue
             }
         }
 
-        results_vec
+        Ok(results_vec)
     }
 
     // `intro_idx` is just the position of this particular intro 
@@ -420,15 +704,15 @@ impl<'p> CompiledIntro<'p> {
     pub fn mk_reduction_rule(&self, 
                              intro_minors_idx : usize, 
                              intro_minors : &Vec<Expr>, 
-                             motive : &Expr, 
-                             elim_declar_name : &Name, 
-                             elim_level_params : &Vec<Level>) -> ReductionRule {
+                             motives : &[Expr], 
+                             elim_names : &[Name], 
+                             elim_level_params : &[Level]) -> KernelResult<ReductionRule> {
         
 
         let rr_arg1 = seq![self.parent.get_params(),
-                           Some(motive),
+                           motives,
                            &intro_minors,
-                           &self.parent.get_indices(),
+                           &self.ind_type().indices,
                            &self.intro_arguments];
         let fold_initial_val = mk_const(self.intro_name.clone(),
                                               self.parent.get_univ_params().clone());
@@ -436,14 +720,14 @@ impl<'p> CompiledIntro<'p> {
         let tail_apps = fold_initial_val.fold_apps(fold_list.iter());
 
         let app_rhs = seq![self.parent.get_params(),
-                           Some(motive),
+                           motives,
                            &intro_minors,
-                           &self.parent.get_indices(),
+                           &self.ind_type().indices,
                            Some(tail_apps)];
-        let const_2 = mk_const(elim_declar_name.clone(), elim_level_params.clone());
+        let const_2 = mk_const(elim_names[self.ty_idx].clone(), elim_level_params.to_vec());
         let rr_arg2 = const_2.fold_apps(app_rhs.iter());
 
-        let rec_calls = self.recursive_calls(motive, intro_minors, elim_declar_name, elim_level_params);
+        let rec_calls = self.recursive_calls(motives, intro_minors, elim_names, elim_level_params)?;
 
         let rr_arg3 = intro_minors[intro_minors_idx].fold_apps(seq![&self.intro_arguments, rec_calls].iter());
 
@@ -455,27 +739,33 @@ impl<'p> CompiledIntro<'p> {
 
 
     // check an introduction rule
-    pub fn check_intro(&self, env : &Arc<RwLock<Env>>) {
-        assert!(self.intro_type_args.len() >= self.parent.num_params);
+    pub fn check_intro(&self) -> KernelResult<()> {
         let req_lhs_rhs = self.intro_type_args.iter().take(self.parent.num_params);
 
         let req_lhs = self.intro_type.fold_apps(req_lhs_rhs);
-        let req_rhs = self.parent.minimal_const.fold_apps(self.parent.get_params().into_iter());
-        self.parent.map_tc(|tc| tc.require_def_eq(&req_lhs, &req_rhs));
+        let req_rhs = self.ind_type().minimal_const.fold_apps(self.parent.get_params());
+        self.parent.map_tc(|tc| tc.require_def_eq(&req_lhs, &req_rhs))?;
+
+        // The indices are only ever compared, never recursed into, so
+        // they can't be made out of the types being declared.
+        if self.intro_type_args[self.parent.num_params..].iter().any(|idx| self.parent.has_ind_occ(idx)) {
+            let result = self.intro_type.fold_apps(self.intro_type_args.iter());
+            return Err(IndOccurrenceInIndex(self.intro_name.clone(), result))
+        }
 
         // ATTN
-        let mut tc0 = TypeChecker::new(None, env.clone());
+        let mut tc0 = self.parent.map_tc(|tc| tc.fork_local());
 
         for elem in self.intro_arg_data.iter() {
             match elem {
                 Left(e) => {
-                    let infd1 = tc0.infer(e);
-                    tc0.infer_universe_of_type(&infd1);
+                    let infd1 = tc0.infer(e)?;
+                    tc0.infer_universe_of_type(&infd1)?;
                 },
-                Right((eps, _)) => {
+                Right((eps, ..)) => {
                     for e in eps {
-                        let inferred = tc0.infer(e);
-                        tc0.infer_universe_of_type(&inferred);
+                        let inferred = tc0.infer(e)?;
+                        tc0.infer_universe_of_type(&inferred)?;
                     }
                 }
             }
@@ -483,11 +773,18 @@ impl<'p> CompiledIntro<'p> {
 
         if self.parent.codomain_sort.maybe_nonzero() {
             for arg in self.intro_arguments.iter() {
-                let inferred = self.parent.map_tc(|tc| tc.infer(arg));
-                let arg_level = self.parent.map_tc(|tc| tc.infer_universe_of_type(&inferred));
-                assert!(arg_level.leq(&self.parent.codomain_sort));
+                let inferred = self.parent.map_tc(|tc| tc.infer(arg))?;
+                let arg_level = self.parent.map_tc(|tc| tc.infer_universe_of_type(&inferred))?;
+                if !arg_level.leq(&self.parent.codomain_sort) {
+                    return Err(IntroUniverseTooBig { intro : self.intro_name.clone(), 
+                                                     arg : arg.clone(), 
+                                                     arg_level, 
+                                                     ind_level : self.parent.codomain_sort.clone() })
+                }
             }
         }
+
+        Ok(())
     }
 }
 
@@ -1,8 +1,12 @@
-use std::sync::Arc;
+use std::sync::{ Arc, OnceLock };
+use std::collections::BTreeMap;
+use std::hash::{ Hash, Hasher };
+use fxhash::hash64;
 use hashbrown::HashSet;
 
 use crate::name::Name;
 use crate::errors;
+use crate::hashcons::{ HashCons, ConsTable, intern, addr };
 
 use InnerLevel::*;
 
@@ -19,10 +23,27 @@ use InnerLevel::*;
 /// of an `IMax` resolves to `Zero`, the whole term resolves to `Zero`.
 /// This behavior has to do with correctly handling `Prop`, which you can read more
 /// about in 'Theorem Proving in Lean'
-#[derive(Clone, PartialEq, Eq, Hash)]
+///
+/// Levels are hash-consed (see `hashcons::HashCons`), so two levels are
+/// equal exactly when they point to the same `InnerLevel`.
+#[derive(Clone, PartialOrd, Ord)]
 pub struct Level(Arc<InnerLevel>);
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+impl PartialEq for Level {
+    fn eq(&self, other : &Level) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Level {}
+
+impl Hash for Level {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        self.as_ref().hash(state)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum InnerLevel {
     Zero,
     Succ (Level),
@@ -32,27 +53,27 @@ pub enum InnerLevel {
 }
 
 pub fn mk_zero() -> Level {
-    Level(Arc::new(InnerLevel::Zero))
+    Level::from(InnerLevel::Zero)
 }
 
 pub fn mk_max(lhs : Level, rhs : Level) -> Level {
-    Level(Arc::new(Max(lhs, rhs)))
+    Level::from(Max(lhs, rhs))
 }
 
 pub fn mk_imax(lhs : Level, rhs : Level) -> Level {
-    Level(Arc::new(IMax(lhs, rhs)))
+    Level::from(IMax(lhs, rhs))
 }
 
 pub fn mk_imax_refs(lhs : &Level, rhs : &Level) -> Level {
-    Level(Arc::new(IMax(lhs.clone(), rhs.clone())))
+    Level::from(IMax(lhs.clone(), rhs.clone()))
 }
 
 pub fn mk_param(n : impl Into<Name>) -> Level {
-    Level(Arc::new(Param(n.into())))
+    Level::from(Param(n.into()))
 }
 
 pub fn mk_succ(l : Level) -> Level {
-    Level(Arc::new(Succ(l)))
+    Level::from(Succ(l))
 }
 
 impl Level {
@@ -64,17 +85,11 @@ impl Level {
     }
 
     pub fn is_param(&self) -> bool {
-        match self.as_ref() {
-            Param(..) => true,
-            _         => false
-        }
+        matches!(self.as_ref(), Param(..))
     }
 
     pub fn is_any_max(&self) -> bool {
-        match self.as_ref() {
-            Max(..) | IMax(..) => true,
-            _                  => false
-        }
+        matches!(self.as_ref(), Max(..) | IMax(..))
     }
 
     /// A non-naive way of combining two `Level` values (naive would be just 
@@ -89,25 +104,114 @@ impl Level {
         }
     }
 
-    /// Brief simplification procedure mostly aimed at simplifying IMax terms 
-    /// (the rule about an IMax with a right hand side of Zero becoming Zero 
-    /// is enforced here).
+    /// Simplification procedure mostly aimed at getting rid of IMax terms.
+    /// An IMax whose right hand side simplifies to Zero becomes Zero, and
+    /// one whose right hand side can never be zero becomes a Max. In a
+    /// simplified level, an IMax is only left behind if whether it's a Max
+    /// or Zero depends on what its parameters get instantiated as.
     pub fn simplify(&self) -> Level {
         match self.as_ref() {
             Zero | Param(..) => self.clone(),
             Succ(lvl)        => mk_succ(lvl.simplify()),
-            Max(a, b)        => mk_max(a.simplify(), b.simplify()),
+            Max(a, b)        => a.simplify().combining(&b.simplify()),
             IMax(a, b)       => {
                 let b_prime = b.simplify();
-                match b_prime.as_ref() {
-                    Zero        => mk_zero(), 
-                    Succ(..)    => a.simplify().combining(&b_prime),
-                    _ => mk_imax(a.simplify(), b_prime)
+                if let Zero = b_prime.as_ref() {
+                    mk_zero()
+                } else if b_prime.never_zero() {
+                    a.simplify().combining(&b_prime)
+                } else {
+                    let a_prime = a.simplify();
+                    if let Zero = a_prime.as_ref() {
+                        b_prime
+                    } else {
+                        mk_imax(a_prime, b_prime)
+                    }
                 }
             }
         }
     }
 
+    /// For a simplified level, whether it's nonzero no matter what its
+    /// parameters are instantiated as. A simplified level that's always
+    /// zero is just `Zero`, so these two cover the levels whose value
+    /// doesn't depend on parameters being zero.
+    fn never_zero(&self) -> bool {
+        match self.as_ref() {
+            Zero | Param(..) => false,
+            Succ(..)         => true,
+            Max(a, b)        => a.never_zero() || b.never_zero(),
+            IMax(_, b)       => b.never_zero(),
+        }
+    }
+
+    /// For a simplified level, a parameter that decides whether one of
+    /// its IMax terms is a Max or Zero. Instantiating it as `Zero` or
+    /// `Succ` of itself gets rid of at least one IMax.
+    fn imax_split_param(&self) -> Option<&Level> {
+        match self.as_ref() {
+            Zero | Param(..) => None,
+            Succ(lvl)        => lvl.imax_split_param(),
+            Max(a, b)        => a.imax_split_param().or_else(|| b.imax_split_param()),
+            IMax(a, b)       => b.deciding_param()
+                                 .or_else(|| a.imax_split_param())
+                                 .or_else(|| b.imax_split_param())
+        }
+    }
+
+    /// A parameter that has to be zero for `self` to be zero.
+    fn deciding_param(&self) -> Option<&Level> {
+        match self.as_ref() {
+            Zero | Succ(..) => None,
+            Param(..)       => Some(self),
+            Max(a, b)       => a.deciding_param().or_else(|| b.deciding_param()),
+            IMax(_, b)      => b.deciding_param(),
+        }
+    }
+
+    /// Flattens a simplified level into the form
+    /// `max(k, Succ^n1(A1), .., Succ^nm(Am))`, returning `k` and the
+    /// biggest offset `n` for each atom `A` (a parameter, or an IMax that
+    /// couldn't be simplified away). The atoms are kept in order, so two
+    /// levels with no IMax in them are equal for every instantiation of
+    /// their parameters exactly when their offsets are.
+    fn offsets(&self) -> (usize, BTreeMap<Level, usize>) {
+        fn go(lvl : &Level, succs : usize, k : &mut usize, acc : &mut BTreeMap<Level, usize>) {
+            match lvl.as_ref() {
+                Zero      => *k = (*k).max(succs),
+                Succ(x)   => go(x, succs + 1, k, acc),
+                Max(a, b) => {
+                    go(a, succs, k, acc);
+                    go(b, succs, k, acc);
+                },
+                Param(..) | IMax(..) => {
+                    let n = acc.entry(lvl.clone()).or_insert(succs);
+                    *n = (*n).max(succs);
+                }
+            }
+        }
+
+        let (mut k, mut acc) = (0usize, BTreeMap::new());
+        go(self, 0, &mut k, &mut acc);
+        (k, acc)
+    }
+
+    /// The normal form of a level; a right-nested `Max` of the terms in
+    /// `offsets`, with the constant first (if it isn't already implied
+    /// by one of the other terms) and the atoms sorted. Levels without IMax
+    /// terms are equivalent exactly when their normal forms are equal.
+    pub fn normalize(&self) -> Level {
+        let (k, offsets) = self.simplify().offsets();
+        let succs = |n : usize, lvl : Level| (0..n).fold(lvl, |acc, _| mk_succ(acc));
+        let mut terms = Vec::with_capacity(offsets.len() + 1);
+        if offsets.values().all(|n| *n < k) {
+            terms.push(succs(k, mk_zero()));
+        }
+        terms.extend(offsets.into_iter().map(|(atom, n)| succs(n, atom)));
+        let last = terms.pop().unwrap_or_else(mk_zero);
+        terms.into_iter().rev().fold(last, |acc, t| mk_max(t, acc))
+    }
+
     /// Given a `Level` `L`, and a mapping of `Level::Param |-> Level` `M`, traverse 
     /// `L` and execute :
     ///  for each node `n` in `L`
@@ -137,111 +241,40 @@ impl Level {
 
 
 
-    /// This is used in `leq_core` to handle the case where one of the levels in question 
-    /// is an IMax whose right hand side is some paramter `P`. In light of the 
-    /// special behvior of the right hand side of an IMax term, we need to essentially 
-    /// do case analysis on our terms before we can say for sure whether lhs <= rhs.
-    /// The cases we need to consider are :
-    /// 1. `P` will eventually be instantiated as `Zero`
-    /// 2. `P` will eventually be instantiated as some non-zero level.
-    /// 
-    /// So, we create two substitutions, and check that `leq` is true for both.
+    /// Determines whether for two levels `L1` and `L2`, `L1 <= L2` no
+    /// matter how their parameters get instantiated, using Lean's
+    /// definition of order on universes, not Rust's definition of order
+    /// on `Level` terms.
+    ///
+    /// If either side has an IMax that can't be simplified away, we split
+    /// on a parameter `P` it depends on; `P` is either zero, or the
+    /// successor of something, so we check both cases :
     ///```pseudo
     /// let (lhs', rhs') = (lhs[Zero/P], rhs[Zero/P])
     /// let (lhs'', rhs'') = (lhs[Succ(P)/P], rhs[Succ(P)/P])
     /// return (lhs' ≤ rhs') && (lhs'' ≤ rhs'')
     ///```
-    pub fn ensure_imax_leq(&self, lhs : &Level, rhs : &Level, diff : i32) -> bool {
-        assert!(self.is_param());
-
-        let zero_map =  vec![(self.clone(), mk_zero())];
-        let nonzero_map = vec![(self.clone(), mk_succ(self.clone()))];
-
-
-        let closure = |subst : &Vec<(Level, Level)>, left : &Level, right : &Level| {
-            let left_prime  = left.instantiate_lvl(subst).simplify();
-            let right_prime = right.instantiate_lvl(subst).simplify();
-            left_prime.leq_core(&right_prime, diff)
-        };
-
-        closure(&zero_map, lhs, rhs)
-        &&
-        closure(&nonzero_map, lhs, rhs)
-    }
-
-    /// Essentially just a big analysis of different cases to determine (in the 
-    /// presence of variables and IMax's weirdness) whether the left hand side 
-    /// is less than or equal to the right hand side (using the ordering specific to 
-    /// Lean's sort terms, not the `Ord` instance Rust would use). 
-    /// `diff` is just a way of tracking applications of `Succ(x)` as we unroll 
-    /// both sides in each recursive call.
-    pub fn leq_core(&self, other : &Level, diff : i32) -> bool {
-
-        match (self.as_ref(), other.as_ref()) {
-            (Zero, _) if diff >= 0             => true,
-            (_, Zero) if diff < 0              => false,
-            (Param(a), Param(x))               => a == x && diff >= 0,
-            (Param(..), Zero)                  => false,
-            (Zero, Param(..))                  => diff >= 0,
-
-            (Succ(s), _)                       => s.leq_core(other, diff - 1),
-            (_, Succ(s))                       => self.leq_core(s, diff + 1),
-
-            (Max(a, b), _)                     => a.leq_core(other, diff) 
-                                               && b.leq_core(other, diff),
-
-            (Param(..), Max(x, y))             => self.leq_core(x, diff)
-                                               || self.leq_core(y, diff),
-
-            (Zero, Max(x, y))                  => self.leq_core(x, diff)
-                                               || self.leq_core(y, diff),
-
-            (IMax(a, b), IMax(x, y)) if a == x 
-                                     && b == y => true,
-
-            (IMax(.., b), _) if b.is_param()   => b.ensure_imax_leq(self, other, diff),
-
-            (_, IMax(.., y)) if y.is_param()   => y.ensure_imax_leq(self, other, diff),
-
-            (IMax(a, b), _) if b.is_any_max()  => match b.as_ref() {
-                IMax(x, y) => {
-                    let new_max = mk_max(mk_imax_refs(a, y), 
-                                         mk_imax_refs(x, y));
-                    Level::leq_core(&new_max, other, diff)
-                },
-
-                Max(x, y) => {
-                    let new_max = mk_max(mk_imax_refs(a, x), 
-                                         mk_imax_refs(a, y)).simplify();
-                    Level::leq_core(&new_max, other, diff)
-
-                },
-                _ => unreachable!(),
-            }
-
-            (_, IMax(x, y)) if y.is_any_max()  => match y.as_ref() {
-                IMax(j, k) => {
-                    let new_max = mk_max(mk_imax_refs(x, k),
-                                         mk_imax_refs(j, k));
-                    self.leq_core(&new_max, diff)
-                },
-                Max(j, k) => {
-                    let new_max = mk_max(mk_imax_refs(x, j), 
-                                         mk_imax_refs(x, k)).simplify();
-                    self.leq_core(&new_max, diff)
-                },
-                _ => unreachable!(),
+    /// Once there are no IMax terms left, `max(k, P1 + n1, ..)` is less than
+    /// or equal to `max(k', Q1 + m1, ..)` if `k` is at most the smallest the
+    /// right hand side can be (which is the biggest of `k'` and the `m`s),
+    /// and each `P + n` on the left has a `P + m` on the right with `n <= m`.
+    pub fn leq(&self, other : &Level) -> bool {
+        let (lhs, rhs) = (self.simplify(), other.simplify());
+        match lhs.imax_split_param().or_else(|| rhs.imax_split_param()).cloned() {
+            Some(p) => {
+                [mk_zero(), mk_succ(p.clone())].iter().all(|case| {
+                    let subst = vec![(p.clone(), case.clone())];
+                    lhs.instantiate_lvl(&subst).leq(&rhs.instantiate_lvl(&subst))
+                })
+            },
+            None => {
+                let (k1, offsets1) = lhs.offsets();
+                let (k2, offsets2) = rhs.offsets();
+                k1 <= offsets2.values().copied().fold(k2, usize::max)
+                && offsets1.iter().all(|(atom, n)| offsets2.get(atom).map(|m| n <= m).unwrap_or(false))
             }
-            _ => unreachable!()
         }
     }
-    
-    /// Outward-facing function that uses `leq_core` to determine whether for two 
-    /// levels `L1` and `L2`, `L1 <= L2` using Lean's definition of order on 
-    /// universes, not Rust's definition of order on `Level` terms.
-    pub fn leq(&self, other : &Level) -> bool {
-        self.simplify().leq_core(&other.simplify(), 0)
-    }
 
     /// Uses antisymmetry to determine whether two levels are equal (according 
     /// to Lean's rules for sorts)
@@ -249,10 +282,7 @@ impl Level {
     ///(x ≤ y ∧ y ≤ x) → x = y
     ///```
     pub fn eq_by_antisymm(&self, other : &Level) -> bool {
-        let l1 = self.simplify();
-        let l2 = other.simplify();
-        
-        l1.leq_core(&l2, 0) && l2.leq_core(&l1, 0)
+        self == other || (self.leq(other) && other.leq(self))
     }
 
     /// There is no level strictly less than Zero, so for any level `L`, if `L` is 
@@ -294,7 +324,7 @@ impl Level {
 }
 
 
-pub fn unique_univ_params<'l, 's>(lvl : &'l Level) -> HashSet<&'l Level> {
+pub fn unique_univ_params<'l>(lvl : &'l Level) -> HashSet<&'l Level> {
     let mut acc = HashSet::with_capacity(40);
     unique_univ_params_core(lvl, &mut acc);
     acc
@@ -327,13 +357,30 @@ impl std::convert::AsRef<InnerLevel> for Level {
 
 impl From<Arc<InnerLevel>> for Level {
     fn from(x : Arc<InnerLevel>) -> Level {
-        Level(x)
+        Level::from(x.as_ref().clone())
     }
 }
 
 impl From<InnerLevel> for Level {
     fn from(x : InnerLevel) -> Level {
-        Level(Arc::new(x))
+        Level(intern(x))
+    }
+}
+
+impl HashCons for InnerLevel {
+    fn cons_hash(&self) -> u64 {
+        match self {
+            Zero => hash64(&0u8),
+            Succ(l) => hash64(&(1u8, addr(&l.0))),
+            Max(l, r) => hash64(&(2u8, addr(&l.0), addr(&r.0))),
+            IMax(l, r) => hash64(&(3u8, addr(&l.0), addr(&r.0))),
+            Param(n) => hash64(&(4u8, n.addr())),
+        }
+    }
+
+    fn table() -> &'static ConsTable<InnerLevel> {
+        static TABLE : OnceLock<ConsTable<InnerLevel>> = OnceLock::new();
+        TABLE.get_or_init(ConsTable::new)
     }
 }
 
@@ -370,3 +417,78 @@ impl std::fmt::Debug for InnerLevel {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn one() -> Level {
+        mk_succ(mk_zero())
+    }
+
+    #[test]
+    fn param_leq_imax() {
+        let (u, v, w) = (mk_param("u"), mk_param("v"), mk_param("w"));
+        // imax u (v + 1) is max u (v + 1).
+        assert!(u.leq(&mk_imax(u.clone(), mk_succ(v.clone()))));
+        assert!(!u.leq(&mk_imax(v.clone(), mk_succ(w.clone()))));
+        // imax v 0 is 0.
+        assert!(!u.leq(&mk_imax(v.clone(), mk_zero())));
+        assert!(mk_imax(v.clone(), mk_zero()).leq(&u));
+        // imax v (imax w u) is at least u, but imax u (imax v w) is 0 when w is.
+        assert!(u.leq(&mk_imax(v.clone(), mk_imax(w.clone(), u.clone()))));
+        assert!(!u.leq(&mk_imax(u.clone(), mk_imax(v.clone(), w.clone()))));
+    }
+
+    #[test]
+    fn nested_imax() {
+        let (u, v, w) = (mk_param("u"), mk_param("v"), mk_param("w"));
+        let nested = mk_imax(u.clone(), mk_imax(v.clone(), w.clone()));
+        let split = mk_max(mk_imax(u.clone(), w.clone()), mk_imax(v.clone(), w.clone()));
+        assert!(nested.eq_by_antisymm(&split));
+        let maxed = mk_max(u.clone(), mk_max(v.clone(), w.clone()));
+        assert!(nested.leq(&maxed));
+        assert!(!maxed.leq(&nested));
+        let other_nesting = mk_imax(mk_imax(u.clone(), v.clone()), w.clone());
+        assert!(!nested.eq_by_antisymm(&other_nesting));
+        assert!(other_nesting.leq(&nested));
+    }
+
+    #[test]
+    fn imax_after_renaming() {
+        let (u, v) = (mk_param("u"), mk_param("v"));
+        let (a, b) = (mk_param("a"), mk_param("b"));
+        let lhs = mk_max(one(), mk_imax(u.clone(), v.clone()));
+        let renamed = lhs.instantiate_lvl(&vec![(u, a.clone()), (v, b.clone())]);
+        let rhs = mk_max(one(), mk_imax(a.clone(), b.clone()));
+        // Check both directions by hand, since `eq_by_antisymm` stops at `==`.
+        assert!(renamed.leq(&rhs) && rhs.leq(&renamed));
+        let swapped = mk_max(mk_imax(a.clone(), b.clone()), one());
+        assert!(swapped.leq(&rhs) && rhs.leq(&swapped));
+        let wrong = mk_max(one(), mk_imax(b, a));
+        assert!(!rhs.eq_by_antisymm(&wrong));
+    }
+
+    #[test]
+    fn equal_after_normalizing() {
+        let (u, v) = (mk_param("u"), mk_param("v"));
+        let pairs = vec![
+            (mk_max(u.clone(), mk_succ(u.clone())), mk_succ(u.clone())),
+            (mk_max(one(), mk_succ(u.clone())), mk_succ(u.clone())),
+            (mk_max(u.clone(), mk_max(v.clone(), u.clone())), mk_max(v.clone(), u.clone())),
+            (mk_imax(u.clone(), mk_succ(v.clone())), mk_max(mk_succ(v.clone()), u.clone())),
+            (mk_succ(mk_max(u.clone(), v.clone())), mk_max(mk_succ(v.clone()), mk_succ(u.clone()))),
+        ];
+        for (lhs, rhs) in pairs {
+            assert_ne!(lhs, rhs);
+            assert!(lhs.eq_by_antisymm(&rhs), "{:?} = {:?}", lhs, rhs);
+            assert_eq!(lhs.normalize(), rhs.normalize());
+        }
+        // An IMax that can't be simplified away is left in the normal
+        // form, so this one only comes out equal after splitting on `u`.
+        let imax_u_u = mk_imax(u.clone(), u.clone());
+        assert!(imax_u_u.eq_by_antisymm(&u));
+        assert_ne!(imax_u_u.normalize(), u.normalize());
+        assert!(!mk_max(u.clone(), one()).eq_by_antisymm(&mk_succ(u)));
+    }
+}
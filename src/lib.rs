@@ -0,0 +1,388 @@
+#![forbid(unsafe_code)]
+#![allow(unused_parens)]
+#![allow(non_snake_case)]
+// Style lints tripped all over code that predates clippy.
+#![allow(clippy::needless_return,
+         clippy::redundant_field_names,
+         clippy::needless_lifetimes,
+         clippy::needless_borrow,
+         clippy::into_iter_on_ref)]
+
+//! nanoda is a type checker for the Lean theorem prover's export format.
+//! The library exposes the same checking pipeline used by the `nanoda`
+//! binary, so other programs can check an export without shelling out.
+//! The main entry point is `check_export`, which parses an export file's
+//! contents, adds each item to a fresh environment, and type checks it.
+//!
+//!```pseudo
+//! let options = CheckOptions::new(4);
+//! let report = check_export(source, &options)?;
+//! println!("checked {} items", report.num_checked);
+//!```
+
+use std::sync::Arc;
+use std::path::PathBuf;
+
+use crossbeam_utils::thread;
+use parking_lot::RwLock;
+
+use crate::env::Env;
+use crate::errors::DeclarError;
+use crate::summary::{ Summary, CheckSummary };
+use crate::parser::{ parse_export, ExportFormat, ParseErr };
+use crate::env::CompiledModification;
+use crate::scheduler::Scheduler;
+use crate::incremental::ResultCache;
+use crate::utils::{ Either::*, WorkQueue, ModQueue, END_MSG_ADD };
+
+pub mod utils;
+pub mod hashcons;
+pub mod append_map;
+pub mod errors;
+pub mod name;
+pub mod level;
+pub mod expr;
+pub mod reduction;
+pub mod tc;
+pub mod env;
+pub mod quot;
+pub mod inductive;
+pub mod nested;
+pub mod nat;
+pub mod string;
+pub mod parser;
+pub mod parser4;
+pub mod pretty;
+pub mod summary;
+pub mod scheduler;
+pub mod axioms;
+pub mod incremental;
+
+// By default, make the 'modifications' hashmap large enough to accomodate
+// core + ~2000 items (core is about 9000 items). If the passed export file
+// has more modifications, the hashmap will just resize, but that's a
+// (relatively) costly operation.
+pub const EXPECTED_NUM_MODS : usize = 11_000;
+
+/// A rough (and pessimistic) guess at the memory taken up by one entry in
+/// one of a type checker's caches, counting the terms it keeps alive.
+pub const CACHE_ENTRY_BYTES : usize = 1 << 10;
+/// Under a memory budget, caches still get at least this many entries.
+pub const MIN_CACHE_LIMIT : usize = 1 << 10;
+
+/// Settings for a single call to `check_export`. `num_threads` of
+/// 0 or 1 checks in serial; anything else spawns that many worker
+/// threads, with parsing and adding each getting their own thread.
+/// With `keep_going` set, a bad item doesn't stop the run; it gets
+/// recorded (along with everything that depends on it) in the
+/// `Report`'s summary instead. `format` is the export format to read;
+/// with `None`, it's detected from the file (see `ExportFormat::detect`).
+/// `guess_theorems` only matters for Lean 3 exports, which don't mark
+/// theorems as such; see `parser::parse_export`.
+/// `memory_budget` (in bytes) turns on memory-bounded checking : the
+/// parser lets go of each expression once nothing later in the file
+/// needs it, and the type checkers' caches are kept under
+/// `cache_limit` entries. It's a target rather than a hard limit; the
+/// environment itself takes what it takes.
+/// `result_cache` is the path of a result cache (see
+/// `incremental::ResultCache`); items that passed in an earlier run and
+/// haven't changed since are added without being checked, and the cache
+/// is rewritten at the end. `incremental::cache_path_for` gives the
+/// usual place for an export's cache.
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    pub num_threads : usize,
+    pub keep_going : bool,
+    pub format : Option<ExportFormat>,
+    pub guess_theorems : bool,
+    pub memory_budget : Option<usize>,
+    pub result_cache : Option<PathBuf>,
+}
+
+impl CheckOptions {
+    pub fn new(num_threads : usize) -> Self {
+        CheckOptions {
+            num_threads,
+            keep_going : false,
+            format : None,
+            guess_theorems : false,
+            memory_budget : None,
+            result_cache : None,
+        }
+    }
+
+    /// How many entries each cache of each type checker can hold under
+    /// the memory budget. A quarter of the budget is shared out between
+    /// the (four) caches of every thread's checker, and the rest is left
+    /// for the parser and the environment.
+    pub fn cache_limit(&self) -> Option<usize> {
+        self.memory_budget.map(|budget| {
+            let num_caches = 4 * self.num_threads.max(1);
+            (budget / 4 / num_caches / CACHE_ENTRY_BYTES).max(MIN_CACHE_LIMIT)
+        })
+    }
+}
+
+impl std::default::Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions::new(4)
+    }
+}
+
+/// The result of successfully checking an export file. `env` is the
+/// environment built up while checking, which callers can use to
+/// look declarations up or pretty print them. `summary` is only
+/// filled in when checking with `keep_going`. `num_unchanged` is how
+/// many of the `num_checked` declarations were skipped because they
+/// hadn't changed since the run that wrote the result cache.
+pub struct Report {
+    pub num_checked : usize,
+    pub num_unchanged : usize,
+    pub env : Arc<Env>,
+    pub summary : Option<CheckSummary>,
+}
+
+/// Failures that stop `check_export` before it produces a `Report`.
+#[derive(Debug, Clone)]
+pub enum CheckError {
+    Parse(ParseErr),
+    Kernel(DeclarError),
+    ThreadPanic,
+    /// The result cache at this path couldn't be written.
+    ResultCache(PathBuf, String),
+}
+
+impl std::fmt::Display for CheckError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CheckError::Parse(e) => write!(f, "failed to parse export file : {}", e),
+            CheckError::Kernel(e) => write!(f, "{}", e),
+            CheckError::ThreadPanic => write!(f, "a worker thread panicked while checking the export file"),
+            CheckError::ResultCache(path, e) => write!(f, "couldn't write the result cache {} : {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+impl From<ParseErr> for CheckError {
+    fn from(e : ParseErr) -> CheckError {
+        CheckError::Parse(e)
+    }
+}
+
+impl From<DeclarError> for CheckError {
+    fn from(e : DeclarError) -> CheckError {
+        CheckError::Kernel(e)
+    }
+}
+
+/// Parse, add, and type check every item in `source`, which should
+/// be the full contents of a Lean export file.
+pub fn check_export(source : String, options : &CheckOptions) -> Result<Report, CheckError> {
+    let summary = if options.keep_going {
+        Some(RwLock::new(Summary::new()))
+    } else {
+        None
+    };
+
+    let cache = options.result_cache.clone().map(ResultCache::load);
+
+    let checked = match options.num_threads {
+        0 | 1 => check_serial(source, options, summary.as_ref(), cache.as_ref()),
+        owise => check_parallel(source, options, owise, summary.as_ref(), cache.as_ref())
+    };
+
+    // Whatever passed is worth remembering even if the run failed, but
+    // the reason it failed matters more than the cache.
+    let saved = match cache.as_ref() {
+        Some(cache) => cache.save().map_err(|e| CheckError::ResultCache(cache.path().to_path_buf(), e.to_string())),
+        None => Ok(())
+    };
+    let env = checked?;
+    saved?;
+
+    let num_checked = env.num_declars();
+    let num_unchanged = cache.map(|cache| cache.num_unchanged()).unwrap_or(0);
+    let summary = summary.map(|s| s.into_inner().finish());
+    Ok(Report { num_checked, num_unchanged, env, summary })
+}
+
+fn check_serial(source : String,
+                options : &CheckOptions,
+                summary : Option<&RwLock<Summary>>,
+                cache : Option<&ResultCache>) -> Result<Arc<Env>, CheckError> {
+    let env = Arc::new(Env::new(EXPECTED_NUM_MODS).with_cache_limit(options.cache_limit()));
+    let add_queue = WorkQueue::with_capacity(EXPECTED_NUM_MODS);
+    let scheduler = Scheduler::new(1);
+
+    parse_export(source, options.format, options.guess_theorems, options.memory_budget.is_some(), &add_queue, &env)?;
+
+    loop_add(&add_queue, &scheduler, 0, &env, summary, cache)?;
+    loop_check(&scheduler, 0, &env, summary, cache)?;
+
+    Ok(env)
+}
+
+fn check_parallel(source : String, 
+                  options : &CheckOptions,
+                  num_threads : usize, 
+                  summary : Option<&RwLock<Summary>>,
+                  cache : Option<&ResultCache>) -> Result<Arc<Env>, CheckError> {
+    let env = Arc::new(Env::new(EXPECTED_NUM_MODS).with_cache_limit(options.cache_limit()));
+    let add_queue = WorkQueue::with_capacity(EXPECTED_NUM_MODS);
+    let scheduler = Scheduler::new(num_threads);
+
+    let scope_ = thread::scope(|s| {
+
+        let mut thread_holder = Vec::with_capacity(num_threads);
+
+        // add and parse can be done separately/concurrently, but both MUST be done
+        // in order. So, when parsing ends, that thread goes immediately to
+        // the check pool instead of adding.
+        let parse_thread = s.builder().stack_size(8388608).spawn(|_| {
+            let parsed = parse_export(source, options.format, options.guess_theorems, options.memory_budget.is_some(), &add_queue, &env);
+            // If parsing failed, the adder is still waiting on the add queue,
+            // so let it know there's nothing more coming.
+            if parsed.is_err() {
+                add_queue.push(END_MSG_ADD);
+            }
+            let checked = loop_check(&scheduler, 0, &env, summary, cache);
+            parsed.map_err(CheckError::from).and(checked.map_err(CheckError::from))
+        }).expect("Failed to spawn scoped thread!");
+
+
+        thread_holder.push(s.spawn(|_s| {
+            loop_add(&add_queue, &scheduler, 1, &env, summary, cache)?;
+            loop_check(&scheduler, 1, &env, summary, cache)
+        }));
+
+        // We spawn (num_threads - 2) checker threads here since
+        // parser and adder will check when they're done.
+        let (scheduler, env) = (&scheduler, &env);
+        for worker in 2..num_threads {
+            thread_holder.push(s.spawn(move |_s| {
+                loop_check(scheduler, worker, env, summary, cache)
+            }));
+        }
+
+        let mut result = parse_thread.join().unwrap_or(Err(CheckError::ThreadPanic));
+
+        for t in thread_holder {
+            let checked = match t.join() {
+                Ok(checked) => checked.map_err(CheckError::from),
+                Err(_) => Err(CheckError::ThreadPanic)
+            };
+            result = result.and(checked);
+        }
+
+        result
+    });
+
+    match scope_ {
+        Ok(result) => result?,
+        Err(_) => return Err(CheckError::ThreadPanic)
+    }
+
+    Ok(env)
+}
+
+
+// Take items off the `add_queue` (blocking until there's one) and
+// add them, in order. A Left(Mod) gets compiled and added, and then
+// handed to the scheduler to be checked once everything it mentions
+// has been added; `worker` is the index this thread goes on to check
+// as, so the items land on its deque. Right(..) means adding is finished, so we close the
+// scheduler and this thread goes to work checking.
+// If an item fails to compile, we stop adding and send the checkers
+// home early, since everything after it may depend on it. With a
+// `summary` (keep going mode), we record the failure and move on
+// instead, skipping anything that depends on an item that failed.
+// With a result `cache`, an item that passed last time and hasn't
+// changed is added, but never handed to the scheduler.
+pub fn loop_add(add_queue : &ModQueue,
+                scheduler : &Scheduler<CompiledModification>,
+                worker : usize,
+                env : &Arc<Env>,
+                summary : Option<&RwLock<Summary>>,
+                cache : Option<&ResultCache>) -> Result<(), DeclarError> {
+    loop {
+        match add_queue.pop() {
+            Left(elem) => {
+                if let Some(summary) = summary {
+                    let mut guard = summary.write();
+                    guard.record_mod(&elem);
+                    let name = elem.get_name();
+                    if guard.has_failed_dependency(&name) {
+                        guard.record_skip(&name);
+                        continue
+                    }
+                }
+
+                let deps = elem.used_names();
+                let added = elem.compile(env).and_then(|compiled| {
+                    compiled.add_only(env).map(|_| compiled)
+                });
+
+                let compiled = match (added, summary) {
+                    (Ok(compiled), _) => compiled,
+                    (Err(e), Some(summary)) => {
+                        summary.write().record_failure(e);
+                        continue
+                    },
+                    (Err(e), None) => {
+                        scheduler.close(worker);
+                        return Err(e)
+                    }
+                };
+                for name in compiled.declared_names() {
+                    scheduler.mark_added(worker, &name);
+                }
+                if let Some(true) = cache.map(|cache| cache.register(&compiled, env)) {
+                    continue
+                }
+                scheduler.submit(worker, compiled, deps);
+            },
+            Right(_) => {
+                scheduler.close(worker);
+                return Ok(())
+            },
+        }
+    }
+}
+
+// Take items from the scheduler and check them until it's closed and
+// out of work; `worker` says which of the scheduler's deques to look
+// in first. A checker that finds a bad item stops and returns the
+// error, unless there's a `summary` to record it in. Items that pass
+// are recorded in the result `cache`, if there is one.
+pub fn loop_check(scheduler : &Scheduler<CompiledModification>,
+                  worker : usize,
+                  env : &Arc<Env>,
+                  summary : Option<&RwLock<Summary>>,
+                  cache : Option<&ResultCache>) -> Result<(), DeclarError> {
+    while let Some(elem) = scheduler.take(worker) {
+        let passed = match summary {
+            None => {
+                elem.check_only(env)?;
+                true
+            },
+            Some(summary) => {
+                let name = elem.get_name();
+                if summary.read().has_failed_dependency(&name) {
+                    summary.write().record_skip(&name);
+                    false
+                } else if let Err(e) = elem.check_only(env) {
+                    summary.write().record_failure(e);
+                    false
+                } else {
+                    true
+                }
+            }
+        };
+        if let (true, Some(cache)) = (passed, cache) {
+            cache.record_pass(&elem);
+        }
+    }
+    Ok(())
+}
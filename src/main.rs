@@ -2,47 +2,23 @@
 #![allow(unused_parens)]
 #![allow(non_snake_case)]
 
-use std::sync::Arc;
 use std::time::SystemTime;
 
-use crossbeam_utils::thread;
-
-use parking_lot::RwLock;
-
 use structopt::StructOpt;
 
-use crate::env::Env;
-use crate::parser::LineParser;
-use crate::utils::{ Either::*, RwQueue, ModQueue, CompiledQueue, END_MSG_CHK };
-use crate::cli::{ Opt, pp_bundle };
-
-pub mod utils;
-pub mod errors;
-pub mod name;
-pub mod level;
-pub mod expr;
-pub mod reduction;
-pub mod tc;
-pub mod env;
-pub mod quot;
-pub mod inductive;
-pub mod parser;
-pub mod pretty;
-pub mod cli;
+use nanoda::{ check_export, CheckOptions };
+use nanoda::utils::peak_memory;
+use crate::cli::{ Opt, pp_bundle, print_axiom_report };
+
+mod cli;
 
 
 #[cfg(feature = "mimalloc")]
 #[global_allocator]
 static GLOBAL: mimallocator::Mimalloc = mimallocator::Mimalloc;
 
-// By default, make the 'modifications' hashmap large enough to accomodate
-// core + ~2000 items (core is about 9000 items). If the passed export file
-// has more modifications, the hashmap will just resize, but that's a
-// (relatively) costly operation.
-pub const EXPECTED_NUM_MODS : usize = 11_000;
-
 pub const WARN_OUTDATED_HEADER : &str = "\n\n    !! This repository has been archived!!";
-pub const WARN_OUTDATED : &str = 
+pub const WARN_OUTDATED : &str =
     "\n
     It is out of date and no longer updated or maintained.
     Current development is continuing in the form of `nanoda_lib` :
@@ -68,141 +44,104 @@ fn main() {
 
     let export_file_strings = match opt.try_read_files() {
         Ok(strings) => strings,
-        Err(e) => errors::export_file_parse_err(line!(), e)
+        Err(e) => {
+            eprintln!("failed to read at least one of the specified export files. Please check that the file exists at the specified path. Error details : {}\n", e);
+            std::process::exit(-1)
+        }
+    };
+
+    let axiom_targets = match opt.axiom_targets().transpose() {
+        Ok(targets) => targets,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(-1)
+        }
     };
 
     let start_instant = SystemTime::now();
+    let check_options = CheckOptions {
+        keep_going : opt.keep_going,
+        format : opt.format,
+        guess_theorems : opt.guess_theorems,
+        memory_budget : opt.memory_budget.map(|mib| mib << 20),
+        ..CheckOptions::new(opt.num_threads as usize)
+    };
 
     let mut num_checked = 0usize;
-    match opt.num_threads {
-        0 | 1 => for s in export_file_strings {
-            num_checked += check_serial(s, opt.print);
-        }
-        owise => for s in export_file_strings {
-            num_checked += check_parallel(s, owise as usize, opt.print)
-        }
-    }
-
-    match start_instant.elapsed() {
-        Ok(dur) => println!("\n### Finished checking {} items in {:?}; to the best \
-                               of our knowledge, all terms were well-typed! ###\n", num_checked, dur),
-        Err(e)  => println!("\n### Finished checking {} items; to the best of our \
-                               knowledge, all terms were well-typed!\n I wasn't able to time \
-                               execution though; here was the error : {} ###", num_checked, e)
-    }
+    // In keep going mode, this is the number of items in the summary, so
+    // the two agree; otherwise it's the number of declarations checked.
+    let mut num_items = 0usize;
+    let mut num_unchanged = 0usize;
+    let mut all_ok = true;
+    for (s, result_cache) in export_file_strings.into_iter().zip(opt.result_caches()) {
+        let options = CheckOptions { result_cache, ..check_options.clone() };
+        let report = match check_export(s, &options) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("\n### Checking failed; {} ###\n", e);
+                std::process::exit(-1)
+            }
+        };
 
-}
+        if opt.print {
+            pp_bundle(&report.env);
+        }
 
+        if let Some(targets) = axiom_targets.as_ref() {
+            if let Err(e) = print_axiom_report(&report.env, targets, opt.json) {
+                eprintln!("\n### Couldn't build the axiom report; {} ###\n", e);
+                std::process::exit(-1)
+            }
+        }
 
-fn check_serial(source : String, print : bool) -> usize {
-    let env = Arc::new(RwLock::new(Env::new(EXPECTED_NUM_MODS)));
-    let add_queue = RwQueue::with_capacity(EXPECTED_NUM_MODS);
-    let check_queue = RwQueue::with_capacity(EXPECTED_NUM_MODS);
+        match report.summary {
+            Some(summary) => {
+                println!("\n{}", summary);
+                all_ok &= summary.is_ok();
+                num_items += summary.num_items();
+            },
+            None => num_items += report.num_checked
+        }
 
-    if let Err(e) =  LineParser::parse_all(source, &add_queue, &env) {
-        errors::export_file_parse_err(line!(), e)
+        num_checked += report.num_checked;
+        num_unchanged += report.num_unchanged;
     }
 
-    loop_add(&add_queue, &check_queue, &env, 1);
-    loop_check(&check_queue, &env);
-
-    if print {
-        pp_bundle(&env);
+    if opt.incremental {
+        println!("\n{} of {} declarations were unchanged since the last run, and weren't checked again", num_unchanged, num_checked);
     }
 
-    let n = env.read().num_declars();
-    n
-}
-
-fn check_parallel(source : String, num_threads : usize, print : bool) -> usize {
-    let env = Arc::new(RwLock::new(Env::new(EXPECTED_NUM_MODS)));
-    let add_queue = RwQueue::with_capacity(EXPECTED_NUM_MODS);
-    let check_queue = RwQueue::with_capacity(EXPECTED_NUM_MODS);
-
-    let scope_ = thread::scope(|s| {
-
-        let mut thread_holder = Vec::with_capacity(num_threads);
-
-        // add and parse can be done separately/concurrently, but both MUST be done 
-        // in order. So, when parsing ends, that thread goes immediately to
-        // the check pool instead of adding.
-        thread_holder.push(s.builder().stack_size(8388608).spawn(|_| {
-            if let Err(e) =  LineParser::parse_all(source, &add_queue, &env) {
-                errors::export_file_parse_err(line!(), e)
-            }
-            loop_check(&check_queue, &env);
-        }).expect("Failed to spawn scoped thread!"));
-
-
-        thread_holder.push(s.spawn(|_s| {
-            loop_add(&add_queue, &check_queue, &env, num_threads);
-            loop_check(&check_queue, &env);
-        }));
-
-        // We spawn (num_threads - 2) checker threads here since 
-        // parser and adder will check when they're done.
-        for _ in 0..(num_threads - 2) {
-            thread_holder.push(s.spawn(|_s| {
-                loop_check(&check_queue, &env);
-            })); 
-        }
-        
-        for t in thread_holder {
-            t.join().expect("scoped thread panicked!")
+    let peak = peak_memory();
+    let peak_note = match peak {
+        Some(bytes) => format!(" (peak memory {} MiB)", bytes >> 20),
+        None => String::new()
+    };
+    if let (Some(bytes), Some(budget)) = (peak, opt.memory_budget) {
+        if bytes >> 20 > budget {
+            eprintln!("\nnote : peak memory use went over the budget of {} MiB", budget);
         }
-    });
+    }
 
-    if scope_.is_err() {
-        errors::scope_err(line!())
+    let items = plural(num_items, "item");
+    if !all_ok {
+        eprintln!("\n### Finished checking {}{}; at least one declaration was ill-typed ###\n", items, peak_note);
+        std::process::exit(1);
     }
 
-    if print {
-        pp_bundle(&env);
+    match start_instant.elapsed() {
+        Ok(dur) => println!("\n### Finished checking {} in {:?}{}; to the best \
+                               of our knowledge, all terms were well-typed! ###\n", items, dur, peak_note),
+        Err(e)  => println!("\n### Finished checking {}{}; to the best of our \
+                               knowledge, all terms were well-typed!\n I wasn't able to time \
+                               execution though; here was the error : {} ###", items, peak_note, e)
     }
 
-    let n = env.read().num_declars();
-    n
 }
 
-
-// Constantly poll the `add_queue` to see if there's something
-// to add. If the item popped off the queue is a Left(Mod), 
-// add said mod. `None` means there aren't any items yet, but
-// there will be later. Right(..) means adding is finished,
-// and this thread is redirected to working on the `check_queue`
-pub fn loop_add(add_queue : &ModQueue,
-                check_queue : &CompiledQueue,
-                env : &Arc<RwLock<Env>>,
-                num_threads : usize) {
-    loop {
-        match add_queue.pop() {
-            Some(Left(elem)) => {
-                let compiled = elem.compile(&env);
-                compiled.add_only(&env);
-                check_queue.push(Left(compiled));
-            },
-            Some(Right(_)) => {
-                for _ in 0..(num_threads * 2) {
-                    check_queue.push(END_MSG_CHK);
-                }
-                break
-            },
-            None => continue,
-        }
+fn plural(n : usize, noun : &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, noun)
+    } else {
+        format!("{} {}s", n, noun)
     }
 }
-
-// Same as above. Constantly poll for new work, with Left(Compiled)
-// indicating an item to be checked, `None` meaning 'try again later'
-// and Right(..) meaning all checking has completed.
-pub fn loop_check(check_queue : &CompiledQueue,
-                  env : &Arc<RwLock<Env>>) {
-    loop {
-         match check_queue.pop() {
-             Some(Left(elem)) => elem.check_only(&env),
-             Some(Right(_)) => break,
-             None => continue
-         }
-     }
-}
-
@@ -1,7 +1,11 @@
-use std::sync::Arc;
+use std::sync::{ Arc, OnceLock };
+use std::hash::{ Hash, Hasher };
 
+use fxhash::hash64;
 use hashbrown::HashSet;
 
+use crate::hashcons::{ HashCons, ConsTable, intern, addr };
+
 use InnerName::*;
 
 /// `Name` is an Arc wrapper for the `InnerName` enum, which together represent Lean's hierarchical names, where
@@ -10,9 +14,25 @@ use InnerName::*;
 /// while `Str` and `Num` act like `cons`, but specialized to consing string and integer elements respectively.
 /// Name values always begin with `Anon`, and can contain any combination of `Str` and `Num` applications, 
 /// IE (in pseudo-code) `Num n (Str s (Num n' (Str s' (Anon))))` would be a valid construction.
-#[derive(Clone, PartialEq, PartialOrd, Ord, Eq, Hash)]
+/// Names are hash-consed (see `hashcons::HashCons`), so two names are
+/// equal exactly when they point to the same `InnerName`.
+#[derive(Clone, PartialOrd, Ord)]
 pub struct Name(Arc<InnerName>);
 
+impl PartialEq for Name {
+    fn eq(&self, other : &Name) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        self.as_ref().hash(state)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq, Hash)]
 pub enum InnerName {
     Anon,
@@ -21,11 +41,16 @@ pub enum InnerName {
 }
 
 pub fn mk_anon() -> Name {
-    Name(Arc::new(InnerName::Anon))
+    Name::from(InnerName::Anon)
 }
 
 impl Name {
 
+    /// Identifies this name among the live ones; see `hashcons::addr`.
+    pub fn addr(&self) -> usize {
+        addr(&self.0)
+    }
+
     pub fn is_anon(&self) -> bool {
         match self {
             Name(inner) => inner.as_ref() == &Anon
@@ -52,10 +77,8 @@ impl Name {
         if !forbidden.contains(&base) {
             return base
         }
-        (0u64..).into_iter()
-                .map(|n| base.extend_num(n))
-                .filter(|candidate| !forbidden.contains(candidate))
-                .next()
+        (0u64..).map(|n| base.extend_num(n))
+                .find(|candidate| !forbidden.contains(candidate))
                 .unwrap()
 
     }
@@ -77,13 +100,28 @@ impl std::convert::AsRef<InnerName> for Name {
 /// Convenience function for converting an Arc<InnerName> into its newtype `Name`
 impl From<Arc<InnerName>> for Name {
     fn from(x : Arc<InnerName>) -> Name {
-        Name(x)
+        Name::from(x.as_ref().clone())
     }
 }
 // Convenience function for converting an InnerName to a Name
 impl From<InnerName> for Name {
     fn from(x : InnerName) -> Name {
-        Name(Arc::new(x))
+        Name(intern(x))
+    }
+}
+
+impl HashCons for InnerName {
+    fn cons_hash(&self) -> u64 {
+        match self {
+            Anon => hash64(&0u8),
+            Str { pfx, hd } => hash64(&(1u8, pfx.addr(), hd)),
+            Num { pfx, hd } => hash64(&(2u8, pfx.addr(), hd)),
+        }
+    }
+
+    fn table() -> &'static ConsTable<InnerName> {
+        static TABLE : OnceLock<ConsTable<InnerName>> = OnceLock::new();
+        TABLE.get_or_init(ConsTable::new)
     }
 }
 
@@ -95,10 +133,10 @@ impl From<&str> for Name {
 }
 
 
-/// Hierarchical names should display from left to right, with a `.` separating elements, and the anonymous name
-/// should display as an empty string.
-/// IE the formatted version of Anon ++ Str(list) ++ Str(cases_on) ++ Num(777) should display as
-/// `list.cases_on.777`
+// Hierarchical names should display from left to right, with a `.` separating elements, and the anonymous name
+// should display as an empty string.
+// IE the formatted version of Anon ++ Str(list) ++ Str(cases_on) ++ Num(777) should display as
+// `list.cases_on.777`
 
 
 impl std::fmt::Debug for Name {
@@ -133,3 +171,34 @@ impl std::fmt::Display for Name {
         write!(f, "{}", self.as_ref())
     }
 }
+
+
+impl std::str::FromStr for Name {
+    type Err = String;
+    fn from_str(s : &str) -> Result<Name, String> {
+        let mut base = mk_anon();
+
+        if s.is_empty() {
+            return Err("Cannot pretty print the empty/anonymous Lean name!".to_string())
+        }
+
+        let fragments = s.split_terminator('.');
+
+        for f in fragments {
+            match f.parse::<u64>() {
+                Ok(n) => { base = base.extend_num(n); },
+                _ => {
+                    if f.is_empty() {
+                        return Err("Name cannot be empty!".to_string())
+                    } else if f.starts_with('#') {
+                        return Err("Commented out".to_string())
+                    } else {
+                        base = base.extend_str(f);
+                    }
+                }
+            }
+        }
+
+        Ok(base)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,383 @@
+use hashbrown::HashMap;
+use num_bigint::BigUint;
+use num_traits::{ Zero, One };
+
+use crate::name::Name;
+use crate::level::{ mk_succ, mk_zero };
+use crate::expr::{ Expr, Binding, BinderStyle, mk_app, mk_const, mk_lambda, mk_local, mk_pi, mk_prop, mk_var };
+use crate::inductive::IndInfo;
+
+use NatOp::*;
+
+/// Kernel support for natural number literals. Without it, the only
+/// way to get at a number like 314 is through `nat.succ`/`nat.zero`,
+/// or `bit0`/`bit1` applied to `has_one.one`, and reducing arithmetic on
+/// those means unfolding the definition of `nat.add` and friends one
+/// `nat.rec` step at a time, which is slow and can blow the stack.
+/// Instead, once `nat` has been declared, the type checker is allowed to
+/// evaluate the operations below on literals (`InnerExpr::NatLit`) with
+/// arbitrary precision arithmetic.
+///
+/// The operations are recognized by name, so they only fire if the
+/// constant is actually in the environment (and visible to whoever is
+/// asking; see `TypeChecker::reduce_nat`), and only once we've made sure
+/// the export's definition agrees with what the extension would compute
+/// (see `NatExt::equations`). Anything that isn't a literal yet is
+/// reduced with `whnf` first, and `nat.zero`/`nat.succ` chains are read
+/// as literals, so the extension works on Lean 3 exports (which never
+/// contain literals) as well as Lean 4 ones.
+///
+/// Lean's `div`, `mod` and `gcd` are defined by well-founded recursion,
+/// so their equations don't hold definitionally and there's nothing to
+/// check them against; they're left to their definitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatOp {
+    Succ,
+    Add,
+    Sub,
+    Mul,
+    Beq,
+    Ble,
+    DecEq,
+    Bit0,
+    Bit1,
+}
+
+impl NatOp {
+    /// The number of arguments the operation's constant has to be
+    /// applied to before we try to reduce it. `bit0` and `bit1` also
+    /// take the type and its instances.
+    pub fn arity(self) -> usize {
+        match self {
+            Succ => 1,
+            Bit0 => 3,
+            Bit1 => 4,
+            _ => 2
+        }
+    }
+
+    /// The operations whose definitions show up in this one's equations,
+    /// which have to be trustworthy for this one to be. `decidable_eq`'s
+    /// answer for unequal literals is proved with `beq`.
+    pub fn prerequisites(self) -> &'static [NatOp] {
+        match self {
+            Mul | Bit0 | Bit1 => &[Add],
+            DecEq => &[Beq],
+            _ => &[]
+        }
+    }
+}
+
+/// The result of applying a binary `NatOp` to two literals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NatResult {
+    Num(BigUint),
+    Bool(bool),
+}
+
+/// The names the extension needs, which are different in Lean 3 and
+/// Lean 4. Everything except `nat`, `zero` and `succ` is only used if
+/// it turns out to be declared.
+#[derive(Debug, Clone)]
+pub struct NatExt {
+    pub nat : Name,
+    pub zero : Name,
+    pub succ : Name,
+    pub bool : Name,
+    pub bool_true : Name,
+    pub bool_false : Name,
+    pub has_add : Option<Name>,
+    pub has_one : Option<Name>,
+    pub eq : Name,
+    /// Lean 4's `Eq.rec` takes a motive that depends on the proof of
+    /// equality as well as the right hand side.
+    pub dep_eq_rec : bool,
+    pub decidable : Name,
+    pub is_true : Name,
+    pub is_false : Name,
+    pub true_ : Name,
+    pub false_ : Name,
+    ops : HashMap<Name, NatOp>,
+}
+
+impl NatExt {
+    /// Called whenever an inductive is added to the environment; returns
+    /// `Some` if it's `nat` (or Lean 4's `Nat`) with the introduction
+    /// rules we expect.
+    pub fn new(info : &IndInfo) -> Option<Self> {
+        let lean4 = match info.name.to_string().as_str() {
+            "nat" => false,
+            "Nat" => true,
+            _ => return None
+        };
+
+        let nat = info.name.clone();
+        let zero = nat.extend_str("zero");
+        let succ = nat.extend_str("succ");
+        if info.num_params != 0 || info.num_indices != 0 || info.intros != [zero.clone(), succ.clone()] {
+            return None
+        }
+
+        let mut ops = HashMap::with_capacity(16);
+        let mut named = |s : &str, op : NatOp| { ops.insert(nat.extend_str(s), op); };
+        named("succ", Succ);
+        named("add", Add);
+        named("sub", Sub);
+        named("mul", Mul);
+        named("beq", Beq);
+        named("ble", Ble);
+
+        let ext = if lean4 {
+            ops.insert(nat.extend_str("decEq"), DecEq);
+            NatExt {
+                nat,
+                zero,
+                succ,
+                bool : Name::from("Bool"),
+                bool_true : Name::from("Bool").extend_str("true"),
+                bool_false : Name::from("Bool").extend_str("false"),
+                has_add : None,
+                has_one : None,
+                eq : Name::from("Eq"),
+                dep_eq_rec : true,
+                decidable : Name::from("Decidable"),
+                is_true : Name::from("Decidable").extend_str("isTrue"),
+                is_false : Name::from("Decidable").extend_str("isFalse"),
+                true_ : Name::from("True"),
+                false_ : Name::from("False"),
+                ops,
+            }
+        } else {
+            ops.insert(nat.extend_str("decidable_eq"), DecEq);
+            ops.insert(Name::from("bit0"), Bit0);
+            ops.insert(Name::from("bit1"), Bit1);
+            NatExt {
+                has_add : Some(nat.extend_str("has_add")),
+                has_one : Some(nat.extend_str("has_one")),
+                nat,
+                zero,
+                succ,
+                bool : Name::from("bool"),
+                bool_true : Name::from("bool").extend_str("tt"),
+                bool_false : Name::from("bool").extend_str("ff"),
+                eq : Name::from("eq"),
+                dep_eq_rec : false,
+                decidable : Name::from("decidable"),
+                is_true : Name::from("decidable").extend_str("is_true"),
+                is_false : Name::from("decidable").extend_str("is_false"),
+                true_ : Name::from("true"),
+                false_ : Name::from("false"),
+                ops,
+            }
+        };
+
+        Some(ext)
+    }
+
+    pub fn get_op(&self, n : &Name) -> Option<NatOp> {
+        self.ops.get(n).copied()
+    }
+
+    /// The name `op` goes by.
+    pub fn op_name(&self, op : NatOp) -> Option<Name> {
+        self.ops.iter().find(|(_, o)| **o == op).map(|(n, _)| n.clone())
+    }
+
+    /// Everything the extension might build a term out of, besides the
+    /// operations themselves.
+    pub fn names(&self) -> Vec<Name> {
+        let names = vec![&self.nat, &self.zero, &self.succ, &self.bool, &self.bool_true, &self.bool_false,
+                         &self.eq, &self.decidable, &self.is_true, &self.is_false, &self.true_, &self.false_];
+        let recs = vec![self.nat.extend_str("rec"), self.bool.extend_str("rec"),
+                        self.eq.extend_str("rec"), self.eq.extend_str("refl"), self.true_.extend_str("intro")];
+        names.into_iter()
+             .chain(self.has_add.as_ref())
+             .chain(self.has_one.as_ref())
+             .cloned()
+             .chain(recs)
+             .collect()
+    }
+
+    fn nat_const(&self) -> Expr {
+        mk_const(self.nat.clone(), Vec::new())
+    }
+
+    fn nat_binder(&self, s : &str) -> Binding {
+        Binding::mk(Name::from(s), self.nat_const(), BinderStyle::Default)
+    }
+
+    /// `eq.{1} nat a b`
+    pub fn mk_eq(&self, a : &Expr, b : &Expr) -> Expr {
+        mk_const(self.eq.clone(), vec![mk_succ(mk_zero())]).fold_apps(vec![&self.nat_const(), a, b])
+    }
+
+    /// The type an operation's definition has to have. `bit0` and `bit1`
+    /// are only ever used at `nat`, which their equations cover.
+    pub fn expected_type(&self, op : NatOp) -> Option<Expr> {
+        let nat = self.nat_const();
+        let result = match op {
+            Add | Sub | Mul => nat,
+            Beq | Ble => mk_const(self.bool.clone(), Vec::new()),
+            DecEq => {
+                let n = mk_local("n", nat.clone(), BinderStyle::Default);
+                let m = mk_local("m", nat, BinderStyle::Default);
+                let decidable = mk_app(mk_const(self.decidable.clone(), Vec::new()), self.mk_eq(&n, &m));
+                return decidable.fold_pis(vec![&n, &m].into_iter()).ok()
+            },
+            Succ | Bit0 | Bit1 => return None
+        };
+        Some(mk_pi(self.nat_binder("n"), mk_pi(self.nat_binder("m"), result)))
+    }
+
+    /// The equations `name` (which is `op`) has to satisfy for the
+    /// extension to stand in for it, in terms of the variables `n` and
+    /// `m`, and of `nat.zero` and `nat.succ`. If both sides of each are
+    /// definitionally equal without the extension, then by induction
+    /// the definition agrees with the extension on every pair of
+    /// literals. They're the equations Lean's own definitions are
+    /// written with, which hold by `rfl`. `succ` is a constructor, so
+    /// there's nothing to check, and `decidable_eq`'s type only has room
+    /// for the right answer (up to proof irrelevance).
+    pub fn equations(&self, op : NatOp, name : &Name) -> Vec<(Expr, Expr)> {
+        let nat = self.nat_const();
+        let n = mk_local("n", nat.clone(), BinderStyle::Default);
+        let m = mk_local("m", nat.clone(), BinderStyle::Default);
+        let zero = mk_const(self.zero.clone(), Vec::new());
+        let succ = |e : &Expr| mk_app(mk_const(self.succ.clone(), Vec::new()), e.clone());
+        let (tt, ff) = (mk_const(self.bool_true.clone(), Vec::new()), mk_const(self.bool_false.clone(), Vec::new()));
+        let f = |a : &Expr, b : &Expr| mk_const(name.clone(), Vec::new()).fold_apps(vec![a, b]);
+        let add = |a : &Expr, b : &Expr| mk_const(self.nat.extend_str("add"), Vec::new()).fold_apps(vec![a, b]);
+
+        match op {
+            Add => vec![(f(&n, &zero), n.clone()),
+                        (f(&n, &succ(&m)), succ(&f(&n, &m)))],
+            Sub => vec![(f(&n, &zero), n.clone()),
+                        (f(&n, &succ(&m)), self.mk_pred(&f(&n, &m)))],
+            Mul => vec![(f(&n, &zero), zero.clone()),
+                        (f(&n, &succ(&m)), add(&f(&n, &m), &n))],
+            Beq => vec![(f(&zero, &zero), tt.clone()),
+                        (f(&zero, &succ(&m)), ff.clone()),
+                        (f(&succ(&n), &zero), ff),
+                        (f(&succ(&n), &succ(&m)), f(&n, &m))],
+            Ble => vec![(f(&zero, &zero), tt.clone()),
+                        (f(&zero, &succ(&m)), tt),
+                        (f(&succ(&n), &zero), ff),
+                        (f(&succ(&n), &succ(&m)), f(&n, &m))],
+            Bit0 | Bit1 => match (&self.has_one, &self.has_add) {
+                (Some(has_one), Some(has_add)) => {
+                    let has_one = mk_const(has_one.clone(), Vec::new());
+                    let has_add = mk_const(has_add.clone(), Vec::new());
+                    let bit = mk_const(name.clone(), vec![mk_zero()]);
+                    if op == Bit0 {
+                        vec![(bit.fold_apps(vec![&nat, &has_add, &n]), add(&n, &n))]
+                    } else {
+                        let one = succ(&zero);
+                        vec![(bit.fold_apps(vec![&nat, &has_one, &has_add, &n]), add(&add(&n, &n), &one))]
+                    }
+                },
+                _ => Vec::new()
+            },
+            Succ | DecEq => Vec::new()
+        }
+    }
+
+    /// `nat.rec (λ _, nat) nat.zero (λ k _, k) e`, which is `nat.pred e`.
+    fn mk_pred(&self, e : &Expr) -> Expr {
+        let motive = mk_lambda(self.nat_binder("n"), self.nat_const());
+        let minor = mk_lambda(self.nat_binder("k"), mk_lambda(self.nat_binder("ih"), mk_var(1)));
+        mk_const(self.nat.extend_str("rec"), vec![mk_succ(mk_zero())])
+        .fold_apps(vec![&motive, &mk_const(self.zero.clone(), Vec::new()), &minor, e])
+    }
+
+    /// A proof of `¬ (a = b)` for literals `a` and `b` that `beq` tells
+    /// apart. With `Q x := bool.rec false true (beq a x)`, `Q a` is `true`,
+    /// so we can rewrite `true.intro` along `a = b` to get `Q b`, which is
+    /// `false`.
+    pub fn mk_ne_proof(&self, a : &Expr, b : &Expr) -> Option<Expr> {
+        let nat = self.nat_const();
+        let beq = mk_const(self.op_name(Beq)?, Vec::new());
+        let x = mk_local("x", nat.clone(), BinderStyle::Default);
+        let h_x = mk_local("h", self.mk_eq(a, &x), BinderStyle::Default);
+        let h_b = mk_local("h", self.mk_eq(a, b), BinderStyle::Default);
+
+        let bool_binder = Binding::mk(Name::from("b"), mk_const(self.bool.clone(), Vec::new()), BinderStyle::Default);
+        let to_prop = mk_lambda(bool_binder, mk_prop());
+        let q_x = mk_const(self.bool.extend_str("rec"), vec![mk_succ(mk_zero())])
+                  .fold_apps(vec![&to_prop,
+                                  &mk_const(self.false_.clone(), Vec::new()),
+                                  &mk_const(self.true_.clone(), Vec::new()),
+                                  &beq.fold_apps(vec![a, &x])]);
+        let motive = if self.dep_eq_rec {
+            q_x.fold_lambdas(vec![&x, &h_x].into_iter()).ok()?
+        } else {
+            q_x.fold_lambdas(vec![&x].into_iter()).ok()?
+        };
+
+        let lvls = vec![mk_zero(), mk_succ(mk_zero())];
+        let trivial = mk_const(self.true_.extend_str("intro"), Vec::new());
+        let rewritten = mk_const(self.eq.extend_str("rec"), lvls)
+                        .fold_apps(vec![&nat, a, &motive, &trivial, b, &h_b]);
+        rewritten.fold_lambdas(vec![&h_b].into_iter()).ok()
+    }
+}
+
+/// `nat.sub` is truncated subtraction, same as the definition in Lean's
+/// core library. `DecEq` is answered with `Bool`; the type checker is
+/// responsible for building the `decidable` term.
+pub fn apply_binop(op : NatOp, a : &BigUint, b : &BigUint) -> Option<NatResult> {
+    let result = match op {
+        Add => NatResult::Num(a + b),
+        Sub => NatResult::Num(if a > b { a - b } else { BigUint::zero() }),
+        Mul => NatResult::Num(a * b),
+        Beq | DecEq => NatResult::Bool(a == b),
+        Ble => NatResult::Bool(a <= b),
+        Succ | Bit0 | Bit1 => return None
+    };
+    Some(result)
+}
+
+/// `bit0 n = 2n`, `bit1 n = 2n + 1`
+pub fn apply_bit(op : NatOp, n : &BigUint) -> Option<BigUint> {
+    match op {
+        Bit0 => Some(n << 1usize),
+        Bit1 => Some((n << 1usize) + BigUint::one()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num(n : u64) -> Option<NatResult> {
+        Some(NatResult::Num(BigUint::from(n)))
+    }
+
+    fn binop(op : NatOp, a : u64, b : u64) -> Option<NatResult> {
+        apply_binop(op, &BigUint::from(a), &BigUint::from(b))
+    }
+
+    #[test]
+    fn sub_is_truncated() {
+        assert_eq!(binop(Sub, 7, 3), num(4));
+        assert_eq!(binop(Sub, 3, 3), num(0));
+        assert_eq!(binop(Sub, 3, 7), num(0));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(binop(Ble, 2, 3), Some(NatResult::Bool(true)));
+        assert_eq!(binop(Ble, 3, 3), Some(NatResult::Bool(true)));
+        assert_eq!(binop(Ble, 4, 3), Some(NatResult::Bool(false)));
+        assert_eq!(binop(Beq, 3, 3), Some(NatResult::Bool(true)));
+        assert_eq!(binop(DecEq, 2, 3), Some(NatResult::Bool(false)));
+    }
+
+    #[test]
+    fn not_binops() {
+        assert_eq!(binop(Succ, 1, 1), None);
+        assert_eq!(binop(Bit0, 1, 1), None);
+        assert_eq!(apply_bit(Bit1, &BigUint::from(3u64)), Some(BigUint::from(7u64)));
+        assert_eq!(apply_bit(Add, &BigUint::from(3u64)), None);
+    }
+}
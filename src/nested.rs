@@ -0,0 +1,384 @@
+use std::sync::Arc;
+
+use crate::name::Name;
+use crate::level::Level;
+use crate::reduction::ReductionRule;
+use crate::env::{ Env, Declaration, CompiledModification, CompiledModification::* };
+use crate::inductive::ProtoInd;
+use crate::tc::TypeChecker;
+use crate::errors::{ KernelResult, KernelError::* };
+use crate::expr::{ Expr,
+                   BinderStyle,
+                   InnerExpr::*,
+                   mk_app,
+                   mk_const,
+                   mk_lambda,
+                   mk_pi,
+                   mk_let,
+                   mk_proj,
+                   mk_prop,
+                   mk_local,
+                   unique_const_names };
+
+/// Kernel support for nested inductives, like
+///```pseudo
+/// inductive tree
+/// | node : list tree -> tree
+///```
+/// where one of the types being declared shows up as a parameter of
+/// some inductive `I` that's already in the environment. `Inductive`
+/// only knows about mutual blocks, so before compiling we replace every
+/// nested occurrence `I As` (`I` applied to its parameters `As`) with an
+/// auxiliary type that gets added to the block. The auxiliary type has
+/// the block's parameters, and one introduction rule for each of `I`'s,
+/// which is `I`'s rule with its parameters instantiated with `As` (and
+/// nested occurrences in *those* replaced in turn), so `tree` above
+/// becomes
+///```pseudo
+/// mutual inductive tree, aux
+/// | tree.node : aux -> tree
+/// | aux.nil : aux
+/// | aux.cons : tree -> aux -> aux
+///```
+/// Once the block is compiled, `restore` drops the auxiliary types and
+/// their introduction rules, maps them back to `I As` and `I`'s rules
+/// in everything that's left, and renames the auxiliary recursors to
+/// `tree.rec_1`, `tree.rec_2`, ... the way Lean does.
+#[derive(Debug, Clone)]
+pub struct ElimNested {
+    /// The first type in the block, which the auxiliary types and
+    /// recursors are named after.
+    main : Name,
+    univ_params : Arc<Vec<Level>>,
+    /// Locals standing in for the block's parameters in `aux`.
+    params : Vec<Expr>,
+    /// The number of types the user actually declared.
+    num_user : usize,
+    aux : Vec<AuxInd>,
+}
+
+/// An auxiliary type along with what it stands for. `occurrence` is
+/// `I As`, and `ctors` pairs each auxiliary introduction rule with
+/// `I`'s rule applied to `As`. Both are abstracted over the block's
+/// parameters, so they can be instantiated with whatever the auxiliary
+/// constant is applied to.
+#[derive(Debug, Clone)]
+struct AuxInd {
+    name : Name,
+    occurrence : Expr,
+    ctors : Vec<(Name, Expr)>,
+    rec_name : Name,
+}
+
+impl ElimNested {
+    /// Returns the block with any nested occurrences replaced by
+    /// auxiliary types, which are added to the end of it. A block
+    /// without nested occurrences comes back as it was.
+    pub fn new(block : Vec<ProtoInd>, env : &Arc<Env>) -> KernelResult<(Self, Vec<ProtoInd>)> {
+        let mut tc = TypeChecker::new(None, env.clone());
+        let (_, binders) = tc.normalize_pis(&block[0].ty)?;
+        let num_params = block[0].num_params;
+
+        let mut elim = ElimNested {
+            main : block[0].name.clone(),
+            univ_params : block[0].params.clone(),
+            params : binders.into_iter().take(num_params).collect(),
+            num_user : block.len(),
+            aux : Vec::new(),
+        };
+
+        // Too few binders for the parameters; `Inductive::new` reports it.
+        if elim.params.len() < num_params {
+            return Ok((elim, block))
+        }
+
+        // The auxiliary types are pushed onto `block` as they're found,
+        // so their introduction rules get the same treatment.
+        let mut block = block;
+        let mut ty_idx = 0;
+        while ty_idx < block.len() {
+            let intros = block[ty_idx].intros.clone();
+            let mut new_intros = Vec::with_capacity(intros.len());
+            for (intro_name, intro_type) in intros {
+                let body = tc.instantiate_pis(&intro_type, &elim.params)?;
+                let new_body = elim.replace_all(&body, &mut tc, &mut block, env)?;
+                if new_body == body {
+                    new_intros.push((intro_name, intro_type));
+                } else {
+                    new_intros.push((intro_name, new_body.fold_pis(elim.params.iter())?));
+                }
+            }
+            block[ty_idx].intros = new_intros;
+            ty_idx += 1;
+        }
+
+        Ok((elim, block))
+    }
+
+    /// Replace the nested occurrences in `e`, looking at each subterm
+    /// before its children (and functions before their arguments), so
+    /// the auxiliary types come out in the same order as in Lean.
+    fn replace_all(&mut self,
+                   e : &Expr,
+                   tc : &mut TypeChecker,
+                   block : &mut Vec<ProtoInd>,
+                   env : &Arc<Env>) -> KernelResult<Expr> {
+        if let Some(replaced) = self.replace_occurrence(e, tc, block, env)? {
+            return Ok(replaced)
+        }
+
+        Ok(match e.as_ref() {
+            App(_, lhs, rhs) => {
+                let new_lhs = self.replace_all(lhs, tc, block, env)?;
+                let new_rhs = self.replace_all(rhs, tc, block, env)?;
+                mk_app(new_lhs, new_rhs)
+            },
+            Lambda(_, dom, body) => {
+                let new_dom_ty = self.replace_all(&dom.ty, tc, block, env)?;
+                let new_body = self.replace_all(body, tc, block, env)?;
+                mk_lambda(dom.swap_ty(new_dom_ty), new_body)
+            },
+            Pi(_, dom, body) => {
+                let new_dom_ty = self.replace_all(&dom.ty, tc, block, env)?;
+                let new_body = self.replace_all(body, tc, block, env)?;
+                mk_pi(dom.swap_ty(new_dom_ty), new_body)
+            },
+            Let(_, dom, val, body) => {
+                let new_dom_ty = self.replace_all(&dom.ty, tc, block, env)?;
+                let new_val = self.replace_all(val, tc, block, env)?;
+                let new_body = self.replace_all(body, tc, block, env)?;
+                mk_let(dom.swap_ty(new_dom_ty), new_val, new_body)
+            },
+            Proj(_, struct_name, idx, structure) => {
+                let new_structure = self.replace_all(structure, tc, block, env)?;
+                mk_proj(struct_name.clone(), *idx, new_structure)
+            },
+            _ => e.clone()
+        })
+    }
+
+    /// If `e` is an inductive `I` from the environment applied to
+    /// parameters that mention the block, replace `I As` with the
+    /// auxiliary type for it (making one if this is the first time
+    /// we've seen `I As`).
+    fn replace_occurrence(&mut self,
+                          e : &Expr,
+                          tc : &mut TypeChecker,
+                          block : &mut Vec<ProtoInd>,
+                          env : &Arc<Env>) -> KernelResult<Option<Expr>> {
+        if !matches!(e.as_ref(), App(..)) {
+            return Ok(None)
+        }
+
+        let (fn_, args) = e.unfold_apps_special();
+        let (name, lvls) = match fn_.as_ref() {
+            Const(_, name, lvls) if !block.iter().any(|ind| &ind.name == name) => (name, lvls),
+            _ => return Ok(None)
+        };
+
+        let info = match env.inductives.get(name) {
+            Some(info) if info.num_params <= args.len() => info.clone(),
+            _ => return Ok(None)
+        };
+
+        let (nested_params, rest) = args.split_at(info.num_params);
+        let mentions_block = nested_params.iter().any(|p| {
+            unique_const_names(p).iter().any(|n| block.iter().any(|ind| &ind.name == *n))
+        });
+        if !mentions_block {
+            return Ok(None)
+        }
+
+        // `I As` becomes a type of its own, so `As` can only depend on
+        // the block's parameters, and not on the introduction rule's
+        // other arguments.
+        if nested_params.iter().any(|p| p.has_vars()) {
+            return Err(InvalidNestedOccurrence(e.clone()))
+        }
+
+        let occurrence = fn_.fold_apps(nested_params).abstract_(self.params.iter().rev());
+        let aux_idx = match self.aux.iter().position(|aux| aux.occurrence == occurrence) {
+            Some(aux_idx) => aux_idx,
+            None => {
+                self.mk_aux(name, lvls, nested_params, &info.intros, occurrence, tc, block, env)?;
+                self.aux.len() - 1
+            }
+        };
+
+        let aux_const = mk_const(self.aux[aux_idx].name.clone(), self.univ_params.clone());
+        Ok(Some(aux_const.fold_apps(self.params.iter()).fold_apps(rest)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn mk_aux(&mut self,
+              name : &Name,
+              lvls : &Arc<Vec<Level>>,
+              nested_params : &[Expr],
+              intros : &[Name],
+              occurrence : Expr,
+              tc : &mut TypeChecker,
+              block : &mut Vec<ProtoInd>,
+              env : &Arc<Env>) -> KernelResult<()> {
+        let aux_name = self.main.extend_str("_nested").extend_num(self.aux.len() as u64 + 1);
+
+        let lookup = |n : &Name| env.declarations.get(n).cloned().ok_or_else(|| UnknownConst(n.clone()));
+        let ind_declaration = lookup(name)?;
+        let substs = ind_declaration.univ_params.iter()
+                                                .cloned()
+                                                .zip(lvls.iter().cloned())
+                                                .collect::<Vec<(Level, Level)>>();
+
+        let instd_ty = ind_declaration.ty.instantiate_ps(&substs);
+        let aux_ty = tc.instantiate_pis(&instd_ty, nested_params)?.fold_pis(self.params.iter())?;
+
+        let mut aux_intros = Vec::with_capacity(intros.len());
+        let mut ctors = Vec::with_capacity(intros.len());
+        for intro in intros {
+            let intro_declaration = lookup(intro)?;
+            let instd_intro = intro_declaration.ty.instantiate_ps(&substs);
+            let aux_intro_ty = tc.instantiate_pis(&instd_intro, nested_params)?.fold_pis(self.params.iter())?;
+            let aux_intro_name = aux_name.extend_str(intro.to_string().as_str());
+            let ctor = mk_const(intro.clone(), lvls.clone()).fold_apps(nested_params)
+                                                            .abstract_(self.params.iter().rev());
+            aux_intros.push((aux_intro_name.clone(), aux_intro_ty));
+            ctors.push((aux_intro_name, ctor));
+        }
+
+        block.push(ProtoInd {
+            name : aux_name.clone(),
+            params : self.univ_params.clone(),
+            ty : aux_ty,
+            num_params : self.params.len(),
+            intros : aux_intros,
+            always_dep_elim : block[0].always_dep_elim,
+            num_indices : None,
+            is_rec : None,
+        });
+
+        self.aux.push(AuxInd {
+            name : aux_name,
+            occurrence,
+            ctors,
+            rec_name : self.main.extend_str(format!("rec_{}", self.aux.len() + 1).as_str()),
+        });
+        Ok(())
+    }
+
+    /// Take the auxiliary types back out of a compiled block, so none of
+    /// them (or their base types) ever go into the environment.
+    pub fn restore(&self, compiled : CompiledModification) -> KernelResult<CompiledModification> {
+        if self.aux.is_empty() {
+            return Ok(compiled)
+        }
+
+        match compiled {
+            CompiledInductive(mut bases, intros, elims, rules, mut infos) => {
+                bases.truncate(self.num_user);
+                infos.truncate(self.num_user);
+
+                let intros = intros.into_iter().filter(|d| {
+                    !self.aux.iter().any(|aux| aux.ctors.iter().any(|(n, _)| n == &d.name))
+                }).map(|d| self.restore_declaration(&d, d.name.clone())).collect();
+
+                let elims = elims.iter().enumerate().map(|(idx, d)| {
+                    let name = match idx.checked_sub(self.num_user) {
+                        Some(aux_idx) => self.aux[aux_idx].rec_name.clone(),
+                        None => d.name.clone()
+                    };
+                    self.restore_declaration(d, name)
+                }).collect();
+
+                let rules = rules.iter().map(|r| self.restore_rule(r)).collect::<KernelResult<Vec<ReductionRule>>>()?;
+
+                Ok(CompiledInductive(bases, intros, elims, rules, infos))
+            },
+            owise => Ok(owise)
+        }
+    }
+
+    fn restore_declaration(&self, d : &Declaration, name : Name) -> Declaration {
+        Declaration::mk(name, d.univ_params.clone(), self.restore_expr(&d.ty), None, Some(d.builtin))
+    }
+
+    /// The sides of a rule have loose variables, which we swap for locals
+    /// while restoring so that `restore_expr` only ever sees closed terms.
+    fn restore_rule(&self, rule : &ReductionRule) -> KernelResult<ReductionRule> {
+        let locals = (0..rule.lhs_var_bound).map(|_| {
+            mk_local(Name::from("x"), mk_prop(), BinderStyle::Default)
+        }).collect::<Vec<Expr>>();
+
+        let restore = |e : &Expr| self.restore_expr(&e.instantiate(locals.iter()));
+        let constraints = rule.def_eq_constraints.iter()
+                                                 .map(|(a, b)| (restore(a), restore(b)))
+                                                 .collect::<Vec<(Expr, Expr)>>();
+
+        ReductionRule::new_nondef_rr(locals.as_slice(),
+                                     restore(&rule.lhs),
+                                     restore(&rule.rhs),
+                                     constraints.iter().map(|(a, b)| (a, b)))
+    }
+
+    /// Replace the auxiliary types, introduction rules and recursors
+    /// in the closed term `e`. Binders are opened with locals, since
+    /// the arguments of an auxiliary type can end up under binders in
+    /// `I As`.
+    fn restore_expr(&self, e : &Expr) -> Expr {
+        match e.as_ref() {
+            App(..) => {
+                let (fn_, args) = e.unfold_apps_special();
+                let args = args.iter().map(|arg| self.restore_expr(arg)).collect::<Vec<Expr>>();
+                self.restore_app(&fn_, args)
+            },
+            Const(..) => self.restore_app(e, Vec::new()),
+            Lambda(_, dom, body) | Pi(_, dom, body) => {
+                let new_dom = dom.swap_ty(self.restore_expr(&dom.ty));
+                let local = new_dom.clone().as_local();
+                let new_body = self.restore_expr(&body.instantiate(Some(&local).into_iter()))
+                                   .abstract_(Some(&local).into_iter());
+                if let Lambda(..) = e.as_ref() {
+                    mk_lambda(new_dom, new_body)
+                } else {
+                    mk_pi(new_dom, new_body)
+                }
+            },
+            Let(_, dom, val, body) => {
+                let new_dom = dom.swap_ty(self.restore_expr(&dom.ty));
+                let new_val = self.restore_expr(val);
+                let local = new_dom.clone().as_local();
+                let new_body = self.restore_expr(&body.instantiate(Some(&local).into_iter()))
+                                   .abstract_(Some(&local).into_iter());
+                mk_let(new_dom, new_val, new_body)
+            },
+            Proj(_, struct_name, idx, structure) => {
+                mk_proj(struct_name.clone(), *idx, self.restore_expr(structure))
+            },
+            _ => e.clone()
+        }
+    }
+
+    /// `fn_` applied to (already restored) `args`.
+    fn restore_app(&self, fn_ : &Expr, args : Vec<Expr>) -> Expr {
+        let num_params = self.params.len();
+        if let Const(_, name, lvls) = fn_.as_ref() {
+            for aux in self.aux.iter() {
+                let abstd = if &aux.name == name {
+                    Some(&aux.occurrence)
+                } else {
+                    aux.ctors.iter().find(|(n, _)| n == name).map(|(_, ctor)| ctor)
+                };
+
+                match abstd {
+                    Some(abstd) if args.len() >= num_params => {
+                        let (params, rest) = args.split_at(num_params);
+                        return abstd.instantiate(params.iter().rev()).fold_apps(rest)
+                    },
+                    _ if &aux.name.extend_str("rec") == name => {
+                        return mk_const(aux.rec_name.clone(), lvls.clone()).fold_apps(args.iter())
+                    },
+                    _ => ()
+                }
+            }
+        }
+        fn_.fold_apps(args.iter())
+    }
+}
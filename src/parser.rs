@@ -2,9 +2,9 @@ use std::sync::Arc;
 use std::str::SplitWhitespace;
 
 use crate::name::{ Name, mk_anon };
-use crate::env::{ Env, Modification, Axiom, Definition };
+use crate::env::{ Env, Modification, Axiom, Definition, DefKind };
 use crate::quot::new_quot;
-use crate::inductive::Inductive;
+use crate::parser4::Lean4Parser;
 use crate::pretty::components::Notation;
 use crate::utils::{ Either::*, END_MSG_ADD, ModQueue };
 use crate::errors;
@@ -12,7 +12,6 @@ use crate::level::{ Level, mk_imax, mk_max, mk_succ, mk_param, mk_zero };
 use crate::expr::{ Expr, Binding, BinderStyle, mk_app, mk_prop, mk_sort,
                    mk_var, mk_let, mk_pi, mk_lambda, mk_const };
 
-use parking_lot::RwLock;
 
 use ParseErr::*;
 
@@ -35,28 +34,141 @@ impl std::fmt::Display for ParseErr {
     }
 }
 
+/// The two export formats we know how to read. Lean 3's is the
+/// original one; Lean 4's (as written by `lean4export`) shares the
+/// name, level and expression lines, but has its own declaration lines.
+/// See `parser4` for the Lean 4 front-end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Lean3,
+    Lean4,
+}
+
+impl ExportFormat {
+    /// Newer Lean 4 exports start with a line giving the format version,
+    /// IE `1.0.0`, which a Lean 3 export never does. Older ones don't have
+    /// a header, so we fall back to looking for a `#CTOR` line, which only
+    /// Lean 4 exports have (every export with an inductive in it has one).
+    pub fn detect(source : &str) -> Self {
+        let header = source.lines().next().unwrap_or("").trim();
+        if is_version_header(header) || source.starts_with("#CTOR ") || source.contains("\n#CTOR ") {
+            ExportFormat::Lean4
+        } else {
+            ExportFormat::Lean3
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+    fn from_str(s : &str) -> Result<ExportFormat, String> {
+        match s.to_lowercase().as_str() {
+            "lean3" | "3" => Ok(ExportFormat::Lean3),
+            "lean4" | "4" => Ok(ExportFormat::Lean4),
+            owise => Err(format!("unrecognized export format {:?}; expected `lean3` or `lean4`", owise))
+        }
+    }
+}
+
+/// A line like `1.0.0`; some digits, separated by at least one dot.
+pub fn is_version_header(line : &str) -> bool {
+    line.contains('.') && line.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Parse an export file in the given format (or whichever one it looks
+/// like, if `format` is `None`), pushing each item onto `queue_handle`.
+/// Lean 3 exports don't say which definitions are theorems; with
+/// `guess_theorems` set, any whose type is a proposition is treated as
+/// one (see `env::DefKind`). Otherwise they're all definitions.
+/// With `release_exprs` set, the parser lets go of each expression
+/// once it's past the last line that mentions it (see `last_expr_uses`),
+/// instead of keeping all of them until the end of the file.
+pub fn parse_export<'s>(s : String, 
+                        format : Option<ExportFormat>,
+                        guess_theorems : bool,
+                        release_exprs : bool,
+                        queue_handle : &'s ModQueue, 
+                        env_handle : &'s Arc<Env>) -> ParseResult<()> {
+    let format = format.unwrap_or_else(|| ExportFormat::detect(&s));
+    let last_uses = if release_exprs { Some(last_expr_uses(&s, format)) } else { None };
+    match format {
+        ExportFormat::Lean3 => LineParser::parse_all(s, guess_theorems, last_uses, queue_handle, env_handle),
+        ExportFormat::Lean4 => Lean4Parser::parse_all(s, last_uses, queue_handle, env_handle),
+    }
+}
+
+/// For each expression in `source`, the last line (counting from 1) that
+/// mentions it, found by a quick pass over the file that only looks
+/// at which of each line's fields are expressions. This has to agree
+/// with the parsers about where those fields are; if it ever missed one,
+/// the parser would give up on the line with an error rather than use
+/// an expression it had already let go of. Lines the parsers skip
+/// (`#REC`, `#RR`) are skipped here too.
+pub fn last_expr_uses(source : &str, format : ExportFormat) -> Vec<usize> {
+    let mut last_uses = Vec::new();
+    let mut fields = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        fields.clear();
+        fields.extend(line.split_whitespace());
+        let positions = match (fields.first().copied(), fields.get(1).copied()) {
+            (_, Some("#EA")) => vec![2, 3],
+            (_, Some("#EL")) | (_, Some("#EP")) => vec![4, 5],
+            (_, Some("#EZ")) => vec![3, 4, 5],
+            (_, Some("#EJ")) => vec![4],
+            (Some("#AX"), _) => vec![2],
+            (Some("#DEF"), _) | (Some("#THM"), _) | (Some("#OPAQ"), _) => vec![2, 3],
+            (Some("#CTOR"), _) => vec![2],
+            (Some("#IND"), _) if format == ExportFormat::Lean4 => vec![2],
+            // `#IND <num_params> <name> <type> <num_intros> (<intro_name> <intro_type>)* ...`
+            (Some("#IND"), _) => {
+                let num_intros = fields.get(4).and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+                Some(3).into_iter().chain((0..num_intros).map(|k| 6 + (2 * k))).collect()
+            },
+            _ => continue
+        };
+
+        for idx in positions.into_iter().filter_map(|pos| fields.get(pos)?.parse::<usize>().ok()) {
+            if idx >= last_uses.len() {
+                last_uses.resize(idx + 1, 0);
+            }
+            last_uses[idx] = line_idx + 1;
+        }
+    }
+    last_uses
+}
+
 
 pub struct LineParser<'s> {
     pub line_num: usize,
     pub names  : Vec<Name>,
     pub levels : Vec<Level>,
-    pub exprs  : Vec<Expr>,
+    /// `None` for an expression that's been let go of; see `last_uses`.
+    pub exprs  : Vec<Option<Expr>>,
+    /// If set, the line each expression is last used on (see
+    /// `last_expr_uses`); an expression is let go of once we're done
+    /// with that line.
+    pub last_uses : Option<Vec<usize>>,
+    /// Expressions whose last use was on the current line.
+    releasing : Vec<usize>,
     pub queue_handle : &'s ModQueue,
-    pub env_handle : &'s Arc<RwLock<Env>>,
-    pub prop : Expr
+    pub env_handle : &'s Arc<Env>,
+    pub prop : Expr,
+    pub guess_theorems : bool,
 }
 
 impl<'s> LineParser<'s> {
-    pub fn new(queue_handle : &'s ModQueue, env_handle : &'s Arc<RwLock<Env>>) -> LineParser<'s> {
+    pub fn new(queue_handle : &'s ModQueue, env_handle : &'s Arc<Env>) -> LineParser<'s> {
         let mut parser = LineParser {
             line_num: 1usize,
             names : Vec::with_capacity(12_000),
             levels : Vec::with_capacity(250),
             exprs : Vec::with_capacity(400_000),
+            last_uses : None,
+            releasing : Vec::new(),
             queue_handle,
             env_handle,
-            prop : mk_prop()
-
+            prop : mk_prop(),
+            guess_theorems : false,
         };
 
         parser.names.push(mk_anon());
@@ -76,8 +188,14 @@ impl<'s> LineParser<'s> {
         self.prop.clone()
     }
 
-    pub fn parse_all(s : String, queue_handle : &'s ModQueue, env_handle : &'s Arc<RwLock<Env>>) -> ParseResult<()> {
+    pub fn parse_all(s : String,
+                     guess_theorems : bool,
+                     last_uses : Option<Vec<usize>>,
+                     queue_handle : &'s ModQueue,
+                     env_handle : &'s Arc<Env>) -> ParseResult<()> {
         let mut parser = LineParser::new(queue_handle, env_handle);
+        parser.guess_theorems = guess_theorems;
+        parser.last_uses = last_uses;
         let mut as_lines = s.lines();
 
         while let Some(line) = &mut as_lines.next() {
@@ -85,7 +203,7 @@ impl<'s> LineParser<'s> {
                 Ok(_) => (),
                 Err(e) => return Err(e)
             }
-            parser.line_num  += 1;
+            parser.next_line();
         }
 
         parser.queue_handle.push(END_MSG_ADD);
@@ -93,6 +211,15 @@ impl<'s> LineParser<'s> {
         Ok(())
     }
 
+    /// Move on to the next line, letting go of any expressions that
+    /// were last used on this one.
+    pub fn next_line(&mut self) {
+        for idx in self.releasing.drain(..) {
+            self.exprs[idx] = None;
+        }
+        self.line_num += 1;
+    }
+
     pub fn try_next(&mut self, line : &str) -> ParseResult<()> {
         let mut ws = line.split_whitespace();
         match ws.next().ok_or(Exhausted(self.line_num, line!()))? {
@@ -106,36 +233,43 @@ impl<'s> LineParser<'s> {
             owise1         => {
                 let leading_num = owise1.parse::<usize>()
                                         .map_err(|e| ParseInt(self.line_num, line!(), e))?;
-                let mut as_chars = ws.next()
-                                     .ok_or(Exhausted(self.line_num, line!()))?
-                                     .chars();
-                assert!(as_chars.next() == Some('#')); 
-
-                match as_chars.next() {
-                    Some('N') => self.make_name(leading_num, as_chars.next().ok_or(Exhausted(self.line_num, line!()))?, &mut ws),
-                    Some('U') => self.make_level(leading_num, as_chars.next().ok_or(Exhausted(self.line_num, line!()))?, &mut ws),
-                    Some('E') => self.make_expr(leading_num, as_chars.next().ok_or(Exhausted(self.line_num, line!()))?, &mut ws),
-                    owise2 => return Err(StringErr(self.line_num, line!(), errors::err_parse_kind(&owise2)))
-                }
+                let kind = ws.next().ok_or(Exhausted(self.line_num, line!()))?;
+                self.make_component(leading_num, kind, line, &mut ws)
             }
         }
     }
 
+    /// Names, levels, and expressions; the lines that start with
+    /// their own index, IE `12 #EA 10 11`.
+    pub fn make_component(&mut self, leading_num : usize, kind : &str, line : &str, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let mut as_chars = kind.chars();
+        if as_chars.next() != Some('#') {
+            return Err(StringErr(self.line_num, line!(), format!("expected a '#' before the item kind on line {}", line)))
+        }
+
+        match as_chars.next() {
+            Some('N') => self.make_name(leading_num, as_chars.next().ok_or(Exhausted(self.line_num, line!()))?, ws),
+            Some('U') => self.make_level(leading_num, as_chars.next().ok_or(Exhausted(self.line_num, line!()))?, ws),
+            Some('E') => self.make_expr(leading_num, as_chars.next().ok_or(Exhausted(self.line_num, line!()))?, ws),
+            owise2 => Err(StringErr(self.line_num, line!(), errors::err_parse_kind(&owise2)))
+        }
+    }
+
 
-    fn parse_usize(&mut self, ws : &mut SplitWhitespace) -> ParseResult<usize> {
+    pub fn parse_usize(&mut self, ws : &mut SplitWhitespace) -> ParseResult<usize> {
           ws.next()
             .ok_or(Exhausted(self.line_num, line!()))
             .and_then(|item| item.parse::<usize>().map_err(|e| ParseInt(self.line_num, line!(), e)))
     }
 
-    fn parse_u64(&mut self, ws : &mut SplitWhitespace) -> ParseResult<u64> {
+    pub fn parse_u64(&mut self, ws : &mut SplitWhitespace) -> ParseResult<u64> {
           ws.next()
             .ok_or(Exhausted(self.line_num, line!()))
             .and_then(|item| item.parse::<u64>().map_err(|e| ParseInt(self.line_num, line!(), e)))
     }
     
     
-    fn parse_rest_usize(&mut self, ws : &mut SplitWhitespace) -> ParseResult<Vec<usize>> {
+    pub fn parse_rest_usize(&mut self, ws : &mut SplitWhitespace) -> ParseResult<Vec<usize>> {
            ws.map(|elem| elem.parse::<usize>().map_err(|e| ParseInt(self.line_num, line!(), e)))
              .collect::<ParseResult<Vec<usize>>>()
     }
@@ -147,7 +281,7 @@ impl<'s> LineParser<'s> {
     pub fn get_levels(&mut self, ws : &mut SplitWhitespace) -> ParseResult<Vec<Level>> {
           ws.into_iter()
             .map(|elem| elem.parse::<usize>().map_err(|e| ParseInt(self.line_num, line!(), e)))
-            .map(|res| res.map(|idx| self.levels.get(idx).map(|x| x).cloned().unwrap_or_else(|| self.ref_zero())))
+            .map(|res| res.map(|idx| self.levels.get(idx).cloned().unwrap_or_else(|| self.ref_zero())))
             .collect::<ParseResult<Vec<Level>>>()
     }
 
@@ -162,29 +296,43 @@ impl<'s> LineParser<'s> {
     }
 
     pub fn parse_binder_info(&mut self, ws : &mut SplitWhitespace) -> ParseResult<BinderStyle> {
-        ws.next().map(|elem| match elem {
-            s if s.contains("#BD") => BinderStyle::Default,
-            s if s.contains("#BI") => BinderStyle::Implicit,
-            s if s.contains("#BC") => BinderStyle::InstImplicit,
-            s if s.contains("#BS") => BinderStyle::StrictImplicit,
-            _ => unreachable!(),
-        }).ok_or(Exhausted(self.line_num, line!()))
+        match ws.next().ok_or(Exhausted(self.line_num, line!()))? {
+            s if s.contains("#BD") => Ok(BinderStyle::Default),
+            s if s.contains("#BI") => Ok(BinderStyle::Implicit),
+            s if s.contains("#BC") => Ok(BinderStyle::InstImplicit),
+            s if s.contains("#BS") => Ok(BinderStyle::StrictImplicit),
+            owise => Err(StringErr(self.line_num, line!(), format!("unrecognized binder info {:?}", owise))),
+        }
     }
 
     pub fn get_name(&mut self, ws : &mut SplitWhitespace) -> ParseResult<Name> {
         self.parse_usize(ws)
-            .map(|idx| self.names.get(idx).map(|x| x).cloned().unwrap_or_else(|| self.ref_anon()))
+            .map(|idx| self.names.get(idx).cloned().unwrap_or_else(|| self.ref_anon()))
     }
 
 
     pub fn get_level(&mut self, ws : &mut SplitWhitespace) -> ParseResult<Level> {
         self.parse_usize(ws)
-            .map(|idx| self.levels.get(idx).map(|x| x).cloned().unwrap_or_else(|| self.ref_zero()))
+            .map(|idx| self.levels.get(idx).cloned().unwrap_or_else(|| self.ref_zero()))
     }
 
     pub fn get_expr(&mut self, ws : &mut SplitWhitespace) -> ParseResult<Expr> {
-        self.parse_usize(ws)
-            .map(|idx| self.exprs.get(idx).map(|x| x).cloned().unwrap_or_else(|| self.ref_prop()))
+        let idx = self.parse_usize(ws)?;
+        self.expr_at(idx)
+    }
+
+    pub fn expr_at(&mut self, idx : usize) -> ParseResult<Expr> {
+        match self.exprs.get(idx) {
+            None => Ok(self.ref_prop()),
+            Some(None) => Err(StringErr(self.line_num, line!(), format!("expression {} was let go of after what should have been its last use", idx))),
+            Some(Some(e)) => {
+                let e = e.clone();
+                if self.last_uses.as_ref().and_then(|uses| uses.get(idx)) == Some(&self.line_num) {
+                    self.releasing.push(idx);
+                }
+                Ok(e)
+            }
+        }
     }
 
     pub fn make_name(&mut self, new_pos : usize, kind : char, ws : &mut SplitWhitespace) -> ParseResult<()> {
@@ -192,11 +340,11 @@ impl<'s> LineParser<'s> {
         let new_name = match kind {
             'S' => prefix_name.extend_str(self.parse_rest_string(ws).as_str()),
             'I' => self.parse_u64(ws).map(|hd| prefix_name.extend_num(hd))?,
-            _ => unreachable!("parser line : {}", line!())
+            owise => return Err(StringErr(self.line_num, line!(), format!("unrecognized name kind {:?}", owise)))
         };
 
 
-        write_elem_strict(&mut self.names, new_name, new_pos)
+        write_elem_strict(&mut self.names, new_name, new_pos, self.line_num)
     }
 
 
@@ -207,10 +355,10 @@ impl<'s> LineParser<'s> {
             'M'  => mk_max(self.get_level(ws)?, self.get_level(ws)?),
             'I'  => mk_imax(self.get_level(ws)?, self.get_level(ws)?),
             'P'  => mk_param(self.get_name(ws)?),
-            _ => unreachable!("parser line : {}", line!())
+            owise => return Err(StringErr(self.line_num, line!(), format!("unrecognized level kind {:?}", owise)))
         };
 
-        write_elem_strict(&mut self.levels, new_level, new_pos)
+        write_elem_strict(&mut self.levels, new_level, new_pos, self.line_num)
     }
 
 
@@ -225,8 +373,7 @@ impl<'s> LineParser<'s> {
                 let binder_info = self.parse_binder_info(ws)?;
                 let binder_name = self.get_name(ws)?;
                 let domain = self.get_expr(ws)?;
-                let lambda = mk_lambda(Binding::mk(binder_name, domain, binder_info), self.get_expr(ws)?);
-                lambda
+                mk_lambda(Binding::mk(binder_name, domain, binder_info), self.get_expr(ws)?)
             },
             'P' => {
                 let binder_info = self.parse_binder_info(ws)?;
@@ -241,10 +388,10 @@ impl<'s> LineParser<'s> {
                 let body = self.get_expr(ws)?;
                 mk_let(Binding::mk(name, ty, BinderStyle::Default), val, body)
             },
-            otherwise => unreachable!("parser line : {} expectex expression cue, got {:?}", line!(), otherwise)
+            owise => return Err(StringErr(self.line_num, line!(), format!("unrecognized expression kind {:?}", owise)))
         };
 
-        write_elem_strict(&mut self.exprs, new_expr, new_pos)
+        write_elem_strict(&mut self.exprs, Some(new_expr), new_pos, self.line_num)
     }
 
 
@@ -263,10 +410,10 @@ impl<'s> LineParser<'s> {
             "#PREFIX"  => Notation::new_prefix(name.clone(), priority, symbol),
             "#INFIX"   => Notation::new_infix(name.clone(), priority, symbol),
             "#POSTFIX" => Notation::new_postfix(name.clone(), priority, symbol),
-            _ => unreachable!()
+            owise => return Err(StringErr(self.line_num, line!(), format!("unrecognized notation kind {:?}", owise)))
         };
 
-        self.env_handle.write().add_notation(&name, made);
+        self.env_handle.add_notation(&name, made);
         Ok(())
     }
 
@@ -275,7 +422,8 @@ impl<'s> LineParser<'s> {
         let ty = self.get_expr(ws)?;
         let uparams = self.get_uparams(ws)?;
         let axiom = Axiom::new(name, Arc::new(uparams), ty);
-        Ok(self.queue_handle.push(Left(Modification::AxiomMod(axiom))))
+        self.queue_handle.push(Left(Modification::AxiomMod(axiom)));
+        Ok(())
 
     }
 
@@ -284,12 +432,15 @@ impl<'s> LineParser<'s> {
         let ty = self.get_expr(ws)?;
         let val = self.get_expr(ws)?;
         let uparams = self.get_uparams(ws)?;
-        let def = Definition::new(name, Arc::new(uparams), ty, val);
-        Ok(self.queue_handle.push(Left(Modification::DefMod(def))))
+        let kind = if self.guess_theorems { None } else { Some(DefKind::Definition) };
+        let def = Definition::new(name, Arc::new(uparams), ty, val).with_kind(kind);
+        self.queue_handle.push(Left(Modification::DefMod(def)));
+        Ok(())
     }
 
     pub fn make_quotient(&mut self) -> ParseResult<()> {
-        self.queue_handle.push(Left(new_quot()));
+        let quot = new_quot(Name::from("quot"), Name::from("eq")).map_err(|e| StringErr(self.line_num, line!(), e.to_string()))?;
+        self.queue_handle.push(Left(quot));
         Ok(())
     }
 
@@ -310,12 +461,13 @@ impl<'s> LineParser<'s> {
 
         for two_slice in intros.chunks(2usize) {
             let name = self.names.get(two_slice[0]).cloned().unwrap_or_else(|| self.ref_anon());
-            let ty = self.exprs.get(two_slice[1]).cloned().unwrap_or_else(|| self.ref_prop());
+            let ty = self.expr_at(two_slice[1])?;
             intros_buf.push((name, ty));
         }
 
-        let ind_mod = crate::inductive::ProtoInd { name, params: Arc::new(param_vec), ty, num_params, intros: intros_buf };
-        Ok(self.queue_handle.push(Left(Modification::IndMod(ind_mod))))
+        let ind_mod = crate::inductive::ProtoInd { name, params: Arc::new(param_vec), ty, num_params, intros: intros_buf, always_dep_elim: false, num_indices: None, is_rec: None };
+        self.queue_handle.push(Left(Modification::IndMod(vec![ind_mod])));
+        Ok(())
     }
 
 }
@@ -324,16 +476,58 @@ impl<'s> LineParser<'s> {
 // FIXME add command-line flag for strict/non-strict export file parsing.
 // Strict assumes that well-formed export files will not have 'holes' when filling
 // in comopnent arrays; IE all items will be placed consecutively.
-fn write_elem_strict<T>(v : &mut Vec<T>, new_elem : T, pos : usize) -> ParseResult<()> {
-    assert!(v.len() == pos);
-    match v.get_mut(pos) {
-        Some(_) => { 
-            eprintln!("malformed export file; components should never require replacement within vectors.");
-            std::process::exit(-1);
-        },
-        None => {
-            v.push(new_elem);
+pub(crate) fn write_elem_strict<T>(v : &mut Vec<T>, new_elem : T, pos : usize, line_num : usize) -> ParseResult<()> {
+    if v.len() == pos {
+        v.push(new_elem);
+        Ok(())
+    } else {
+        Err(StringErr(line_num, line!(), format!("malformed export file; expected the next component at index {}, but it was declared at index {}", v.len(), pos)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::WorkQueue;
+
+    /// `nat`, then `f : nat → nat := fun n, let m : nat := nat.zero in nat.succ m`.
+    /// Expression 9 is never used.
+    const SOURCE : &str = "\
+1 #NS 0 nat
+2 #NS 1 zero
+3 #NS 1 succ
+4 #NS 0 n
+5 #NS 0 f
+6 #NS 0 m
+1 #US 0
+0 #ES 1
+1 #EC 1
+2 #EP #BD 4 1 1
+#IND 0 1 0 2 2 1 3 2
+3 #EC 2
+4 #EC 3
+5 #EV 0
+6 #EA 4 5
+7 #EZ 6 1 3 6
+8 #EL #BD 4 1 7
+#DEF 5 2 8
+9 #ES 0
+";
+
+    /// Every expression is let go of on the line `last_expr_uses` says
+    /// it's last used on, which only happens if the parser reads it there,
+    /// and parsing succeeding means nothing was read after that.
+    #[test]
+    fn release_positions_match_the_parser() {
+        let queue = WorkQueue::with_capacity(10);
+        let env = Arc::new(Env::new(10));
+        let mut parser = LineParser::new(&queue, &env);
+        parser.last_uses = Some(last_expr_uses(SOURCE, ExportFormat::Lean3));
+        for line in SOURCE.lines() {
+            parser.try_next(line).unwrap();
+            parser.next_line();
         }
+        let kept = parser.exprs.iter().map(Option::is_some).collect::<Vec<bool>>();
+        assert_eq!(kept, [vec![false; 9], vec![true]].concat());
     }
-    Ok(())
 }
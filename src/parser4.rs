@@ -0,0 +1,522 @@
+use std::sync::Arc;
+use std::str::SplitWhitespace;
+use std::collections::VecDeque;
+
+use hashbrown::HashSet;
+use num_bigint::BigUint;
+
+use crate::name::Name;
+use crate::level::Level;
+use crate::expr::{ Expr, mk_nat_lit, mk_str_lit, mk_proj };
+use crate::env::{ Env, Modification, Axiom, Definition, DefKind, ReducibilityHint };
+use crate::inductive::ProtoInd;
+use crate::quot::new_quot;
+use crate::parser::{ LineParser, ParseResult, ParseErr::*, is_version_header, write_elem_strict };
+use crate::utils::{ Either::*, END_MSG_ADD, ModQueue };
+
+/// Front-end for exports written by Lean 4's `lean4export`. Names, levels
+/// and expressions are written the same way as in Lean 3, so those lines
+/// are handed straight to a `LineParser`. The declaration lines are
+/// different :
+///```pseudo
+/// #AX <name> <type> <uparams>*
+/// #DEF <name> <type> <value> <hint> <uparams>*
+/// #THM <name> <type> <value> <uparams>*
+/// #OPAQ <name> <type> <value> <uparams>*
+/// #QUOT <name> <type> <uparams>*
+/// #IND <name> <type> <is_rec> <is_nested> <num_params> <num_indices>
+///      <num_inds> <ind_name>* <num_ctors> <ctor_name>* <uparams>*
+/// #CTOR <name> <type> <inductive> <ctor_idx> <num_params> <num_fields> <uparams>*
+/// #REC ...
+/// <idx> #RR ...
+/// <idx> #ELN <digits>
+/// <idx> #ELS <utf8 byte in hex>*
+/// <idx> #EJ <struct_name> <field_idx> <expr>
+///```
+/// where `<hint>` is `O`, `A`, or `R <height>`. Lean 4 spreads an
+/// inductive over one `#IND` line and one `#CTOR` line per constructor,
+/// which don't have to be next to each other, and writes each of the
+/// four quotient constants on its own `#QUOT` line. The types of a
+/// mutual block each get their own `#IND` line, all of which list the
+/// whole block in `<ind_name>*`. We put these back
+/// together into the same `Modification`s the Lean 3 front-end
+/// produces. Recursors (and their rules) are derived by
+/// `inductive::Inductive` rather than taken from the file, so `#REC`
+/// and `#RR` lines are skipped.
+pub struct Lean4Parser<'s> {
+    inner : LineParser<'s>,
+    slots : VecDeque<Slot>,
+}
+
+/// Items waiting to go out to the add queue. An item can't go out
+/// until everything before it has, since it might depend on them.
+enum Slot {
+    Ready(Modification),
+    Ind(PendingInd),
+    Quot(HashSet<Name>),
+}
+
+/// A block of (mutually) inductive types, some of whose `#IND` lines
+/// or constructors haven't shown up yet. A lone inductive is a block of
+/// one. `all` is the block as listed on each `#IND` line, and `types`
+/// lines up with it.
+struct PendingInd {
+    all : Vec<Name>,
+    univ_params : Arc<Vec<Level>>,
+    num_params : usize,
+    types : Vec<Option<PendingType>>,
+}
+
+struct PendingType {
+    ty : Expr,
+    num_indices : usize,
+    is_rec : bool,
+    ctor_names : Vec<Name>,
+    ctors : Vec<Option<Expr>>,
+}
+
+impl PendingInd {
+    fn is_complete(&self) -> bool {
+        self.types.iter().all(|t| t.as_ref().map(|t| t.ctors.iter().all(|c| c.is_some())).unwrap_or(false))
+    }
+
+    /// Names of the types and constructors we're still waiting on.
+    fn missing(&self) -> Vec<String> {
+        self.all.iter().zip(self.types.iter()).flat_map(|(name, t)| match t {
+            None => vec![name.to_string()],
+            Some(t) => t.ctor_names.iter()
+                                   .zip(t.ctors.iter())
+                                   .filter(|(_, c)| c.is_none())
+                                   .map(|(n, _)| n.to_string())
+                                   .collect()
+        }).collect()
+    }
+
+    fn into_modification(self) -> Modification {
+        let univ_params = self.univ_params;
+        let num_params = self.num_params;
+        let block = self.all.into_iter().zip(self.types.into_iter().flatten()).map(|(name, t)| {
+            let intros = t.ctor_names
+                          .into_iter()
+                          .zip(t.ctors.into_iter().flatten())
+                          .collect::<Vec<(Name, Expr)>>();
+            ProtoInd {
+                name,
+                params : univ_params.clone(),
+                ty : t.ty,
+                num_params,
+                intros,
+                always_dep_elim : true,
+                num_indices : Some(t.num_indices),
+                is_rec : Some(t.is_rec),
+            }
+        }).collect();
+        Modification::IndMod(block)
+    }
+}
+
+fn quot_names() -> Vec<Name> {
+    let quot = Name::from("Quot");
+    vec![quot.clone(), quot.extend_str("mk"), quot.extend_str("lift"), quot.extend_str("ind")]
+}
+
+impl Slot {
+    fn declared_names(&self) -> Vec<Name> {
+        match self {
+            Slot::Ready(m) => m.declared_names(),
+            Slot::Ind(ind) => {
+                let mut acc = ind.all.iter().flat_map(|n| vec![n.clone(), n.extend_str("rec")]).collect::<Vec<Name>>();
+                acc.extend(ind.types.iter().flatten().flat_map(|t| t.ctor_names.iter().cloned()));
+                acc
+            },
+            Slot::Quot(_) => quot_names(),
+        }
+    }
+
+    fn mentions_any(&self, names : &HashSet<Name>) -> bool {
+        match self {
+            Slot::Ready(m) => m.used_names().iter().any(|n| names.contains(n)),
+            Slot::Ind(ind) => {
+                ind.types.iter()
+                         .flatten()
+                         .flat_map(|t| Some(&t.ty).into_iter().chain(t.ctors.iter().flatten()))
+                         .any(|e| crate::expr::unique_const_names(e).iter().any(|n| names.contains(*n)))
+            },
+            Slot::Quot(_) => false
+        }
+    }
+}
+
+impl<'s> Lean4Parser<'s> {
+    pub fn new(queue_handle : &'s ModQueue, env_handle : &'s Arc<Env>) -> Lean4Parser<'s> {
+        Lean4Parser {
+            inner : LineParser::new(queue_handle, env_handle),
+            slots : VecDeque::new(),
+        }
+    }
+
+    /// See `LineParser::parse_all` for `last_uses`.
+    pub fn parse_all(s : String,
+                     last_uses : Option<Vec<usize>>,
+                     queue_handle : &'s ModQueue,
+                     env_handle : &'s Arc<Env>) -> ParseResult<()> {
+        let mut parser = Lean4Parser::new(queue_handle, env_handle);
+        parser.inner.last_uses = last_uses;
+
+        for line in s.lines() {
+            // skip the format version header, if there is one.
+            if !(parser.inner.line_num == 1 && is_version_header(line.trim())) {
+                parser.try_next(line)?;
+                parser.flush();
+            }
+            parser.inner.next_line();
+        }
+
+        parser.finish()?;
+        parser.inner.queue_handle.push(END_MSG_ADD);
+        parser.inner.queue_handle.push(END_MSG_ADD);
+        Ok(())
+    }
+
+    pub fn try_next(&mut self, line : &str) -> ParseResult<()> {
+        let mut ws = line.split_whitespace();
+        match ws.next().ok_or(Exhausted(self.inner.line_num, line!()))? {
+            "#AX"   => self.make_axiom(&mut ws),
+            "#DEF"  => self.make_definition(&mut ws, DefKind::Definition),
+            "#THM"  => self.make_definition(&mut ws, DefKind::Theorem),
+            "#OPAQ" => self.make_definition(&mut ws, DefKind::Opaque),
+            "#QUOT" => self.make_quotient(&mut ws),
+            "#IND"  => self.make_inductive(&mut ws),
+            "#CTOR" => self.make_constructor(&mut ws),
+            // Recursors are derived from the `#IND` and `#CTOR` lines, and
+            // the exported ones never go into the environment. Anything that
+            // uses `T.rec` is checked against the derived recursor, so a bad
+            // `#REC` line can't get anything past the kernel.
+            "#REC"  => Ok(()),
+            owise   => {
+                let leading_num = owise.parse::<usize>()
+                                       .map_err(|e| ParseInt(self.inner.line_num, line!(), e))?;
+                match ws.next().ok_or(Exhausted(self.inner.line_num, line!()))? {
+                    "#RR" => Ok(()),
+                    "#ELN" => self.make_nat_lit(leading_num, &mut ws),
+                    "#ELS" => self.make_str_lit(leading_num, &mut ws),
+                    "#EJ" => self.make_proj(leading_num, &mut ws),
+                    kind => self.inner.make_component(leading_num, kind, line, &mut ws)
+                }
+            }
+        }
+    }
+
+    /// `<idx> #ELN <digits>`
+    fn make_nat_lit(&mut self, new_pos : usize, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let digits = ws.next().ok_or(Exhausted(self.inner.line_num, line!()))?;
+        let n = digits.parse::<BigUint>()
+                      .map_err(|e| self.err(format!("bad natural number literal {:?}; {}", digits, e)))?;
+        write_elem_strict(&mut self.inner.exprs, Some(mk_nat_lit(n)), new_pos, self.inner.line_num)
+    }
+
+    /// `<idx> #EJ <struct_name> <field_idx> <expr>`
+    fn make_proj(&mut self, new_pos : usize, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let struct_name = self.inner.get_name(ws)?;
+        let idx = self.inner.parse_usize(ws)?;
+        let structure = self.inner.get_expr(ws)?;
+        write_elem_strict(&mut self.inner.exprs, Some(mk_proj(struct_name, idx, structure)), new_pos, self.inner.line_num)
+    }
+
+    /// `<idx> #ELS <byte>*`, with the string's UTF-8 bytes written in hex.
+    fn make_str_lit(&mut self, new_pos : usize, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let bytes = ws.map(|b| u8::from_str_radix(b, 16).map_err(|e| self.err(format!("bad byte {:?} in string literal; {}", b, e))))
+                      .collect::<ParseResult<Vec<u8>>>()?;
+        let s = String::from_utf8(bytes).map_err(|e| self.err(format!("string literal is not valid UTF-8; {}", e)))?;
+        write_elem_strict(&mut self.inner.exprs, Some(mk_str_lit(s)), new_pos, self.inner.line_num)
+    }
+
+    fn err(&self, msg : String) -> crate::parser::ParseErr {
+        StringErr(self.inner.line_num, line!(), msg)
+    }
+
+    fn push(&mut self, m : Modification) {
+        self.slots.push_back(Slot::Ready(m));
+    }
+
+    /// Send everything at the front of the line that's ready.
+    fn flush(&mut self) {
+        while let Some(Slot::Ready(_)) = self.slots.front() {
+            if let Some(Slot::Ready(m)) = self.slots.pop_front() {
+                self.inner.queue_handle.push(Left(m));
+            }
+        }
+    }
+
+    /// The pending item at `idx` is done. Since it's only done now, it
+    /// goes after everything that showed up while it was pending, except
+    /// for the items that mention it (or mention those items, and so on),
+    /// which have to stay behind it.
+    fn complete(&mut self, idx : usize) -> ParseResult<()> {
+        let rest = self.slots.split_off(idx + 1);
+        let m = match self.slots.pop_back() {
+            Some(Slot::Ready(m)) => m,
+            Some(Slot::Ind(ind)) => ind.into_modification(),
+            Some(Slot::Quot(_)) => new_quot(Name::from("Quot"), Name::from("Eq")).map_err(|e| self.err(e.to_string()))?,
+            None => return Ok(())
+        };
+        let mut moved_names = m.declared_names().into_iter().collect::<HashSet<Name>>();
+
+        let mut moved = Vec::new();
+        for slot in rest {
+            if slot.mentions_any(&moved_names) {
+                moved_names.extend(slot.declared_names());
+                moved.push(slot);
+            } else {
+                self.slots.push_back(slot);
+            }
+        }
+
+        self.slots.push_back(Slot::Ready(m));
+        self.slots.extend(moved);
+        Ok(())
+    }
+
+    /// At the end of the file every inductive should have all of its
+    /// constructors. If only some of the quotient constants were
+    /// exported, that's fine; we add all of them anyway.
+    fn finish(&mut self) -> ParseResult<()> {
+        if let Some(idx) = self.slots.iter().position(|slot| matches!(slot, Slot::Quot(_))) {
+            self.complete(idx)?;
+        }
+
+        for slot in self.slots.iter() {
+            if let Slot::Ind(ind) = slot {
+                return Err(self.err(format!("reached the end of the file, but inductive {} is missing {}", ind.all[0], ind.missing().join(", "))))
+            }
+        }
+
+        self.flush();
+        Ok(())
+    }
+
+    fn make_axiom(&mut self, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let name = self.inner.get_name(ws)?;
+        let ty = self.inner.get_expr(ws)?;
+        let uparams = self.inner.get_uparams(ws)?;
+        self.push(Modification::AxiomMod(Axiom::new(name, Arc::new(uparams), ty)));
+        Ok(())
+    }
+
+    /// Definitions, theorems and opaque constants. Only `#DEF` lines have
+    /// a reducibility hint; theorems and opaque definitions are never
+    /// unfolded, so they don't need one.
+    fn make_definition(&mut self, ws : &mut SplitWhitespace, kind : DefKind) -> ParseResult<()> {
+        let name = self.inner.get_name(ws)?;
+        let ty = self.inner.get_expr(ws)?;
+        let val = self.inner.get_expr(ws)?;
+        let hint = if kind == DefKind::Definition {
+            match ws.next().ok_or(Exhausted(self.inner.line_num, line!()))? {
+                "O" => ReducibilityHint::Opaque,
+                "A" => ReducibilityHint::Abbrev,
+                "R" => ReducibilityHint::Regular(self.inner.parse_u64(ws)?.min(u16::MAX as u64) as u16),
+                owise => return Err(self.err(format!("unrecognized reducibility hint {:?}", owise)))
+            }
+        } else {
+            ReducibilityHint::Opaque
+        };
+        let uparams = self.inner.get_uparams(ws)?;
+        let def = Definition::new(name, Arc::new(uparams), ty, val).with_hint(hint).with_kind(Some(kind));
+        self.push(Modification::DefMod(def));
+        Ok(())
+    }
+
+    /// The kernel builds the quotient constants itself, so all we need from
+    /// these lines is to know that the quotient is being used. We wait
+    /// until all four constants have shown up (or the file ends) before
+    /// adding it, since the quotient needs `Eq`, which might come after
+    /// the first `#QUOT` line.
+    fn make_quotient(&mut self, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let name = self.inner.get_name(ws)?;
+        if !quot_names().contains(&name) {
+            return Err(self.err(format!("expected #QUOT to declare one of Quot, Quot.mk, Quot.lift or Quot.ind, but got {}", name)))
+        }
+
+        match self.slots.iter().position(|slot| matches!(slot, Slot::Quot(_))) {
+            Some(idx) => {
+                let done = match &mut self.slots[idx] {
+                    Slot::Quot(seen) => {
+                        seen.insert(name);
+                        seen.len() == quot_names().len()
+                    },
+                    _ => false
+                };
+                if done {
+                    self.complete(idx)?;
+                }
+            },
+            None => {
+                let mut seen = HashSet::new();
+                seen.insert(name);
+                self.slots.push_back(Slot::Quot(seen));
+            }
+        }
+        Ok(())
+    }
+
+    fn make_inductive(&mut self, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let name = self.inner.get_name(ws)?;
+        let ty = self.inner.get_expr(ws)?;
+        let is_rec = self.inner.parse_usize(ws)? != 0;
+        let _is_nested = self.inner.parse_usize(ws)?;
+        let num_params = self.inner.parse_usize(ws)?;
+        let num_indices = self.inner.parse_usize(ws)?;
+        let num_inds = self.inner.parse_usize(ws)?;
+        let all = (0..num_inds).map(|_| self.inner.get_name(ws))
+                               .collect::<ParseResult<Vec<Name>>>()?;
+
+        let num_ctors = self.inner.parse_usize(ws)?;
+        let ctor_names = (0..num_ctors).map(|_| self.inner.get_name(ws))
+                                       .collect::<ParseResult<Vec<Name>>>()?;
+        let univ_params = Arc::new(self.inner.get_uparams(ws)?);
+
+        let ty_idx = match all.iter().position(|n| n == &name) {
+            Some(ty_idx) => ty_idx,
+            None => return Err(self.err(format!("inductive {} isn't one of the inductives in its own block", name)))
+        };
+
+        let pending_type = PendingType {
+            ty,
+            num_indices,
+            is_rec,
+            ctors : vec![None; ctor_names.len()],
+            ctor_names,
+        };
+
+        // The other types in a mutual block each have their own `#IND` line,
+        // which might have come already.
+        let idx = match self.slots.iter().position(|slot| matches!(slot, Slot::Ind(ind) if ind.all == all)) {
+            Some(idx) => idx,
+            None => {
+                self.slots.push_back(Slot::Ind(PendingInd {
+                    types : all.iter().map(|_| None).collect(),
+                    all,
+                    univ_params : univ_params.clone(),
+                    num_params,
+                }));
+                self.slots.len() - 1
+            }
+        };
+
+        let done = match &mut self.slots[idx] {
+            Slot::Ind(ind) => {
+                if ind.num_params != num_params || ind.univ_params != univ_params {
+                    return Err(StringErr(self.inner.line_num, line!(), format!("inductive {} doesn't have the same parameters or universe parameters as {}, which is in the same mutual block", name, ind.all[0])))
+                }
+                if ind.types[ty_idx].is_some() {
+                    return Err(StringErr(self.inner.line_num, line!(), format!("inductive {} was already declared", name)))
+                }
+                ind.types[ty_idx] = Some(pending_type);
+                ind.is_complete()
+            },
+            _ => false
+        };
+
+        if done {
+            self.complete(idx)?;
+        }
+        Ok(())
+    }
+
+    fn make_constructor(&mut self, ws : &mut SplitWhitespace) -> ParseResult<()> {
+        let name = self.inner.get_name(ws)?;
+        let ty = self.inner.get_expr(ws)?;
+        let induct = self.inner.get_name(ws)?;
+        let ctor_idx = self.inner.parse_usize(ws)?;
+
+        let found = self.slots.iter().enumerate().find_map(|(idx, slot)| match slot {
+            Slot::Ind(ind) => ind.all.iter().position(|n| n == &induct).map(|ty_idx| (idx, ty_idx)),
+            _ => None
+        });
+        let (idx, ty_idx) = match found {
+            Some(idx) => idx,
+            None => return Err(self.err(format!("constructor {} belongs to {}, which isn't an inductive waiting on its constructors", name, induct)))
+        };
+
+        let done = match &mut self.slots[idx] {
+            Slot::Ind(ind) => {
+                let pending_type = match ind.types[ty_idx].as_mut() {
+                    Some(t) if t.ctor_names.get(ctor_idx) == Some(&name) && t.ctors[ctor_idx].is_none() => t,
+                    _ => return Err(StringErr(self.inner.line_num, line!(), format!("{} is not constructor #{} of {}, or it was already declared", name, ctor_idx, induct)))
+                };
+                pending_type.ctors[ctor_idx] = Some(ty);
+                ind.is_complete()
+            },
+            _ => false
+        };
+
+        if done {
+            self.complete(idx)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ ExportFormat, last_expr_uses };
+    use crate::utils::WorkQueue;
+
+    /// `A : Type`, `a : A`, a structure `P` with `P.mk : A → P`, the
+    /// projection `t : A := (P.mk a).1`, an opaque `o : A := a`, and the
+    /// theorem `th : ∀ x : Prop, x → x`. Expressions 15 and 16 are never
+    /// used.
+    const SOURCE : &str = "\
+1 #NS 0 A
+2 #NS 0 P
+3 #NS 2 mk
+4 #NS 0 x
+5 #NS 0 a
+6 #NS 0 t
+7 #NS 0 o
+8 #NS 0 th
+1 #US 0
+0 #ES 1
+1 #EC 1
+2 #EC 2
+3 #EP #BD 4 1 2
+#AX 1 0
+#AX 5 1
+#IND 2 0 0 0 0 0 1 2 1 3
+#CTOR 3 3 2 0 0 1
+4 #EC 5
+5 #EC 3
+6 #EA 5 4
+7 #EJ 2 0 6
+#DEF 6 1 7 R 1
+#OPAQ 7 1 4
+8 #ES 0
+9 #EV 0
+10 #EV 1
+11 #EP #BD 4 9 10
+12 #EP #BD 4 8 11
+13 #EL #BD 4 9 9
+14 #EL #BD 4 8 13
+#THM 8 12 14
+15 #ELN 5
+16 #ELS 68 69
+";
+
+    /// See `parser::tests::release_positions_match_the_parser`.
+    #[test]
+    fn release_positions_match_the_parser() {
+        let queue = WorkQueue::with_capacity(10);
+        let env = Arc::new(Env::new(10));
+        let mut parser = Lean4Parser::new(&queue, &env);
+        parser.inner.last_uses = Some(last_expr_uses(SOURCE, ExportFormat::Lean4));
+        for line in SOURCE.lines() {
+            parser.try_next(line).unwrap();
+            parser.flush();
+            parser.inner.next_line();
+        }
+        parser.finish().unwrap();
+        let kept = parser.inner.exprs.iter().map(Option::is_some).collect::<Vec<bool>>();
+        assert_eq!(kept, [vec![false; 15], vec![true; 2]].concat());
+    }
+}
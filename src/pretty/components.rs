@@ -107,11 +107,11 @@ impl From<&InnerDoc> for Doc {
 impl Doc {
 
     pub fn line() -> Doc {
-        Line(format!(" ")).into()
+        Line(String::from(" ")).into()
     }
 
     pub fn zero_width_line() -> Doc {
-        Line(format!("")).into()
+        Line(String::new()).into()
     }
 
     pub fn as_text(t : String) -> Doc {
@@ -218,8 +218,8 @@ impl Doc {
                     acc.push_str(x.as_str());
                 } else {
                     assert!(!flatmode);
-                    acc.push_str("\n");
-                    std::mem::replace(eol, (acc.len() + line_width));
+                    acc.push('\n');
+                    *eol = acc.len() + line_width;
                     for _ in 0..nest {
                         acc.push(' ');
                     }
@@ -246,7 +246,7 @@ impl Doc {
     }
 
     pub fn concat_plus(self, rhs : impl Into<Doc>) -> Doc {
-        let lhs = Concat(self, Text(format!(" ")).into()).into();
+        let lhs = Concat(self, Text(String::from(" ")).into()).into();
         Concat(lhs, rhs.into()).into()
     }
 
@@ -1,13 +1,12 @@
 use std::cell::RefCell;
 use std::sync::Arc;
 use hashbrown::HashSet;
-use parking_lot::RwLock;
 
 use crate::name::Name;
 use crate::level::{ Level, InnerLevel::* };
 use crate::expr::{ Expr, InnerExpr::*, Binding, BinderStyle };
 use crate::tc::TypeChecker;
-use crate::env::{ Declaration, Env };
+use crate::env::{ Declaration, Env, DefKind };
 use crate::pretty::components::{ word_wrap_val, Notation, Parenable, Notation::*, Doc, InnerDoc::*, MAX_PRIORITY };
 
 // We're using a RefCell since we need the ability to 
@@ -22,8 +21,8 @@ pub struct PrettyPrinter {
 
 
 impl PrettyPrinter {
-    pub fn new(options : Option<PPOptions>, env : &Arc<RwLock<Env>>) -> Self {
-        let options = options.unwrap_or_else(|| PPOptions::new_default());
+    pub fn new(options : Option<PPOptions>, env : &Arc<Env>) -> Self {
+        let options = options.unwrap_or_else(PPOptions::new_default);
         PrettyPrinter {
             pp_options : options,
             tc : RefCell::new(TypeChecker::new(Some(true), env.clone())),
@@ -32,7 +31,7 @@ impl PrettyPrinter {
     }
 
     pub fn lookup_notation(&self, name : &Name) -> Option<Notation> {
-        self.tc.borrow().env.read().notations.get(name).cloned()
+        self.tc.borrow().env.notations.get(name).cloned()
     }
 
     pub fn nest(&self, doc : Doc) -> Doc {
@@ -77,7 +76,7 @@ impl PrettyPrinter {
 
     pub fn already_used(&self, n : &Name) -> bool {
         self.used_lcs.borrow().contains(n) 
-        || self.tc.borrow().env.read().declarations.get(n).is_some()
+        || self.tc.borrow().env.declarations.get(n).is_some()
     }
 
 
@@ -85,7 +84,7 @@ impl PrettyPrinter {
         let as_string = format!("{}", n);
         let filtered = as_string.chars()
                                 .filter(|c| c.is_alphanumeric() || *c == '_')
-                                .skip_while(|c| c.is_digit(10) || *c == '_')
+                                .skip_while(|c| c.is_ascii_digit() || *c == '_')
                                 .collect::<String>();
         if filtered.is_empty() {
             Name::from("a")
@@ -128,9 +127,12 @@ impl PrettyPrinter {
     }
 
     pub fn is_implicit(&self, fun : &Expr) -> bool {
-        let inferred = self.tc.borrow_mut().infer(fun);
-        match self.tc.borrow_mut().whnf(&inferred).as_ref() {
-            Pi(_, dom, _) => dom.style != BinderStyle::Default,
+        // the printer only deals with checked terms, so a failure here
+        // just means we print the argument explicitly.
+        let mut tc = self.tc.borrow_mut();
+        let whnfd = tc.infer(fun).and_then(|inferred| tc.whnf(&inferred));
+        match whnfd.as_ref().map(|e| e.as_ref()) {
+            Ok(Pi(_, dom, _)) => dom.style != BinderStyle::Default,
             _ => false
         }
     }
@@ -258,7 +260,7 @@ impl PrettyPrinter {
                     },
                     Some(Postfix(_, ref prio, ref op)) if apps.len() == 1 => {
                         let z = &apps[apps.len() - 1];
-                        let doc = Doc::from(self.pp_expr(z).parens(*prio))
+                        let doc = self.pp_expr(z).parens(*prio)
                                   .concat(Doc::zero_width_line())
                                   .concat(op).group();
                         Parenable::new(prio - 1, doc)
@@ -289,6 +291,20 @@ impl PrettyPrinter {
         Parenable::new(MAX_PRIORITY - 1, self.nest(word_wrap_val(iter)))
     }
 
+    /// `e.field`, using the field's name from the structure's
+    /// introduction rule, or its position (from 1) if we can't find it.
+    pub fn pp_proj_core(&self, struct_name : &Name, idx : usize, structure : &Expr) -> Parenable {
+        let field_name = self.tc.borrow().env
+                             .inductives.get(struct_name)
+                             .and_then(|info| info.structure.as_ref())
+                             .and_then(|info| info.field_names.get(idx).cloned());
+        let field = match field_name {
+            Some(name) => self.pp_name(&name),
+            None => format!("{}", idx + 1).into()
+        };
+        Parenable::new_max(self.pp_expr(structure).parens(MAX_PRIORITY).concat(".").concat(field))
+    }
+
     pub fn pp_sort_core(&self, level : &Level) -> Parenable {
         if level.is_zero() && self.pp_options.notation {
             Parenable::new_max(Doc::from("Prop"))
@@ -300,7 +316,7 @@ impl PrettyPrinter {
     }
 
     pub fn pp_const_core(&self, name : &Name, levels : &Vec<Level>) -> Parenable {
-        if self.tc.borrow().env.read().declarations.get(name).is_some() {
+        if self.tc.borrow().env.declarations.get(name).is_some() {
             self.const_name(name)
         } else {
             let uparams = if levels.is_empty() {
@@ -339,12 +355,15 @@ impl PrettyPrinter {
     }
 
     pub fn pp_expr(&self, e : &Expr) -> Parenable {
-        if !self.pp_options.proofs && self.tc.borrow_mut().is_proof(e).0 {
+        if !self.pp_options.proofs && self.tc.borrow_mut().is_proof(e).map(|x| x.0).unwrap_or(false) {
             return Parenable::new_max("_".into())
         }
 
         match e.as_ref() {
             Var(_, idx) => Parenable::new_max(format!("#{}", idx).into()),
+            NatLit(_, n) => Parenable::new_max(format!("{}", n).into()),
+            StrLit(_, s) => Parenable::new_max(escape_str(s).into()),
+            Proj(_, struct_name, idx, structure) => self.pp_proj_core(struct_name, *idx, structure),
             Sort(_, level) => self.pp_sort_core(level),
             Const(_, name, levels) => self.pp_const_core(name, levels.as_ref()),
             Local(.., of) => Parenable::new_max(self.pp_name(&of.pp_name)),
@@ -399,7 +418,7 @@ impl PrettyPrinter {
         let instd = val_acc.instantiate(params_slice.into_iter().rev().map(|x| &x.lc));
         // end inlined
 
-        let is_prop = self.tc.borrow_mut().is_proposition(&declar.ty);
+        let is_prop = self.tc.borrow_mut().is_proposition(&declar.ty).unwrap_or(false);
         let cmd = match is_prop {
             true => "lemma",
             false => "def"
@@ -430,6 +449,20 @@ impl PrettyPrinter {
 
 
     pub fn main_axiom(&self, declar : &Declaration) -> Doc {
+        self.main_signature(declar, "axiom", false)
+    }
+
+    /// Theorems and opaque definitions; their values aren't kept in the
+    /// environment (see `Env::opaque_deps`), so they're shown as `_`.
+    pub fn main_opaque(&self, declar : &Declaration, kind : DefKind) -> Doc {
+        let cmd = match kind {
+            DefKind::Opaque => "opaque",
+            _ => "lemma"
+        };
+        self.main_signature(declar, cmd, true)
+    }
+
+    fn main_signature(&self, declar : &Declaration, cmd : &str, hidden_value : bool) -> Doc {
         let (binders, instd) = self.parse_binders(&declar.ty);
         let doc = {
             let (prms, rst) = take_while_slice(binders.as_slice(), |x| x.is_forall()); 
@@ -439,9 +472,13 @@ impl PrettyPrinter {
                               .concat_line(
                                   self.pp_binders(
                                       rst, self.pp_expr(&instd)).parens(0).group()));
-            Doc::from("axiom").concat(self.get_ups(declar))
-                              .concat_plus(sub_doc_new)
-                              .concat(Doc::line())
+            let sub_doc_new = match hidden_value {
+                true => sub_doc_new.concat_plus(":= _"),
+                false => sub_doc_new
+            };
+            Doc::from(cmd).concat(self.get_ups(declar))
+                          .concat_plus(sub_doc_new)
+                          .concat(Doc::line())
         };
         self.restore_lc_names(&binders);
         match declar.builtin {
@@ -454,14 +491,15 @@ impl PrettyPrinter {
 
         let env_result = self.tc.borrow()
                                 .env
-                                .read()
                                 .get_value(&declar.name)
                                 .cloned();
-        match env_result {
+        match (env_result, declar.kind) {
             // definition/lemma branch
-            Some(val) => self.main_def(declar, val.clone()),
+            (Some(val), _) => self.main_def(declar, val.clone()),
+            // theorem/opaque branch
+            (None, Some(kind)) => self.main_opaque(declar, kind),
             // axiom branch
-            None => self.main_axiom(declar)
+            (None, None) => self.main_axiom(declar)
         }
 
     }
@@ -471,8 +509,8 @@ impl PrettyPrinter {
     }
 
 
-    pub fn print_declar(options : Option<PPOptions>, n : &Name, env : &Arc<RwLock<Env>>) -> String {
-        let declar = match env.read().declarations.get(n) {
+    pub fn print_declar(options : Option<PPOptions>, n : &Name, env : &Arc<Env>) -> String {
+        let declar = match env.declarations.get(n) {
             Some(d) => d.clone(),
             None => return String::new()
         };
@@ -559,6 +597,25 @@ impl ParsedBinder {
 }
 
 
+/// Quote `s` the way Lean would write it in source.
+pub fn escape_str(s : &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() && (c as u32) < 0x100 => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
 pub fn has_var(e : &Expr, i : u64) -> bool {
     if e.var_bound() as u64 <= i {
         return false
@@ -569,6 +626,7 @@ pub fn has_var(e : &Expr, i : u64) -> bool {
         Lambda(_, dom, body) => has_var(&dom.ty, i) || has_var(body, i + 1),
         Pi(_, dom, body) => has_var(&dom.ty, i) || has_var(body, i + 1),
         Let(_, dom, val, body) => has_var(&dom.ty, i) || has_var(val, i) || has_var(body, i + 1),
+        Proj(.., structure) => has_var(structure, i),
         _ => unreachable!()
     }
 }
@@ -583,7 +641,7 @@ pub fn take_while_slice<T>(s : &[T], f : impl Fn(&T) -> bool) -> (&[T], &[T]) {
     (lhs, rhs)
 }
 
-pub fn render_expr(e : &Expr, env : &Arc<RwLock<Env>>) -> String {
+pub fn render_expr(e : &Expr, env : &Arc<Env>) -> String {
     let pp = PrettyPrinter::new(None, env);
     pp.pp_expr(e)
       .doc
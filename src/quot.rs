@@ -5,7 +5,10 @@ use crate::chain;
 use crate::name::Name;
 use crate::level::{ mk_param, };
 use crate::reduction::ReductionRule;
-use crate::env::{ Declaration, Modification, CompiledModification };
+use crate::env::{ Env, Declaration, Modification, CompiledModification };
+use crate::tc::TypeChecker;
+use crate::utils::ShortCircuit::*;
+use crate::errors::{ KernelResult, KernelError::* };
 use crate::expr::{ BinderStyle::*, 
                    mk_prop, 
                    mk_local, 
@@ -23,9 +26,12 @@ use crate::expr::{ BinderStyle::*,
 pub struct Quot {
     pub declarations: Vec<Declaration>,
     pub reduction_rule: ReductionRule,
+    pub eq_name : Name,
 }
 
-pub fn new_quot() -> Modification {
+/// Lean 3 calls these `quot` and `eq`; Lean 4 calls them `Quot`
+/// and `Eq`. Everything else is the same.
+pub fn new_quot(quot_name : Name, eq_name : Name) -> KernelResult<Modification> {
     // There are a bunch of expressions that get used ad nauseum here,
     // so we define some of them as reusable omponents to make later definitions
     // (a little bit) more compact. The key definitions are annotated with their
@@ -44,33 +50,33 @@ pub fn new_quot() -> Modification {
     let _b = mk_local("b", _A.clone(), Default);
 
 
-    let quot_const_univ_u = || mk_const("quot", vec![param_u()]);
-    let quot_mk_const_univ_u = || mk_const(Name::from("quot").extend_str("mk"), vec![param_u()]);
-    let quot_pi_app = sort_u.fold_pis(chain![&_A, &_R]);
+    let quot_const_univ_u = || mk_const(quot_name.clone(), vec![param_u()]);
+    let quot_mk_const_univ_u = || mk_const(quot_name.extend_str("mk"), vec![param_u()]);
+    let quot_pi_app = sort_u.fold_pis(chain![&_A, &_R])?;
 
     // First introduction rule. in Lean : 
     // quot : Π {α : Sort u}, (α → α → Prop) → Sort u
-    let quot = Declaration::mk(Name::from("quot"),
+    let quot = Declaration::mk(quot_name.clone(),
                                     params_u(),
                                     quot_pi_app,
                                     None,
                                     Some(true));
 
-    let quot_mk_f_a = mk_const("quot", params_u()).fold_apps(vec![&_A, &_R]);
+    let quot_mk_f_a = mk_const(quot_name.clone(), params_u()).fold_apps(vec![&_A, &_R]);
 
     let quot_mk_f = _A.mk_arrow(&quot_mk_f_a);
         
     // Second introduction rule. In lean : 
     // quot.mk : Π {α : Sort u} (r : α → α → Prop), α → @quot α r
     let quot_mk = Declaration::mk(
-        Name::from("quot").extend_str("mk"),
+        quot_name.extend_str("mk"),
         params_u(),
-        quot_mk_f.fold_pis(chain![&_A, &_R]),
+        quot_mk_f.fold_pis(chain![&_A, &_R])?,
         None,
         Some(true)
     );
 
-    let eq_const = mk_const("eq", vec![param_v()]);
+    let eq_const = mk_const(eq_name.clone(), vec![param_v()]);
     let app1 = mk_app(_f.clone(), _a.clone());
     let app2 = mk_app(_f.clone(), _b.clone());
     let eq_app = eq_const.fold_apps(vec![&_B, &app1, &app2]);
@@ -79,9 +85,9 @@ pub fn new_quot() -> Modification {
     let inner_app = eq_lhs.mk_arrow(&eq_app);
     let lower_const = quot_const_univ_u();
     let lower_apps = lower_const.fold_apps(vec![&_A, &_R]);
-    let lhs_pis = inner_app.fold_pis(chain![&_a, &_b]);
+    let lhs_pis = inner_app.fold_pis(chain![&_a, &_b])?;
     let triple_arrow = lhs_pis.mk_arrow(&(lower_apps.mk_arrow(&_B)));
-    let pis_together = triple_arrow.fold_pis(chain![&_A, &_R, &_B, &_f]);
+    let pis_together = triple_arrow.fold_pis(chain![&_A, &_R, &_B, &_f])?;
 
 
     // Third introduction rule. In lean : 
@@ -105,9 +111,9 @@ pub fn new_quot() -> Modification {
 
     let ind_pi_1_inner = quot_mk_const_univ_u().fold_apps(vec![&_A, &_R, &_a]);
     let ind_pi_1_mid = _B2.fold_apps(Some(&ind_pi_1_inner));
-    let ind_pi_1 = ind_pi_1_mid.fold_pis(chain![&_a]);
+    let ind_pi_1 = ind_pi_1_mid.fold_pis(chain![&_a])?;
     let B2_q = _B2.fold_apps(vec![&_q]);
-    let ind_pi_2 = B2_q.fold_pis(chain![&_q]);
+    let ind_pi_2 = B2_q.fold_pis(chain![&_q])?;
     let ind_arrows = ind_pi_1.mk_arrow(&ind_pi_2);
 
     // Last introduction rule. In Lean : 
@@ -116,12 +122,12 @@ pub fn new_quot() -> Modification {
     let quot_ind = Declaration::mk(
         quot.name.extend_str("ind"),
         params_u(),
-        ind_arrows.fold_pis(chain![&_A, &_R, &_B2]),
+        ind_arrows.fold_pis(chain![&_A, &_R, &_B2])?,
         None,
         Some(true)
     );
 
-    let const_eq_v = mk_const("eq", vec![param_v()]);
+    let const_eq_v = mk_const(eq_name.clone(), vec![param_v()]);
 
 
     let _h = mk_local("h",
@@ -144,17 +150,75 @@ pub fn new_quot() -> Modification {
         quot_red_arg2,
         _f.fold_apps(vec![&_a]),
         None.into_iter()
-    );
+    )?;
 
     let q = Quot {
             declarations : vec![quot, quot_mk, quot_ind, quot_lift],
-            reduction_rule : quot_red
+            reduction_rule : quot_red,
+            eq_name
     };
 
-    Modification::QuotMod(q)
+    Ok(Modification::QuotMod(q))
 }
 
 impl Quot {
+    /// The quotient is only sound if `eq` is the real equality type, so
+    /// before we enable it, make sure that `eq` was declared as an inductive
+    /// with exactly the following shape. That this is the only `#QUOT` in
+    /// the file is checked when it's added (see `CompiledModification::add_only`).
+    ///```pseudo
+    /// inductive eq {α : Sort u} (a : α) : α → Prop
+    /// | refl : eq a
+    ///```
+    pub fn check_preconditions(&self, env : &Arc<Env>) -> KernelResult<()> {
+        let eq_name = self.eq_name.clone();
+
+        let info = match env.inductives.get(&eq_name) {
+            Some(info) => info,
+            None => return Err(QuotBadEq(format!("expected {} to be declared as an inductive type", eq_name)))
+        };
+
+        if info.num_params != 2 || info.num_indices != 1 {
+            return Err(QuotBadEq(format!("expected {} to have 2 parameters and 1 index, but it has {} and {}", eq_name, info.num_params, info.num_indices)))
+        }
+
+        let refl_name = match info.intros.as_slice() {
+            [refl_name] => refl_name,
+            owise => return Err(QuotBadEq(format!("expected {} to have exactly one introduction rule, but it has {}", eq_name, owise.len())))
+        };
+
+        let (eq_decl, refl_decl) = match (env.declarations.get(&eq_name), env.declarations.get(refl_name)) {
+            (Some(eq_decl), Some(refl_decl)) => (eq_decl.clone(), refl_decl.clone()),
+            _ => return Err(UnknownConst(refl_name.clone()))
+        };
+
+        let u = match (eq_decl.univ_params.as_slice(), refl_decl.univ_params.as_slice()) {
+            ([u], [_]) => u.clone(),
+            _ => return Err(QuotBadEq(format!("expected {} and its introduction rule to each have exactly one universe parameter", eq_name)))
+        };
+
+        let _A = mk_local("A", mk_sort(u.clone()), Implicit);
+        let _a = mk_local("a", _A.clone(), Default);
+        // Π {α : Sort u}, α → α → Prop
+        let expected_eq_ty = _A.mk_arrow(&_A.mk_arrow(&mk_prop())).fold_pis(chain![&_A])?;
+        // Π {α : Sort u} (a : α), @eq α a a
+        let expected_refl_ty = mk_const(eq_name.clone(), vec![u.clone()]).fold_apps(vec![&_A, &_a, &_a])
+                                                                          .fold_pis(chain![&_A, &_a])?;
+
+        // the introduction rule's universe parameter might have a different
+        // name than the inductive's, so line it up with `u` first.
+        let refl_ty = refl_decl.ty.instantiate_ps(&vec![(refl_decl.univ_params[0].clone(), u)]);
+
+        let mut tc = TypeChecker::new(None, env.clone());
+        for (expected, got) in [(expected_eq_ty, eq_decl.ty), (expected_refl_ty, refl_ty)] {
+            if tc.check_def_eq(&expected, &got)? != EqShort {
+                return Err(QuotBadEq(format!("expected a declaration with type {:?}, but got {:?}", expected, got)))
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn compile_self(self) -> CompiledModification {
         CompiledModification::CompiledQuotMod(self.declarations, self.reduction_rule)
     }
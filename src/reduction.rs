@@ -8,6 +8,8 @@ use crate::name::Name;
 use crate::level::Level;
 use crate::expr::{ Expr, InnerExpr::* };
 use crate::errors;
+use crate::errors::{ KernelResult, KernelError::* };
+use crate::append_map::AppendMap;
 
 
 /// Maps (ReductionRule, [(Level, Level)]) to an Exprssion;
@@ -51,11 +53,15 @@ impl Hash for ReductionRule {
 /// the reduction rule. `Definition` items can just call `new_rr`
 /// directly.
 impl ReductionRule {
-    pub fn new_rr(lhs : Expr, rhs : Expr, def_eq_constraints : Vec<(Expr, Expr)>) -> Self {
+    pub fn new_rr(lhs : Expr, rhs : Expr, def_eq_constraints : Vec<(Expr, Expr)>) -> KernelResult<Self> {
         let lhs_var_bound = lhs.var_bound();
-        assert!(!lhs.has_locals());
-        assert!(!rhs.has_locals());
-        assert!(rhs.var_bound() <= lhs_var_bound);
+        if lhs.has_locals() {
+            return Err(HasLocals(lhs))
+        } else if rhs.has_locals() {
+            return Err(HasLocals(rhs))
+        } else if rhs.var_bound() > lhs_var_bound {
+            return Err(LooseBVars(rhs))
+        }
 
         let (app_fn, lhs_args) = lhs.unfold_apps_refs();
         let lhs_args_size = lhs_args.len();
@@ -77,7 +83,7 @@ impl ReductionRule {
         // from them.
         let digest = hash64(&(&lhs.get_digest(), &rhs.get_digest()));
 
-        ReductionRule {
+        Ok(ReductionRule {
             lhs_const_name,
             lhs,
             rhs,
@@ -86,13 +92,13 @@ impl ReductionRule {
             lhs_args_size : lhs_args_size,
             majors : majors,
             digest
-        }
+        })
     }
 
     pub fn new_nondef_rr<'r, R>(locals : &[Expr], 
                                 lhs : Expr, 
                                 rhs : Expr, 
-                                def_eq_constraints : R) -> Self
+                                def_eq_constraints : R) -> KernelResult<Self>
       where R : Iterator<Item = (&'r Expr, &'r Expr)> {
         let lhs_abstd = lhs.abstract_(locals.into_iter());
         let rhs_abstd = rhs.abstract_(locals.into_iter());
@@ -125,7 +131,7 @@ impl ReductionRule {
             },
             (Var(_, idx), _) => {
                 match var_subs.get_mut(*idx as usize) {
-                    Some(already) => { std::mem::replace(already, e2); },
+                    Some(already) => { *already = e2; },
                     None => {
                         // FIXME find a better way to pad placeholder values.
                         // var_subs will eventually (on correct execution)
@@ -135,7 +141,6 @@ impl ReductionRule {
                             var_subs.push(e2);
                         }
                         var_subs.push(e2);
-                        assert!(var_subs.len() == (*idx as usize) + 1);
                     }
                 }
                 true
@@ -144,11 +149,11 @@ impl ReductionRule {
         }        
     }
 
-    pub fn apply_reduction<'l>(&self, 
+    pub fn apply_reduction(&self, 
                                    e : Expr,
                                    cache : &mut ReductionCache) 
                                    -> Option<(Expr, Vec<(Expr, Expr)>)> {
-        let mut var_subs = Vec::<&'l Expr>::with_capacity(100);
+        let mut var_subs = Vec::<&Expr>::with_capacity(100);
         let mut univ_subs = Vec::with_capacity(100);
 
         if !self.collect_substs(&self.lhs, &e, &mut var_subs, &mut univ_subs) {
@@ -196,10 +201,11 @@ impl ReductionRule {
 
 }
 
-#[derive(Clone)]
+/// The reduction rules of every constant that has any, which
+/// only ever get added to; see `AppendMap`.
 pub struct ReductionMap {
-    pub reduction_rules : HashMap<Name, Vec<ReductionRule>>,
-    major_premises : HashMap<Name, Vec<usize>>
+    pub reduction_rules : AppendMap<Name, Vec<ReductionRule>>,
+    major_premises : AppendMap<Name, Vec<usize>>
 }
 
 
@@ -207,8 +213,8 @@ impl ReductionMap {
 
     pub fn new(num_mods : usize) -> Self {
         ReductionMap {
-            reduction_rules : HashMap::with_capacity(num_mods),
-            major_premises : HashMap::with_capacity(num_mods),
+            reduction_rules : AppendMap::with_capacity(num_mods),
+            major_premises : AppendMap::with_capacity(num_mods),
         }
     }
 
@@ -229,8 +235,7 @@ impl ReductionMap {
         let (hd, apps) = e.unfold_apps_special(); 
 
         if let Const(_, name, _) = hd.as_ref() {
-            let source = self.reduction_rules.get(&name).cloned()?; 
-            for elem in source {
+            for elem in self.reduction_rules.get(name)? {
                 match elem.apply_hd_tl(&hd, apps.as_slice(), cache) {
                     found @ Some(_) => return found,
                     None => continue
@@ -242,27 +247,30 @@ impl ReductionMap {
         }
     }
 
-    /// Inserting a new ReductionRule into rules, and ADDING its majors to the cumulative
-    /// set of the other Name |-> Vec<ReductionRule> mapping's majors. Also asserting some
-    /// invariants; if the original lookup of Name |-> Rule was Some, then Majors should
-    /// exist, and if it was None, then majors should not exist.
-    pub fn add_rule(&mut self, new_rule : ReductionRule) {
-        let name_key = new_rule.lhs_const_name.clone();
-        let major_prem_vec = new_rule.majors.clone();
-        match self.reduction_rules.get_mut(&name_key) {
-            Some(already_rules) => {
-                already_rules.push(new_rule);
-                self.major_premises.get_mut(&name_key)
-                           .unwrap_or_else(|| errors::err_add_rule(line!(), &name_key))
-                           .extend(major_prem_vec.into_iter());
-            },
-            None => {
-                let res1 = self.reduction_rules.insert(name_key.clone(), vec![new_rule]);
-                let res2 = self.major_premises.insert(name_key.clone(), major_prem_vec);
-                // assert they weren't already in the map
-                assert!(res1.is_none() && res2.is_none());
+    /// Add a batch of rules, along with their majors. Since the map
+    /// can't be changed once something's in it, every rule for a given
+    /// constant has to come in the same batch (all of a recursor's rules
+    /// are made together, so this is never a problem).
+    pub fn add_rules(&self, new_rules : impl IntoIterator<Item = ReductionRule>) -> KernelResult<()> {
+        let mut grouped = Vec::<(Name, Vec<ReductionRule>)>::new();
+        for rule in new_rules {
+            match grouped.iter_mut().find(|(name, _)| *name == rule.lhs_const_name) {
+                Some((_, rules)) => rules.push(rule),
+                None => grouped.push((rule.lhs_const_name.clone(), vec![rule]))
+            }
+        }
+
+        for (name_key, rules) in grouped {
+            let majors = rules.iter().flat_map(|rule| rule.majors.iter().copied()).collect();
+            // The majors go in first, so anyone who can see the rules can
+            // see their majors.
+            let res1 = self.major_premises.insert(name_key.clone(), majors);
+            let res2 = self.reduction_rules.insert(name_key.clone(), rules);
+            if res1.is_err() || res2.is_err() {
+                return Err(RulesAlreadyAdded(name_key))
             }
         }
+        Ok(())
     }
 
     pub fn get_major_premises(&self, key : &Name) -> Option<&Vec<usize>> {
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+use hashbrown::{ HashMap, HashSet };
+use parking_lot::{ Mutex, Condvar };
+
+use crate::name::Name;
+
+/// Hands out items to a fixed number of worker threads. Each worker has
+/// its own deque; items go on the deque of the worker that produced
+/// them, and a worker takes the newest item off its own deque first.
+/// When that's empty it steals the oldest item from one of the others,
+/// and when there's nothing to steal it parks until more work shows up
+/// (or the scheduler is closed) instead of polling.
+///
+/// Items are held back until every name they depend on has been marked
+/// as added with `mark_added`, so a checker never looks for a constant
+/// the adder hasn't gotten to yet. Since things are added in export
+/// order, this only actually holds an item back if it refers to
+/// something declared later in the file; closing the scheduler lets
+/// those through, so checking them reports the bad reference.
+///
+/// Waiting for dependencies to be added, and not checked, is enough:
+/// checking an item only reads the types and values of what it mentions
+/// out of the environment, the same way it would for an axiom, and
+/// doesn't depend on whether those have been checked yet. If one of them
+/// turns out to be bad, the whole run fails, or in keep going mode
+/// `Summary::finish` reports everything that depends on it as skipped,
+/// whichever order they were checked in.
+pub struct Scheduler<T> {
+    deques : Vec<Mutex<VecDeque<T>>>,
+    gate : Mutex<DepGate<T>>,
+    closed : Mutex<bool>,
+    wakeup : Condvar,
+}
+
+/// Items waiting for their dependencies, along with the number of
+/// dependencies each one is still waiting for.
+struct DepGate<T> {
+    added : HashSet<Name>,
+    blocked : HashMap<usize, (T, usize)>,
+    waiting_on : HashMap<Name, Vec<usize>>,
+    next_id : usize,
+}
+
+impl<T> Scheduler<T> {
+    pub fn new(num_workers : usize) -> Self {
+        Scheduler {
+            deques : (0..num_workers.max(1)).map(|_| Mutex::new(VecDeque::new())).collect(),
+            gate : Mutex::new(DepGate {
+                added : HashSet::new(),
+                blocked : HashMap::new(),
+                waiting_on : HashMap::new(),
+                next_id : 0usize,
+            }),
+            closed : Mutex::new(false),
+            wakeup : Condvar::new(),
+        }
+    }
+
+    /// Schedule `item`, produced by `worker`, once every name in `deps`
+    /// has been added.
+    pub fn submit(&self, worker : usize, item : T, deps : impl IntoIterator<Item = Name>) {
+        let mut gate = self.gate.lock();
+        let missing = deps.into_iter()
+                          .filter(|dep| !gate.added.contains(dep))
+                          .collect::<HashSet<Name>>();
+        if missing.is_empty() {
+            drop(gate);
+            return self.push(worker, item)
+        }
+
+        let id = gate.next_id;
+        gate.next_id += 1;
+        gate.blocked.insert(id, (item, missing.len()));
+        for dep in missing {
+            gate.waiting_on.entry(dep).or_default().push(id);
+        }
+    }
+
+    /// Record that `name` is in the environment, scheduling anything
+    /// that was only waiting on it on `worker`'s deque.
+    pub fn mark_added(&self, worker : usize, name : &Name) {
+        let mut gate = self.gate.lock();
+        gate.added.insert(name.clone());
+        let mut ready = Vec::new();
+        for id in gate.waiting_on.remove(name).unwrap_or_default() {
+            let now_ready = match gate.blocked.get_mut(&id) {
+                Some((_, missing)) => {
+                    *missing -= 1;
+                    *missing == 0
+                },
+                None => false
+            };
+            if now_ready {
+                ready.extend(gate.blocked.remove(&id).map(|(item, _)| item));
+            }
+        }
+        drop(gate);
+        for item in ready {
+            self.push(worker, item);
+        }
+    }
+
+    fn push(&self, worker : usize, item : T) {
+        self.deques[worker % self.deques.len()].lock().push_back(item);
+        // Taking the lock means a worker that just came up empty in
+        // `take` is already waiting, so it can't miss this.
+        let _closed = self.closed.lock();
+        self.wakeup.notify_one();
+    }
+
+    /// No more items are coming. Anything still waiting on dependencies
+    /// is scheduled as is (on `worker`'s deque), and workers return from
+    /// `take` once everything's been handed out.
+    pub fn close(&self, worker : usize) {
+        let blocked = std::mem::take(&mut self.gate.lock().blocked);
+        let mut blocked = blocked.into_iter().collect::<Vec<_>>();
+        blocked.sort_by_key(|(id, _)| *id);
+        for (_, (item, _)) in blocked {
+            self.push(worker, item);
+        }
+        *self.closed.lock() = true;
+        self.wakeup.notify_all();
+    }
+
+    /// The next item for `worker`, blocking until there is one. Returns
+    /// `None` once the scheduler is closed and every item has been
+    /// handed out.
+    pub fn take(&self, worker : usize) -> Option<T> {
+        if let Some(item) = self.try_take(worker) {
+            return Some(item)
+        }
+
+        let mut closed = self.closed.lock();
+        loop {
+            // Look again with the lock held; anything pushed after this
+            // wakes us up. Nothing gets pushed once the scheduler is
+            // closed, so coming up empty then means we're done.
+            if let Some(item) = self.try_take(worker) {
+                return Some(item)
+            }
+            if *closed {
+                return None
+            }
+            self.wakeup.wait(&mut closed);
+        }
+    }
+
+    fn try_take(&self, worker : usize) -> Option<T> {
+        let n = self.deques.len();
+        let own = worker % n;
+        // Let go of our own deque before stealing; two workers each
+        // holding their own while waiting on the other's would deadlock.
+        let own_item = self.deques[own].lock().pop_back();
+        own_item.or_else(|| {
+            (1..n).find_map(|offset| self.deques[(own + offset) % n].lock().pop_front())
+        })
+    }
+}
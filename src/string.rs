@@ -0,0 +1,77 @@
+use num_bigint::BigUint;
+
+use crate::name::Name;
+use crate::level::mk_zero;
+use crate::expr::{ Expr, mk_app, mk_const, mk_nat_lit };
+use crate::inductive::IndInfo;
+
+/// Kernel support for string literals (`InnerExpr::StrLit`). Spelled out,
+/// a string is its constructor applied to a list of characters, each of
+/// which is `char.of_nat` applied to its code point, so even a short
+/// string is a big term that we'd have to hash, instantiate and
+/// compare node by node. A literal is one node until something actually
+/// needs to look inside it; see `unfold`.
+///
+/// Lean 4 declares `String` as a structure over `List Char`. In Lean 3 the
+/// structure is `string_imp`, and `string` is a definition that unfolds to it.
+#[derive(Debug, Clone)]
+pub struct StrExt {
+    /// The type of a string literal.
+    pub string : Name,
+    pub mk : Name,
+    pub char : Name,
+    pub of_nat : Name,
+    pub nil : Name,
+    pub cons : Name,
+}
+
+impl StrExt {
+    /// Called whenever an inductive is added to the environment; returns
+    /// `Some` if it's Lean 4's `String` or Lean 3's `string_imp` with the
+    /// one constructor we expect.
+    pub fn new(info : &IndInfo) -> Option<Self> {
+        let (string, char, list, of_nat) = match info.name.to_string().as_str() {
+            "String" => ("String", "Char", "List", "ofNat"),
+            "string_imp" => ("string", "char", "list", "of_nat"),
+            _ => return None
+        };
+
+        let mk = info.name.extend_str("mk");
+        if info.num_params != 0 || info.num_indices != 0 || info.intros != [mk.clone()] {
+            return None
+        }
+
+        let char = Name::from(char);
+        let list = Name::from(list);
+        Some(StrExt {
+            string : Name::from(string),
+            mk,
+            of_nat : char.extend_str(of_nat),
+            char,
+            nil : list.extend_str("nil"),
+            cons : list.extend_str("cons"),
+        })
+    }
+
+    /// The names that have to be visible before a literal can be unfolded.
+    pub fn unfold_names(&self) -> [&Name; 4] {
+        [&self.mk, &self.of_nat, &self.nil, &self.cons]
+    }
+
+    /// `s` as the constructor applied to a list of characters.
+    /// `list.{0} char` since `char : Type`.
+    pub fn unfold(&self, s : &str) -> Expr {
+        let lvls = vec![mk_zero()];
+        let char = mk_const(self.char.clone(), Vec::new());
+        let of_nat = mk_const(self.of_nat.clone(), Vec::new());
+        let cons = mk_app(mk_const(self.cons.clone(), lvls.clone()), char.clone());
+
+        let nil = mk_app(mk_const(self.nil.clone(), lvls), char);
+        let chars = s.chars().rev().fold(nil, |tl, c| {
+            let hd = mk_app(of_nat.clone(), mk_nat_lit(BigUint::from(c as u32)));
+            mk_app(mk_app(cons.clone(), hd), tl)
+        });
+
+        mk_app(mk_const(self.mk.clone(), Vec::new()), chars)
+    }
+}
@@ -0,0 +1,138 @@
+use hashbrown::{ HashMap, HashSet };
+
+use crate::name::Name;
+use crate::env::Modification;
+use crate::errors::DeclarError;
+
+/// Bookkeeping for "keep going" mode, where a bad item doesn't stop the
+/// pipeline. We remember, for each item (in the order they were added),
+/// which constants it mentions and which names it declares. Failures
+/// get recorded as they come in, and anything that mentions a name
+/// declared by a failed (or skipped) item gets skipped instead of checked.
+///
+/// Since checking happens on several threads, an item can occasionally
+/// be checked before one of its dependencies fails. `finish` settles
+/// this by walking the items in export order, so the final
+/// failed/skipped/passed split doesn't depend on scheduling.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    order : Vec<Name>,
+    uses : HashMap<Name, HashSet<Name>>,
+    owners : HashMap<Name, Name>,
+    failures : HashMap<Name, DeclarError>,
+    skipped : HashSet<Name>,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Summary::default()
+    }
+
+    /// Register an item before it's compiled. `item` is the name
+    /// errors are reported under (see `Modification::get_name`).
+    /// If a name gets declared twice, the first declaration keeps it; the
+    /// second one will be rejected by `Env::insert_declaration` anyway.
+    pub fn record_mod(&mut self, m : &Modification) {
+        let item = m.get_name();
+        for declared in m.declared_names() {
+            self.owners.entry(declared).or_insert_with(|| item.clone());
+        }
+        self.uses.entry(item.clone()).or_insert_with(|| m.used_names());
+        self.order.push(item);
+    }
+
+    pub fn record_failure(&mut self, e : DeclarError) {
+        self.failures.insert(e.name.clone(), e);
+    }
+
+    pub fn record_skip(&mut self, item : &Name) {
+        self.skipped.insert(item.clone());
+    }
+
+    fn is_bad(&self, item : &Name) -> bool {
+        self.failures.contains_key(item) || self.skipped.contains(item)
+    }
+
+    /// Does `item` mention a name declared by an item that has
+    /// already failed or been skipped?
+    pub fn has_failed_dependency(&self, item : &Name) -> bool {
+        match self.uses.get(item) {
+            None => false,
+            Some(used) => used.iter()
+                              .filter_map(|n| self.owners.get(n))
+                              .any(|owner| owner != item && self.is_bad(owner))
+        }
+    }
+
+    /// Settle the final results; see the note on `Summary`.
+    pub fn finish(self) -> CheckSummary {
+        let Summary { order, uses, owners, mut failures, skipped } = self;
+        let mut bad = HashSet::with_capacity(failures.len() + skipped.len());
+        let mut failed = Vec::new();
+        let mut skipped = Vec::with_capacity(skipped.len());
+        let mut num_passed = 0usize;
+
+        // When an item name shows up more than once, the failure belongs
+        // to the later (duplicate) one.
+        let last_idx = order.iter()
+                                 .enumerate()
+                                 .map(|(idx, item)| (item, idx))
+                                 .collect::<HashMap<&Name, usize>>();
+
+        for (idx, item) in order.iter().enumerate() {
+            let dep_failed = uses.get(item).map(|used| {
+                used.iter()
+                    .filter_map(|n| owners.get(n))
+                    .any(|owner| owner != item && bad.contains(owner))
+            }).unwrap_or(false);
+
+            if dep_failed {
+                skipped.push(item.clone());
+                bad.insert(item.clone());
+            } else if last_idx.get(item) == Some(&idx) && failures.contains_key(item) {
+                failed.extend(failures.remove(item));
+                bad.insert(item.clone());
+            } else {
+                num_passed += 1;
+            }
+        }
+
+        CheckSummary {
+            failed,
+            skipped,
+            num_passed
+        }
+    }
+}
+
+/// The outcome of a "keep going" run, counted per item. An inductive
+/// (with its introduction and elimination rules) counts as one item.
+#[derive(Debug, Clone)]
+pub struct CheckSummary {
+    pub failed : Vec<DeclarError>,
+    pub skipped : Vec<Name>,
+    pub num_passed : usize,
+}
+
+impl CheckSummary {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty() && self.skipped.is_empty()
+    }
+
+    /// Every item in the export, whether it failed, was skipped, or passed.
+    pub fn num_items(&self) -> usize {
+        self.failed.len() + self.skipped.len() + self.num_passed
+    }
+}
+
+impl std::fmt::Display for CheckSummary {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        for e in self.failed.iter() {
+            writeln!(f, "FAILED : {}\n", e)?;
+        }
+        for n in self.skipped.iter() {
+            writeln!(f, "SKIPPED : {} (depends on a failed declaration)", n)?;
+        }
+        write!(f, "{} failed, {} skipped, {} passed", self.failed.len(), self.skipped.len(), self.num_passed)
+    }
+}
@@ -1,31 +1,49 @@
 
 use std::sync::Arc;
+use std::cmp::Ordering;
 use hashbrown::HashMap;
-use parking_lot::RwLock;
 use stacker::maybe_grow;
+use num_bigint::BigUint;
+use num_traits::{ Zero, One };
 
-use crate::utils::{ ShortCircuit, ShortCircuit::*, EqCache };
+use crate::utils::{ ShortCircuit, ShortCircuit::*, EqCache, evict_half };
 use crate::name::Name;
-use crate::level::{ Level, mk_imax, mk_succ };
-use crate::expr::{ Expr, Binding, InnerExpr::*, mk_app, mk_lambda, mk_var, mk_sort, mk_prop, mk_pi };
+use crate::level::{ Level, mk_imax, mk_succ, mk_zero };
+use crate::expr::{ Expr, Binding, InnerExpr::*, mk_app, mk_lambda, mk_var, mk_sort, mk_prop, mk_pi,
+                   mk_const, mk_nat_lit, mk_proj, unique_const_names };
 use crate::reduction::ReductionCache;
-use crate::env::Env;
-use crate::errors::*;
+use crate::env::{ Env, Declaration, DefKind, ReducibilityHint };
+use crate::inductive::StructInfo;
+use crate::nat::{ NatExt, NatOp, NatResult, apply_binop, apply_bit };
+use crate::errors::{ KernelResult, KernelError::* };
 use Flag::*;
 
 
 /// "A Typechecker" is just a collection of caches and a handle to the current
 /// environment (we only ever need to read from it in this case). 
 /// unsafe_unchecked should be true iff the TypeChecker will only ever
-/// be used by the pretty printer.
+/// be used by the pretty printer. If `visible_below` is set, only
+/// declarations whose position (see `Declaration::pos`) is smaller than it
+/// can be referred to, which is how we keep a declaration from being checked
+/// against itself or anything that comes after it in the export file.
+/// `local_declarations` are treated as if they were in the environment;
+/// see `add_local_declaration`.
 #[derive(Clone)]
 pub struct TypeChecker {
     unsafe_unchecked: bool,
+    visible_below : Option<usize>,
+    local_declarations : HashMap<Name, Declaration>,
+    /// Whether the nat extension can stand in for definitions other than
+    /// `nat.succ`. It's switched off while we make sure a definition
+    /// agrees with it (see `nat_op_ok`).
+    nat_ops : bool,
+    /// See `Env::cache_limit`.
+    cache_limit : Option<usize>,
     pub infer_cache : HashMap<Expr, Expr>,
     pub eq_cache : EqCache,
     pub whnf_cache : HashMap<Expr, Expr>,
     pub reduction_cache : ReductionCache,
-    pub env : Arc<RwLock<Env>>,
+    pub env : Arc<Env>,
 }
 
 impl std::fmt::Debug for TypeChecker {
@@ -35,9 +53,13 @@ impl std::fmt::Debug for TypeChecker {
 }
 
 impl TypeChecker {
-    pub fn new(unsafe_unchecked : Option<bool>, env : Arc<RwLock<Env>>) -> Self {
+    pub fn new(unsafe_unchecked : Option<bool>, env : Arc<Env>) -> Self {
         TypeChecker {
             unsafe_unchecked : unsafe_unchecked.unwrap_or(false),
+            visible_below : None,
+            local_declarations : HashMap::new(),
+            nat_ops : true,
+            cache_limit : env.cache_limit,
             infer_cache : HashMap::with_capacity(1000),
             eq_cache : EqCache::with_capacity(500),
             whnf_cache : HashMap::with_capacity(100),
@@ -46,87 +68,197 @@ impl TypeChecker {
         }
     }
 
-    pub fn fork_env(&self) -> Arc<RwLock<Env>> {
+    pub fn with_visible_below(mut self, pos : Option<usize>) -> Self {
+        self.visible_below = pos;
+        self
+    }
+
+    /// Let this checker refer to `d` without it going into the
+    /// environment. An inductive block is built against its own base
+    /// types this way, so they only go in once the whole block has been
+    /// accepted (see `CompiledModification::add_only`).
+    pub fn add_local_declaration(&mut self, d : Declaration) -> KernelResult<()> {
+        if let Some(original) = self.get_declaration(&d.name) {
+            return Err(DuplicateDeclaration(Box::new(original.clone()), Box::new(d)))
+        }
+        self.local_declarations.insert(d.name.clone(), d);
+        Ok(())
+    }
+
+    /// A fresh checker for the same environment that can see the same
+    /// local declarations as this one.
+    pub fn fork_local(&self) -> Self {
+        let mut tc = TypeChecker::new(Some(self.unsafe_unchecked), self.env.clone())
+                     .with_visible_below(self.visible_below);
+        tc.local_declarations = self.local_declarations.clone();
+        tc
+    }
+
+    fn get_declaration(&self, name : &Name) -> Option<&Declaration> {
+        self.env.declarations.get(name).or_else(|| self.local_declarations.get(name))
+    }
+
+    pub fn fork_env(&self) -> Arc<Env> {
         self.env.clone()
     }
 
+    /// Drop half of any cache that's grown to `cache_limit` entries, to
+    /// make room for the entry that's about to go in.
+    fn evict_full_caches(&mut self) {
+        if let Some(limit) = self.cache_limit {
+            if self.infer_cache.len() >= limit {
+                evict_half(&mut self.infer_cache);
+            }
+            if self.whnf_cache.len() >= limit {
+                evict_half(&mut self.whnf_cache);
+            }
+            if self.eq_cache.len() >= limit {
+                self.eq_cache.evict_half();
+            }
+            if self.reduction_cache.inner.len() >= limit {
+                evict_half(&mut self.reduction_cache.inner);
+            }
+        }
+    }
+
     pub fn should_check(&self) -> bool {
         !self.unsafe_unchecked
     }
 
-    /// The "heights" of two terms `E1` and `E2` are used to determine whether one 
-    /// is defined in terms of or uses terms derived from the other. If at some point 
-    /// we need to unify `E1 == E2`, we want to unfold the HIGHER one FIRST, since 
-    /// it will eventually unfold into (something resembling) the lower term, 
-    /// whereas continued unfolding of the lower term will just get us more and 
-    /// more primitive terms that get further away from the goal.
-    /// Thanks to @Gebner for explaining this to me.
-    fn def_height(&self, _fn : &Expr) -> u16 {
-        if let Const(_, name, _) = _fn.as_ref() {
-            self.env.read()
-                    .declarations
-                    .get(name)
-                    .map(|h| h.height + 1)
-                    .unwrap_or(0u16)
-        } else {
-            0u16
+    /// If the head of `e` is a definition we can unfold, its reducibility
+    /// hint. Thanks to @Gebner for explaining heights to me.
+    fn delta_hint(&self, e : &Expr) -> Option<ReducibilityHint> {
+        match e.unfold_apps_refs().0.as_ref() {
+            Const(_, name, _) => {
+                self.env.get_value(name)?;
+                if self.is_guessed_theorem(name) {
+                    return None
+                }
+                self.env.declarations.get(name).map(|d| d.hint)
+            },
+            _ => None
         }
     }
 
-    /// e is a prop iff it destructures as Sort(Level(Zero))
-    pub fn is_prop(&mut self, e : &Expr) -> bool {
-        match self.whnf(e).as_ref() {
-            Sort(_, lvl) => lvl.is_zero(),
+    /// What kind of definition `d` is. If the export didn't say, it's a
+    /// theorem if its type is a proposition. That's worked out the first
+    /// time anyone asks, which is when `d` is checked, or before that if
+    /// something else needs to know whether it can unfold `d`. Either way
+    /// it's worked out by `d`'s own bounded checker, so the answer doesn't
+    /// depend on who asks first.
+    pub fn def_kind(&self, d : &Declaration) -> Option<DefKind> {
+        if !d.guessed_kind {
+            return d.kind
+        }
+        if let Some(kind) = self.env.guessed_kinds.get(&d.name) {
+            return Some(*kind)
+        }
+        // a type with loose bound variables is rejected when it's checked.
+        let is_prop = !d.ty.has_vars() && d.bounded_tc(self.env.clone()).is_proposition(&d.ty).unwrap_or(false);
+        let kind = if is_prop { DefKind::Theorem } else { DefKind::Definition };
+        let _ = self.env.guessed_kinds.insert(d.name.clone(), kind);
+        Some(kind)
+    }
+
+    /// A theorem whose kind we had to guess has a reduction rule like any
+    /// other definition, but it's never used.
+    fn is_guessed_theorem(&self, name : &Name) -> bool {
+        match self.env.declarations.get(name) {
+            Some(d) if d.guessed_kind => self.def_kind(d) == Some(DefKind::Theorem),
             _ => false
         }
     }
 
+    /// Replace the definition at the head of `e` with its value.
+    fn unfold_definition(&mut self, e : &Expr) -> Option<Expr> {
+        self.evict_full_caches();
+        self.env
+            .reduction_map
+            .apply_to_map(e.clone(), &mut self.reduction_cache)
+            .map(|(unfolded, _)| unfolded)
+    }
+
+    /// Is `name` in the environment, and allowed to be referred to by
+    /// whatever we're checking? (see `visible_below`)
+    fn is_visible(&self, name : &Name) -> bool {
+        match self.get_declaration(name).map(|d| d.pos) {
+            Some(pos) => self.visible_below.map(|bound| pos < bound).unwrap_or(true),
+            None => false
+        }
+    }
+
+    /// e is a prop iff it destructures as Sort(Level(Zero))
+    pub fn is_prop(&mut self, e : &Expr) -> KernelResult<bool> {
+        match self.whnf(e)?.as_ref() {
+            Sort(_, lvl) => Ok(lvl.is_zero()),
+            _ => Ok(false)
+        }
+    }
+
     /// tries is_prop after inferring e
-    pub fn is_proposition(&mut self, e : &Expr) -> bool {
-        let inferred = self.infer(e);
+    pub fn is_proposition(&mut self, e : &Expr) -> KernelResult<bool> {
+        let inferred = self.infer(e)?;
         self.is_prop(&inferred)
     }
 
-    pub fn is_proof(&mut self, p: &Expr) -> (bool, Expr) {
-        let inferred = self.infer(p);
-        (self.is_proposition(&inferred), inferred)
+    pub fn is_proof(&mut self, p: &Expr) -> KernelResult<(bool, Expr)> {
+        let inferred = self.infer(p)?;
+        Ok((self.is_proposition(&inferred)?, inferred))
     }
 
-    fn is_proof_irrel_eq(&mut self, e1: &Expr, e2: &Expr) -> bool {
-        match self.is_proof(e1) {
-            (true, t1) => match self.is_proof(e2) {
-                (true, t2) => self.check_def_eq(&t1, &t2) == EqShort,
-                _ => false
+    fn is_proof_irrel_eq(&mut self, e1: &Expr, e2: &Expr) -> KernelResult<bool> {
+        match self.is_proof(e1)? {
+            (true, t1) => match self.is_proof(e2)? {
+                (true, t2) => self.def_eq(&t1, &t2),
+                _ => Ok(false)
             },
-            _ => false
+            _ => Ok(false)
         }
     }
 
 
+    /// Two terms whose type is a unit-like inductive (see `IndInfo::is_unit_like`)
+    /// are equal as long as their types are.
+    fn is_unit_like_eq(&mut self, e1 : &Expr, e2 : &Expr) -> KernelResult<bool> {
+        let t1 = self.infer(e1)?;
+        let t1 = self.whnf(&t1)?;
+        let is_unit_like = match t1.unfold_apps_refs().0.as_ref() {
+            Const(_, name, _) => self.env.inductives.get(name).map(|info| info.is_unit_like).unwrap_or(false),
+            _ => false
+        };
+
+        if is_unit_like {
+            let t2 = self.infer(e2)?;
+            self.def_eq(&t1, &t2)
+        } else {
+            Ok(false)
+        }
+    }
+
     /// More aggressive version of `unfold_pis`. Given some term `E`, repeats  
     /// `{ apply whnf(e), then unfold_pis(e) }` until that combination 
     /// fails to strip any more binders out.
-    pub fn normalize_pis(&mut self, e : &Expr) -> (Expr, Vec<Expr>) {
+    pub fn normalize_pis(&mut self, e : &Expr) -> KernelResult<(Expr, Vec<Expr>)> {
         let mut collected_binders = Vec::new();
         let mut acc = e.clone();
 
         loop {
             let len_before = collected_binders.len();
-            acc = self.whnf(&acc);
+            acc = self.whnf(&acc)?;
             acc.unfold_pis(&mut collected_binders);
             if len_before == collected_binders.len() {
                 break
             }
         }
 
-        (acc, collected_binders)
+        Ok((acc, collected_binders))
     }
 
     // This only gets used once in inductive. Will use &[Expr]
     // that comes as `toplevel_params` used during formation of intro rules.
     // I'm not really sure how the length of the subst sequence corresponds
     // to the number of times whnf is supposed to be executed to be honest.
-    pub fn instantiate_pis(&mut self, intro_type : &Expr, toplevel_intro_params : &[Expr]) -> Expr {
+    pub fn instantiate_pis(&mut self, intro_type : &Expr, toplevel_intro_params : &[Expr]) -> KernelResult<Expr> {
         let mut iterations_left = toplevel_intro_params.len();
         let mut acc = intro_type.clone();
 
@@ -137,39 +269,40 @@ impl TypeChecker {
                     acc = body.clone();
                 },
                 _ => { 
-                    acc = self.whnf(&acc);
-                    // assert that the result is a Pi
-                    assert!(match acc.as_ref() {
-                        Pi(..) => true,
-                        _ => false
-                    });
+                    let whnfd = self.whnf(&acc)?;
+                    // the result has to be a Pi, or we'll never run out of params
+                    if !matches!(whnfd.as_ref(), Pi(..)) {
+                        return Err(NotAPi(intro_type.clone(), whnfd))
+                    }
+                    acc = whnfd;
                 }
             }
         }
 
-        acc.instantiate(toplevel_intro_params.into_iter().rev())
+        Ok(acc.instantiate(toplevel_intro_params.into_iter().rev()))
     }
 
     /// Outward facing function/entry point for reduction to weak head normal form. 
     /// Checks cache for a previous result, calling whnf_core on a cache miss.
-    pub fn whnf(&mut self, e : &Expr) -> Expr {
+    pub fn whnf(&mut self, e : &Expr) -> KernelResult<Expr> {
         if let Some(cached) = self.whnf_cache.get(e) {
-            return cached.clone()
+            Ok(cached.clone())
         } else {
             let cache_key = e.clone();
-            let result = self.whnf_core(e.clone(), Some(FlagT));
+            let result = self.whnf_core(e.clone(), Some(FlagT))?;
+            self.evict_full_caches();
             self.whnf_cache.insert(cache_key, result.clone());
-            result
+            Ok(result)
         }
     }
 
-    pub fn whnf_core(&mut self, mut e : Expr, mut _flag : Option<Flag>) -> Expr {
+    pub fn whnf_core(&mut self, mut e : Expr, mut _flag : Option<Flag>) -> KernelResult<Expr> {
         loop {
             let flag = _flag.unwrap_or(FlagT);
             let (_fn, apps) = e.unfold_apps_refs();
 
             match _fn.as_ref() {
-                Sort(_, lvl) => return mk_sort(lvl.simplify()),
+                Sort(_, lvl) => return Ok(mk_sort(lvl.normalize())),
                 Lambda(..) if !apps.is_empty() => {
                     e = self.whnf_lambda(_fn, apps);
                     _flag = Some(flag);
@@ -179,14 +312,23 @@ impl TypeChecker {
                     e = instd.fold_apps(apps.into_iter().rev());
                     _flag = Some(flag);
                 },
+                Proj(_, struct_name, idx, structure) => {
+                    match self.reduce_proj(struct_name, *idx, structure, flag)? {
+                        Some(field) => {
+                            e = field.fold_apps(apps.into_iter().rev());
+                            _flag = Some(flag);
+                        },
+                        None => return Ok(e)
+                    }
+                },
                 _ => {
-                    let reduced = self.reduce_hdtl(_fn, apps.as_slice(), Some(flag));
+                    let reduced = self.reduce_hdtl(_fn, apps.as_slice(), Some(flag))?;
                     match reduced {
                         Some(eprime) => { 
                             _flag = Some(flag);
                             e = eprime;
                         },
-                        None => return e 
+                        None => return Ok(e)
                     }
                 }
             }
@@ -213,37 +355,69 @@ impl TypeChecker {
     }
 
     /// The entry point for executing a single reduction step on two
-    /// expressions.
-    pub fn reduce_exps(&mut self, e1 : Expr, e2 : Expr, flag : Option<Flag>) -> Option<(Expr, Expr)> {
-        assert!(flag == Some(FlagT));
+    /// expressions, trying `e1` first. Definitions are unfolded by
+    /// `lazy_delta_step` before we get here, so this is for everything else
+    /// (recursors, quotient and projections).
+    pub fn reduce_exps(&mut self, e1 : Expr, e2 : Expr) -> KernelResult<Option<(Expr, Expr)>> {
+        let flag = Some(FlagT);
         let (fn1, apps1) = e1.unfold_apps_refs();
         let (fn2, apps2) = e2.unfold_apps_refs();
 
-        // we want to evaluate these lazily.
-        let red1 = |tc : &mut TypeChecker| tc.reduce_hdtl(fn1, apps1.as_slice(), flag).map(|r| (r, e2.clone()));
-        let red2 = |tc : &mut TypeChecker| tc.reduce_hdtl(fn2, apps2.as_slice(), flag).map(|r| (e1.clone(), r));
-
-        if self.def_height(fn1) > self.def_height(fn2) {
-            red1(self).or(red2(self))
-        } else {
-            red2(self).or(red1(self))
+        match self.reduce_hdtl(fn1, apps1.as_slice(), flag)? {
+            Some(r) => Ok(Some((r, e2.clone()))),
+            None => Ok(self.reduce_hdtl(fn2, apps2.as_slice(), flag)?.map(|r| (e1.clone(), r)))
         }
     }
 
+    /// One step of lazy delta reduction. If either side has a definition
+    /// at its head, unfold only the one that should go first according to
+    /// the hints (both of them if they tie), and leave the other side as it
+    /// is, since it's usually what the unfolded side will turn into.
+    /// If both sides have the same head, `check_def_eq_patterns` has already
+    /// tried comparing their arguments by the time we get here, so we only
+    /// unfold once that's failed. Returns `None` if neither side has a
+    /// definition at its head.
+    fn lazy_delta_step(&mut self, e1 : &Expr, e2 : &Expr) -> Option<(Expr, Expr)> {
+        let (unfold1, unfold2) = match (self.delta_hint(e1), self.delta_hint(e2)) {
+            (None, None) => return None,
+            (Some(_), None) => (true, false),
+            (None, Some(_)) => (false, true),
+            (Some(h1), Some(h2)) => match h1.unfold_order(&h2) {
+                Ordering::Greater => (true, false),
+                Ordering::Less => (false, true),
+                Ordering::Equal => (true, true),
+            }
+        };
+
+        let new1 = if unfold1 { self.unfold_definition(e1)? } else { e1.clone() };
+        let new2 = if unfold2 { self.unfold_definition(e2)? } else { e2.clone() };
+        Some((new1, new2))
+    }
+
 
-    pub fn reduce_hdtl(&mut self, _fn : &Expr, apps : &[&Expr], flag : Option<Flag>) -> Option<Expr> {
+    pub fn reduce_hdtl(&mut self, _fn : &Expr, apps : &[&Expr], flag : Option<Flag>) -> KernelResult<Option<Expr>> {
 
         if let Some(FlagF) = flag {
-            return None
+            return Ok(None)
         }
 
         let name : &Name = match _fn.as_ref() {
             Const(_, name, _) => (name),
-            _ => return None
+            Proj(_, struct_name, idx, structure) => {
+                let field = self.reduce_proj(struct_name, *idx, structure, FlagT)?;
+                return Ok(field.map(|field| field.fold_apps(apps.iter().rev().copied())))
+            },
+            _ => return Ok(None)
         };
 
+        if let Some(reduced) = self.reduce_nat(name, apps)? {
+            return Ok(Some(reduced))
+        }
+        if self.is_guessed_theorem(name) {
+            return Ok(None)
+        }
+
         let major_prems = self.env
-                              .read()
                               .reduction_map
                               .get_major_premises(&name)
                               .cloned();
@@ -255,32 +429,366 @@ impl TypeChecker {
                    .as_ref()
                    .map(|set| set.contains(&idx))
                    .unwrap_or(false) {
-                       collected.push(self.whnf(&elem));
+                       let whnfd = self.whnf(elem)?;
+                       collected.push(self.lit_to_constructor(whnfd));
                    } else {
-                       collected.push(elem.clone().clone());
+                       collected.push((*elem).clone());
                    }
         }
 
         let applied = _fn.fold_apps(collected.iter()); 
-        let (result, constraints) = self.env
-                                        .read()
-                                        .reduction_map
-                                        .apply_to_map(applied, &mut self.reduction_cache)?;
+        self.evict_full_caches();
+        let applied = self.env
+                          .reduction_map
+                          .apply_to_map(applied, &mut self.reduction_cache);
+        let (result, constraints) = match applied {
+            Some(found) => found,
+            None => return Ok(None)
+        };
+
+        for (a, b) in constraints.iter() {
+            if !self.def_eq(a, b)? {
+                return Ok(None)
+            }
+        }
 
-        match constraints.iter()
-                         .all(|(a, b)| self.def_eq(a, b)) {
-                             true => Some(result),
-                             false => None
-                         }
+        Ok(Some(result))
     }
 
 
-    fn def_eq(&mut self, a : &Expr, b : &Expr) -> bool {
-        if self.check_def_eq(a, b) == EqShort {
-            return true
-        } else {
+    /// The number of parameters of `struct_name`, and its introduction rule,
+    /// if it's a structure.
+    fn struct_info(&self, struct_name : &Name) -> Option<(usize, StructInfo)> {
+        let info = self.env.inductives.get(struct_name)?;
+        info.structure.clone().map(|structure| (info.num_params, structure))
+    }
+
+    /// `Proj(struct_name, idx, structure)` reduces to the field once
+    /// `structure` reduces to the introduction rule applied to all of its
+    /// arguments. Without delta reduction (FlagF), we only go as far
+    /// as `whnf_core` looking for it.
+    fn reduce_proj(&mut self, struct_name : &Name, idx : usize, structure : &Expr, flag : Flag) -> KernelResult<Option<Expr>> {
+        let (num_params, info) = match self.struct_info(struct_name) {
+            Some((num_params, info)) if idx < info.num_fields => (num_params, info),
+            _ => return Ok(None)
+        };
+
+        let whnfd = match flag {
+            FlagT => self.whnf(structure)?,
+            FlagF => self.whnf_core(structure.clone(), Some(FlagF))?
+        };
+        let whnfd = self.lit_to_constructor(whnfd);
+
+        // `args` is reversed, so field `idx` is counted from the back.
+        let (_fn, args) = whnfd.unfold_apps_refs();
+        match _fn.as_ref() {
+            Const(_, name, _) if *name == info.ctor && args.len() == num_params + info.num_fields => {
+                Ok(Some(args[info.num_fields - 1 - idx].clone()))
+            },
+            _ => Ok(None)
+        }
+    }
+
+    /// Structure eta; `t` is equal to `s`, if `s` is `S.mk p_1 .. p_n f_1 .. f_m`
+    /// for a non-recursive structure `S`, `t` has the same type as `s`,
+    /// and each `f_i` is equal to the projection `t.i`.
+    fn eta_struct(&mut self, t : &Expr, s : &Expr) -> KernelResult<bool> {
+        let (_fn, args) = s.unfold_apps_refs();
+        let struct_name = match _fn.as_ref() {
+            Const(_, ctor, _) => match self.env.struct_ctors.get(ctor) {
+                Some(struct_name) => struct_name.clone(),
+                None => return Ok(false)
+            },
+            _ => return Ok(false)
+        };
+
+        let num_params = match self.struct_info(&struct_name) {
+            Some((num_params, info)) if args.len() == num_params + info.num_fields => num_params,
+            _ => return Ok(false)
+        };
+
+        let t_ty = self.infer(t)?;
+        let s_ty = self.infer(s)?;
+        if !self.def_eq(&t_ty, &s_ty)? {
+            return Ok(false)
+        }
+
+        for (idx, field) in args.iter().rev().skip(num_params).enumerate() {
+            let proj = mk_proj(struct_name.clone(), idx, t.clone());
+            if !self.def_eq(&proj, field)? {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// Kernel extension for natural number literals; see the `nat` module.
+    /// If `name` is one of the operations it knows about and the arguments
+    /// reduce to literals, we compute the answer directly instead of
+    /// unfolding `name`. `apps` is in the (reversed) order that
+    /// `unfold_apps_refs` returns.
+    fn reduce_nat(&mut self, name : &Name, apps : &[&Expr]) -> KernelResult<Option<Expr>> {
+        let ext = match self.env.nat_ext.get().cloned() {
+            Some(ext) => ext,
+            None => return Ok(None)
+        };
+
+        let op = match ext.get_op(name) {
+            Some(op) if op.arity() == apps.len() => op,
+            _ => return Ok(None)
+        };
+
+        if !self.is_visible(name) || !self.is_visible(&ext.nat) {
+            return Ok(None)
+        }
+        let bits = op == NatOp::Bit0 || op == NatOp::Bit1;
+        if op != NatOp::Succ && !bits && !self.nat_op_ok(name, op, &ext) {
+            return Ok(None)
+        }
+
+        let args = apps.iter().rev().copied().collect::<Vec<&Expr>>();
+
+        match op {
+            NatOp::Succ => {
+                let n = self.whnf_nat_lit(args[0], &ext)?;
+                Ok(n.map(|n| mk_nat_lit(n + BigUint::one())))
+            },
+            NatOp::Bit0 | NatOp::Bit1 => {
+                let (instances, arg) = args.split_at(args.len() - 1);
+                if !self.is_nat_bit_app(instances, &ext)? || !self.nat_op_ok(name, op, &ext) {
+                    return Ok(None)
+                }
+                let n = self.whnf_nat_lit(arg[0], &ext)?;
+                Ok(n.and_then(|n| apply_bit(op, &n)).map(mk_nat_lit))
+            },
+            binop => {
+                let lhs = match self.whnf_nat_lit(args[0], &ext)? {
+                    Some(n) => n,
+                    None => return Ok(None)
+                };
+                let rhs = match self.whnf_nat_lit(args[1], &ext)? {
+                    Some(n) => n,
+                    None => return Ok(None)
+                };
+
+                match apply_binop(binop, &lhs, &rhs) {
+                    Some(NatResult::Num(n)) => Ok(Some(mk_nat_lit(n))),
+                    Some(NatResult::Bool(_)) if binop == NatOp::DecEq => self.mk_dec_eq(lhs, rhs, &ext),
+                    Some(NatResult::Bool(b)) => {
+                        let ctor = if b { &ext.bool_true } else { &ext.bool_false };
+                        Ok(Some(mk_const(ctor.clone(), Vec::new())))
+                    },
+                    None => Ok(None)
+                }
+            }
+        }
+    }
+
+    /// `reduce_nat` for an application in `check_def_eq_core`. That has to
+    /// go before lazy delta, or a definition the nat extension stands in
+    /// for would be unfolded instead.
+    fn reduce_nat_app(&mut self, e : &Expr) -> KernelResult<Option<Expr>> {
+        let (_fn, apps) = e.unfold_apps_refs();
+        match _fn.as_ref() {
+            Const(_, name, _) => self.reduce_nat(name, apps.as_slice()),
+            _ => Ok(None)
+        }
+    }
+
+    /// Whether the nat extension can stand in for `name`, which it knows
+    /// as `op`. That's the case if `name` is an unfoldable definition of
+    /// the right type that satisfies `op`'s equations (see
+    /// `NatExt::equations`), and the operations those mention can be
+    /// trusted too. This is only worked out once per operation, by a
+    /// checker without the extension that can only see the constants
+    /// involved and what came before them, so the answer doesn't depend
+    /// on who asks first. Until all of those constants have been added,
+    /// the answer is no, and nothing is recorded.
+    fn nat_op_ok(&self, name : &Name, op : NatOp, ext : &NatExt) -> bool {
+        if !self.nat_ops {
             return false
         }
+        if let Some(ok) = self.env.checked_nat_ops.get(name) {
+            return *ok
+        }
+        match self.check_nat_op(name, op, ext) {
+            Some(ok) => {
+                let _ = self.env.checked_nat_ops.insert(name.clone(), ok);
+                ok
+            },
+            None => false
+        }
+    }
+
+    /// `None` if something `nat_op_ok` needs hasn't been added yet.
+    fn check_nat_op(&self, name : &Name, op : NatOp, ext : &NatExt) -> Option<bool> {
+        let declaration = self.env.declarations.get(name)?;
+        if self.env.get_value(name).is_none() {
+            return Some(false)
+        }
+        let num_univ_params = match op {
+            NatOp::Bit0 | NatOp::Bit1 => 1,
+            _ => 0
+        };
+        if declaration.univ_params.len() != num_univ_params {
+            return Some(false)
+        }
+
+        for prereq in op.prerequisites() {
+            let prereq_name = match ext.op_name(*prereq) {
+                Some(prereq_name) => prereq_name,
+                None => return Some(false)
+            };
+            self.env.declarations.get(&prereq_name)?;
+            if !self.nat_op_ok(&prereq_name, *prereq, ext) {
+                return Some(false)
+            }
+        }
+
+        let expected_type = ext.expected_type(op);
+        let equations = ext.equations(op, name);
+        if equations.is_empty() && op != NatOp::DecEq {
+            return Some(false)
+        }
+
+        let mut visible_below = declaration.pos;
+        let sides = equations.iter().flat_map(|(lhs, rhs)| vec![lhs, rhs]);
+        for e in sides.chain(expected_type.as_ref()) {
+            for n in unique_const_names(e) {
+                visible_below = visible_below.max(self.env.declarations.get(n)?.pos);
+            }
+        }
+
+        let mut tc = TypeChecker::new(None, self.env.clone()).with_visible_below(Some(visible_below + 1));
+        tc.nat_ops = false;
+        let mut ok = match &expected_type {
+            Some(ty) => tc.def_eq(&declaration.ty, ty),
+            None => Ok(true)
+        };
+        for (lhs, rhs) in equations.iter() {
+            ok = ok.and_then(|ok| if ok { tc.def_eq(lhs, rhs) } else { Ok(false) });
+        }
+        Some(ok.unwrap_or(false))
+    }
+
+    /// Reduce `e`, and read it as a number if it turns out to be a
+    /// literal or `nat.zero`. Since `nat.succ` of a literal reduces to a
+    /// literal (see `reduce_nat`), this also reads `nat.succ` chains.
+    fn whnf_nat_lit(&mut self, e : &Expr, ext : &NatExt) -> KernelResult<Option<BigUint>> {
+        let whnfd = self.whnf(e)?;
+        match whnfd.as_ref() {
+            NatLit(_, n) => Ok(Some(n.clone())),
+            Const(_, name, _) if *name == ext.zero => Ok(Some(BigUint::zero())),
+            _ => Ok(None)
+        }
+    }
+
+    /// `bit0` and `bit1` work for any type with `has_add` (and `has_one`),
+    /// so we only evaluate them when they're used at `nat` with nat's own
+    /// instances. `args` are the arguments before the number itself.
+    fn is_nat_bit_app(&mut self, args : &[&Expr], ext : &NatExt) -> KernelResult<bool> {
+        let (has_one, has_add) = match (&ext.has_one, &ext.has_add) {
+            (Some(has_one), Some(has_add)) => (has_one.clone(), has_add.clone()),
+            _ => return Ok(false)
+        };
+
+        let expected = match args.len() {
+            2 => vec![ext.nat.clone(), has_add],
+            3 => vec![ext.nat.clone(), has_one, has_add],
+            _ => return Ok(false)
+        };
+
+        for (arg, name) in args.iter().zip(expected) {
+            if !self.is_visible(&name) || !self.def_eq(arg, &mk_const(name, Vec::new()))? {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// `decidable (a = b)` for literals `a` and `b`. Two equal literals are
+    /// equal by `eq.refl`, and unequal ones get the proof built by
+    /// `NatExt::mk_ne_proof`. That proof is made out of constants the
+    /// export declares, so it's checked before we hand it out; if it
+    /// doesn't check, or the constants aren't visible from here, the
+    /// literals are left to the definition.
+    fn mk_dec_eq(&mut self, a : BigUint, b : BigUint, ext : &NatExt) -> KernelResult<Option<Expr>> {
+        let nat = mk_const(ext.nat.clone(), Vec::new());
+        let (lit_a, lit_b) = (mk_nat_lit(a.clone()), mk_nat_lit(b.clone()));
+        let prop = ext.mk_eq(&lit_a, &lit_b);
+
+        if a == b {
+            let refl = ext.eq.extend_str("refl");
+            if !self.is_visible(&ext.is_true) || !self.is_visible(&refl) {
+                return Ok(None)
+            }
+            let proof = mk_const(refl, vec![mk_succ(mk_zero())]).fold_apps(vec![&nat, &lit_a]);
+            return Ok(Some(mk_const(ext.is_true.clone(), Vec::new()).fold_apps(vec![&prop, &proof])))
+        }
+
+        let proof = match ext.mk_ne_proof(&lit_a, &lit_b) {
+            Some(proof) => proof,
+            None => return Ok(None)
+        };
+        let decided = mk_const(ext.is_false.clone(), Vec::new()).fold_apps(vec![&prop, &proof]);
+        let expected = mk_app(mk_const(ext.decidable.clone(), Vec::new()), prop);
+        match self.check_type(&decided, &expected) {
+            Ok(()) => Ok(Some(decided)),
+            Err(_) => Ok(None)
+        }
+    }
+
+    /// The other direction; where we need to see a constructor (IE
+    /// the major premise of `nat.rec`), a literal `n + 1` becomes
+    /// `nat.succ n`, and `0` becomes `nat.zero`. String literals are
+    /// unfolded all the way to a list of characters (see `unfold_str_lit`).
+    pub fn lit_to_constructor(&self, e : Expr) -> Expr {
+        match e.as_ref() {
+            NatLit(_, n) => match self.env.nat_ext.get() {
+                Some(ext) if n.is_zero() => mk_const(ext.zero.clone(), Vec::new()),
+                Some(ext) => mk_app(mk_const(ext.succ.clone(), Vec::new()), mk_nat_lit(n - BigUint::one())),
+                None => e.clone()
+            },
+            StrLit(_, s) => self.unfold_str_lit(s).unwrap_or_else(|| e.clone()),
+            _ => e
+        }
+    }
+
+    /// `None` if strings aren't declared, or if the constants the
+    /// unfolded literal is made of aren't visible from here.
+    fn unfold_str_lit(&self, s : &str) -> Option<Expr> {
+        let ext = self.env.str_ext.get().cloned()?;
+        if ext.unfold_names().iter().all(|name| self.is_visible(name)) {
+            Some(ext.unfold(s))
+        } else {
+            None
+        }
+    }
+
+    /// Both sides are `nat.zero` or `nat.succ _`.
+    fn check_def_eq_nat_ctors(&mut self, e1 : &Expr, e2 : &Expr) -> KernelResult<ShortCircuit> {
+        let (fn1, apps1) = e1.unfold_apps_refs();
+        let (fn2, apps2) = e2.unfold_apps_refs();
+        if fn1 != fn2 || apps1.len() != apps2.len() {
+            return Ok(NeqShort)
+        }
+        for (a1, a2) in apps1.into_iter().zip(apps2) {
+            if !self.def_eq(a1, a2)? {
+                return Ok(NeqShort)
+            }
+        }
+        Ok(EqShort)
+    }
+
+    fn is_nat_constructor(&self, name : &Name) -> bool {
+        match self.env.nat_ext.get() {
+            Some(ext) => *name == ext.zero || *name == ext.succ,
+            None => false
+        }
+    }
+
+    fn def_eq(&mut self, a : &Expr, b : &Expr) -> KernelResult<bool> {
+        Ok(self.check_def_eq(a, b)? == EqShort)
     }
 
 
@@ -288,125 +796,179 @@ impl TypeChecker {
     /// to prevent `patterns` from getting too big/hard to read.
     pub fn apps_eq(&mut self, 
                    apps1 : Vec<&Expr>, 
-                   apps2 : Vec<&Expr>) -> ShortCircuit {
+                   apps2 : Vec<&Expr>) -> KernelResult<ShortCircuit> {
         if apps1.len() != apps2.len() {
-            return NeqShort
+            Ok(NeqShort)
         } else {
             for (a, b) in apps1.iter().zip(apps2).rev() {
-                let closure = maybe_grow(64 * 1024, 1024 * 1024, || self.check_def_eq(a, b));
+                let closure = maybe_grow(64 * 1024, 1024 * 1024, || self.check_def_eq(a, b))?;
                 if closure == EqShort {
                     continue
                 } else {
-                    return NeqShort
+                    return Ok(NeqShort)
                 }
             }
-            EqShort
+            Ok(EqShort)
         }
     }
 
     /// Main entry point for checking definitional equality of two terms, which 
     /// dispatches out into a number of different functions. 
     /// 1. `check_def_eq_core` does some destructuring and reduction to weak head
-    ///     normal form.
+    ///    normal form.
     /// 2. `check_def_eq_patterns` just consults a big list of cases/patterns 
-    ///     to determine which decision procedure it needs to use move forward.
+    ///    to determine which decision procedure it needs to use move forward.
     /// 3. `patterns` may call `check_def_eq_pi/lambda` to determine whether
-    ///     a pair of Pi or Lambda expressions are definitionally equal.
-    pub fn check_def_eq(&mut self, e1 : &Expr, e2 : &Expr) -> ShortCircuit {
+    ///    a pair of Pi or Lambda expressions are definitionally equal.
+    pub fn check_def_eq(&mut self, e1 : &Expr, e2 : &Expr) -> KernelResult<ShortCircuit> {
         // checks for both pointer and structural equality
         if e1 == e2 {
-            return EqShort
+            return Ok(EqShort)
         } 
         
         // check whether this equality has been seen before.
         if let Some(cached) = self.eq_cache.get(&e1, &e2) {
-            return cached
+            return Ok(cached)
         }
 
         // otherwise, compute a result, then cache it in case we see these terms again.
-        let result = if self.is_proof_irrel_eq(e1, e2) {
+        let result = if self.is_proof_irrel_eq(e1, e2)? {
             EqShort
         } else {
-           self.check_def_eq_core(e1, e2)
+           self.check_def_eq_core(e1, e2)?
         };
 
+        self.evict_full_caches();
         self.eq_cache.insert(e1.clone(), e2.clone(), result);
-        result
+        Ok(result)
     }
 
 
 
     /// Dispatch point for different decision procedures used to determine
     /// whether two expressions are definitionally equal in a certain context.
-    pub fn check_def_eq_patterns(&mut self, whnfd_1 : &Expr, whnfd_2 : &Expr) -> ShortCircuit {
+    pub fn check_def_eq_patterns(&mut self, whnfd_1 : &Expr, whnfd_2 : &Expr) -> KernelResult<ShortCircuit> {
         let (fn1, apps1) = whnfd_1.unfold_apps_refs();
         let (fn2, apps2) = whnfd_2.unfold_apps_refs();
 
         match (fn1.as_ref(), fn2.as_ref()) {
+            (NatLit(_, n1), NatLit(_, n2)) => match n1 == n2 {
+                true => Ok(EqShort),
+                false => Ok(NeqShort)
+            },
+            // Only unfold a literal when the other side is a constructor, and
+            // then compare the arguments directly; going through `check_def_eq_core`
+            // would turn `nat.succ n` right back into `n + 1`.
+            (NatLit(..), Const(_, name, _)) if self.is_nat_constructor(name) => {
+                let unfolded = self.lit_to_constructor(whnfd_1.clone());
+                self.check_def_eq_nat_ctors(&unfolded, whnfd_2)
+            },
+            (Const(_, name, _), NatLit(..)) if self.is_nat_constructor(name) => {
+                let unfolded = self.lit_to_constructor(whnfd_2.clone());
+                self.check_def_eq_nat_ctors(whnfd_1, &unfolded)
+            },
+            (Proj(_, n1, idx1, s1), Proj(_, n2, idx2, s2)) if n1 == n2 && idx1 == idx2 => {
+                match self.def_eq(s1, s2)? {
+                    true => self.apps_eq(apps1, apps2),
+                    false => Ok(NeqShort)
+                }
+            },
+            (StrLit(_, s1), StrLit(_, s2)) => match s1 == s2 {
+                true => Ok(EqShort),
+                false => Ok(NeqShort)
+            },
+            // The unfolded literal has no literals left in it, so
+            // this can't go back and forth like the `nat` case could.
+            (StrLit(_, s), _) => match self.unfold_str_lit(s) {
+                Some(unfolded) => self.check_def_eq_core(&unfolded, whnfd_2),
+                None => Ok(NeqShort)
+            },
+            (_, StrLit(_, s)) => match self.unfold_str_lit(s) {
+                Some(unfolded) => self.check_def_eq_core(whnfd_1, &unfolded),
+                None => Ok(NeqShort)
+            },
             (Sort(_, l1), Sort(_, l2)) => 
             match apps1.is_empty() && apps2.is_empty() {
                     true => match Level::eq_by_antisymm(l1, l2) {
-                        true => EqShort,
-                        false => NeqShort,
+                        true => Ok(EqShort),
+                        false => Ok(NeqShort),
                     }
-                    _ => NeqShort
+                    _ => Ok(NeqShort)
             },
             (Const(_, n1, lvls1), Const(_, n2, lvls2)) => {
                 if n1 == n2 && lvls1.iter().zip(lvls2.as_ref()).all(|(a, b)| Level::eq_by_antisymm(a, b)) {
                     self.apps_eq(apps1, apps2)
                 } else {
-                    NeqShort
+                    Ok(NeqShort)
                 }
             },
             (Local(.., of1), Local(.., of2)) => {
                 if of1 == of2 {
                     self.apps_eq(apps1, apps2)
                 } else {
-                    NeqShort
+                    Ok(NeqShort)
                 }
             },
             (Lambda(..), Lambda(..)) => self.check_def_eq_lambdas(fn1, fn2),
-            (Lambda(_, dom,  _), _) => {
-                assert!(apps1.is_empty());
+            // `whnf_core` beta reduces, so neither lambda should be applied
+            // here; if one is, eta expanding against it would be wrong.
+            (Lambda(_, dom,  _), _) if apps1.is_empty() => {
                 let app = mk_app(whnfd_2.clone(), mk_var(0));
                 let new_lam = mk_lambda(dom.clone(), app);
                 self.check_def_eq_core(fn1, &new_lam)
             },
-            (_, Lambda(_, dom, _)) => {
+            (_, Lambda(_, dom, _)) if apps2.is_empty() => {
                 let app = mk_app(whnfd_1.clone(), mk_var(0));
                 let new_lam = mk_lambda(dom.clone(), app);
                 self.check_def_eq_core(&new_lam, fn2)
             },
             (Pi(..), Pi(..)) => self.check_def_eq_pis(fn1, fn2),
-            _ => NeqShort
+            _ => Ok(NeqShort)
         }
     }
 
 
 
-    pub fn check_def_eq_core(&mut self, e1_0 : &Expr, e2_0 : &Expr) -> ShortCircuit {
+    pub fn check_def_eq_core(&mut self, e1_0 : &Expr, e2_0 : &Expr) -> KernelResult<ShortCircuit> {
 
-        let whnfd_1 = self.whnf_core(e1_0.clone(), Some(FlagF));
-        let whnfd_2 = self.whnf_core(e2_0.clone(), Some(FlagF));
+        let whnfd_1 = self.whnf_core(e1_0.clone(), Some(FlagF))?;
+        let whnfd_2 = self.whnf_core(e2_0.clone(), Some(FlagF))?;
 
         // consult different patterns laid out in 
         // check_def_eq_patterns to see how to proceed
-        match self.check_def_eq_patterns(&whnfd_1, &whnfd_2) {
-            EqShort => return EqShort,
-            NeqShort => {
-                match self.reduce_exps(whnfd_1, whnfd_2, Some(FlagT)) {
+        match self.check_def_eq_patterns(&whnfd_1, &whnfd_2)? {
+            EqShort => Ok(EqShort),
+            NeqShort | Unknown => {
+                if let Some(reduced) = self.reduce_nat_app(&whnfd_1)? {
+                    return self.check_def_eq_core(&reduced, &whnfd_2)
+                }
+                if let Some(reduced) = self.reduce_nat_app(&whnfd_2)? {
+                    return self.check_def_eq_core(&whnfd_1, &reduced)
+                }
+                if let Some((unfolded_1, unfolded_2)) = self.lazy_delta_step(&whnfd_1, &whnfd_2) {
+                    return self.check_def_eq_core(&unfolded_1, &unfolded_2)
+                }
+
+                match self.reduce_exps(whnfd_1.clone(), whnfd_2.clone())? {
                     Some((red1, red2)) => self.check_def_eq_core(&red1, &red2),
-                    _ => return NeqShort
+                    // Neither side reduces any further; the last things to
+                    // try are structure eta and unit-like types, which both
+                    // need to infer types.
+                    None => match self.eta_struct(&whnfd_1, &whnfd_2)?
+                                  || self.eta_struct(&whnfd_2, &whnfd_1)?
+                                  || self.is_unit_like_eq(&whnfd_1, &whnfd_2)? {
+                        true => Ok(EqShort),
+                        false => Ok(NeqShort)
+                    }
                 }
-            },
-            _ => unreachable!()
+            }
         }
     }
 
 
     // Literally the same function as its Lambda counterpart, but checks for a different
     // enum discriminant (Pis instead of Lambdas).
-    pub fn check_def_eq_pis(&mut self, mut e1 : &Expr, mut e2 : &Expr) -> ShortCircuit {
+    pub fn check_def_eq_pis(&mut self, mut e1 : &Expr, mut e2 : &Expr) -> KernelResult<ShortCircuit> {
 
         let mut substs = Vec::new();
 
@@ -422,8 +984,8 @@ impl TypeChecker {
                 lhs_type = Some(dom2.clone().swap_ty(instd_d2_ty.clone()));
                 // If the domains are found not to be equal, return early
                 // with NeqShort since the whole thing is therefore not equal
-                if !self.def_eq(&instd_d1_ty, &instd_d2_ty) {
-                    return NeqShort
+                if !self.def_eq(&instd_d1_ty, &instd_d2_ty)? {
+                    return Ok(NeqShort)
                 }
             }
 
@@ -446,16 +1008,16 @@ impl TypeChecker {
         }
 
         match self.def_eq(&e1.instantiate(substs.iter().rev()), 
-                          &e2.instantiate(substs.iter().rev())) {
-            true => EqShort,
-            false => NeqShort
+                          &e2.instantiate(substs.iter().rev()))? {
+            true => Ok(EqShort),
+            false => Ok(NeqShort)
         }
     }
 
 
     // Literally the same function as its Pi counterpart, but checks for a different
     // enum discriminant (Lambdas instead of Pis).
-    pub fn check_def_eq_lambdas(&mut self, mut e1 : &Expr, mut e2 : &Expr) -> ShortCircuit {
+    pub fn check_def_eq_lambdas(&mut self, mut e1 : &Expr, mut e2 : &Expr) -> KernelResult<ShortCircuit> {
         let mut substs = Vec::new();
 
         // weird rust syntax; just means "for as long as e1 and e2 
@@ -470,8 +1032,8 @@ impl TypeChecker {
                 lhs_type = Some(dom2.clone().swap_ty(instd_d2_ty.clone()));
                 // If the lambda domains are found not to be equal, return early
                 // with NeqShort since the whole thing is therefore not equal
-                if !self.def_eq(&instd_d1_ty, &instd_d2_ty) {
-                    return NeqShort
+                if !self.def_eq(&instd_d1_ty, &instd_d2_ty)? {
+                    return Ok(NeqShort)
                 }
             }
 
@@ -495,9 +1057,9 @@ impl TypeChecker {
            }
 
         match self.def_eq(&e1.instantiate(substs.iter().rev()), 
-                          &e2.instantiate(substs.iter().rev())) {
-            true => EqShort,
-            false => NeqShort
+                          &e2.instantiate(substs.iter().rev()))? {
+            true => Ok(EqShort),
+            false => Ok(NeqShort)
         }
     }
 
@@ -508,44 +1070,117 @@ impl TypeChecker {
     /// Some of the methods are fairly long so they've been broken out 
     /// into separate functions, trusting in the compiler to inline 
     /// where appropriate.
-    pub fn infer(&mut self, term : &Expr) -> Expr {
+    pub fn infer(&mut self, term : &Expr) -> KernelResult<Expr> {
         if let Some(cached) = self.infer_cache.get(&term) {
-            return cached.clone()
+            return Ok(cached.clone())
         }
 
         let cache_key = term.clone();
 
         let result = match term.as_ref() {
             Sort(_, lvl)           => mk_sort(mk_succ(lvl.clone())),
-            Const(_, name, lvls)   => self.infer_const(name, lvls),
+            Const(_, name, lvls)   => self.infer_const(name, lvls)?,
             Local(.., bind)        => (bind.ty).clone(),
-            App(..)                => self.infer_apps(term),
-            Lambda(..)             => self.infer_lambda(term),
-            Pi(..)                 => mk_sort(self.infer_pi(term)),
-            Let(_, dom, val, body) => self.infer_let(dom, val, body),
-            owise                  => err_infer_var(line!(), owise),
+            App(..)                => self.infer_apps(term)?,
+            Lambda(..)             => self.infer_lambda(term)?,
+            Pi(..)                 => mk_sort(self.infer_pi(term)?),
+            Let(_, dom, val, body) => self.infer_let(dom, val, body)?,
+            NatLit(..)             => self.infer_nat_lit(term)?,
+            StrLit(..)             => self.infer_str_lit(term)?,
+            Proj(_, n, idx, e)     => self.infer_proj(term, n, *idx, e)?,
+            Var(..)                => return Err(InferVar(term.clone())),
         };
 
+        self.evict_full_caches();
         self.infer_cache.insert(cache_key, result.clone());
 
-        result
+        Ok(result)
     }
 
 
 
-    pub fn infer_const(&mut self, name : &Name, levels : &Arc<Vec<Level>>) -> Expr {
-        match self.env.read().declarations.get(name) {
+    pub fn infer_const(&mut self, name : &Name, levels : &Arc<Vec<Level>>) -> KernelResult<Expr> {
+        match self.get_declaration(name) {
             Some(dec) => {
+                match self.visible_below {
+                    Some(bound) if dec.pos == bound => return Err(SelfReference(name.clone())),
+                    Some(bound) if dec.pos > bound => return Err(ForwardReference(name.clone())),
+                    _ => ()
+                }
                 let univ_params = dec.univ_params.as_ref();
-                assert!(univ_params.len() == levels.len());
+                if univ_params.len() != levels.len() {
+                    return Err(NumLevelsMismatch(name.clone(), univ_params.len(), levels.len()))
+                }
                 let subst_map = univ_params.clone().into_iter().zip(levels.as_ref().clone()).collect::<Vec<(Level, Level)>>();
-                dec.ty.instantiate_ps(&subst_map)
+                Ok(dec.ty.instantiate_ps(&subst_map))
             },
-            None => err_infer_const(line!(), name)
+            None => Err(UnknownConst(name.clone()))
+        }
+    }
+
+    pub fn infer_nat_lit(&mut self, term : &Expr) -> KernelResult<Expr> {
+        let nat = self.env.nat_ext.get().map(|ext| ext.nat.clone());
+        match nat {
+            Some(nat) if self.is_visible(&nat) => Ok(mk_const(nat, Vec::new())),
+            _ => Err(NatLitWithoutNat(term.clone()))
+        }
+    }
+
+    pub fn infer_str_lit(&mut self, term : &Expr) -> KernelResult<Expr> {
+        let string = self.env.str_ext.get().map(|ext| ext.string.clone());
+        match string {
+            Some(string) if self.is_visible(&string) => Ok(mk_const(string, Vec::new())),
+            _ => Err(StrLitWithoutString(term.clone()))
         }
     }
 
-    pub fn infer_lambda(&mut self, mut term : &Expr) -> Expr {
+    /// The type of field `idx` in the structure's introduction rule, with
+    /// the parameters filled in from the type of `structure`, and earlier
+    /// fields the type depends on filled in with their own projections.
+    /// Out of a proof, only proofs can be projected, and only if the
+    /// type doesn't depend on any data in the proof.
+    pub fn infer_proj(&mut self, term : &Expr, struct_name : &Name, idx : usize, structure : &Expr) -> KernelResult<Expr> {
+        let structure_ty = self.infer(structure)?;
+        let structure_ty = self.whnf(&structure_ty)?;
+        let invalid = || InvalidProj { proj : term.clone(), ty : structure_ty.clone() };
+
+        let (_fn, args) = structure_ty.unfold_apps_refs();
+        let levels = match _fn.as_ref() {
+            Const(_, name, levels) if name == struct_name => levels,
+            _ => return Err(invalid())
+        };
+
+        let info = match self.struct_info(struct_name) {
+            Some((num_params, info)) if idx < info.num_fields && args.len() == num_params => info,
+            _ => return Err(invalid())
+        };
+
+        let ctor_ty = self.infer_const(&info.ctor, levels)?;
+        let params = args.iter().rev().map(|x| (*x).clone()).collect::<Vec<Expr>>();
+        let mut ty = self.instantiate_pis(&ctor_ty, params.as_slice())?;
+        let in_prop = self.is_proposition(&structure_ty)?;
+
+        for field_idx in 0..idx {
+            ty = self.whnf(&ty)?;
+            let (dom, body) = match ty.as_ref() {
+                Pi(_, dom, body) => (dom.ty.clone(), body.clone()),
+                _ => return Err(invalid())
+            };
+            if in_prop && body.var_bound() > 0 && !self.is_proposition(&dom)? {
+                return Err(PropProj(term.clone()))
+            }
+            let field = mk_proj(struct_name.clone(), field_idx, structure.clone());
+            ty = body.instantiate(Some(&field).into_iter());
+        }
+
+        match self.whnf(&ty)?.as_ref() {
+            Pi(_, dom, _) if in_prop && !self.is_proposition(&dom.ty)? => Err(PropProj(term.clone())),
+            Pi(_, dom, _) => Ok(dom.ty.clone()),
+            _ => Err(invalid())
+        }
+    }
+
+    pub fn infer_lambda(&mut self, mut term : &Expr) -> KernelResult<Expr> {
         let mut domains = Vec::with_capacity(50);
         let mut locals  = Vec::with_capacity(50);
 
@@ -555,7 +1190,7 @@ impl TypeChecker {
             let new_dom = old_dom.clone().swap_ty(new_dom_ty.clone());
 
             if self.should_check() {
-                self.infer_universe_of_type(&new_dom_ty);
+                self.infer_universe_of_type(&new_dom_ty)?;
             }
 
             let new_local = new_dom.as_local();
@@ -564,69 +1199,70 @@ impl TypeChecker {
         }
 
         let instd = term.instantiate(locals.iter().rev());
-        let inferred = self.infer(&instd);
+        let inferred = self.infer(&instd)?;
         let mut abstrd = inferred.abstract_(locals.iter().rev());
 
         while let Some(d) = domains.pop() {
             abstrd = mk_pi(d, abstrd);
         }
 
-        abstrd
+        Ok(abstrd)
     }
 
 
 
   
-    pub fn infer_universe_of_type(&mut self, term : &Expr) -> Level {
-        let inferred = self.infer(term);
-        match self.whnf(&inferred).as_ref() {
-            Sort(_, lvl) => lvl.clone(),
-            owise => err_infer_universe(line!(), owise),
+    pub fn infer_universe_of_type(&mut self, term : &Expr) -> KernelResult<Level> {
+        let inferred = self.infer(term)?;
+        let whnfd = self.whnf(&inferred)?;
+        match whnfd.as_ref() {
+            Sort(_, lvl) => Ok(lvl.clone()),
+            _ => Err(NotASort(term.clone(), whnfd.clone())),
         }
     }
 
 
-    fn infer_apps(&mut self, term : &Expr) -> Expr {
+    fn infer_apps(&mut self, term : &Expr) -> KernelResult<Expr> {
         let (fn_, mut apps) = term.unfold_apps_refs();
 
-        let mut acc = self.infer(fn_);
+        let mut acc = self.infer(fn_)?;
         let mut context = Vec::<&Expr>::with_capacity(apps.len());
 
         while let Some(elem) = apps.pop() {
             if let Pi(_, ref old_dom, ref old_body) = acc.as_ref() {
                 if self.should_check() {
                     let new_dom_ty = old_dom.ty
-                                     .instantiate(context.iter().map(|x| *x).rev());
-                    self.check_type(elem, &new_dom_ty);
+                                     .instantiate(context.iter().copied().rev());
+                    self.check_type(elem, &new_dom_ty)?;
                 }
                 context.push(elem);
                 acc = (old_body).clone();
             } else {
-                let instd = acc.instantiate(context.iter().map(|x| *x).rev());
-                let whnfd = self.whnf(&instd);
+                let instd = acc.instantiate(context.iter().copied().rev());
+                let whnfd = self.whnf(&instd)?;
                 match whnfd.as_ref() {
                     Pi(..) => {
                         apps.push(elem);
                         context = Vec::new();
                         acc = whnfd;
                     },
-                    owise => err_infer_apps(line!(), owise),
+                    _ => return Err(NotAPi(term.clone(), whnfd)),
                 }
             }
         }
 
-        acc.instantiate(context.iter().map(|x| *x).rev())
+        Ok(acc.instantiate(context.iter().copied().rev()))
    }
 
 
-    pub fn infer_pi(&mut self, mut term : &Expr) -> Level {
+    pub fn infer_pi(&mut self, mut term : &Expr) -> KernelResult<Level> {
         let mut locals = Vec::new();
         let mut universes = Vec::new();
 
         while let Pi(_, ref old_dom, ref old_body) = term.as_ref() {
             let new_dom_ty = old_dom.ty.instantiate(locals.iter().rev());
             let new_dom = old_dom.clone().swap_ty(new_dom_ty.clone());
-            let dom_univ = self.infer_universe_of_type(&new_dom_ty);
+            let dom_univ = self.infer_universe_of_type(&new_dom_ty)?;
             universes.push(dom_univ);
             let new_local = new_dom.as_local();
             locals.push(new_local);
@@ -634,7 +1270,7 @@ impl TypeChecker {
         }
 
         let instd = term.clone().instantiate(locals.iter().rev());
-        let mut inferred = self.infer_universe_of_type(&instd);
+        let mut inferred = self.infer_universe_of_type(&instd)?;
         //let inferred = self.infer_universe_of_type(&instd);
         //foldr(|acc, next| mk_imax(acc, next), universes, inferred)
 
@@ -642,15 +1278,15 @@ impl TypeChecker {
             inferred = mk_imax(u, inferred);
         };
 
-        inferred
+        Ok(inferred)
     }
     
-    pub fn infer_let(&mut self, dom : &Binding, val : &Expr, body : &Expr) -> Expr {
+    pub fn infer_let(&mut self, dom : &Binding, val : &Expr, body : &Expr) -> KernelResult<Expr> {
         if self.should_check() {
-            self.infer_universe_of_type(&dom.ty);
+            self.infer_universe_of_type(&dom.ty)?;
         }
         if self.should_check() {
-            self.check_type(val, &dom.ty);
+            self.check_type(val, &dom.ty)?;
         }
 
         let instd_body = body.instantiate(Some(val).into_iter());
@@ -658,18 +1294,18 @@ impl TypeChecker {
     }
 
 
-    pub fn check_type(&mut self, e : &Expr, ty : &Expr) {
-        let inferred = self.infer(e);
-        match self.check_def_eq(ty, &inferred) {
-            EqShort => (),
-            _ => err_check_type(line!(), e, ty),
+    pub fn check_type(&mut self, e : &Expr, ty : &Expr) -> KernelResult<()> {
+        let inferred = self.infer(e)?;
+        match self.check_def_eq(ty, &inferred)? {
+            EqShort => Ok(()),
+            _ => Err(TypeMismatch { term : e.clone(), expected : ty.clone(), inferred }),
         }
     }
 
-    pub fn require_def_eq(&mut self, e1 : &Expr, e2 : &Expr) {
-        match self.check_def_eq(e1, e2) {
-            EqShort => (),
-            _ => err_req_def_eq(line!(), e1, e2)
+    pub fn require_def_eq(&mut self, e1 : &Expr, e2 : &Expr) -> KernelResult<()> {
+        match self.check_def_eq(e1, e2)? {
+            EqShort => Ok(()),
+            _ => Err(DefEqFailure(e1.clone(), e2.clone()))
         }
     }
 
@@ -687,3 +1323,89 @@ pub enum Flag {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ check_export, CheckOptions };
+    use crate::parser::ExportFormat;
+
+    /// `A : Type` and `a : A`, then definitions of `A` equal to `a` with
+    /// each kind of hint, and an opaque one.
+    const HINTS : &str = "\
+1 #NS 0 A
+2 #NS 0 a
+3 #NS 0 low
+4 #NS 0 high
+5 #NS 0 abbrev
+6 #NS 0 opaque
+7 #NS 0 hidden
+1 #US 0
+0 #ES 1
+1 #EC 1
+2 #EC 2
+3 #EC 3
+#AX 1 0
+#AX 2 1
+#DEF 3 1 2 R 1
+#DEF 4 1 3 R 2
+#DEF 5 1 2 A
+#DEF 6 1 2 O
+#OPAQ 7 1 2
+";
+
+    fn checker() -> TypeChecker {
+        let options = CheckOptions { format : Some(ExportFormat::Lean4), ..CheckOptions::new(1) };
+        let report = check_export(HINTS.to_string(), &options).unwrap();
+        TypeChecker::new(None, report.env)
+    }
+
+    fn c(n : &str) -> Expr {
+        mk_const(n, Vec::new())
+    }
+
+    fn head(e : &Expr) -> String {
+        match e.as_ref() {
+            Const(_, name, _) => name.to_string(),
+            owise => panic!("expected a constant, got {:?}", owise)
+        }
+    }
+
+    /// The heads of both sides after one step of lazy delta on `l =?= r`.
+    fn step(tc : &mut TypeChecker, l : &str, r : &str) -> Option<(String, String)> {
+        tc.lazy_delta_step(&c(l), &c(r)).map(|(l, r)| (head(&l), head(&r)))
+    }
+
+    #[test]
+    fn unfold_order() {
+        use ReducibilityHint::*;
+        assert_eq!(Regular(2).unfold_order(&Regular(1)), Ordering::Greater);
+        assert_eq!(Regular(1).unfold_order(&Regular(1)), Ordering::Equal);
+        assert_eq!(Abbrev.unfold_order(&Regular(9)), Ordering::Greater);
+        assert_eq!(Opaque.unfold_order(&Regular(0)), Ordering::Less);
+        assert_eq!(Opaque.unfold_order(&Abbrev), Ordering::Less);
+        assert_eq!(Opaque.unfold_order(&Opaque), Ordering::Equal);
+    }
+
+    #[test]
+    fn hints_decide_which_side_unfolds() {
+        let mut tc = checker();
+        let pair = |l : &str, r : &str| Some((l.to_string(), r.to_string()));
+        assert_eq!(step(&mut tc, "low", "high"), pair("low", "low"));
+        assert_eq!(step(&mut tc, "high", "low"), pair("low", "low"));
+        assert_eq!(step(&mut tc, "low", "low"), pair("a", "a"));
+        assert_eq!(step(&mut tc, "abbrev", "high"), pair("a", "high"));
+        assert_eq!(step(&mut tc, "opaque", "low"), pair("opaque", "a"));
+        assert_eq!(step(&mut tc, "opaque", "a"), pair("a", "a"));
+    }
+
+    #[test]
+    fn opaque_definitions_never_unfold() {
+        let mut tc = checker();
+        assert_eq!(step(&mut tc, "hidden", "hidden"), None);
+        assert_eq!(step(&mut tc, "hidden", "opaque"), Some(("hidden".to_string(), "a".to_string())));
+        assert_eq!(head(&tc.whnf(&c("hidden")).unwrap()), "hidden");
+        assert!(!tc.def_eq(&c("hidden"), &c("a")).unwrap());
+        assert!(tc.def_eq(&c("opaque"), &c("a")).unwrap());
+    }
+}
@@ -2,23 +2,20 @@ use std::collections::VecDeque;
 use std::sync::Arc;
 
 use hashbrown::HashMap;
-use parking_lot::RwLock;
+use parking_lot::{ Mutex, Condvar };
 
 use crate::expr::Expr;
-use crate::env::{ Modification, CompiledModification };
+use crate::env::Modification;
 use crate::pretty::components::Notation;
 
 use Either::*;
 use ShortCircuit::*;
 
-/// Items used to communicate with the threads looping through
-/// the queues that hold the typechecker's work. Needed in order
-/// to discriminate between the case of "the queue doesn't have
-/// any work for you right now" and "the job this queue was needed
-/// for is complete"
+/// Items used to tell the thread taking work off a `WorkQueue`
+/// that the job the queue was needed for is complete, and
+/// nothing else is coming.
 pub const END_MSG_ADD : QueueMsg<Modification> = Right(());
 pub const END_MSG_NOTATION : QueueMsg<Notation> = Right(());
-pub const END_MSG_CHK : QueueMsg<CompiledModification> = Right(());
 
 
 pub fn foldr<A, B, I>(f : impl Fn(A, B) -> B, i : I, init : B) -> B 
@@ -136,11 +133,11 @@ impl EqCache {
     pub fn get(&self, e1 : &Expr, e2 : &Expr) -> Option<ShortCircuit> {
         match self.inner.get(e1) {
             None => match self.inner.get(e2) {
-                Some(v) => v.iter().find(|(a, _)| a == e1).map(|(_, b)| b.clone()),
+                Some(v) => v.iter().find(|(a, _)| a == e1).map(|(_, b)| *b),
                 None => return None
             },
             Some(v) => {
-                v.iter().find(|(a, _)| a == e2).map(|(_, b)| b.clone())
+                v.iter().find(|(a, _)| a == e2).map(|(_, b)| *b)
             }
         }
     }
@@ -157,34 +154,91 @@ impl EqCache {
             }
         }
     }
+
+    /// The number of distinct left hand sides.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    /// See `evict_half`.
+    pub fn evict_half(&mut self) {
+        evict_half(&mut self.inner)
+    }
+}
+
+/// Drop about half of the entries in `map`, to make room in a cache
+/// that's full without forgetting everything it's learned. Which half
+/// goes is up to the map's iteration order.
+pub fn evict_half<K : Eq + std::hash::Hash, V>(map : &mut HashMap<K, V>) {
+    let mut keep = false;
+    map.retain(|_, _| {
+        keep = !keep;
+        keep
+    });
 }
 
 
 
-/// Queue backed by a thread-safe VecDeque. 
+/// Queue backed by a thread-safe VecDeque. Popping blocks until
+/// there's something in the queue, so consumers don't need to poll.
 #[derive(Debug, Clone)]
-pub struct RwQueue<T>(Arc<RwLock<VecDeque<T>>>);
+pub struct WorkQueue<T>(Arc<(Mutex<VecDeque<T>>, Condvar)>);
 
-impl<T> RwQueue<T> {
+impl<T> WorkQueue<T> {
     pub fn with_capacity(n : usize) -> Self {
         let inner = VecDeque::with_capacity(n);
-        RwQueue(Arc::new(RwLock::new(inner)))
+        WorkQueue(Arc::new((Mutex::new(inner), Condvar::new())))
     }
 
     pub fn push(&self, t : T) {
-        match self {
-            RwQueue(inner) => inner.write().push_back(t)
-        }
+        let WorkQueue(inner) = self;
+        inner.0.lock().push_back(t);
+        inner.1.notify_one();
     }
 
-    pub fn pop(&self) -> Option<T> {
-        match self {
-            RwQueue(inner) => inner.write().pop_front()
+    pub fn pop(&self) -> T {
+        let WorkQueue(inner) = self;
+        let mut guard = inner.0.lock();
+        loop {
+            match guard.pop_front() {
+                Some(t) => return t,
+                None => inner.1.wait(&mut guard)
+            }
         }
     }
 }
 
 pub type QueueMsg<T> = Either<T, ()>;
 
-pub type ModQueue = RwQueue<QueueMsg<Modification>>;
-pub type CompiledQueue = RwQueue<QueueMsg<CompiledModification>>;
+pub type ModQueue = WorkQueue<QueueMsg<Modification>>;
+
+/// The most memory this process has had resident at once, in bytes, if
+/// the OS will tell us; for now that means reading `VmHWM` from
+/// `/proc/self/status` on Linux.
+pub fn peak_memory() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_half_keeps_half() {
+        let mut map = (0..1000).map(|n| (n, n)).collect::<HashMap<usize, usize>>();
+        evict_half(&mut map);
+        assert_eq!(map.len(), 500);
+        assert!(map.iter().all(|(k, v)| k == v));
+    }
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use nanoda::Report;
+use nanoda::name::Name;
+use nanoda::axioms::AxiomReport;
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// `nat`, the axioms `propext : Prop` and `my_ax : Prop`, and the
+/// definitions `lemma := propext`, `thm := lemma` (so `propext` only
+/// comes in through `lemma`), `user := my_ax`, `both : user → Prop`,
+/// and `rec_user`, which mentions `nat` and `nat.rec` and nothing else.
+fn axioms() -> Report {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let nat = ex.nat();
+    let prop = ex.sort(0);
+    ex.axiom("propext", prop);
+    ex.axiom("my_ax", prop);
+
+    let propext = ex.cnst("propext", &[]);
+    ex.def("lemma", prop, propext);
+    let lemma = ex.cnst("lemma", &[]);
+    ex.def("thm", prop, lemma);
+    let my_ax = ex.cnst("my_ax", &[]);
+    ex.def("user", prop, my_ax);
+
+    // `both : user → Prop := fun _, thm`
+    let thm = ex.cnst("thm", &[]);
+    let user = ex.cnst("user", &[]);
+    let user_prop = ex.pi("h", user, prop);
+    let both = ex.lam("x", user, thm);
+    ex.def("both", user_prop, both);
+
+    // `rec_user : nat → nat := nat.rec nat.zero (fun n ih, nat.succ ih)`
+    let one = ex.succ(0);
+    let rec = ex.cnst("nat.rec", &[one]);
+    let motive = ex.lam("n", nat, nat);
+    let zero = ex.cnst("nat.zero", &[]);
+    let succ = ex.cnst("nat.succ", &[]);
+    let v0 = ex.var(0);
+    let succ_ih = ex.app(succ, &[v0]);
+    let step = ex.lam("ih", nat, succ_ih);
+    let step = ex.lam("n", nat, step);
+    let rec_user = ex.app(rec, &[motive, zero, step]);
+    let nat_nat = ex.pi("n", nat, nat);
+    ex.def("rec_user", nat_nat, rec_user);
+
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), Vec::<String>::new());
+    report
+}
+
+fn axioms_of(report : &AxiomReport, n : &str) -> Vec<String> {
+    let (_, axioms) = report.entries.iter().find(|(m, _)| m.to_string() == n).unwrap();
+    axioms.iter().map(|ax| ax.to_string()).collect()
+}
+
+#[test]
+fn closure_goes_through_definitions() {
+    let report = AxiomReport::new(&axioms().env, None).unwrap();
+    assert_eq!(axioms_of(&report, "lemma"), vec!["propext"]);
+    assert_eq!(axioms_of(&report, "thm"), vec!["propext"]);
+    assert_eq!(axioms_of(&report, "both"), vec!["my_ax", "propext"]);
+    assert_eq!(axioms_of(&report, "propext"), vec!["propext"]);
+}
+
+/// `nat`, `nat.rec` and the rest of what an inductive declaration adds
+/// aren't axioms, and don't get entries of their own either.
+#[test]
+fn inductives_arent_axioms() {
+    let report = AxiomReport::new(&axioms().env, None).unwrap();
+    assert_eq!(axioms_of(&report, "rec_user"), Vec::<String>::new());
+    let names = report.entries.iter().map(|(n, _)| n.to_string()).collect::<Vec<String>>();
+    assert_eq!(names, vec!["propext", "my_ax", "lemma", "thm", "user", "both", "rec_user"]);
+}
+
+#[test]
+fn user_axioms_are_marked() {
+    let targets = ["thm", "user", "rec_user"].iter().map(|n| Name::from(*n)).collect::<Vec<Name>>();
+    let report = AxiomReport::new(&axioms().env, Some(&targets)).unwrap();
+    assert_eq!(report.to_string(), "\
+thm depends on axioms : [propext]
+user depends on axioms : [my_ax (user declared)]
+rec_user does not depend on any axioms
+");
+    assert_eq!(report.to_json(), r#"[
+  {"name": "thm", "standard_axioms": ["propext"], "user_axioms": []},
+  {"name": "user", "standard_axioms": [], "user_axioms": ["my_ax"]},
+  {"name": "rec_user", "standard_axioms": [], "user_axioms": []}
+]"#);
+}
+
+#[test]
+fn unknown_targets_are_errors() {
+    let targets = vec![Name::from("thm"), Name::from("nope")];
+    assert!(AxiomReport::new(&axioms().env, Some(&targets)).is_err());
+}
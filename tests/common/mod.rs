@@ -0,0 +1,231 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use nanoda::{ check_export, CheckOptions, CheckError, Report };
+use nanoda::parser::ExportFormat;
+
+/// Check a Lean 4 export given as a string, in serial.
+pub fn check_lean4(source : &str) -> Result<Report, CheckError> {
+    let options = CheckOptions { format : Some(ExportFormat::Lean4), ..CheckOptions::new(1) };
+    check_export(source.to_string(), &options)
+}
+
+/// Like `check_lean4`, but with `keep_going` set.
+pub fn check_lean4_keep_going(source : &str) -> Report {
+    let options = CheckOptions { format : Some(ExportFormat::Lean4), keep_going : true, ..CheckOptions::new(1) };
+    check_export(source.to_string(), &options).expect("keep going never fails")
+}
+
+/// Check `export` with `keep_going` set.
+pub fn check_keep_going(export : &Export) -> Report {
+    let options = CheckOptions { format : Some(export.format), keep_going : true, ..CheckOptions::new(1) };
+    check_export(export.source(), &options).expect("keep going never fails")
+}
+
+/// The names of the items that failed in a keep going `report`.
+pub fn failed_names(report : &Report) -> Vec<String> {
+    report.summary.as_ref().unwrap().failed.iter().map(|e| e.name.to_string()).collect()
+}
+
+/// Check `export` with `keep_going` set, and return the names of the
+/// items that failed.
+pub fn failures(export : &Export) -> Vec<String> {
+    failed_names(&check_keep_going(export))
+}
+
+/// Writes an export file one line at a time, handing out the indices of
+/// names, levels and expressions as they're added. Names are given as
+/// strings like `"Nat.succ"`, and levels and expressions by the index
+/// they were given. Level 0 is always zero.
+pub struct Export {
+    pub format : ExportFormat,
+    lines : Vec<String>,
+    names : HashMap<String, usize>,
+    num_levels : usize,
+    num_exprs : usize,
+}
+
+impl Export {
+    pub fn new(format : ExportFormat) -> Self {
+        Export {
+            format,
+            lines : Vec::new(),
+            names : HashMap::new(),
+            num_levels : 1,
+            num_exprs : 0,
+        }
+    }
+
+    pub fn source(&self) -> String {
+        let mut source = self.lines.join("\n");
+        source.push('\n');
+        source
+    }
+
+    pub fn name(&mut self, s : &str) -> usize {
+        if let Some(idx) = self.names.get(s) {
+            return *idx
+        }
+        let (prefix, last) = match s.rfind('.') {
+            Some(pos) => (self.name(&s[..pos]), &s[pos + 1..]),
+            None => (0, s)
+        };
+        let idx = self.names.len() + 1;
+        self.lines.push(format!("{} #NS {} {}", idx, prefix, last));
+        self.names.insert(s.to_string(), idx);
+        idx
+    }
+
+    fn level(&mut self, line : String) -> usize {
+        let idx = self.num_levels;
+        self.lines.push(format!("{} {}", idx, line));
+        self.num_levels += 1;
+        idx
+    }
+
+    pub fn succ(&mut self, l : usize) -> usize {
+        self.level(format!("#US {}", l))
+    }
+
+    pub fn param(&mut self, s : &str) -> usize {
+        let n = self.name(s);
+        self.level(format!("#UP {}", n))
+    }
+
+    fn expr(&mut self, line : String) -> usize {
+        let idx = self.num_exprs;
+        self.lines.push(format!("{} {}", idx, line));
+        self.num_exprs += 1;
+        idx
+    }
+
+    pub fn sort(&mut self, l : usize) -> usize {
+        self.expr(format!("#ES {}", l))
+    }
+
+    pub fn cnst(&mut self, s : &str, levels : &[usize]) -> usize {
+        let n = self.name(s);
+        let levels = levels.iter().map(|l| format!(" {}", l)).collect::<String>();
+        self.expr(format!("#EC {}{}", n, levels))
+    }
+
+    pub fn var(&mut self, i : usize) -> usize {
+        self.expr(format!("#EV {}", i))
+    }
+
+    pub fn app(&mut self, f : usize, args : &[usize]) -> usize {
+        args.iter().fold(f, |f, a| self.expr(format!("#EA {} {}", f, a)))
+    }
+
+    pub fn pi(&mut self, binder : &str, dom : usize, body : usize) -> usize {
+        let n = self.name(binder);
+        self.expr(format!("#EP #BD {} {} {}", n, dom, body))
+    }
+
+    pub fn lam(&mut self, binder : &str, dom : usize, body : usize) -> usize {
+        let n = self.name(binder);
+        self.expr(format!("#EL #BD {} {} {}", n, dom, body))
+    }
+
+    pub fn let_in(&mut self, binder : &str, ty : usize, val : usize, body : usize) -> usize {
+        let n = self.name(binder);
+        self.expr(format!("#EZ {} {} {} {}", n, ty, val, body))
+    }
+
+    /// Field `idx` of `e`, an inhabitant of the structure `s`; Lean 4 only.
+    pub fn proj(&mut self, s : &str, idx : usize, e : usize) -> usize {
+        let n = self.name(s);
+        self.expr(format!("#EJ {} {} {}", n, idx, e))
+    }
+
+    /// A natural number literal; Lean 4 only.
+    pub fn lit(&mut self, n : u64) -> usize {
+        self.expr(format!("#ELN {}", n))
+    }
+
+    /// A string literal; Lean 4 only.
+    pub fn str_lit(&mut self, s : &str) -> usize {
+        let bytes = s.bytes().map(|b| format!(" {:02x}", b)).collect::<String>();
+        self.expr(format!("#ELS{}", bytes))
+    }
+
+    fn uparams(&mut self, uparams : &[&str]) -> String {
+        uparams.iter().map(|u| format!(" {}", self.name(u))).collect()
+    }
+
+    pub fn axiom(&mut self, s : &str, ty : usize) {
+        let n = self.name(s);
+        self.lines.push(format!("#AX {} {}", n, ty));
+    }
+
+    pub fn def(&mut self, s : &str, ty : usize, val : usize) {
+        self.def_u(s, ty, val, &[])
+    }
+
+    /// A definition with universe parameters.
+    pub fn def_u(&mut self, s : &str, ty : usize, val : usize, uparams : &[&str]) {
+        let n = self.name(s);
+        let uparams = self.uparams(uparams);
+        match self.format {
+            ExportFormat::Lean3 => self.lines.push(format!("#DEF {} {} {}{}", n, ty, val, uparams)),
+            ExportFormat::Lean4 => self.lines.push(format!("#DEF {} {} {} R 1{}", n, ty, val, uparams)),
+        }
+    }
+
+    /// A single inductive type with `num_params` parameters, no indices,
+    /// and no recursive arguments; see `inductive_counts` for the rest.
+    pub fn inductive(&mut self, s : &str, num_params : usize, ty : usize, ctors : &[(&str, usize)], uparams : &[&str]) {
+        self.inductive_counts(s, num_params, 0, false, ty, ctors, uparams)
+    }
+
+    /// A single inductive type, along with the number of indices it has
+    /// and whether it's recursive, which Lean 4 exports spell out.
+    #[allow(clippy::too_many_arguments)]
+    pub fn inductive_counts(&mut self,
+                            s : &str,
+                            num_params : usize,
+                            num_indices : usize,
+                            is_rec : bool,
+                            ty : usize,
+                            ctors : &[(&str, usize)],
+                            uparams : &[&str]) {
+        let n = self.name(s);
+        let ctor_names = ctors.iter().map(|(c, _)| self.name(c)).collect::<Vec<usize>>();
+        let uparams = self.uparams(uparams);
+        match self.format {
+            ExportFormat::Lean3 => {
+                let ctors = ctor_names.iter().zip(ctors).map(|(c, (_, ty))| format!(" {} {}", c, ty)).collect::<String>();
+                self.lines.push(format!("#IND {} {} {} {}{}{}", num_params, n, ty, ctor_names.len(), ctors, uparams));
+            },
+            ExportFormat::Lean4 => {
+                let names = ctor_names.iter().map(|c| format!(" {}", c)).collect::<String>();
+                self.lines.push(format!("#IND {} {} {} 0 {} {} 1 {} {}{}{}", n, ty, is_rec as usize, num_params, num_indices, n, ctor_names.len(), names, uparams));
+                for (idx, (c, (_, ty))) in ctor_names.iter().zip(ctors).enumerate() {
+                    self.lines.push(format!("#CTOR {} {} {} {} {} 0", c, ty, n, idx, num_params));
+                }
+            }
+        }
+    }
+
+    /// A `#QUOT` line; Lean 3 only, since Lean 4 exports the quotient's
+    /// constants one at a time.
+    pub fn quot(&mut self) {
+        self.lines.push("#QUOT".to_string());
+    }
+
+    /// `Nat` (or Lean 3's `nat`) with its usual constructors; returns the
+    /// index of the type.
+    pub fn nat(&mut self) -> usize {
+        let (nat, zero, succ) = match self.format {
+            ExportFormat::Lean3 => ("nat", "nat.zero", "nat.succ"),
+            ExportFormat::Lean4 => ("Nat", "Nat.zero", "Nat.succ"),
+        };
+        let one = self.succ(0);
+        let ty = self.sort(one);
+        let nat_e = self.cnst(nat, &[]);
+        let succ_ty = self.pi("n", nat_e, nat_e);
+        self.inductive_counts(nat, 0, 0, true, ty, &[(zero, nat_e), (succ, succ_ty)], &[]);
+        nat_e
+    }
+}
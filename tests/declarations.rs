@@ -0,0 +1,107 @@
+mod common;
+
+use nanoda::{ check_export, CheckOptions, Report };
+use nanoda::errors::KernelError;
+use nanoda::expr::InnerExpr;
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// Check `ex` with `keep_going`, in serial and in parallel.
+fn check_both(ex : &Export, f : impl Fn(Report)) {
+    for &num_threads in [1, 4].iter() {
+        let options = CheckOptions { format : Some(ex.format), keep_going : true, ..CheckOptions::new(num_threads) };
+        f(check_export(ex.source(), &options).expect("keep going never fails"));
+    }
+}
+
+/// The error `report`'s only failure was rejected with.
+fn only_error(report : &Report) -> KernelError {
+    let failed = &report.summary.as_ref().unwrap().failed;
+    assert_eq!(failed.len(), 1, "{:?}", failed_names(report));
+    failed[0].err.clone()
+}
+
+fn is_declared(report : &Report, name : &str) -> bool {
+    report.env.declarations.get(&name.parse().unwrap()).is_some()
+}
+
+#[test]
+fn redeclarations_are_rejected() {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let nat = ex.nat();
+    let prop = ex.sort(0);
+    ex.axiom("a", prop);
+    ex.axiom("a", nat);
+    // Not `a`, since anything that uses a name that failed is skipped.
+    ex.axiom("p", prop);
+    let p = ex.cnst("p", &[]);
+    ex.def("d", prop, p);
+    ex.def("d", prop, p);
+    ex.axiom("nat.rec", prop);
+
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), vec!["a", "d", "nat.rec"]);
+    for (failed, name) in report.summary.as_ref().unwrap().failed.iter().zip(["a", "d", "nat.rec"]) {
+        match &failed.err {
+            KernelError::DuplicateDeclaration(original, new) => {
+                assert_eq!(original.name.to_string(), name);
+                assert_eq!(new.name.to_string(), name);
+                let msg = failed.err.to_string();
+                assert!(msg.contains(&format!("declare {} with", name)), "{}", msg);
+                assert!(msg.contains(&format!("the name {} was", name)), "{}", msg);
+            },
+            owise => panic!("expected a duplicate declaration, got {}", owise)
+        }
+    }
+    // The originals are untouched.
+    let a = report.env.declarations.get(&"a".parse().unwrap()).unwrap();
+    assert!(matches!(a.ty.as_ref(), InnerExpr::Sort(..)));
+}
+
+/// `foo.rec` is taken by an axiom, so the whole `foo` inductive is
+/// rejected, and none of its other declarations go in.
+#[test]
+fn rejected_batch_leaves_nothing_behind() {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let prop = ex.sort(0);
+    ex.axiom("foo.rec", prop);
+    let one = ex.succ(0);
+    let ty = ex.sort(one);
+    let foo = ex.cnst("foo", &[]);
+    ex.inductive("foo", 0, ty, &[("foo.mk", foo)], &[]);
+
+    check_both(&ex, |report| {
+        assert!(matches!(only_error(&report), KernelError::DuplicateDeclaration(..)));
+        assert!(is_declared(&report, "foo.rec"));
+        assert!(!is_declared(&report, "foo"));
+        assert!(!is_declared(&report, "foo.mk"));
+        assert!(report.env.inductives.get(&"foo".parse().unwrap()).is_none());
+    });
+}
+
+#[test]
+fn self_references_are_rejected() {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let prop = ex.sort(0);
+    let d = ex.cnst("d", &[]);
+    ex.def("d", prop, d);
+
+    check_both(&ex, |report| {
+        assert!(matches!(only_error(&report), KernelError::SelfReference(n) if n.to_string() == "d"));
+    });
+}
+
+/// `a`'s type mentions `b`, which comes after it.
+#[test]
+fn forward_references_are_rejected() {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let prop = ex.sort(0);
+    let b = ex.cnst("b", &[]);
+    ex.axiom("a", b);
+    ex.axiom("b", prop);
+
+    check_both(&ex, |report| {
+        assert!(matches!(only_error(&report), KernelError::ForwardReference(n) if n.to_string() == "b"));
+        assert!(is_declared(&report, "b"));
+    });
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use nanoda::Report;
+use nanoda::name::Name;
+use nanoda::inductive::IndInfo;
+use nanoda::parser::ExportFormat;
+use common::*;
+
+fn info(report : &Report, n : &str) -> IndInfo {
+    report.env.inductives.get(&Name::from(n)).cloned().unwrap()
+}
+
+/// `Pair : Type` with `Pair.mk : Nat → Nat → Pair`, `Unit : Type` with
+/// `Unit.star : Unit`, and for each an axiom `P_T : T → Prop`.
+fn pair_and_unit(ex : &mut Export) {
+    let nat = ex.nat();
+    let one = ex.succ(0);
+    let type_ = ex.sort(one);
+    let prop = ex.sort(0);
+
+    let pair = ex.cnst("Pair", &[]);
+    let snd = ex.pi("snd", nat, pair);
+    let mk_ty = ex.pi("fst", nat, snd);
+    ex.inductive("Pair", 0, type_, &[("Pair.mk", mk_ty)], &[]);
+
+    let unit = ex.cnst("Unit", &[]);
+    ex.inductive("Unit", 0, type_, &[("Unit.star", unit)], &[]);
+
+    for t in ["Pair", "Unit"].iter() {
+        let c = ex.cnst(t, &[]);
+        let p_ty = ex.pi("x", c, prop);
+        ex.axiom(&format!("P_{}", t), p_ty);
+    }
+}
+
+/// `def <name> : P_<t> <to> := h` for an axiom `h : P_<t> <from>`.
+fn transport(ex : &mut Export, name : &str, t : &str, from : usize, to : usize) {
+    let p = ex.cnst(&format!("P_{}", t), &[]);
+    let p_from = ex.app(p, &[from]);
+    let h = format!("h_{}", name);
+    ex.axiom(&h, p_from);
+    let h = ex.cnst(&h, &[]);
+    let p_to = ex.app(p, &[to]);
+    ex.def(name, p_to, h);
+}
+
+#[test]
+fn structure_eta() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    pair_and_unit(&mut ex);
+    let pair = ex.cnst("Pair", &[]);
+    ex.axiom("p", pair);
+    let p = ex.cnst("p", &[]);
+    let fst = ex.proj("Pair", 0, p);
+    let snd = ex.proj("Pair", 1, p);
+    let mk = ex.cnst("Pair.mk", &[]);
+    let eta = ex.app(mk, &[fst, snd]);
+    let swapped = ex.app(mk, &[snd, fst]);
+    transport(&mut ex, "expand", "Pair", p, eta);
+    transport(&mut ex, "contract", "Pair", eta, p);
+    transport(&mut ex, "swap", "Pair", p, swapped);
+    assert_eq!(failures(&ex), vec!["swap"]);
+}
+
+#[test]
+fn unit_like_eta() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    pair_and_unit(&mut ex);
+    let unit = ex.cnst("Unit", &[]);
+    ex.axiom("x", unit);
+    ex.axiom("y", unit);
+    let (x, y) = (ex.cnst("x", &[]), ex.cnst("y", &[]));
+    transport(&mut ex, "unit", "Unit", x, y);
+
+    // `Pair` has fields, so two of them aren't equal just by their type.
+    let pair = ex.cnst("Pair", &[]);
+    ex.axiom("a", pair);
+    ex.axiom("b", pair);
+    let (a, b) = (ex.cnst("a", &[]), ex.cnst("b", &[]));
+    transport(&mut ex, "pair", "Pair", a, b);
+    assert_eq!(failures(&ex), vec!["pair"]);
+}
+
+/// Neither rule applies to `R` with `R.mk : R → R`, which is recursive.
+/// `T : Prop` with `T.intro : T` isn't unit-like, and although `A : Prop`
+/// with `A.mk : T → T → A` keeps its structure info for projections,
+/// equality of proofs is left to proof irrelevance.
+#[test]
+fn recursive_and_prop_types_are_excluded() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let one = ex.succ(0);
+    let type_ = ex.sort(one);
+    let prop = ex.sort(0);
+
+    let r = ex.cnst("R", &[]);
+    let r_mk = ex.pi("r", r, r);
+    ex.inductive_counts("R", 0, 0, true, type_, &[("R.mk", r_mk)], &[]);
+
+    let t = ex.cnst("T", &[]);
+    ex.inductive("T", 0, prop, &[("T.intro", t)], &[]);
+
+    let a = ex.cnst("A", &[]);
+    let a_right = ex.pi("right", t, a);
+    let a_mk = ex.pi("left", t, a_right);
+    ex.inductive("A", 0, prop, &[("A.mk", a_mk)], &[]);
+
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), Vec::<String>::new());
+    let (r, t, a) = (info(&report, "R"), info(&report, "T"), info(&report, "A"));
+    assert!(r.structure.is_none() && !r.is_unit_like);
+    assert!(!t.is_unit_like);
+    assert!(!a.is_unit_like && a.structure.is_some());
+}
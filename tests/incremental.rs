@@ -0,0 +1,71 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use nanoda::{ check_export, CheckOptions, Report };
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// `leaf := <leaf_val>`, `mid := leaf`, and `top : P mid := h`, where
+/// `h : P 1`, so `top` only checks if `leaf` is `1`. `other` doesn't
+/// depend on any of them.
+fn export(leaf_val : u64) -> String {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let nat = ex.nat();
+    let prop = ex.sort(0);
+    let p_ty = ex.pi("n", nat, prop);
+    ex.axiom("P", p_ty);
+    let p = ex.cnst("P", &[]);
+    let one = ex.lit(1);
+    let p_one = ex.app(p, &[one]);
+    ex.axiom("h", p_one);
+    let h = ex.cnst("h", &[]);
+
+    let val = ex.lit(leaf_val);
+    ex.def("leaf", nat, val);
+    let leaf = ex.cnst("leaf", &[]);
+    ex.def("mid", nat, leaf);
+    let mid = ex.cnst("mid", &[]);
+    let p_mid = ex.app(p, &[mid]);
+    ex.def("top", p_mid, h);
+    let five = ex.lit(5);
+    ex.def("other", nat, five);
+    ex.source()
+}
+
+fn check(source : String, cache : &Path) -> Report {
+    let options = CheckOptions {
+        format : Some(ExportFormat::Lean4),
+        keep_going : true,
+        result_cache : Some(cache.to_path_buf()),
+        ..CheckOptions::new(1)
+    };
+    check_export(source, &options).expect("keep going never fails")
+}
+
+fn failed(report : &Report) -> Vec<String> {
+    report.summary.as_ref().unwrap().failed.iter().map(|e| e.name.to_string()).collect()
+}
+
+#[test]
+fn editing_a_leaf_rechecks_its_dependents() {
+    let cache = std::env::temp_dir().join(format!("nanoda-incremental-{}.nanoda-cache", std::process::id()));
+    let _ = fs::remove_file(&cache);
+
+    let first = check(export(1), &cache);
+    assert!(failed(&first).is_empty());
+    assert_eq!(first.num_unchanged, 0);
+
+    let second = check(export(1), &cache);
+    assert!(failed(&second).is_empty());
+    assert_eq!(second.num_unchanged, second.num_checked);
+
+    // `top` only fails if it's checked again, even though it doesn't
+    // mention `leaf` itself.
+    let edited = check(export(2), &cache);
+    assert_eq!(failed(&edited), vec!["top"]);
+    assert_eq!(edited.num_unchanged, edited.num_checked - 3);
+
+    let _ = fs::remove_file(&cache);
+}
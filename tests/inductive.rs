@@ -0,0 +1,295 @@
+mod common;
+
+use nanoda::CheckError;
+use nanoda::name::Name;
+use nanoda::errors::KernelError;
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// `inductive Bad | mk : (Bad → Bad) → Bad`, followed by an unrelated axiom.
+const NON_POSITIVE : &str = "\
+1 #NS 0 Bad
+2 #NS 1 mk
+3 #NS 0 f
+4 #NS 0 x
+5 #NS 0 Ok
+1 #US 0
+0 #ES 1
+1 #EC 1
+2 #EP #BD 4 1 1
+3 #EP #BD 3 2 1
+#IND 1 0 1 0 0 0 1 1 1 2
+#CTOR 2 3 1 0 0 1
+#AX 5 0
+";
+
+#[test]
+fn rejected_inductive_leaves_nothing_behind() {
+    assert!(check_lean4(NON_POSITIVE).is_err());
+
+    let report = check_lean4_keep_going(NON_POSITIVE);
+    let summary = report.summary.unwrap();
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.num_passed, 1);
+    assert!(report.env.declarations.get(&Name::from("Bad")).is_none());
+    assert!(report.env.declarations.get(&Name::from("Ok")).is_some());
+}
+
+/// `inductive I : Type → Type | mk : I (I Prop)` mentions `I` in the
+/// index of `mk`'s result; `mk : I Prop` is fine.
+#[test]
+fn ind_occurrence_in_intro_index_is_rejected() {
+    for &nested in [false, true].iter() {
+        let mut ex = Export::new(ExportFormat::Lean3);
+        let prop = ex.sort(0);
+        let one = ex.succ(0);
+        let type_ = ex.sort(one);
+        let ty = ex.pi("α", type_, type_);
+        let i = ex.cnst("I", &[]);
+        let mut index = prop;
+        if nested {
+            index = ex.app(i, &[prop]);
+        }
+        let mk_ty = ex.app(i, &[index]);
+        ex.inductive("I", 0, ty, &[("I.mk", mk_ty)], &[]);
+
+        let report = check_keep_going(&ex);
+        if nested {
+            assert!(matches!(last_error(&ex), KernelError::IndOccurrenceInIndex(n, _) if n == "I.mk".parse().unwrap()));
+            assert!(report.env.declarations.get(&Name::from("I")).is_none());
+        } else {
+            assert_eq!(failed_names(&report), Vec::<String>::new());
+        }
+    }
+}
+
+/// Lean 4 recursors always take a dependent motive, even for a `Prop`.
+/// `def foo.{u} : {motive : True → Sort u} → motive True.intro → (t : True) → motive t := True.rec.{u}`
+const TRUE_REC : &str = "\
+1 #NS 0 True
+2 #NS 1 intro
+3 #NS 0 foo
+4 #NS 0 motive
+5 #NS 0 h
+6 #NS 0 t
+7 #NS 0 u
+8 #NS 1 rec
+1 #UP 7
+0 #ES 0
+1 #EC 1
+#IND 1 0 0 0 0 0 1 1 1 2
+#CTOR 2 1 1 0 0 0
+2 #ES 1
+3 #EP #BD 6 1 2
+4 #EV 0
+5 #EC 2
+6 #EA 4 5
+7 #EV 2
+8 #EV 0
+9 #EA 7 8
+10 #EP #BD 6 1 9
+11 #EP #BD 5 6 10
+12 #EP #BI 4 3 11
+13 #EC 8 1
+#DEF 3 12 13 R 1 7
+";
+
+#[test]
+fn lean4_prop_recursor_is_dependent() {
+    check_lean4(TRUE_REC).unwrap();
+}
+
+/// A mutual block of propositions,
+///```pseudo
+/// mutual
+///   inductive A : Prop | a : A
+///   inductive B : Prop | b : A → B
+/// end
+/// def bar : {motive_1 : A → Prop} → {motive_2 : B → Prop} → motive_1 A.a
+///           → ((x : A) → motive_1 x → motive_2 (B.b x)) → (t : B) → motive_2 t := B.rec
+///```
+const MUTUAL_PROP_REC : &str = "\
+1 #NS 0 A
+2 #NS 1 a
+3 #NS 0 B
+4 #NS 3 b
+5 #NS 0 x
+6 #NS 0 bar
+7 #NS 0 motive_1
+8 #NS 0 motive_2
+9 #NS 0 ha
+10 #NS 0 hb
+11 #NS 0 t
+12 #NS 0 ih
+13 #NS 3 rec
+0 #ES 0
+1 #EC 1
+2 #EC 3
+3 #EP #BD 5 1 2
+#IND 1 0 1 0 0 0 2 1 3 1 2
+#IND 3 0 1 0 0 0 2 1 3 1 4
+#CTOR 2 1 1 0 0 0
+#CTOR 4 3 3 0 0 1
+4 #EP #BD 5 1 0
+5 #EP #BD 5 2 0
+6 #EV 1
+7 #EC 2
+8 #EA 6 7
+9 #EV 3
+10 #EV 0
+11 #EA 9 10
+12 #EV 3
+13 #EC 4
+14 #EV 1
+15 #EA 13 14
+16 #EA 12 15
+17 #EP #BD 12 11 16
+18 #EP #BD 5 1 17
+19 #EV 3
+20 #EV 0
+21 #EA 19 20
+22 #EP #BD 11 2 21
+23 #EP #BD 10 18 22
+24 #EP #BD 9 8 23
+25 #EP #BI 8 5 24
+26 #EP #BI 7 4 25
+27 #EC 13
+#DEF 6 26 27 R 1
+";
+
+#[test]
+fn lean4_mutual_prop_recursor_is_dependent() {
+    check_lean4(MUTUAL_PROP_REC).unwrap();
+}
+
+/// The `#IND` lines of a mutual block have to agree about the parameters
+/// and universe parameters.
+#[test]
+fn mutual_ind_lines_must_agree() {
+    let more_params = MUTUAL_PROP_REC.replace("#IND 3 0 1 0 0 0", "#IND 3 0 1 0 1 0");
+    let more_uparams = MUTUAL_PROP_REC.replace("#IND 3 0 1 0 0 0 2 1 3 1 4", "#IND 3 0 1 0 0 0 2 1 3 1 4 5");
+    for source in [more_params, more_uparams].iter() {
+        assert!(matches!(check_lean4(source), Err(CheckError::Parse(_))));
+    }
+}
+
+/// `Nat` with the wrong recursiveness, and a type with one index
+/// exported as having none (or two).
+#[test]
+fn exported_counts_are_checked() {
+    let export = |is_rec, num_indices| {
+        let mut ex = Export::new(ExportFormat::Lean4);
+        let one = ex.succ(0);
+        let ty = ex.sort(one);
+        let nat = ex.cnst("Nat", &[]);
+        let succ_ty = ex.pi("n", nat, nat);
+        ex.inductive_counts("Nat", 0, 0, is_rec, ty, &[("Nat.zero", nat), ("Nat.succ", succ_ty)], &[]);
+        let fam_ty = ex.pi("n", nat, ty);
+        let fam = ex.cnst("Fam", &[]);
+        let zero = ex.cnst("Nat.zero", &[]);
+        let fam_zero = ex.app(fam, &[zero]);
+        ex.inductive_counts("Fam", 0, num_indices, false, fam_ty, &[("Fam.mk", fam_zero)], &[]);
+        ex
+    };
+
+    assert_eq!(failures(&export(true, 1)), Vec::<String>::new());
+    let report = check_keep_going(&export(false, 1));
+    assert_eq!(failed_names(&report), vec!["Nat"]);
+    assert!(matches!(&report.summary.as_ref().unwrap().failed[0].err, KernelError::ExportMismatch { what : "recursiveness", .. }));
+    for &num_indices in [0, 2].iter() {
+        assert!(matches!(last_error(&export(true, num_indices)), KernelError::ExportMismatch { what : "number of indices", .. }));
+    }
+}
+
+/// `inductive R | mk : R → R` has a single introduction rule and no
+/// indices, but it's recursive, so it isn't a structure.
+/// `def g : R → R := fun r => r.1`
+const RECURSIVE_PROJ : &str = "\
+1 #NS 0 R
+2 #NS 1 mk
+3 #NS 0 r
+4 #NS 0 g
+1 #US 0
+0 #ES 1
+1 #EC 1
+2 #EP #BD 3 1 1
+#IND 1 0 1 0 0 0 1 1 1 2
+#CTOR 2 2 1 0 0 1
+3 #EV 0
+4 #EJ 1 0 3
+5 #EL #BD 3 1 4
+#DEF 4 2 5 R 1
+";
+
+#[test]
+fn no_projections_out_of_recursive_types() {
+    let err = match check_lean4(RECURSIVE_PROJ) {
+        Err(CheckError::Kernel(err)) => err,
+        _ => panic!("a projection out of a recursive type was accepted")
+    };
+    assert_eq!(err.name, Name::from("g"));
+    assert!(matches!(err.err, KernelError::InvalidProj { .. }));
+}
+
+/// Check a Lean 3 export that should fail on its last item, and
+/// return that item's error.
+fn last_error(ex : &Export) -> KernelError {
+    let report = check_keep_going(ex);
+    let failed = &report.summary.as_ref().unwrap().failed;
+    assert_eq!(failed.len(), 1, "{:?}", failed_names(&report));
+    failed[0].err.clone()
+}
+
+#[test]
+fn duplicate_univ_params_are_rejected() {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let u = ex.param("u");
+    let sort_u = ex.sort(u);
+    let ty = ex.pi("α", sort_u, sort_u);
+    let val = ex.lam("α", sort_u, sort_u);
+    ex.def_u("d", ty, val, &["u", "u"]);
+    assert!(matches!(last_error(&ex), KernelError::DuplicateUnivParam(n, _) if n == Name::from("d")));
+
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let u = ex.param("u");
+    let sort_u = ex.sort(u);
+    let i = ex.cnst("I", &[u]);
+    ex.inductive("I", 0, sort_u, &[("I.mk", i)], &["u", "u"]);
+    assert!(matches!(last_error(&ex), KernelError::DuplicateUnivParam(n, _) if n == Name::from("I")));
+}
+
+/// `inductive I : Type | mk : Sort v → I`, where `v` isn't one of
+/// `I`'s universe parameters.
+#[test]
+fn undeclared_univ_param_in_intro_is_rejected() {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let one = ex.succ(0);
+    let ty = ex.sort(one);
+    let v = ex.param("v");
+    let sort_v = ex.sort(v);
+    let i = ex.cnst("I", &[]);
+    let mk_ty = ex.pi("a", sort_v, i);
+    ex.inductive("I", 0, ty, &[("I.mk", mk_ty)], &[]);
+    assert!(matches!(last_error(&ex), KernelError::UndeclaredUnivParams(n, _) if n == "I.mk".parse().unwrap()));
+}
+
+#[test]
+fn wrong_num_params_is_rejected() {
+    // `I : Type` can't have a parameter.
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let one = ex.succ(0);
+    let ty = ex.sort(one);
+    let i = ex.cnst("I", &[]);
+    ex.inductive("I", 1, ty, &[("I.mk", i)], &[]);
+    assert!(matches!(last_error(&ex), KernelError::IndNumParams(n, 1, 0) if n == Name::from("I")));
+
+    // `I (α : Type) : Type | mk : Π (α : Type), I`, which leaves out the parameter.
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let one = ex.succ(0);
+    let type_ = ex.sort(one);
+    let ty = ex.pi("α", type_, type_);
+    let i = ex.cnst("I", &[]);
+    let mk_ty = ex.pi("α", type_, i);
+    ex.inductive("I", 1, ty, &[("I.mk", mk_ty)], &[]);
+    assert!(matches!(last_error(&ex), KernelError::IntroNumParams(n, 1, 0) if n == "I.mk".parse().unwrap()));
+}
@@ -0,0 +1,34 @@
+use nanoda::{ check_export, CheckOptions, CheckError };
+use nanoda::parser::ExportFormat;
+
+/// Lean 3 exports that are broken in various ways, from lines that
+/// don't parse to items the kernel has to reject.
+const MALFORMED : &[&str] = &[
+    // A name line with its prefix missing.
+    "1 #NS\n",
+    // Names have to be declared in order.
+    "2 #NS 0 a\n",
+    // An expression kind that doesn't exist.
+    "1 #NS 0 a\n0 #EZ 0\n",
+    // An expression pointing at ones that were never declared.
+    "1 #NS 0 a\n0 #EA 4 5\n#AX 1 0\n",
+    // An axiom whose type has a loose bound variable.
+    "1 #NS 0 a\n0 #EV 0\n#AX 1 0\n",
+    // An axiom whose type isn't a type.
+    "1 #NS 0 a\n0 #ES 0\n1 #EC 1\n#AX 1 1\n",
+    // A quotient without `eq`.
+    "#QUOT\n",
+];
+
+#[test]
+fn malformed_exports_are_errors() {
+    for &num_threads in [1, 4].iter() {
+        for source in MALFORMED {
+            let options = CheckOptions { format : Some(ExportFormat::Lean3), ..CheckOptions::new(num_threads) };
+            match check_export(source.to_string(), &options) {
+                Err(CheckError::Parse(_)) | Err(CheckError::Kernel(_)) => (),
+                owise => panic!("expected {:?} to be rejected, but got {:?}", source, owise.map(|r| r.num_checked))
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use nanoda::{ check_export, CheckOptions, Report };
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// An inductive with two introduction rules, binders, a `let`, and in
+/// Lean 4 a projection and a literal. `bad` is rejected.
+fn sample(format : ExportFormat) -> Export {
+    let mut ex = Export::new(format);
+    let one = ex.succ(0);
+    let type_ = ex.sort(one);
+
+    let two = ex.cnst("Two", &[]);
+    let b_ty = ex.pi("n", two, two);
+    ex.inductive_counts("Two", 0, 0, true, type_, &[("Two.a", two), ("Two.b", b_ty)], &[]);
+
+    let a = ex.cnst("Two.a", &[]);
+    let b = ex.cnst("Two.b", &[]);
+    let v0 = ex.var(0);
+    let b_v0 = ex.app(b, &[v0]);
+    let body = ex.let_in("m", two, a, b_v0);
+    let f = ex.lam("n", two, body);
+    let f_ty = ex.pi("n", two, two);
+    ex.def("f", f_ty, f);
+
+    let f = ex.cnst("f", &[]);
+    let f_a = ex.app(f, &[a]);
+    ex.def("x", two, f_a);
+    ex.def("bad", type_, f_a);
+
+    if format == ExportFormat::Lean4 {
+        let nat = ex.nat();
+        let pair = ex.cnst("Pair", &[]);
+        let snd = ex.pi("snd", nat, pair);
+        let mk_ty = ex.pi("fst", two, snd);
+        ex.inductive("Pair", 0, type_, &[("Pair.mk", mk_ty)], &[]);
+        let mk = ex.cnst("Pair.mk", &[]);
+        let lit = ex.lit(2);
+        let p = ex.app(mk, &[f_a, lit]);
+        let fst = ex.proj("Pair", 0, p);
+        ex.def("fst", two, fst);
+    }
+    ex
+}
+
+/// `t : P <tree>` from an axiom `h : P <tree'>`, where `tree` is a
+/// balanced tree of applications of `g : Nat → Nat → Nat` whose leaves
+/// are `Nat.succ i` and `tree'` is the same with leaves `i + 1`, for
+/// `i < num_leaves`. If `bad` is set, one of the leaves is off by one.
+fn wide(num_leaves : u64, bad : bool) -> Export {
+    fn tree(ex : &mut Export, g : usize, leaves : &[usize]) -> usize {
+        match leaves {
+            [leaf] => *leaf,
+            _ => {
+                let (l, r) = leaves.split_at(leaves.len() / 2);
+                let (l, r) = (tree(ex, g, l), tree(ex, g, r));
+                ex.app(g, &[l, r])
+            }
+        }
+    }
+
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let nat = ex.nat();
+    let prop = ex.sort(0);
+    let binop = ex.pi("m", nat, nat);
+    let binop = ex.pi("n", nat, binop);
+    ex.axiom("g", binop);
+    let p_ty = ex.pi("n", nat, prop);
+    ex.axiom("P", p_ty);
+    let (g, p, succ) = (ex.cnst("g", &[]), ex.cnst("P", &[]), ex.cnst("Nat.succ", &[]));
+
+    let succs = (0..num_leaves).map(|i| {
+        let lit = ex.lit(i);
+        ex.app(succ, &[lit])
+    }).collect::<Vec<_>>();
+    let lits = (0..num_leaves).map(|i| ex.lit(i + 1 + (bad && i == num_leaves / 2) as u64)).collect::<Vec<_>>();
+    let (succs, lits) = (tree(&mut ex, g, &succs), tree(&mut ex, g, &lits));
+
+    let p_lits = ex.app(p, &[lits]);
+    ex.axiom("h", p_lits);
+    let h = ex.cnst("h", &[]);
+    let p_succs = ex.app(p, &[succs]);
+    ex.def("t", p_succs, h);
+    ex
+}
+
+fn check(ex : &Export, num_threads : usize, memory_budget : Option<usize>) -> Report {
+    let options = CheckOptions {
+        format : Some(ex.format),
+        keep_going : true,
+        memory_budget,
+        ..CheckOptions::new(num_threads)
+    };
+    check_export(ex.source(), &options).unwrap()
+}
+
+/// With a budget so small that every cache is at its minimum size, and
+/// checking `t` in `wide` overflows them, the results are the same.
+#[test]
+fn tiny_budget_gives_the_same_results() {
+    let exports = [sample(ExportFormat::Lean3), sample(ExportFormat::Lean4), wide(2048, false), wide(2048, true)];
+    for ex in exports.iter() {
+        let unbounded = check(ex, 1, None);
+        for &num_threads in [1, 4].iter() {
+            let bounded = check(ex, num_threads, Some(1));
+            assert_eq!(failed_names(&bounded), failed_names(&unbounded));
+            assert_eq!(bounded.num_checked, unbounded.num_checked);
+        }
+    }
+    assert_eq!(failed_names(&check(&exports[0], 1, None)), vec!["bad"]);
+    assert_eq!(failed_names(&check(&exports[3], 1, None)), vec!["t"]);
+}
@@ -0,0 +1,520 @@
+mod common;
+
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// `Nat`, `P : Nat → Prop` and `h : P <proved>`; returns `(nat, P, h)`.
+fn with_predicate(ex : &mut Export, proved : impl FnOnce(&mut Export) -> usize) -> (usize, usize, usize) {
+    let nat = ex.nat();
+    let prop = ex.sort(0);
+    let p_ty = ex.pi("n", nat, prop);
+    ex.axiom("P", p_ty);
+    let p = ex.cnst("P", &[]);
+    let proved = proved(ex);
+    let h_ty = ex.app(p, &[proved]);
+    ex.axiom("h", h_ty);
+    let h = ex.cnst("h", &[]);
+    (nat, p, h)
+}
+
+/// Declare `name : P e := h`.
+fn prove(ex : &mut Export, name : &str, p : usize, h : usize, e : usize) {
+    let ty = ex.app(p, &[e]);
+    ex.def(name, ty, h);
+}
+
+/// Declare `name : P e`.
+fn fact(ex : &mut Export, name : &str, p : usize, e : usize) -> usize {
+    let ty = ex.app(p, &[e]);
+    ex.axiom(name, ty);
+    ex.cnst(name, &[])
+}
+
+/// `Bool`, with `false` first as in Lean 4.
+fn bool_type(ex : &mut Export) -> usize {
+    let one_lvl = ex.succ(0);
+    let ty = ex.sort(one_lvl);
+    let bool = ex.cnst("Bool", &[]);
+    ex.inductive("Bool", 0, ty, &[("Bool.false", bool), ("Bool.true", bool)], &[]);
+    bool
+}
+
+/// `Nat.rec.{1} (fun _ => ret) zero_case succ_case major`
+fn nat_rec(ex : &mut Export, ret : usize, zero_case : usize, succ_case : usize, major : usize) -> usize {
+    let nat = ex.cnst("Nat", &[]);
+    let one_lvl = ex.succ(0);
+    let rec = ex.cnst("Nat.rec", &[one_lvl]);
+    let motive = ex.lam("n", nat, ret);
+    ex.app(rec, &[motive, zero_case, succ_case, major])
+}
+
+/// `Nat → Nat → ret`
+fn binop_ty(ex : &mut Export, ret : usize) -> usize {
+    let nat = ex.cnst("Nat", &[]);
+    let m = ex.pi("m", nat, ret);
+    ex.pi("n", nat, m)
+}
+
+/// `fun n m => body`
+fn binop(ex : &mut Export, body : usize) -> usize {
+    let nat = ex.cnst("Nat", &[]);
+    let m = ex.lam("m", nat, body);
+    ex.lam("n", nat, m)
+}
+
+/// `Nat.add`, `Nat.pred`, `Nat.sub` and `Nat.mul` by recursion on the
+/// second argument.
+fn arith(ex : &mut Export) {
+    let nat = ex.cnst("Nat", &[]);
+    let zero = ex.cnst("Nat.zero", &[]);
+    let succ = ex.cnst("Nat.succ", &[]);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+    let v3 = ex.var(3);
+    let ty = binop_ty(ex, nat);
+
+    let succ_ih = ex.app(succ, &[v0]);
+    let succ_case = { let ih = ex.lam("ih", nat, succ_ih); ex.lam("k", nat, ih) };
+    let body = nat_rec(ex, nat, v1, succ_case, v0);
+    let val = binop(ex, body);
+    ex.def("Nat.add", ty, val);
+
+    let pred_case = { let ih = ex.lam("ih", nat, v1); ex.lam("k", nat, ih) };
+    let body = nat_rec(ex, nat, zero, pred_case, v0);
+    let val = ex.lam("n", nat, body);
+    let pred_ty = ex.pi("n", nat, nat);
+    ex.def("Nat.pred", pred_ty, val);
+
+    let pred = ex.cnst("Nat.pred", &[]);
+    let pred_ih = ex.app(pred, &[v0]);
+    let succ_case = { let ih = ex.lam("ih", nat, pred_ih); ex.lam("k", nat, ih) };
+    let body = nat_rec(ex, nat, v1, succ_case, v0);
+    let val = binop(ex, body);
+    ex.def("Nat.sub", ty, val);
+
+    let add = ex.cnst("Nat.add", &[]);
+    let add_ih = ex.app(add, &[v0, v3]);
+    let succ_case = { let ih = ex.lam("ih", nat, add_ih); ex.lam("k", nat, ih) };
+    let body = nat_rec(ex, nat, zero, succ_case, v0);
+    let val = binop(ex, body);
+    ex.def("Nat.mul", ty, val);
+}
+
+/// `Nat.beq` and `Nat.ble` by recursion on the first argument, then the
+/// second.
+fn comparisons(ex : &mut Export, bool : usize) {
+    let nat = ex.cnst("Nat", &[]);
+    let tt = ex.cnst("Bool.true", &[]);
+    let ff = ex.cnst("Bool.false", &[]);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+    let v3 = ex.var(3);
+    let nat_bool = ex.pi("m", nat, bool);
+    let ty = binop_ty(ex, bool);
+
+    // fun n' ih m => Nat.rec false (fun m' _ => ih m') m
+    let ih_m = ex.app(v3, &[v1]);
+    let on_succ = { let ih = ex.lam("ih", bool, ih_m); ex.lam("m", nat, ih) };
+    let body = nat_rec(ex, bool, ff, on_succ, v0);
+    let succ_case = {
+        let m = ex.lam("m", nat, body);
+        let ih = ex.lam("ih", nat_bool, m);
+        ex.lam("n", nat, ih)
+    };
+
+    let on_succ = { let ih = ex.lam("ih", bool, ff); ex.lam("m", nat, ih) };
+    let is_zero = nat_rec(ex, bool, tt, on_succ, v0);
+    let zero_case = ex.lam("m", nat, is_zero);
+    let body = nat_rec(ex, nat_bool, zero_case, succ_case, v0);
+    let val = ex.lam("n", nat, body);
+    ex.def("Nat.beq", ty, val);
+
+    let zero_case = ex.lam("m", nat, tt);
+    let body = nat_rec(ex, nat_bool, zero_case, succ_case, v0);
+    let val = ex.lam("n", nat, body);
+    ex.def("Nat.ble", ty, val);
+}
+
+fn checked(report : &nanoda::Report, op : &str) -> Option<bool> {
+    report.env.checked_nat_ops.get(&op.parse().unwrap()).copied()
+}
+
+#[test]
+fn succ_and_literals() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (nat, p, h) = with_predicate(&mut ex, |ex| ex.lit(5));
+    ex.axiom("n", nat);
+    let succ = ex.cnst("Nat.succ", &[]);
+    let zero = ex.cnst("Nat.zero", &[]);
+    let n = ex.cnst("n", &[]);
+
+    let four = ex.lit(4);
+    let succ_four = ex.app(succ, &[four]);
+    prove(&mut ex, "succ_lit", p, h, succ_four);
+
+    let chain = (0..5).fold(zero, |e, _| ex.app(succ, &[e]));
+    prove(&mut ex, "succ_chain", p, h, chain);
+
+    let six = ex.lit(6);
+    let succ_six = ex.app(succ, &[six]);
+    prove(&mut ex, "succ_wrong_lit", p, h, succ_six);
+
+    let succ_n = ex.app(succ, &[n]);
+    prove(&mut ex, "succ_var", p, h, succ_n);
+
+    assert_eq!(failures(&ex), vec!["succ_wrong_lit", "succ_var"]);
+}
+
+/// `bit0` and `bit1` only count as doubling when they're used at `nat`
+/// with `nat`'s own instances.
+#[test]
+fn bits_only_at_nat_instances() {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let nat = ex.nat();
+    let u = ex.param("u");
+    let succ_u = ex.succ(u);
+    let ty_u = ex.sort(succ_u);
+    let class_ty = ex.pi("α", ty_u, ty_u);
+    let has_add_u = ex.cnst("has_add", &[u]);
+    let has_one_u = ex.cnst("has_one", &[u]);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+    let v2 = ex.var(2);
+    let v3 = ex.var(3);
+    let v4 = ex.var(4);
+
+    // has_add.{u} (α : Type u) | mk : (α → α → α) → has_add α
+    let add_0 = ex.app(has_add_u, &[v0]);
+    let add_1 = ex.app(has_add_u, &[v1]);
+    let op_0 = { let b = ex.pi("b", v1, v2); ex.pi("a", v0, b) };
+    let op_1 = { let b = ex.pi("b", v2, v3); ex.pi("a", v1, b) };
+    let op_2 = { let b = ex.pi("b", v3, v4); ex.pi("a", v2, b) };
+    let mk_ty = { let b = ex.pi("add", op_0, add_1); ex.pi("α", ty_u, b) };
+    ex.inductive("has_add", 1, class_ty, &[("has_add.mk", mk_ty)], &["u"]);
+    // has_one.{u} (α : Type u) | mk : α → has_one α
+    let one_0 = ex.app(has_one_u, &[v0]);
+    let one_1 = ex.app(has_one_u, &[v1]);
+    let mk_ty = { let b = ex.pi("one", v0, one_1); ex.pi("α", ty_u, b) };
+    ex.inductive("has_one", 1, class_ty, &[("has_one.mk", mk_ty)], &["u"]);
+
+    // has_add.add.{u} := λ α s, has_add.rec (λ _, α → α → α) (λ add, add) s
+    let rec = ex.cnst("has_add.rec", &[succ_u, u]);
+    let motive = ex.lam("s", add_1, op_2);
+    let minor = ex.lam("add", op_1, v0);
+    let body = ex.app(rec, &[v1, motive, minor, v0]);
+    let val = { let b = ex.lam("s", add_0, body); ex.lam("α", ty_u, b) };
+    let ty = { let b = ex.pi("s", add_0, op_1); ex.pi("α", ty_u, b) };
+    ex.def_u("has_add.add", ty, val, &["u"]);
+    // has_one.one.{u} := λ α s, has_one.rec (λ _, α) (λ one, one) s
+    let rec = ex.cnst("has_one.rec", &[succ_u, u]);
+    let motive = ex.lam("s", one_1, v2);
+    let minor = ex.lam("one", v1, v0);
+    let body = ex.app(rec, &[v1, motive, minor, v0]);
+    let val = { let b = ex.lam("s", one_0, body); ex.lam("α", ty_u, b) };
+    let ty = { let b = ex.pi("s", one_0, v1); ex.pi("α", ty_u, b) };
+    ex.def_u("has_one.one", ty, val, &["u"]);
+
+    // bit0.{u} := λ α s a, has_add.add α s a a
+    let add = ex.cnst("has_add.add", &[u]);
+    let one = ex.cnst("has_one.one", &[u]);
+    let body = ex.app(add, &[v2, v1, v0, v0]);
+    let val = { let a = ex.lam("a", v1, body); let s = ex.lam("s", add_0, a); ex.lam("α", ty_u, s) };
+    let ty = { let a = ex.pi("a", v1, v2); let s = ex.pi("s", add_0, a); ex.pi("α", ty_u, s) };
+    ex.def_u("bit0", ty, val, &["u"]);
+    // bit1.{u} := λ α s1 s2 a, has_add.add α s2 (has_add.add α s2 a a) (has_one.one α s1)
+    let double = ex.app(add, &[v3, v1, v0, v0]);
+    let one_a = ex.app(one, &[v3, v2]);
+    let body = ex.app(add, &[v3, v1, double, one_a]);
+    let add_1 = ex.app(has_add_u, &[v1]);
+    let val = {
+        let a = ex.lam("a", v2, body);
+        let s2 = ex.lam("s2", add_1, a);
+        let s1 = ex.lam("s1", one_0, s2);
+        ex.lam("α", ty_u, s1)
+    };
+    let ty = {
+        let a = ex.pi("a", v2, v3);
+        let s2 = ex.pi("s2", add_1, a);
+        let s1 = ex.pi("s1", one_0, s2);
+        ex.pi("α", ty_u, s1)
+    };
+    ex.def_u("bit1", ty, val, &["u"]);
+
+    // nat.add := λ n m, nat.rec n (λ k ih, nat.succ ih) m
+    let zero = ex.cnst("nat.zero", &[]);
+    let succ = ex.cnst("nat.succ", &[]);
+    let one_lvl = ex.succ(0);
+    let nat_rec = ex.cnst("nat.rec", &[one_lvl]);
+    let motive = ex.lam("k", nat, nat);
+    let succ_ih = ex.app(succ, &[v0]);
+    let minor = { let ih = ex.lam("ih", nat, succ_ih); ex.lam("k", nat, ih) };
+    let body = ex.app(nat_rec, &[motive, v1, minor, v0]);
+    let val = { let m = ex.lam("m", nat, body); ex.lam("n", nat, m) };
+    let ty = { let m = ex.pi("m", nat, nat); ex.pi("n", nat, m) };
+    ex.def("nat.add", ty, val);
+
+    let has_add_nat = ex.cnst("has_add", &[0]);
+    let has_add_nat = ex.app(has_add_nat, &[nat]);
+    let has_one_nat = ex.cnst("has_one", &[0]);
+    let has_one_nat = ex.app(has_one_nat, &[nat]);
+    let add_mk = ex.cnst("has_add.mk", &[0]);
+    let one_mk = ex.cnst("has_one.mk", &[0]);
+    let nat_add = ex.cnst("nat.add", &[]);
+    let val = ex.app(add_mk, &[nat, nat_add]);
+    ex.def("nat.has_add", has_add_nat, val);
+    let one = ex.app(succ, &[zero]);
+    let val = ex.app(one_mk, &[nat, one]);
+    ex.def("nat.has_one", has_one_nat, val);
+    let first = { let b = ex.lam("b", nat, v1); ex.lam("a", nat, b) };
+    let val = ex.app(add_mk, &[nat, first]);
+    ex.def("weird_add", has_add_nat, val);
+    let val = ex.app(one_mk, &[nat, zero]);
+    ex.def("weird_one", has_one_nat, val);
+
+    let prop = ex.sort(0);
+    let p_ty = ex.pi("n", nat, prop);
+    ex.axiom("P", p_ty);
+    let p = ex.cnst("P", &[]);
+    let two = ex.app(succ, &[one]);
+    let three = ex.app(succ, &[two]);
+    let p_two = ex.app(p, &[two]);
+    let p_three = ex.app(p, &[three]);
+    ex.axiom("h2", p_two);
+    ex.axiom("h3", p_three);
+    let h2 = ex.cnst("h2", &[]);
+    let h3 = ex.cnst("h3", &[]);
+
+    let bit0 = ex.cnst("bit0", &[0]);
+    let bit1 = ex.cnst("bit1", &[0]);
+    let nat_add = ex.cnst("nat.has_add", &[]);
+    let nat_one = ex.cnst("nat.has_one", &[]);
+    let weird_add = ex.cnst("weird_add", &[]);
+    let weird_one = ex.cnst("weird_one", &[]);
+
+    let e = ex.app(bit0, &[nat, nat_add, one]);
+    prove(&mut ex, "bit0_nat", p, h2, e);
+    let e = ex.app(bit0, &[nat, weird_add, one]);
+    prove(&mut ex, "bit0_weird_add", p, h2, e);
+    let e = ex.app(bit1, &[nat, nat_one, nat_add, one]);
+    prove(&mut ex, "bit1_nat", p, h3, e);
+    let e = ex.app(bit1, &[nat, weird_one, nat_add, one]);
+    prove(&mut ex, "bit1_weird_one", p, h3, e);
+    let e = ex.app(bit1, &[nat, nat_one, weird_add, one]);
+    prove(&mut ex, "bit1_weird_add", p, h3, e);
+
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), vec!["bit0_weird_add", "bit1_weird_one", "bit1_weird_add"]);
+    for op in ["bit0", "bit1", "nat.add"].iter() {
+        assert_eq!(report.env.checked_nat_ops.get(&op.parse().unwrap()), Some(&true), "{}", op);
+    }
+}
+
+/// `Nat.decEq` is only accelerated if it has the right type. Here it's
+/// just `fun a b => 1`, so it's left to its definition. It's used as the
+/// major premise of `Nat.rec`, since that's reduced with `whnf` rather
+/// than unfolded by lazy delta.
+#[test]
+fn dec_eq_on_unequal_literals_unfolds() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (nat, p, h) = with_predicate(&mut ex, |ex| ex.lit(9));
+    let one = ex.lit(1);
+    let b_to_one = ex.lam("b", nat, one);
+    let dec_eq = ex.lam("a", nat, b_to_one);
+    let nat_nat = ex.pi("b", nat, nat);
+    let dec_eq_ty = ex.pi("a", nat, nat_nat);
+    ex.def("Nat.decEq", dec_eq_ty, dec_eq);
+
+    // Nat.rec.{1} (fun _ => Nat) 7 (fun n ih => 9) (Nat.decEq 2 3)
+    let one_lvl = ex.succ(0);
+    let rec = ex.cnst("Nat.rec", &[one_lvl]);
+    let motive = ex.lam("n", nat, nat);
+    let seven = ex.lit(7);
+    let nine = ex.lit(9);
+    let ih_to_nine = ex.lam("ih", nat, nine);
+    let succ_case = ex.lam("n", nat, ih_to_nine);
+    let dec_eq = ex.cnst("Nat.decEq", &[]);
+    let two = ex.lit(2);
+    let three = ex.lit(3);
+    let major = ex.app(dec_eq, &[two, three]);
+    let e = ex.app(rec, &[motive, seven, succ_case, major]);
+    prove(&mut ex, "unequal", p, h, e);
+
+    assert!(failures(&ex).is_empty());
+}
+
+/// Definitions that satisfy the equations are accelerated, so they work
+/// on literals far too big to unfold.
+#[test]
+fn ops_are_accelerated_once_checked() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (_, p, h_zero) = with_predicate(&mut ex, |ex| ex.lit(0));
+    let bool = bool_type(&mut ex);
+    arith(&mut ex);
+    comparisons(&mut ex, bool);
+    let prop = ex.sort(0);
+    let q_ty = ex.pi("b", bool, prop);
+    ex.axiom("Q", q_ty);
+    let q = ex.cnst("Q", &[]);
+    let tt = ex.cnst("Bool.true", &[]);
+    let hq = fact(&mut ex, "hq", q, tt);
+
+    let billion = ex.lit(1_000_000_000);
+    let e = ex.lit(2_000_000_000);
+    let h_add = fact(&mut ex, "h_add", p, e);
+    let e = ex.lit(1_000_000_000_000_000_000);
+    let h_mul = fact(&mut ex, "h_mul", p, e);
+    let e = ex.lit(999_999_999);
+    let h_sub = fact(&mut ex, "h_sub", p, e);
+
+    let add = ex.cnst("Nat.add", &[]);
+    let e = ex.app(add, &[billion, billion]);
+    prove(&mut ex, "add_big", p, h_add, e);
+    let mul = ex.cnst("Nat.mul", &[]);
+    let e = ex.app(mul, &[billion, billion]);
+    prove(&mut ex, "mul_big", p, h_mul, e);
+    let sub = ex.cnst("Nat.sub", &[]);
+    let one = ex.lit(1);
+    let e = ex.app(sub, &[billion, one]);
+    prove(&mut ex, "sub_big", p, h_sub, e);
+    let three = ex.lit(3);
+    let e = ex.app(sub, &[three, billion]);
+    prove(&mut ex, "sub_truncates", p, h_zero, e);
+
+    let beq = ex.cnst("Nat.beq", &[]);
+    let ble = ex.cnst("Nat.ble", &[]);
+    let e = ex.app(beq, &[billion, billion]);
+    prove(&mut ex, "beq_big", q, hq, e);
+    let more = ex.lit(1_000_000_001);
+    let e = ex.app(ble, &[billion, more]);
+    prove(&mut ex, "ble_big", q, hq, e);
+    let two = ex.lit(2);
+    let e = ex.app(beq, &[one, two]);
+    prove(&mut ex, "beq_wrong", q, hq, e);
+
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), vec!["beq_wrong"]);
+    for op in ["Nat.add", "Nat.sub", "Nat.mul", "Nat.beq", "Nat.ble"].iter() {
+        assert_eq!(checked(&report, op), Some(true), "{}", op);
+    }
+}
+
+/// A `Nat.add` that isn't addition, a `Nat.mul` defined in terms of it,
+/// and an axiomatized `Nat.sub` are all left alone, so they mean what
+/// the export says they mean.
+#[test]
+fn misdefined_ops_are_not_accelerated() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (nat, p, h0) = with_predicate(&mut ex, |ex| ex.lit(0));
+    let zero = ex.cnst("Nat.zero", &[]);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+    let v3 = ex.var(3);
+    let ty = binop_ty(&mut ex, nat);
+    let first = binop(&mut ex, v1);
+    ex.def("Nat.add", ty, first);
+    let add = ex.cnst("Nat.add", &[]);
+    let add_ih = ex.app(add, &[v0, v3]);
+    let succ_case = { let ih = ex.lam("ih", nat, add_ih); ex.lam("k", nat, ih) };
+    let body = nat_rec(&mut ex, nat, zero, succ_case, v0);
+    let val = binop(&mut ex, body);
+    ex.def("Nat.mul", ty, val);
+    ex.axiom("Nat.sub", ty);
+
+    let (two, three, four, five) = (ex.lit(2), ex.lit(3), ex.lit(4), ex.lit(5));
+    let h2 = fact(&mut ex, "h2", p, two);
+    let h3 = fact(&mut ex, "h3", p, three);
+    let h4 = fact(&mut ex, "h4", p, four);
+
+    let e = ex.app(add, &[two, two]);
+    prove(&mut ex, "add_by_definition", p, h2, e);
+    prove(&mut ex, "add_accelerated", p, h4, e);
+    let mul = ex.cnst("Nat.mul", &[]);
+    let e = ex.app(mul, &[two, three]);
+    prove(&mut ex, "mul_by_definition", p, h0, e);
+    let sub = ex.cnst("Nat.sub", &[]);
+    let e = ex.app(sub, &[five, two]);
+    prove(&mut ex, "sub_axiom", p, h3, e);
+
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), vec!["add_accelerated", "sub_axiom"]);
+    for op in ["Nat.add", "Nat.mul", "Nat.sub"].iter() {
+        assert_eq!(checked(&report, op), Some(false), "{}", op);
+    }
+}
+
+/// `Nat.decEq` of unequal literals reduces to `isFalse`, with a proof
+/// built from `Eq.rec` and `Nat.beq`. `Nat.decEq` itself is opaque here;
+/// only its type is checked.
+#[test]
+fn dec_eq_on_unequal_literals_is_false() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (nat, p, h0) = with_predicate(&mut ex, |ex| ex.lit(0));
+    let bool = bool_type(&mut ex);
+    comparisons(&mut ex, bool);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+    let prop = ex.sort(0);
+    let one_lvl = ex.succ(0);
+    let ty = ex.sort(one_lvl);
+
+    // Eq.{u} {α : Sort u} (a : α) : α → Prop | refl : Eq a a
+    let u = ex.param("u");
+    let sort_u = ex.sort(u);
+    let eq_u = ex.cnst("Eq", &[u]);
+    let eq_ty = { let b = ex.pi("b", v1, prop); let a = ex.pi("a", v0, b); ex.pi("α", sort_u, a) };
+    let refl = ex.app(eq_u, &[v1, v0, v0]);
+    let refl_ty = { let a = ex.pi("a", v0, refl); ex.pi("α", sort_u, a) };
+    ex.inductive_counts("Eq", 2, 1, false, eq_ty, &[("Eq.refl", refl_ty)], &["u"]);
+    let true_ = ex.cnst("True", &[]);
+    ex.inductive("True", 0, prop, &[("True.intro", true_)], &[]);
+    ex.inductive("False", 0, prop, &[], &[]);
+    let false_ = ex.cnst("False", &[]);
+    let not_ty = ex.pi("a", prop, prop);
+    let not_body = ex.pi("h", v0, false_);
+    let not_val = ex.lam("a", prop, not_body);
+    ex.def("Not", not_ty, not_val);
+    let not = ex.cnst("Not", &[]);
+
+    // Decidable (p : Prop) | isFalse : ¬p → Decidable p | isTrue : p → Decidable p
+    let dec = ex.cnst("Decidable", &[]);
+    let dec_ty = ex.pi("p", prop, ty);
+    let dec_1 = ex.app(dec, &[v1]);
+    let not_0 = ex.app(not, &[v0]);
+    let is_false = { let h = ex.pi("h", not_0, dec_1); ex.pi("p", prop, h) };
+    let is_true = { let h = ex.pi("h", v0, dec_1); ex.pi("p", prop, h) };
+    ex.inductive("Decidable", 1, dec_ty, &[("Decidable.isFalse", is_false), ("Decidable.isTrue", is_true)], &[]);
+
+    let eq = ex.cnst("Eq", &[one_lvl]);
+    let eq_nm = ex.app(eq, &[nat, v1, v0]);
+    let dec_eq_nm = ex.app(dec, &[eq_nm]);
+    let dec_eq_ty = binop_ty(&mut ex, dec_eq_nm);
+    ex.axiom("decEqAx", dec_eq_ty);
+    let dec_eq_ax = ex.cnst("decEqAx", &[]);
+    ex.def("Nat.decEq", dec_eq_ty, dec_eq_ax);
+
+    // Decidable.rec (fun _ => Nat) (fun _ => 0) (fun _ => 1) (Nat.decEq a b)
+    let dec_rec = ex.cnst("Decidable.rec", &[one_lvl]);
+    let dec_eq = ex.cnst("Nat.decEq", &[]);
+    let (zero, one) = (ex.lit(0), ex.lit(1));
+    let decide = |ex : &mut Export, a : u64, b : u64| {
+        let (a, b) = (ex.lit(a), ex.lit(b));
+        let eq_ab = ex.app(eq, &[nat, a, b]);
+        let dec_ab = ex.app(dec, &[eq_ab]);
+        let motive = ex.lam("d", dec_ab, nat);
+        let not_eq_ab = ex.app(not, &[eq_ab]);
+        let on_false = ex.lam("h", not_eq_ab, zero);
+        let on_true = ex.lam("h", eq_ab, one);
+        let major = ex.app(dec_eq, &[a, b]);
+        ex.app(dec_rec, &[eq_ab, motive, on_false, on_true, major])
+    };
+    let h1 = fact(&mut ex, "h1", p, one);
+    let unequal = decide(&mut ex, 2, 3);
+    let equal = decide(&mut ex, 2, 2);
+    prove(&mut ex, "dec_eq_unequal", p, h0, unequal);
+    prove(&mut ex, "dec_eq_equal", p, h1, equal);
+    prove(&mut ex, "dec_eq_wrong", p, h1, unequal);
+
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), vec!["dec_eq_wrong"]);
+    assert_eq!(checked(&report, "Nat.decEq"), Some(true));
+}
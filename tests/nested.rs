@@ -0,0 +1,186 @@
+mod common;
+
+use nanoda::{ CheckError, Report };
+use nanoda::name::Name;
+use nanoda::errors::KernelError;
+use nanoda::parser::ExportFormat;
+use nanoda::pretty::pretty_printer::{ PrettyPrinter, PPOptions };
+use common::*;
+
+/// `List.{u} (α : Type u)`, with `nil` and `cons`.
+fn list(ex : &mut Export) {
+    let u = ex.param("u");
+    let su = ex.succ(u);
+    let ty_u = ex.sort(su);
+    let list_ty = ex.pi("α", ty_u, ty_u);
+    let list = ex.cnst("List", &[u]);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+    let v2 = ex.var(2);
+    let list_v0 = ex.app(list, &[v0]);
+    let nil_ty = ex.pi("α", ty_u, list_v0);
+    let list_v1 = ex.app(list, &[v1]);
+    let list_v2 = ex.app(list, &[v2]);
+    let cons_tail = ex.pi("tail", list_v1, list_v2);
+    let cons_head = ex.pi("head", v0, cons_tail);
+    let cons_ty = ex.pi("α", ty_u, cons_head);
+    ex.inductive_counts("List", 1, 0, true, list_ty, &[("List.nil", nil_ty), ("List.cons", cons_ty)], &["u"]);
+}
+
+/// `inductive tree | node : List tree → tree`; returns `(tree, List tree)`.
+fn tree(ex : &mut Export) -> (usize, usize) {
+    list(ex);
+    let one = ex.succ(0);
+    let ty = ex.sort(one);
+    let tree = ex.cnst("tree", &[]);
+    let list = ex.cnst("List", &[0]);
+    let list_tree = ex.app(list, &[tree]);
+    let node_ty = ex.pi("a", list_tree, tree);
+    ex.inductive_counts("tree", 0, 0, true, ty, &[("tree.node", node_ty)], &[]);
+    (tree, list_tree)
+}
+
+fn show(report : &Report, n : &str) -> String {
+    let options = PPOptions { width : 1000, ..PPOptions::new_default() };
+    PrettyPrinter::print_declar(Some(options), &n.parse::<Name>().unwrap(), &report.env).trim().to_string()
+}
+
+#[test]
+fn tree_of_lists() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    tree(&mut ex);
+    let report = check_lean4(&ex.source()).unwrap();
+
+    assert_eq!(show(&report, "tree.node"), "/- builtin -/ axiom tree.node (a : List tree) : tree");
+    let minors = "{C_1 : (∀ (c : tree), Sort l)} {C_2 : (∀ (c : List tree), Sort l)} \
+                  (h : (∀ (a : List tree) (ih : C_2 a), C_1 (tree.node a))) \
+                  (h_0 : C_2 (List.nil tree)) \
+                  (h_1 : (∀ (head : tree) (tail : List tree) (ih : C_1 head) (ih_0 : C_2 tail), C_2 (List.cons tree head tail)))";
+    assert_eq!(show(&report, "tree.rec"), format!("/- builtin -/ axiom {{l}} tree.rec {} (x : tree) : C_1 x", minors));
+    assert_eq!(show(&report, "tree.rec_1"), format!("/- builtin -/ axiom {{l}} tree.rec_1 {} (x : List tree) : C_2 x", minors));
+
+    // None of the auxiliary types make it into the environment.
+    assert!(report.env.declarations.get(&"tree._nested.1".parse::<Name>().unwrap()).is_none());
+    assert_eq!(report.env.num_declars(), 8);
+}
+
+/// The number of nodes in a tree, by `tree.rec` and `tree.rec_1`. `plus`
+/// is addition by recursion on the second argument.
+#[test]
+fn nested_iota() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (tree, list_tree) = tree(&mut ex);
+    let nat = ex.nat();
+    let one = ex.succ(0);
+    let succ = ex.cnst("Nat.succ", &[]);
+    let zero = ex.cnst("Nat.zero", &[]);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+
+    let nat_to_nat = ex.pi("n", nat, nat);
+    let plus_ty = ex.pi("m", nat, nat_to_nat);
+    let nat_rec = ex.cnst("Nat.rec", &[one]);
+    let nat_motive = ex.lam("n", nat, nat);
+    let succ_ih = ex.app(succ, &[v0]);
+    let step_ih = ex.lam("ih", nat, succ_ih);
+    let step = ex.lam("k", nat, step_ih);
+    let plus_body = ex.app(nat_rec, &[nat_motive, v1, step, v0]);
+    let plus_n = ex.lam("n", nat, plus_body);
+    let plus_val = ex.lam("m", nat, plus_n);
+    ex.def("plus", plus_ty, plus_val);
+    let plus = ex.cnst("plus", &[]);
+
+    // fun a ih => succ ih, zero, and fun h t ih_h ih_t => plus ih_h ih_t
+    let motive_1 = ex.lam("t", tree, nat);
+    let motive_2 = ex.lam("t", list_tree, nat);
+    let node_ih = ex.lam("ih", nat, succ_ih);
+    let node_case = ex.lam("a", list_tree, node_ih);
+    let sum = ex.app(plus, &[v1, v0]);
+    let cons_ih_t = ex.lam("ih_t", nat, sum);
+    let cons_ih_h = ex.lam("ih_h", nat, cons_ih_t);
+    let cons_t = ex.lam("t", list_tree, cons_ih_h);
+    let cons_case = ex.lam("h", tree, cons_t);
+    let cases = [motive_1, motive_2, node_case, zero, cons_case];
+
+    let size_ty = ex.pi("t", tree, nat);
+    let tree_rec = ex.cnst("tree.rec", &[one]);
+    let size_val = ex.app(tree_rec, &cases);
+    ex.def("size", size_ty, size_val);
+    let sizes_ty = ex.pi("t", list_tree, nat);
+    let tree_rec_1 = ex.cnst("tree.rec_1", &[one]);
+    let sizes_val = ex.app(tree_rec_1, &cases);
+    ex.def("sizes", sizes_ty, sizes_val);
+
+    // node [node [], node [node []]]
+    let node = ex.cnst("tree.node", &[]);
+    let nil = ex.cnst("List.nil", &[0]);
+    let cons = ex.cnst("List.cons", &[0]);
+    let nil = ex.app(nil, &[tree]);
+    let leaf = ex.app(node, &[nil]);
+    let leaves = ex.app(cons, &[tree, leaf, nil]);
+    let inner = ex.app(node, &[leaves]);
+    let tail = ex.app(cons, &[tree, inner, nil]);
+    let children = ex.app(cons, &[tree, leaf, tail]);
+    let t = ex.app(node, &[children]);
+
+    let prop = ex.sort(0);
+    let p_ty = ex.pi("n", nat, prop);
+    ex.axiom("P", p_ty);
+    let p = ex.cnst("P", &[]);
+    let three = ex.lit(3);
+    let four = ex.lit(4);
+    let p_three = ex.app(p, &[three]);
+    let p_four = ex.app(p, &[four]);
+    ex.axiom("h3", p_three);
+    ex.axiom("h4", p_four);
+    let h3 = ex.cnst("h3", &[]);
+    let h4 = ex.cnst("h4", &[]);
+
+    let size = ex.cnst("size", &[]);
+    let sizes = ex.cnst("sizes", &[]);
+    let size_t = ex.app(size, &[t]);
+    let sizes_children = ex.app(sizes, &[children]);
+    let p_size_t = ex.app(p, &[size_t]);
+    let p_sizes_children = ex.app(p, &[sizes_children]);
+    ex.def("size_t", p_size_t, h4);
+    ex.def("sizes_children", p_sizes_children, h3);
+    ex.def("size_t_wrong", p_size_t, h3);
+
+    assert_eq!(failures(&ex), vec!["size_t_wrong"]);
+}
+
+/// `Box.{} (n : Nat) (α : Type) | mk : α → Box n α`, and
+/// `bad | mk : (n : Nat) → Box n bad → bad`, where the nested occurrence's
+/// parameters mention the constructor's own argument `n`.
+#[test]
+fn nested_params_cant_mention_ctor_args() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let nat = ex.nat();
+    let one = ex.succ(0);
+    let ty = ex.sort(one);
+    let boxed = ex.cnst("Box", &[]);
+    let v0 = ex.var(0);
+    let v1 = ex.var(1);
+    let v2 = ex.var(2);
+    let box_a = ex.pi("α", ty, ty);
+    let box_ty = ex.pi("n", nat, box_a);
+    let box_n_a = ex.app(boxed, &[v2, v1]);
+    let mk_body = ex.pi("a", v0, box_n_a);
+    let mk_a = ex.pi("α", ty, mk_body);
+    let mk_ty = ex.pi("n", nat, mk_a);
+    ex.inductive("Box", 2, box_ty, &[("Box.mk", mk_ty)], &[]);
+
+    let bad = ex.cnst("bad", &[]);
+    let box_n_bad = ex.app(boxed, &[v0, bad]);
+    let bad_mk_body = ex.pi("b", box_n_bad, bad);
+    let bad_mk_ty = ex.pi("n", nat, bad_mk_body);
+    ex.inductive_counts("bad", 0, 0, true, ty, &[("bad.mk", bad_mk_ty)], &[]);
+
+    match check_lean4(&ex.source()) {
+        Err(CheckError::Kernel(err)) => {
+            assert_eq!(err.name, Name::from("bad"));
+            assert!(matches!(err.err, KernelError::InvalidNestedOccurrence(..)));
+        },
+        _ => panic!("a nested occurrence depending on a constructor argument was accepted")
+    }
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use nanoda::{ check_export, CheckOptions, Report };
+use nanoda::parser::ExportFormat;
+use common::*;
+
+const CHAIN_LEN : usize = 64;
+
+/// `d0 : Nat := 0` and `d<i+1> : Nat := Nat.succ d<i>`, then
+/// `t : P d<last>` from an axiom `h : P <last>`, so checking `t` unfolds
+/// the whole chain. If `bad` is given, `d<bad>` is a `Prop` instead.
+fn chain(bad : Option<usize>) -> Export {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let nat = ex.nat();
+    let succ = ex.cnst("Nat.succ", &[]);
+    let prop = ex.sort(0);
+    let p_ty = ex.pi("n", nat, prop);
+    ex.axiom("P", p_ty);
+
+    let mut prev = ex.lit(0);
+    for i in 0..CHAIN_LEN {
+        let val = match (i, bad) {
+            (0, _) => prev,
+            (i, Some(bad)) if i == bad => prop,
+            _ => ex.app(succ, &[prev])
+        };
+        let name = format!("d{}", i);
+        ex.def(&name, nat, val);
+        prev = ex.cnst(&name, &[]);
+    }
+
+    let p = ex.cnst("P", &[]);
+    let last = ex.lit(CHAIN_LEN as u64 - 1);
+    let p_last = ex.app(p, &[last]);
+    ex.axiom("h", p_last);
+    let h = ex.cnst("h", &[]);
+    let p_chain = ex.app(p, &[prev]);
+    ex.def("t", p_chain, h);
+    ex
+}
+
+fn check(ex : &Export, num_threads : usize, keep_going : bool) -> Report {
+    let options = CheckOptions { format : Some(ex.format), keep_going, ..CheckOptions::new(num_threads) };
+    check_export(ex.source(), &options).unwrap()
+}
+
+/// Every item in the chain waits on the one before it, so the checkers
+/// mostly have nothing to steal.
+#[test]
+fn chain_longer_than_num_workers() {
+    let ex = chain(None);
+    let num_checked = check(&ex, 1, false).num_checked;
+    for &num_threads in [2, 4, 8].iter() {
+        assert_eq!(check(&ex, num_threads, false).num_checked, num_checked);
+    }
+}
+
+/// Links after the bad one may be checked before it fails, but they're
+/// reported as skipped all the same.
+#[test]
+fn chain_with_a_bad_link() {
+    let bad = CHAIN_LEN / 2;
+    let ex = chain(Some(bad));
+    for &num_threads in [1, 2, 4].iter() {
+        for _ in 0..4 {
+            let summary = check(&ex, num_threads, true).summary.unwrap();
+            let skipped = summary.skipped.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+            let mut expected = (bad + 1..CHAIN_LEN).map(|i| format!("d{}", i)).collect::<Vec<_>>();
+            expected.push("t".to_string());
+            assert_eq!(summary.failed.iter().map(|e| e.name.to_string()).collect::<Vec<_>>(), vec![format!("d{}", bad)]);
+            assert_eq!(skipped, expected);
+        }
+    }
+}
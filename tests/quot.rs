@@ -0,0 +1,90 @@
+mod common;
+
+use nanoda::errors::KernelError;
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// The ways `eq` can be declared before a `#QUOT`.
+#[derive(Clone, Copy, PartialEq)]
+enum Eq {
+    Genuine,
+    /// `eq {α} : α → α → Prop`, with one parameter and two indices.
+    OneParam,
+    /// `eq {α} (a b : α) : Prop`, with no indices.
+    NoIndices,
+    /// A second introduction rule, just like `refl`.
+    TwoIntros,
+    /// `refl : Π {α} (a b : α), eq a b`
+    WrongIntro,
+}
+
+/// `eq` declared as `shape`, followed by `num_quots` `#QUOT` lines.
+fn export(shape : Eq, num_quots : usize) -> Export {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let u = ex.param("u");
+    let sort_u = ex.sort(u);
+    let prop = ex.sort(0);
+    let eq = ex.cnst("eq", &[u]);
+    let (v0, v1, v2) = (ex.var(0), ex.var(1), ex.var(2));
+
+    let b_to_prop = ex.pi("b", v1, prop);
+    let a_to_prop = ex.pi("a", v0, b_to_prop);
+    let eq_ty = ex.pi("α", sort_u, a_to_prop);
+
+    let eq_a_a = ex.app(eq, &[v1, v0, v0]);
+    let a_to_eq = ex.pi("a", v0, eq_a_a);
+    let refl_ty = ex.pi("α", sort_u, a_to_eq);
+
+    match shape {
+        Eq::Genuine => ex.inductive("eq", 2, eq_ty, &[("eq.refl", refl_ty)], &["u"]),
+        Eq::OneParam => ex.inductive("eq", 1, eq_ty, &[("eq.refl", refl_ty)], &["u"]),
+        Eq::TwoIntros => ex.inductive("eq", 2, eq_ty, &[("eq.refl", refl_ty), ("eq.refl2", refl_ty)], &["u"]),
+        Eq::NoIndices => {
+            let b_to_prop = ex.pi("b", v1, prop);
+            let a_to_prop = ex.pi("a", v0, b_to_prop);
+            let eq_ty = ex.pi("α", sort_u, a_to_prop);
+            let eq_a_b = ex.app(eq, &[v2, v1, v0]);
+            let b_to_eq = ex.pi("b", v1, eq_a_b);
+            let a_to_eq = ex.pi("a", v0, b_to_eq);
+            let refl_ty = ex.pi("α", sort_u, a_to_eq);
+            ex.inductive("eq", 3, eq_ty, &[("eq.refl", refl_ty)], &["u"])
+        },
+        Eq::WrongIntro => {
+            let eq_a_b = ex.app(eq, &[v2, v1, v0]);
+            let b_to_eq = ex.pi("b", v1, eq_a_b);
+            let a_to_eq = ex.pi("a", v0, b_to_eq);
+            let refl_ty = ex.pi("α", sort_u, a_to_eq);
+            ex.inductive("eq", 2, eq_ty, &[("eq.refl", refl_ty)], &["u"])
+        },
+    }
+    for _ in 0..num_quots {
+        ex.quot();
+    }
+    ex
+}
+
+#[test]
+fn genuine_eq_is_accepted() {
+    let report = check_keep_going(&export(Eq::Genuine, 1));
+    assert_eq!(failed_names(&report), Vec::<String>::new());
+    assert!(report.env.declarations.get(&"quot.lift".parse().unwrap()).is_some());
+}
+
+#[test]
+fn fake_eq_is_rejected() {
+    for &shape in [Eq::OneParam, Eq::NoIndices, Eq::TwoIntros, Eq::WrongIntro].iter() {
+        let report = check_keep_going(&export(shape, 1));
+        assert_eq!(failed_names(&report), vec!["quot"]);
+        let failed = &report.summary.as_ref().unwrap().failed[0];
+        assert!(matches!(failed.err, KernelError::QuotBadEq(_)), "{}", failed);
+        assert!(report.env.declarations.get(&"quot".parse().unwrap()).is_none());
+        assert!(!report.env.quot_initialized.load(std::sync::atomic::Ordering::SeqCst));
+    }
+}
+
+#[test]
+fn second_quot_is_rejected() {
+    let report = check_keep_going(&export(Eq::Genuine, 2));
+    assert_eq!(failed_names(&report), vec!["quot"]);
+    assert_eq!(report.summary.unwrap().num_passed, 2);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use nanoda::{ Report };
+use nanoda::name::Name;
+use nanoda::parser::ExportFormat;
+use nanoda::pretty::pretty_printer::{ PrettyPrinter, PPOptions };
+use common::*;
+
+/// `Nat`, `List.{u}`, an axiomatized `Char` with `Char.ofNat`, the
+/// structure `String`, and `P : String → Prop`. Returns `(String, P)`.
+fn strings(ex : &mut Export) -> (usize, usize) {
+    let nat = ex.nat();
+    let u = ex.param("u");
+    let su = ex.succ(u);
+    let ty_u = ex.sort(su);
+    let list_ty = ex.pi("α", ty_u, ty_u);
+    let list = ex.cnst("List", &[u]);
+    let (v0, v1, v2) = (ex.var(0), ex.var(1), ex.var(2));
+    let list_v0 = ex.app(list, &[v0]);
+    let nil_ty = ex.pi("α", ty_u, list_v0);
+    let list_v1 = ex.app(list, &[v1]);
+    let list_v2 = ex.app(list, &[v2]);
+    let cons_tail = ex.pi("tail", list_v1, list_v2);
+    let cons_head = ex.pi("head", v0, cons_tail);
+    let cons_ty = ex.pi("α", ty_u, cons_head);
+    ex.inductive_counts("List", 1, 0, true, list_ty, &[("List.nil", nil_ty), ("List.cons", cons_ty)], &["u"]);
+
+    let one = ex.succ(0);
+    let type_ = ex.sort(one);
+    ex.axiom("Char", type_);
+    let char = ex.cnst("Char", &[]);
+    let of_nat_ty = ex.pi("n", nat, char);
+    ex.axiom("Char.ofNat", of_nat_ty);
+
+    let string = ex.cnst("String", &[]);
+    let list_0 = ex.cnst("List", &[0]);
+    let list_char = ex.app(list_0, &[char]);
+    let mk_ty = ex.pi("data", list_char, string);
+    ex.inductive("String", 0, type_, &[("String.mk", mk_ty)], &[]);
+
+    let prop = ex.sort(0);
+    let p_ty = ex.pi("s", string, prop);
+    ex.axiom("P", p_ty);
+    (string, ex.cnst("P", &[]))
+}
+
+/// `String.mk` applied to the characters of `s`, spelled out.
+fn spelled_out(ex : &mut Export, s : &str) -> usize {
+    let char = ex.cnst("Char", &[]);
+    let of_nat = ex.cnst("Char.ofNat", &[]);
+    let nil = ex.cnst("List.nil", &[0]);
+    let cons = ex.cnst("List.cons", &[0]);
+    let nil_char = ex.app(nil, &[char]);
+    let chars = s.chars().rev().fold(nil_char, |tl, c| {
+        let code = ex.lit(c as u64);
+        let hd = ex.app(of_nat, &[code]);
+        ex.app(cons, &[char, hd, tl])
+    });
+    let mk = ex.cnst("String.mk", &[]);
+    ex.app(mk, &[chars])
+}
+
+fn show(report : &Report, n : &str) -> String {
+    let options = PPOptions { width : 1000, ..PPOptions::new_default() };
+    PrettyPrinter::print_declar(Some(options), &n.parse::<Name>().unwrap(), &report.env).trim().to_string()
+}
+
+#[test]
+fn literals_have_type_string() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (string, _) = strings(&mut ex);
+    let lit = ex.str_lit("héllo");
+    ex.def("s", string, lit);
+    let nat = ex.cnst("Nat", &[]);
+    ex.def("not_nat", nat, lit);
+    assert_eq!(failures(&ex), vec!["not_nat"]);
+}
+
+/// A literal is equal to its constructor applied to its characters, and
+/// to nothing else, including for the empty string and a character
+/// outside of ASCII.
+#[test]
+fn literals_unfold_to_their_characters() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (_, p) = strings(&mut ex);
+    for (i, s) in ["", "h", "hé", "λx"].iter().enumerate() {
+        let spelled = spelled_out(&mut ex, s);
+        let p_spelled = ex.app(p, &[spelled]);
+        let h = format!("h{}", i);
+        ex.axiom(&h, p_spelled);
+        let h = ex.cnst(&h, &[]);
+
+        let lit = ex.str_lit(s);
+        let p_lit = ex.app(p, &[lit]);
+        ex.def(&format!("ok{}", i), p_lit, h);
+
+        let wrong = ex.str_lit(&format!("{}e", s));
+        let p_wrong = ex.app(p, &[wrong]);
+        ex.def(&format!("wrong{}", i), p_wrong, h);
+    }
+    assert_eq!(failures(&ex), vec!["wrong0", "wrong1", "wrong2", "wrong3"]);
+}
+
+#[test]
+fn literals_are_printed_escaped() {
+    let mut ex = Export::new(ExportFormat::Lean4);
+    let (string, _) = strings(&mut ex);
+    let lit = ex.str_lit("a\"b\\c\nd\te\u{1}λ");
+    ex.def("s", string, lit);
+    let report = check_keep_going(&ex);
+    assert_eq!(failed_names(&report), Vec::<String>::new());
+    assert!(show(&report, "s").ends_with(r#":= "a\"b\\c\nd\te\x01λ""#), "{}", show(&report, "s"));
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use nanoda::{ check_export, CheckOptions };
+use nanoda::env::DefKind;
+use nanoda::parser::ExportFormat;
+use common::*;
+
+/// A Lean 3 export with a proof `thm : wrap` that has to be unfolded
+/// for `unfolds_thm` to check, since it's the major premise of
+/// `wrap.rec` (which isn't K-like, since `wrap.mk` takes an argument),
+/// and a definition `d` that has to be unfolded for `unfolds_def` to
+/// check.
+fn export() -> Export {
+    let mut ex = Export::new(ExportFormat::Lean3);
+    let nat = ex.nat();
+    let prop = ex.sort(0);
+    let true_ = ex.cnst("true", &[]);
+    ex.inductive("true", 0, prop, &[("true.intro", true_)], &[]);
+    let wrap = ex.cnst("wrap", &[]);
+    let mk_ty = ex.pi("h", true_, wrap);
+    ex.inductive("wrap", 0, prop, &[("wrap.mk", mk_ty)], &[]);
+
+    let zero = ex.cnst("nat.zero", &[]);
+    let mk = ex.cnst("wrap.mk", &[]);
+    let intro = ex.cnst("true.intro", &[]);
+    let val = ex.app(mk, &[intro]);
+    ex.def("thm", wrap, val);
+    ex.def("d", nat, zero);
+
+    let p_ty = ex.pi("n", nat, prop);
+    ex.axiom("P", p_ty);
+    let p = ex.cnst("P", &[]);
+    let p_zero = ex.app(p, &[zero]);
+    ex.axiom("h", p_zero);
+    let h = ex.cnst("h", &[]);
+
+    // wrap.rec.{1} nat (λ _, nat.zero) thm
+    let one_lvl = ex.succ(0);
+    let rec = ex.cnst("wrap.rec", &[one_lvl]);
+    let minor = ex.lam("h", true_, zero);
+    let thm = ex.cnst("thm", &[]);
+    let cast = ex.app(rec, &[nat, minor, thm]);
+    let ty = ex.app(p, &[cast]);
+    ex.def("unfolds_thm", ty, h);
+    let d = ex.cnst("d", &[]);
+    let ty = ex.app(p, &[d]);
+    ex.def("unfolds_def", ty, h);
+    ex
+}
+
+/// With `guess_theorems`, `thm` is a theorem and isn't unfolded, while
+/// `d` still is. That's the same however many threads there are.
+#[test]
+fn guessed_theorems_arent_unfolded() {
+    let ex = export();
+    for &num_threads in [1, 4].iter() {
+        for &guess_theorems in [false, true].iter() {
+            let options = CheckOptions {
+                format : Some(ExportFormat::Lean3),
+                keep_going : true,
+                guess_theorems,
+                ..CheckOptions::new(num_threads)
+            };
+            let report = check_export(ex.source(), &options).expect("keep going never fails");
+            let expected : Vec<&str> = if guess_theorems { vec!["unfolds_thm"] } else { vec![] };
+            assert_eq!(failed_names(&report), expected, "{} threads", num_threads);
+
+            let kind = |n : &str| report.env.guessed_kinds.get(&n.parse().unwrap()).copied();
+            if guess_theorems {
+                assert_eq!(kind("thm"), Some(DefKind::Theorem));
+                assert_eq!(kind("d"), Some(DefKind::Definition));
+            } else {
+                assert_eq!(kind("thm"), None);
+            }
+        }
+    }
+}